@@ -0,0 +1,74 @@
+//! Compare Frame::serialize, which allocates a fresh buffer for every frame,
+//! against Frame::encode into a buffer that is reused across frames.
+//!
+//! Run with "cargo bench --bench frame_encode"
+use bytes::{Bytes, BytesMut};
+use redis::Frame;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Run the closure repeatedly for about a second and return the mean time
+/// taken by one call
+fn bench<F: FnMut()>(mut f: F) -> Duration {
+    // Warm up caches and the allocator
+    for _ in 0..10 {
+        f();
+    }
+
+    let budget = Duration::from_secs(1);
+    let start = Instant::now();
+    let mut iters = 0;
+    while start.elapsed() < budget {
+        f();
+        iters += 1;
+    }
+    return start.elapsed() / iters;
+}
+
+/// An MGET-style reply nested "depth" levels deep, with "width" elements per
+/// level
+fn deep_array(depth: usize, width: usize) -> Frame {
+    if depth == 0 {
+        return Frame::Bulk(Bytes::from("some moderately sized value"));
+    }
+    let elems = (0..width).map(|_| deep_array(depth - 1, width)).collect();
+    return Frame::Array(elems);
+}
+
+fn compare(name: &str, frame: &Frame) {
+    let serialize = bench(|| {
+        black_box(frame.serialize());
+    });
+    let mut buf = BytesMut::with_capacity(frame.encoded_len());
+    let encode = bench(|| {
+        buf.clear();
+        frame.encode(&mut buf);
+        black_box(&buf);
+    });
+    println!("{name:<24} serialize: {serialize:>12?}    encode: {encode:>12?}");
+}
+
+fn main() {
+    compare("array depth=4 width=10", &deep_array(4, 10));
+    compare("array depth=12 width=2", &deep_array(12, 2));
+    compare(
+        "mget 10k values",
+        &Frame::Array(
+            (0..10_000)
+                .map(|i| match i % 3 {
+                    0 => Frame::Null,
+                    _ => Frame::Bulk(Bytes::from(format!("value-{i}"))),
+                })
+                .collect(),
+        ),
+    );
+    compare("bulk 4MiB", &Frame::Bulk(Bytes::from(vec![b'x'; 4 << 20])));
+    compare(
+        "array of 4 x bulk 1MiB",
+        &Frame::Array(
+            (0..4)
+                .map(|_| Frame::Bulk(Bytes::from(vec![b'x'; 1 << 20])))
+                .collect(),
+        ),
+    );
+}
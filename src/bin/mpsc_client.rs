@@ -1,7 +1,6 @@
 //! A sample Rust client that demonstrates the usage patterns of tokio::sync::mpsc
 //! and tokio::sync::oneshot
 use bytes::Bytes;
-use mini_redis;
 use tokio::sync::{
     mpsc,
     oneshot::{self, Sender},
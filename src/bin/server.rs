@@ -1,44 +1,115 @@
 use bytes::Bytes;
-use redis::{Command, Connection, Frame, MyResult};
-use std::collections::HashMap;
+use clap::Parser;
+use redis::command::{ConnectionCommand, ServerCommand};
+use redis::db::{expire_keys, Config, Subscriptions, DB};
+use redis::{Command, Connection, Frame, Limits, MyResult, ParseError, Protocol};
 use std::error::Error;
-use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
 use tokio::net::TcpListener;
 
-struct DB<T, U> {
-    db: Mutex<HashMap<T, U>>,
-}
+/// A Redis server that keeps its data in memory
+#[derive(Parser, Debug)]
+struct Args {
+    /// The longest bulk string a client may send, defaults to 512MB
+    #[arg(long)]
+    proto_max_bulk_len: Option<usize>,
 
-impl<T: Eq + Hash, U: Clone> DB<T, U> {
-    fn insert(&self, key: T, val: U) -> Option<U> {
-        let mut lock = self.db.lock().unwrap();
-        return lock.insert(key, val);
-    }
+    /// The most elements a client may send in one frame, defaults to 1048576
+    #[arg(long)]
+    max_multibulk_len: Option<usize>,
 
-    fn get(&self, key: &T) -> Option<U> {
-        let lock = self.db.lock().unwrap();
-        return lock.get(key).cloned();
-    }
+    /// How deep a client may nest aggregate frames, defaults to 128
+    #[arg(long)]
+    max_nesting_depth: Option<usize>,
+
+    /// The longest inline command a client may send, defaults to 64KB
+    #[arg(long)]
+    max_inline_len: Option<usize>,
+
+    /// The most fields a hash may have before it is converted from a compact
+    /// listpack into a hash table, defaults to 128
+    #[arg(long)]
+    hash_max_listpack_entries: Option<usize>,
+
+    /// The longest field or value a hash may hold before it is converted from
+    /// a compact listpack into a hash table, defaults to 64
+    #[arg(long)]
+    hash_max_listpack_value: Option<usize>,
+
+    /// The most members a set of integers may have before it is converted
+    /// from a compact intset into a hash table, defaults to 512
+    #[arg(long)]
+    set_max_intset_entries: Option<usize>,
+
+    /// The most members a set may have before it is converted from a compact
+    /// listpack into a hash table, defaults to 128
+    #[arg(long)]
+    set_max_listpack_entries: Option<usize>,
+
+    /// The longest member a set may hold before it is converted from a
+    /// compact listpack into a hash table, defaults to 64
+    #[arg(long)]
+    set_max_listpack_value: Option<usize>,
 
-    fn remove(&self, key: &T) -> Option<U> {
-        let mut lock = self.db.lock().unwrap();
-        return lock.remove(&key);
+    /// The longest string a HyperLogLog may take up before it is converted
+    /// from the sparse encoding into the dense one, defaults to 3000
+    #[arg(long)]
+    hll_sparse_max_bytes: Option<usize>,
+}
+
+impl Args {
+    /// Collect the protocol limits, falling back to the defaults
+    fn limits(&self) -> Limits {
+        let defaults = Limits::default();
+        return Limits {
+            max_bulk_len: self.proto_max_bulk_len.unwrap_or(defaults.max_bulk_len),
+            max_multibulk_len: self.max_multibulk_len.unwrap_or(defaults.max_multibulk_len),
+            max_depth: self.max_nesting_depth.unwrap_or(defaults.max_depth),
+            max_inline_len: self.max_inline_len.unwrap_or(defaults.max_inline_len),
+        };
     }
 
-    fn new() -> Self {
-        let db = Mutex::new(HashMap::new());
-        return Self { db };
+    /// Collect the settings of the keyspace, falling back to the defaults
+    fn config(&self) -> Config {
+        let defaults = Config::default();
+        return Config {
+            hash_max_listpack_entries: self
+                .hash_max_listpack_entries
+                .unwrap_or(defaults.hash_max_listpack_entries),
+            hash_max_listpack_value: self
+                .hash_max_listpack_value
+                .unwrap_or(defaults.hash_max_listpack_value),
+            set_max_intset_entries: self
+                .set_max_intset_entries
+                .unwrap_or(defaults.set_max_intset_entries),
+            set_max_listpack_entries: self
+                .set_max_listpack_entries
+                .unwrap_or(defaults.set_max_listpack_entries),
+            set_max_listpack_value: self
+                .set_max_listpack_value
+                .unwrap_or(defaults.set_max_listpack_value),
+            hll_sparse_max_bytes: self
+                .hll_sparse_max_bytes
+                .unwrap_or(defaults.hll_sparse_max_bytes),
+        };
     }
 }
 
+/// Every connection is identified by a unique, increasing ID
+static NEXT_CLIENT_ID: AtomicI64 = AtomicI64::new(1);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let limits = args.limits();
     let listener = TcpListener::bind("0.0.0.0:6379").await?;
-    let db: Arc<DB<Bytes, Bytes>> = Arc::new(DB::new());
+    let db = Arc::new(DB::with_config(args.config()));
+    tokio::spawn(expire_keys(Arc::clone(&db)));
     loop {
         let (socket, _addr) = listener.accept().await?;
-        let connection = Connection::new(socket);
+        let mut connection = Connection::new(socket);
+        connection.set_limits(limits.clone());
         let db_copy = Arc::clone(&db);
 
         tokio::spawn(async move {
@@ -47,45 +118,342 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
 }
 
-async fn process(mut connection: Connection, db: Arc<DB<Bytes, Bytes>>) -> MyResult<()> {
+/// Serve a connection until it closes, then drop its subscriptions
+async fn process(mut connection: Connection, db: Arc<DB>) -> MyResult<()> {
+    let mut subscriptions = db.subscriptions();
+    let result = serve(&mut connection, &db, &mut subscriptions).await;
+    db.unsubscribe_all(&mut subscriptions);
+    return result;
+}
+
+/// Serve the commands sent over a single connection. Replies are buffered and
+/// only flushed once the connection runs out of pipelined commands to process.
+/// While waiting for commands, the messages published to the subscriptions of
+/// the connection are pushed to it.
+async fn serve(
+    connection: &mut Connection,
+    db: &DB,
+    subscriptions: &mut Subscriptions,
+) -> MyResult<()> {
+    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
     loop {
-        let frame = connection.read_frame().await?;
+        let frame = tokio::select! {
+            frame = connection.read_frame() => frame,
+            message = subscriptions.next_message() => {
+                connection.buffer_frame(&message).await?;
+                continue;
+            }
+        };
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(err) => {
+                // Like Redis, tell the client what went wrong before hanging
+                // up on a protocol violation
+                if let Some(err @ ParseError::Protocol { .. }) = err.downcast_ref() {
+                    connection
+                        .write_frame(&Frame::Error(format!("ERR {err}")))
+                        .await?;
+                }
+                return Err(err);
+            }
+        };
         match frame {
             None => {
                 return Ok(());
             }
             Some(frame) => {
-                let cmd = Command::parse_command(&frame);
+                let cmd = Command::from_frame(&frame);
+                let subscribed_resp2 =
+                    !subscriptions.is_empty() && connection.protocol() == Protocol::Resp2;
                 match cmd {
-                    None => {
+                    Err(err) => {
+                        connection.buffer_frame(&err.to_frame()).await?;
+                    }
+                    // Like in Redis, a subscribed RESP2 connection is mostly
+                    // limited to changing its subscriptions
+                    Ok(cmd) if subscribed_resp2 && !cmd.is_allowed_when_subscribed() => {
+                        let reply = Frame::Error(format!(
+                            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / \
+                             (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                            command_name(&frame)
+                        ));
+                        connection.buffer_frame(&reply).await?;
+                    }
+                    Ok(cmd) if cmd.is_blocking() => {
+                        // Stop waiting as soon as the client disconnects
+                        let reply = tokio::select! {
+                            reply = db.block(cmd) => reply,
+                            closed = connection.closed() => return closed,
+                        };
+                        connection.buffer_frame(&reply).await?;
+                    }
+                    Ok(Command::String(cmd)) => {
+                        connection.buffer_frame(&db.execute_string(cmd)).await?;
+                    }
+                    Ok(Command::Bitmap(cmd)) => {
+                        connection.buffer_frame(&db.execute_bitmap(cmd)).await?;
+                    }
+                    Ok(Command::Geo(cmd)) => {
+                        connection.buffer_frame(&db.execute_geo(cmd)).await?;
+                    }
+                    Ok(Command::Hash(cmd)) => {
+                        connection.buffer_frame(&db.execute_hash(cmd)).await?;
+                    }
+                    Ok(Command::HyperLogLog(cmd)) => {
                         connection
-                            .write_frame(&Frame::Error("Illegal command".into()))
+                            .buffer_frame(&db.execute_hyperloglog(cmd))
                             .await?;
                     }
-                    Some(Command::Set { key, val }) => {
-                        db.insert(key, val);
-                        connection.write_frame(&Frame::Simple("OK".into())).await?;
+                    Ok(Command::Key(cmd)) => {
+                        connection.buffer_frame(&db.execute_key(cmd)).await?;
                     }
-                    Some(Command::Get { key }) => match db.get(&key) {
-                        None => {
-                            connection
-                                .write_frame(&Frame::Error("Key not found".into()))
-                                .await?;
-                        }
-                        Some(val) => {
-                            connection.write_frame(&Frame::Bulk(val)).await?;
-                        }
-                    },
-                    Some(Command::Del { key }) => match db.remove(&key) {
-                        None => {
-                            connection.write_frame(&Frame::Integer(0)).await?;
+                    Ok(Command::List(cmd)) => {
+                        connection.buffer_frame(&db.execute_list(cmd)).await?;
+                    }
+                    Ok(Command::Set(cmd)) => {
+                        connection.buffer_frame(&db.execute_set(cmd)).await?;
+                    }
+                    Ok(Command::SortedSet(cmd)) => {
+                        connection.buffer_frame(&db.execute_sorted_set(cmd)).await?;
+                    }
+                    Ok(Command::Stream(cmd)) => {
+                        connection.buffer_frame(&db.execute_stream(cmd)).await?;
+                    }
+                    Ok(Command::PubSub(cmd)) => {
+                        for reply in db.execute_pubsub(subscriptions, cmd) {
+                            connection.buffer_frame(&reply).await?;
                         }
-                        Some(_) => {
-                            connection.write_frame(&Frame::Integer(1)).await?;
+                    }
+                    Ok(Command::Connection(ConnectionCommand::Hello {
+                        protover, auth, ..
+                    })) => {
+                        let reply = hello(connection, client_id, protover, auth);
+                        connection.buffer_frame(&reply).await?;
+                    }
+                    // A subscribed RESP2 connection gets a reply shaped like
+                    // a message, so that it can be told apart from them
+                    Ok(Command::Connection(ConnectionCommand::Ping { message })) => {
+                        let reply = match message {
+                            message if subscribed_resp2 => Frame::Array(vec![
+                                Frame::Bulk(Bytes::from("pong")),
+                                Frame::Bulk(message.unwrap_or_default()),
+                            ]),
+                            Some(message) => Frame::Bulk(message),
+                            None => Frame::Simple("PONG".into()),
+                        };
+                        connection.buffer_frame(&reply).await?;
+                    }
+                    Ok(Command::Connection(ConnectionCommand::Quit)) => {
+                        connection.write_frame(&Frame::Simple("OK".into())).await?;
+                        return Ok(());
+                    }
+                    Ok(Command::Connection(ConnectionCommand::Reset)) => {
+                        for message in db.unsubscribe_all(subscriptions) {
+                            connection.buffer_frame(&message).await?;
                         }
-                    },
+                        connection.set_protocol(Protocol::Resp2);
+                        connection
+                            .buffer_frame(&Frame::Simple("RESET".into()))
+                            .await?;
+                    }
+                    Ok(Command::Server(ServerCommand::Info { sections })) => {
+                        connection.buffer_frame(&info(db, &sections)).await?;
+                    }
                 }
             }
         }
     }
 }
+
+/// The name of the command a request frame calls, in lower case
+fn command_name(frame: &Frame) -> String {
+    return match frame {
+        Frame::Array(args) => match args.first() {
+            Some(Frame::Bulk(name)) => String::from_utf8_lossy(name).to_lowercase(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    };
+}
+
+/// Describe the server in the INFO format: sections that start with a
+/// "# Name" header and list "field:value" lines
+fn info(db: &DB, sections: &[Bytes]) -> Frame {
+    let wants = |section: &str| {
+        return sections.is_empty()
+            || sections.iter().any(|wanted| {
+                let wanted = wanted.to_ascii_lowercase();
+                return wanted == section.as_bytes()
+                    || [&b"all"[..], b"everything", b"default"].contains(&&wanted[..]);
+            });
+    };
+    let keyspace = db.lock();
+    let mut text = String::new();
+    if wants("clients") {
+        text.push_str("# Clients\r\n");
+        text.push_str(&format!("blocked_clients:{}\r\n", keyspace.blocked_len()));
+    }
+    if wants("stats") {
+        if !text.is_empty() {
+            text.push_str("\r\n");
+        }
+        let stats = keyspace.stats();
+        text.push_str("# Stats\r\n");
+        text.push_str(&format!("expired_keys:{}\r\n", stats.expired_keys));
+        text.push_str(&format!("expired_subkeys:{}\r\n", stats.expired_fields));
+        text.push_str(&format!(
+            "expired_time_cap_reached_count:{}\r\n",
+            stats.time_cap_reached
+        ));
+        text.push_str(&format!("expire_cycle_count:{}\r\n", stats.cycles));
+        text.push_str(&format!(
+            "expire_cycle_cpu_milliseconds:{}\r\n",
+            stats.cycle_time.as_millis()
+        ));
+        text.push_str(&format!(
+            "expire_cycle_last_microseconds:{}\r\n",
+            stats.last_cycle_time.as_micros()
+        ));
+    }
+    if wants("keyspace") {
+        if !text.is_empty() {
+            text.push_str("\r\n");
+        }
+        text.push_str("# Keyspace\r\n");
+        if !keyspace.is_empty() {
+            text.push_str(&format!(
+                "db0:keys={},expires={}\r\n",
+                keyspace.len(),
+                keyspace.expires_len()
+            ));
+        }
+    }
+    return Frame::Verbatim {
+        format: "txt".into(),
+        text: Bytes::from(text),
+    };
+}
+
+/// Negotiate the protocol requested by a HELLO command and describe the
+/// server. The reply is meant to be encoded in the newly negotiated protocol.
+fn hello(
+    connection: &mut Connection,
+    client_id: i64,
+    protover: Option<i64>,
+    auth: Option<(Bytes, Bytes)>,
+) -> Frame {
+    let protocol = match protover {
+        None => connection.protocol(),
+        Some(2) => Protocol::Resp2,
+        Some(3) => Protocol::Resp3,
+        Some(_) => return Frame::Error("NOPROTO unsupported protocol version".into()),
+    };
+    // There are no users besides "default", which accepts any password
+    if let Some((username, _password)) = auth {
+        if username != "default" {
+            return Frame::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".into(),
+            );
+        }
+    }
+    connection.set_protocol(protocol);
+
+    let field = |name: &'static str| Frame::Bulk(Bytes::from(name));
+    return Frame::Map(vec![
+        (field("server"), field("redis")),
+        (field("version"), field(env!("CARGO_PKG_VERSION"))),
+        (field("proto"), Frame::Integer(protocol.version())),
+        (field("id"), Frame::Integer(client_id)),
+        (field("mode"), field("standalone")),
+        (field("role"), field("master")),
+        (field("modules"), Frame::Array(vec![])),
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::command::PubSubCommand;
+    use tokio::net::TcpStream;
+
+    /// Send a request and return the next frame the server sends back
+    async fn call(client: &mut Connection, args: &[&str]) -> Frame {
+        let request = args
+            .iter()
+            .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect();
+        client.write_frame(&Frame::Array(request)).await.unwrap();
+        return client.read_frame().await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscribed_resp2_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let db = Arc::new(DB::new());
+        let server = tokio::spawn({
+            let db = db.clone();
+            async move {
+                let (socket, _) = listener.accept().await.unwrap();
+                return process(Connection::new(socket), db).await.is_ok();
+            }
+        });
+        let mut client = Connection::new(TcpStream::connect(addr).await.unwrap());
+        let bulks = |elems: &[&'static str]| {
+            return Frame::Array(
+                elems
+                    .iter()
+                    .map(|elem| Frame::Bulk(Bytes::from(*elem)))
+                    .collect(),
+            );
+        };
+
+        assert_eq!(
+            call(&mut client, &["SUBSCRIBE", "a"]).await,
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("subscribe")),
+                Frame::Bulk(Bytes::from("a")),
+                Frame::Integer(1),
+            ])
+        );
+        assert_eq!(
+            call(&mut client, &["GET", "k"]).await,
+            Frame::Error(
+                "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / \
+                 PING / QUIT / RESET are allowed in this context"
+                    .into()
+            )
+        );
+        assert_eq!(call(&mut client, &["PING"]).await, bulks(&["pong", ""]));
+        assert_eq!(
+            call(&mut client, &["ping", "hi"]).await,
+            bulks(&["pong", "hi"])
+        );
+
+        // Messages published before RESET arrive before its reply
+        let publish = PubSubCommand::Publish {
+            channel: Bytes::from("a"),
+            message: Bytes::from("hello"),
+        };
+        db.execute_pubsub(&mut db.subscriptions(), publish);
+        assert_eq!(
+            call(&mut client, &["RESET"]).await,
+            bulks(&["message", "a", "hello"])
+        );
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            Some(Frame::Simple("RESET".into()))
+        );
+        assert_eq!(call(&mut client, &["GET", "k"]).await, Frame::Null);
+        assert_eq!(
+            call(&mut client, &["PING"]).await,
+            Frame::Simple("PONG".into())
+        );
+        assert_eq!(
+            call(&mut client, &["QUIT"]).await,
+            Frame::Simple("OK".into())
+        );
+        assert_eq!(client.read_frame().await.unwrap(), None);
+        assert!(server.await.unwrap());
+    }
+}
@@ -1,70 +1,95 @@
 //! A synchronous Redis server: every request is blocking
 use bytes::Bytes;
-use mini_redis::Command;
-use mini_redis::{Connection, Frame};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use redis::command::{CommandError, ConnectionCommand};
+use redis::db::{expire_keys, DB};
+use redis::{Command, Connection, Frame, MyResult, ParseError};
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
-use std::hash::Hash;
-
-struct DB<T, U> {
-    db: Mutex<HashMap<T, U>>,
-}
-
-impl<T: Eq + Hash, U: Clone> DB<T, U> {
-    fn new() -> Self {
-        let db = Mutex::new(HashMap::new());
-        return Self { db };
-    }
-
-    fn get(&self, key: &T) -> Option<U> {
-        return match self.db.lock() {
-            Ok(lock) => {
-                match lock.get(key) {
-                    Some(val) => Some(val.clone()),
-                    None => None,
-                }
-            },
-            _ => None,
-        }
-    }
-
-    fn set(&self, key: T, val: U) {
-        if let Ok(mut lock) = self.db.lock() {
-            lock.insert(key, val);
-        }
-    }
-}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
-    let db: Arc<DB<String, Bytes>> = Arc::new(DB::new());
+    let db = Arc::new(DB::new());
+    tokio::spawn(expire_keys(db.clone()));
 
     while let Ok((stream, _addr)) = listener.accept().await {
         let db = db.clone();
         tokio::spawn(async move {
-            process(stream, db).await;
+            let _ = process(stream, db).await;
         });
     }
 }
 
-async fn process(stream: TcpStream, db: Arc<DB<String, Bytes>>) {
+/// Serve a connection until it closes. I/O errors end the connection like a
+/// disconnect would.
+async fn process(stream: TcpStream, db: Arc<DB>) -> MyResult<()> {
     let mut connection = Connection::new(stream);
-    while let Some(frame) = connection.read_frame().await.unwrap() {
-        let cmd = Command::from_frame(frame).unwrap();
-        let response = match cmd {
-            Command::Set(cmd) => {
-                db.set(cmd.key().to_string(), cmd.value().clone());
-                Frame::Simple("OK".to_string())
+    loop {
+        let frame = match connection.read_frame().await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                // Like Redis, tell the client what went wrong before hanging
+                // up on a protocol violation
+                if let Some(err @ ParseError::Protocol { .. }) = err.downcast_ref() {
+                    connection
+                        .write_frame(&Frame::Error(format!("ERR {err}")))
+                        .await?;
+                }
+                return Err(err);
             }
-            Command::Get(cmd) => match db.get(&cmd.key().to_string()) {
-                Some(val) => Frame::Bulk(val.clone().into()),
-                None => Frame::Null,
+        };
+        let response = match Command::from_frame(&frame) {
+            // Stop waiting as soon as the client disconnects, so that no
+            // data is handed to a client that is gone
+            Ok(cmd) if cmd.is_blocking() => tokio::select! {
+                reply = db.block(cmd) => reply,
+                closed = connection.closed() => return closed,
             },
-            _ => unimplemented!("{:?} not implemented!", cmd),
+            Ok(Command::String(cmd)) => db.execute_string(cmd),
+            Ok(Command::Hash(cmd)) => db.execute_hash(cmd),
+            Ok(Command::Bitmap(cmd)) => db.execute_bitmap(cmd),
+            Ok(Command::Geo(cmd)) => db.execute_geo(cmd),
+            Ok(Command::HyperLogLog(cmd)) => db.execute_hyperloglog(cmd),
+            Ok(Command::Key(cmd)) => db.execute_key(cmd),
+            Ok(Command::List(cmd)) => db.execute_list(cmd),
+            Ok(Command::Set(cmd)) => db.execute_set(cmd),
+            Ok(Command::SortedSet(cmd)) => db.execute_sorted_set(cmd),
+            Ok(Command::Stream(cmd)) => db.execute_stream(cmd),
+            Ok(Command::Connection(ConnectionCommand::Ping { message })) => match message {
+                Some(message) => Frame::Bulk(message),
+                None => Frame::Simple("PONG".into()),
+            },
+            Ok(Command::Connection(ConnectionCommand::Quit)) => {
+                let ok = Frame::Simple("OK".into());
+                connection.write_frame(&ok).await?;
+                return Ok(());
+            }
+            // This server keeps no state per connection, so it does not know
+            // the commands that need some, like SUBSCRIBE or HELLO
+            Ok(_) => unknown_command(&frame),
+            Err(err) => err.to_frame(),
         };
 
-        connection.write_frame(&response).await.unwrap();
+        connection.write_frame(&response).await?;
     }
 }
+
+/// Reject a request the way Redis rejects the commands it does not know
+fn unknown_command(frame: &Frame) -> Frame {
+    let args: Vec<Bytes> = match frame {
+        Frame::Array(args) => args
+            .iter()
+            .filter_map(|arg| match arg {
+                Frame::Bulk(arg) => Some(arg.clone()),
+                Frame::Simple(arg) => Some(Bytes::copy_from_slice(arg.as_bytes())),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    let Some((name, args)) = args.split_first() else {
+        return CommandError::InvalidRequest.to_frame();
+    };
+    return CommandError::unknown_command(name, args).to_frame();
+}
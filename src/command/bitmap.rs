@@ -0,0 +1,435 @@
+//! Commands that operate on string values as arrays of bits
+use super::key::single_key;
+use super::{parse_integer, Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitmapCommand {
+    /// SETBIT, which replies with the previous value of the bit
+    SetBit {
+        key: Bytes,
+        offset: u64,
+        value: bool,
+    },
+    GetBit {
+        key: Bytes,
+        offset: u64,
+    },
+    /// BITCOUNT, which counts the set bits of the whole string, or of a
+    /// range that always has an end
+    Count {
+        key: Bytes,
+        range: Option<BitRange>,
+    },
+    /// BITPOS, which finds the first bit set to `bit`
+    Pos {
+        key: Bytes,
+        bit: bool,
+        range: Option<BitRange>,
+    },
+    /// BITOP, which stores the result under the destination and replies with
+    /// its length
+    Op {
+        op: BitOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    /// BITFIELD, and BITFIELD_RO when `readonly` is set, which only allows
+    /// GET
+    Field {
+        key: Bytes,
+        ops: Vec<BitFieldOp>,
+        readonly: bool,
+    },
+}
+
+/// A range of a string, counting from the end when negative
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitRange {
+    pub start: i64,
+    /// The last byte or bit of the range, which BITPOS does not require
+    pub end: Option<i64>,
+    pub unit: BitUnit,
+}
+
+/// What the indexes of a range count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+/// How BITOP combines the strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    /// The inverse of a single string
+    Not,
+}
+
+impl BitOp {
+    fn name(&self) -> &'static str {
+        return match self {
+            Self::And => "AND",
+            Self::Or => "OR",
+            Self::Xor => "XOR",
+            Self::Not => "NOT",
+        };
+    }
+}
+
+/// An operation of BITFIELD on an integer of the given type, starting at a
+/// bit offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitFieldOp {
+    Get {
+        encoding: BitFieldType,
+        offset: u64,
+    },
+    /// Set the integer, replying with its previous value
+    Set {
+        encoding: BitFieldType,
+        offset: u64,
+        value: i64,
+    },
+    /// Increment the integer, replying with its new value
+    IncrBy {
+        encoding: BitFieldType,
+        offset: u64,
+        increment: i64,
+    },
+    /// How the following SET and INCRBY operations handle overflows
+    Overflow(Overflow),
+}
+
+/// The type of a BITFIELD integer: i1 to i64, or u1 to u63
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wrap around, both ways
+    #[default]
+    Wrap,
+    /// Saturate at the smallest or largest value
+    Sat,
+    /// Do nothing and reply with Null
+    Fail,
+}
+
+/// The number of bits in the longest string a value may grow to, like
+/// Redis's default proto-max-bulk-len of 512MB
+const MAX_BITS: u64 = 512 * 1024 * 1024 * 8;
+
+fn invalid_offset() -> CommandError {
+    return CommandError::Other("ERR bit offset is not an integer or out of range".into());
+}
+
+/// Parse the offset of a bit
+fn parse_offset(arg: &[u8]) -> Result<u64, CommandError> {
+    return match parse_integer(arg) {
+        Some(offset) if offset >= 0 && (offset as u64) < MAX_BITS => Ok(offset as u64),
+        _ => Err(invalid_offset()),
+    };
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("SETBIT", 4, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let offset = parse_offset(&args.next_bytes()?)?;
+        let value = match args.next_bytes()?.as_ref() {
+            b"0" => false,
+            b"1" => true,
+            _ => {
+                return Err(CommandError::Other(
+                    "ERR bit is not an integer or out of range".into(),
+                ))
+            }
+        };
+        return Ok(Command::Bitmap(BitmapCommand::SetBit {
+            key,
+            offset,
+            value,
+        }));
+    }),
+    single_key("GETBIT", 3, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        let offset = parse_offset(&args.next_bytes()?)?;
+        return Ok(Command::Bitmap(BitmapCommand::GetBit { key, offset }));
+    }),
+    single_key("BITCOUNT", -2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let range = parse_range(args)?;
+        if range.is_some_and(|range| range.end.is_none()) {
+            return Err(CommandError::Syntax);
+        }
+        return Ok(Command::Bitmap(BitmapCommand::Count { key, range }));
+    }),
+    single_key("BITPOS", -3, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let bit = match args.next_bytes()?.as_ref() {
+            b"0" => false,
+            b"1" => true,
+            _ => {
+                return Err(CommandError::Other(
+                    "ERR The bit argument must be 1 or 0.".into(),
+                ))
+            }
+        };
+        let range = parse_range(args)?;
+        return Ok(Command::Bitmap(BitmapCommand::Pos { key, bit, range }));
+    }),
+    CommandSpec {
+        name: "BITOP",
+        arity: -4,
+        flags: WRITE,
+        first_key: 2,
+        last_key: -1,
+        key_step: 1,
+        parse: |args| {
+            let op = match args.next_keyword()?.as_str() {
+                "AND" => BitOp::And,
+                "OR" => BitOp::Or,
+                "XOR" => BitOp::Xor,
+                "NOT" => BitOp::Not,
+                _ => return Err(CommandError::Syntax),
+            };
+            let destination = args.next_bytes()?;
+            let keys = args.rest();
+            if op == BitOp::Not && keys.len() != 1 {
+                return Err(CommandError::Other(
+                    "ERR BITOP NOT must be called with a single source key.".into(),
+                ));
+            }
+            return Ok(Command::Bitmap(BitmapCommand::Op {
+                op,
+                destination,
+                keys,
+            }));
+        },
+    },
+    single_key("BITFIELD", -2, WRITE, |args| parse_field(args, false)),
+    single_key("BITFIELD_RO", -2, READONLY_FAST, |args| {
+        parse_field(args, true)
+    }),
+];
+
+impl BitmapCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let name = |name: &'static str, key: &Bytes| vec![Bytes::from(name), key.clone()];
+        let number = |n: i64| Bytes::from(n.to_string());
+        let push_range = |args: &mut Vec<Bytes>, range: &Option<BitRange>| {
+            let Some(range) = range else {
+                return;
+            };
+            args.push(number(range.start));
+            if let Some(end) = range.end {
+                args.push(number(end));
+                if range.unit == BitUnit::Bit {
+                    args.push(Bytes::from("BIT"));
+                }
+            }
+        };
+        return match self {
+            Self::SetBit { key, offset, value } => {
+                let mut args = name("SETBIT", key);
+                args.extend([number(*offset as i64), number(*value as i64)]);
+                args
+            }
+            Self::GetBit { key, offset } => {
+                let mut args = name("GETBIT", key);
+                args.push(number(*offset as i64));
+                args
+            }
+            Self::Count { key, range } => {
+                let mut args = name("BITCOUNT", key);
+                push_range(&mut args, range);
+                args
+            }
+            Self::Pos { key, bit, range } => {
+                let mut args = name("BITPOS", key);
+                args.push(number(*bit as i64));
+                push_range(&mut args, range);
+                args
+            }
+            Self::Op {
+                op,
+                destination,
+                keys,
+            } => {
+                let mut args = vec![
+                    Bytes::from("BITOP"),
+                    Bytes::from(op.name()),
+                    destination.clone(),
+                ];
+                args.extend(keys.iter().cloned());
+                args
+            }
+            Self::Field { key, ops, readonly } => {
+                let mut args = match readonly {
+                    true => name("BITFIELD_RO", key),
+                    false => name("BITFIELD", key),
+                };
+                let encoding = |encoding: &BitFieldType| {
+                    let sign = if encoding.signed { 'i' } else { 'u' };
+                    return Bytes::from(format!("{sign}{}", encoding.bits));
+                };
+                for op in ops {
+                    match op {
+                        BitFieldOp::Get {
+                            encoding: e,
+                            offset,
+                        } => args.extend([Bytes::from("GET"), encoding(e), number(*offset as i64)]),
+                        BitFieldOp::Set {
+                            encoding: e,
+                            offset,
+                            value,
+                        } => args.extend([
+                            Bytes::from("SET"),
+                            encoding(e),
+                            number(*offset as i64),
+                            number(*value),
+                        ]),
+                        BitFieldOp::IncrBy {
+                            encoding: e,
+                            offset,
+                            increment,
+                        } => args.extend([
+                            Bytes::from("INCRBY"),
+                            encoding(e),
+                            number(*offset as i64),
+                            number(*increment),
+                        ]),
+                        BitFieldOp::Overflow(overflow) => args.extend([
+                            Bytes::from("OVERFLOW"),
+                            Bytes::from(match overflow {
+                                Overflow::Wrap => "WRAP",
+                                Overflow::Sat => "SAT",
+                                Overflow::Fail => "FAIL",
+                            }),
+                        ]),
+                    }
+                }
+                args
+            }
+        };
+    }
+}
+
+/// Parse the optional range of BITCOUNT and BITPOS: [start [end [BYTE|BIT]]]
+fn parse_range(args: &mut CommandArgs) -> Result<Option<BitRange>, CommandError> {
+    if args.remaining() == 0 {
+        return Ok(None);
+    }
+    let start = args.next_integer()?;
+    let end = match args.remaining() {
+        0 => None,
+        _ => Some(args.next_integer()?),
+    };
+    let unit = match args.remaining() {
+        0 => BitUnit::Byte,
+        _ => match args.next_keyword()?.as_str() {
+            "BYTE" => BitUnit::Byte,
+            "BIT" => BitUnit::Bit,
+            _ => return Err(CommandError::Syntax),
+        },
+    };
+    args.finish()?;
+    return Ok(Some(BitRange { start, end, unit }));
+}
+
+/// Parse the type of a BITFIELD integer, such as i16 or u8
+fn parse_type(arg: &[u8]) -> Result<BitFieldType, CommandError> {
+    let invalid = || {
+        return CommandError::Other(
+            "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.".into(),
+        );
+    };
+    let (signed, max) = match arg.first() {
+        Some(b'i' | b'I') => (true, 64),
+        Some(b'u' | b'U') => (false, 63),
+        _ => return Err(invalid()),
+    };
+    return match parse_integer(&arg[1..]) {
+        Some(bits) if (1..=max).contains(&bits) => Ok(BitFieldType {
+            signed,
+            bits: bits as u32,
+        }),
+        _ => Err(invalid()),
+    };
+}
+
+/// Parse the offset of a BITFIELD integer, which is in bits, or in multiples
+/// of the width of the integer when it starts with #
+fn parse_field_offset(arg: &[u8], encoding: BitFieldType) -> Result<u64, CommandError> {
+    let offset = match arg.strip_prefix(b"#") {
+        Some(index) => parse_integer(index)
+            .filter(|index| *index >= 0)
+            .and_then(|index| index.checked_mul(encoding.bits as i64))
+            .ok_or_else(invalid_offset)?,
+        None => parse_integer(arg)
+            .filter(|offset| *offset >= 0)
+            .ok_or_else(invalid_offset)?,
+    } as u64;
+    if offset + encoding.bits as u64 > MAX_BITS {
+        return Err(invalid_offset());
+    }
+    return Ok(offset);
+}
+
+/// BITFIELD key [GET encoding offset | [OVERFLOW WRAP|SAT|FAIL]
+/// SET encoding offset value | INCRBY encoding offset increment ...]
+fn parse_field(args: &mut CommandArgs, readonly: bool) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let mut ops = vec![];
+    while args.remaining() > 0 {
+        let subcommand = args.next_keyword()?;
+        if readonly && subcommand != "GET" {
+            return Err(CommandError::Other(
+                "ERR BITFIELD_RO only supports the GET subcommand".into(),
+            ));
+        }
+        let op = match subcommand.as_str() {
+            "GET" | "SET" | "INCRBY" => {
+                let encoding = parse_type(&args.next_bytes()?)?;
+                let offset = parse_field_offset(&args.next_bytes()?, encoding)?;
+                match subcommand.as_str() {
+                    "GET" => BitFieldOp::Get { encoding, offset },
+                    "SET" => BitFieldOp::Set {
+                        encoding,
+                        offset,
+                        value: args.next_integer()?,
+                    },
+                    _ => BitFieldOp::IncrBy {
+                        encoding,
+                        offset,
+                        increment: args.next_integer()?,
+                    },
+                }
+            }
+            "OVERFLOW" => BitFieldOp::Overflow(match args.next_keyword()?.as_str() {
+                "WRAP" => Overflow::Wrap,
+                "SAT" => Overflow::Sat,
+                "FAIL" => Overflow::Fail,
+                _ => {
+                    return Err(CommandError::Other(
+                        "ERR Invalid OVERFLOW type specified".into(),
+                    ))
+                }
+            }),
+            _ => return Err(CommandError::Syntax),
+        };
+        ops.push(op);
+    }
+    return Ok(Command::Bitmap(BitmapCommand::Field { key, ops, readonly }));
+}
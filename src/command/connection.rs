@@ -0,0 +1,129 @@
+//! Commands that manage the connection rather than the data
+use super::{Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionCommand {
+    Hello {
+        protover: Option<i64>,
+        auth: Option<(Bytes, Bytes)>,
+        setname: Option<Bytes>,
+    },
+    /// Reply with the message, or with PONG if there is none
+    Ping { message: Option<Bytes> },
+    /// Reply OK, then close the connection
+    Quit,
+    /// Drop the subscriptions of the connection and go back to RESP2
+    Reset,
+}
+
+const NO_AUTH_FAST: &[CommandFlag] = &[CommandFlag::NoAuth, CommandFlag::Fast];
+
+/// Describe a command that takes no keys
+const fn no_keys(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [CommandFlag],
+    parse: fn(&mut CommandArgs) -> Result<Command, CommandError>,
+) -> CommandSpec {
+    return CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        parse,
+    };
+}
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    no_keys("HELLO", -1, NO_AUTH_FAST, parse_hello),
+    no_keys("PING", -1, &[CommandFlag::Fast], parse_ping),
+    no_keys("QUIT", -1, NO_AUTH_FAST, parse_quit),
+    no_keys("RESET", 1, NO_AUTH_FAST, parse_reset),
+];
+
+impl ConnectionCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        return match self {
+            Self::Hello {
+                protover,
+                auth,
+                setname,
+            } => {
+                let mut args = vec![Bytes::from("HELLO")];
+                if let Some(protover) = protover {
+                    args.push(Bytes::from(protover.to_string()));
+                    if let Some((username, password)) = auth {
+                        args.push(Bytes::from("AUTH"));
+                        args.push(username.clone());
+                        args.push(password.clone());
+                    }
+                    if let Some(name) = setname {
+                        args.push(Bytes::from("SETNAME"));
+                        args.push(name.clone());
+                    }
+                }
+                args
+            }
+            Self::Ping { message } => {
+                let mut args = vec![Bytes::from("PING")];
+                args.extend(message.iter().cloned());
+                args
+            }
+            Self::Quit => vec![Bytes::from("QUIT")],
+            Self::Reset => vec![Bytes::from("RESET")],
+        };
+    }
+
+    /// Return whether a RESP2 connection may send the command once it
+    /// subscribed to anything, besides the commands that change its
+    /// subscriptions
+    pub fn is_allowed_when_subscribed(&self) -> bool {
+        return matches!(self, Self::Ping { .. } | Self::Quit | Self::Reset);
+    }
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME name]]
+fn parse_hello(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let (mut protover, mut auth, mut setname) = (None, None, None);
+    if args.remaining() > 0 {
+        protover = Some(args.next_integer().map_err(|_| {
+            CommandError::Other("ERR Protocol version is not an integer or out of range".into())
+        })?);
+    }
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "AUTH" => auth = Some((args.next_bytes()?, args.next_bytes()?)),
+            "SETNAME" => setname = Some(args.next_bytes()?),
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::Connection(ConnectionCommand::Hello {
+        protover,
+        auth,
+        setname,
+    }));
+}
+
+/// PING [message]
+fn parse_ping(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    if args.remaining() > 1 {
+        return Err(CommandError::WrongArity("PING"));
+    }
+    return Ok(Command::Connection(ConnectionCommand::Ping {
+        message: args.rest().pop(),
+    }));
+}
+
+/// QUIT, which ignores any arguments like Redis does
+fn parse_quit(_args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::Connection(ConnectionCommand::Quit));
+}
+
+/// RESET
+fn parse_reset(_args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::Connection(ConnectionCommand::Reset));
+}
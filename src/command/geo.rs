@@ -0,0 +1,434 @@
+//! Commands that operate on sorted sets as geospatial indexes
+use super::key::{multi_key, single_key};
+use super::string::SetCondition;
+use super::{parse_float, Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+
+/// The largest latitude that can be indexed. Like in Redis, the poles are
+/// left out so that the index covers the same area as the Web Mercator
+/// projection.
+pub(crate) const LATITUDE_MAX: f64 = 85.05112878;
+
+pub(crate) const LONGITUDE_MAX: f64 = 180.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoCommand {
+    /// GEOADD, which replies with the number of members added, or changed
+    /// with CH
+    Add {
+        key: Bytes,
+        condition: Option<SetCondition>,
+        changed: bool,
+        items: Vec<(Coordinates, Bytes)>,
+    },
+    Pos {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Dist {
+        key: Bytes,
+        member1: Bytes,
+        member2: Bytes,
+        unit: DistanceUnit,
+    },
+    /// GEOHASH, which replies with the standard 11 character geohash of each
+    /// member
+    Hash {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Search {
+        key: Bytes,
+        search: GeoSearch,
+    },
+    /// GEOSEARCHSTORE, which stores the members found with their geohash as
+    /// their score, or their distance with STOREDIST
+    SearchStore {
+        destination: Bytes,
+        source: Bytes,
+        search: GeoSearch,
+        store_dist: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceUnit {
+    Meters,
+    Kilometers,
+    Feet,
+    Miles,
+}
+
+impl DistanceUnit {
+    /// Return the number of meters in one unit
+    pub fn meters(&self) -> f64 {
+        return match self {
+            Self::Meters => 1.0,
+            Self::Kilometers => 1000.0,
+            Self::Feet => 0.3048,
+            Self::Miles => 1609.34,
+        };
+    }
+
+    fn name(&self) -> &'static str {
+        return match self {
+            Self::Meters => "m",
+            Self::Kilometers => "km",
+            Self::Feet => "ft",
+            Self::Miles => "mi",
+        };
+    }
+}
+
+/// The options shared by GEOSEARCH and GEOSEARCHSTORE
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoSearch {
+    pub origin: GeoOrigin,
+    pub shape: GeoShape,
+    pub unit: DistanceUnit,
+    /// The order by distance from the origin, which defaults to ascending
+    /// with a COUNT and to no particular order otherwise
+    pub order: Option<Order>,
+    pub count: Option<usize>,
+    /// Whether to stop at the first COUNT members found rather than at the
+    /// closest ones
+    pub any: bool,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+}
+
+/// Where a search is centered
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoOrigin {
+    Member(Bytes),
+    Coordinates(Coordinates),
+}
+
+/// The area a search covers, in the unit of the search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("GEOADD", -5, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let (mut nx, mut xx, mut changed) = (false, false, false);
+        let mut rest = args.rest().into_iter().peekable();
+        while let Some(arg) = rest.peek() {
+            match String::from_utf8_lossy(arg).to_ascii_uppercase().as_str() {
+                "NX" => nx = true,
+                "XX" => xx = true,
+                "CH" => changed = true,
+                _ => break,
+            }
+            rest.next();
+        }
+        let rest: Vec<Bytes> = rest.collect();
+        if rest.is_empty() || !rest.len().is_multiple_of(3) || (nx && xx) {
+            return Err(CommandError::Syntax);
+        }
+        let items = rest
+            .chunks(3)
+            .map(|item| return Ok((parse_coordinates(&item[0], &item[1])?, item[2].clone())))
+            .collect::<Result<Vec<_>, CommandError>>()?;
+        let condition = match (nx, xx) {
+            (true, _) => Some(SetCondition::Nx),
+            (_, true) => Some(SetCondition::Xx),
+            _ => None,
+        };
+        return Ok(Command::Geo(GeoCommand::Add {
+            key,
+            condition,
+            changed,
+            items,
+        }));
+    }),
+    single_key("GEOPOS", -2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let members = args.rest();
+        return Ok(Command::Geo(GeoCommand::Pos { key, members }));
+    }),
+    single_key("GEODIST", -4, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let (member1, member2) = (args.next_bytes()?, args.next_bytes()?);
+        let unit = match args.remaining() {
+            0 => DistanceUnit::Meters,
+            _ => parse_unit(&args.next_bytes()?)?,
+        };
+        args.finish()?;
+        return Ok(Command::Geo(GeoCommand::Dist {
+            key,
+            member1,
+            member2,
+            unit,
+        }));
+    }),
+    single_key("GEOHASH", -2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let members = args.rest();
+        return Ok(Command::Geo(GeoCommand::Hash { key, members }));
+    }),
+    single_key("GEOSEARCH", -7, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let (search, _) = parse_search(args, false)?;
+        return Ok(Command::Geo(GeoCommand::Search { key, search }));
+    }),
+    multi_key("GEOSEARCHSTORE", -8, WRITE, 1, |args| {
+        let (destination, source) = (args.next_bytes()?, args.next_bytes()?);
+        let (search, store_dist) = parse_search(args, true)?;
+        return Ok(Command::Geo(GeoCommand::SearchStore {
+            destination,
+            source,
+            search,
+            store_dist,
+        }));
+    }),
+];
+
+impl GeoCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let name = |name: &'static str, key: &Bytes| vec![Bytes::from(name), key.clone()];
+        let float = |num: f64| Bytes::from(num.to_string());
+        return match self {
+            Self::Add {
+                key,
+                condition,
+                changed,
+                items,
+            } => {
+                let mut args = name("GEOADD", key);
+                match condition {
+                    Some(SetCondition::Nx) => args.push(Bytes::from("NX")),
+                    Some(SetCondition::Xx) => args.push(Bytes::from("XX")),
+                    None => {}
+                }
+                if *changed {
+                    args.push(Bytes::from("CH"));
+                }
+                for (coordinates, member) in items {
+                    args.extend([
+                        float(coordinates.longitude),
+                        float(coordinates.latitude),
+                        member.clone(),
+                    ]);
+                }
+                args
+            }
+            Self::Pos { key, members } => [name("GEOPOS", key), members.clone()].concat(),
+            Self::Dist {
+                key,
+                member1,
+                member2,
+                unit,
+            } => {
+                let mut args = name("GEODIST", key);
+                args.extend([member1.clone(), member2.clone(), Bytes::from(unit.name())]);
+                args
+            }
+            Self::Hash { key, members } => [name("GEOHASH", key), members.clone()].concat(),
+            Self::Search { key, search } => {
+                let mut args = name("GEOSEARCH", key);
+                search.push_args(&mut args);
+                args
+            }
+            Self::SearchStore {
+                destination,
+                source,
+                search,
+                store_dist,
+            } => {
+                let mut args = name("GEOSEARCHSTORE", destination);
+                args.push(source.clone());
+                search.push_args(&mut args);
+                if *store_dist {
+                    args.push(Bytes::from("STOREDIST"));
+                }
+                args
+            }
+        };
+    }
+}
+
+impl GeoSearch {
+    fn push_args(&self, args: &mut Vec<Bytes>) {
+        let float = |num: f64| Bytes::from(num.to_string());
+        match &self.origin {
+            GeoOrigin::Member(member) => {
+                args.extend([Bytes::from("FROMMEMBER"), member.clone()]);
+            }
+            GeoOrigin::Coordinates(coordinates) => args.extend([
+                Bytes::from("FROMLONLAT"),
+                float(coordinates.longitude),
+                float(coordinates.latitude),
+            ]),
+        }
+        match self.shape {
+            GeoShape::Radius(radius) => args.extend([Bytes::from("BYRADIUS"), float(radius)]),
+            GeoShape::Box { width, height } => {
+                args.extend([Bytes::from("BYBOX"), float(width), float(height)])
+            }
+        }
+        args.push(Bytes::from(self.unit.name()));
+        match self.order {
+            Some(Order::Asc) => args.push(Bytes::from("ASC")),
+            Some(Order::Desc) => args.push(Bytes::from("DESC")),
+            None => {}
+        }
+        if let Some(count) = self.count {
+            args.extend([Bytes::from("COUNT"), Bytes::from(count.to_string())]);
+            if self.any {
+                args.push(Bytes::from("ANY"));
+            }
+        }
+        for (option, name) in [
+            (self.with_coord, "WITHCOORD"),
+            (self.with_dist, "WITHDIST"),
+            (self.with_hash, "WITHHASH"),
+        ] {
+            if option {
+                args.push(Bytes::from(name));
+            }
+        }
+    }
+}
+
+/// Parse a longitude and a latitude, which must be within the area that can
+/// be indexed
+fn parse_coordinates(longitude: &[u8], latitude: &[u8]) -> Result<Coordinates, CommandError> {
+    let longitude = parse_float(longitude).ok_or(CommandError::NotFloat)?;
+    let latitude = parse_float(latitude).ok_or(CommandError::NotFloat)?;
+    if longitude.abs() > LONGITUDE_MAX || latitude.abs() > LATITUDE_MAX {
+        return Err(CommandError::Other(format!(
+            "ERR invalid longitude,latitude pair {longitude:.6},{latitude:.6}"
+        )));
+    }
+    return Ok(Coordinates {
+        longitude,
+        latitude,
+    });
+}
+
+fn parse_unit(arg: &[u8]) -> Result<DistanceUnit, CommandError> {
+    return match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(DistanceUnit::Meters),
+        b"km" => Ok(DistanceUnit::Kilometers),
+        b"ft" => Ok(DistanceUnit::Feet),
+        b"mi" => Ok(DistanceUnit::Miles),
+        _ => Err(CommandError::Other(
+            "ERR unsupported unit provided. please use M, KM, FT, MI".into(),
+        )),
+    };
+}
+
+/// Parse a distance of a search, which must not be negative
+fn parse_distance(args: &mut CommandArgs, what: &str) -> Result<f64, CommandError> {
+    let distance = parse_float(&args.next_bytes()?)
+        .ok_or_else(|| CommandError::Other(format!("ERR need numeric {what}")))?;
+    if distance < 0.0 {
+        return Err(CommandError::Other(match what {
+            "radius" => "ERR radius cannot be negative".into(),
+            _ => "ERR height or width cannot be negative".into(),
+        }));
+    }
+    return Ok(distance);
+}
+
+/// Parse the options of GEOSEARCH: FROMMEMBER member | FROMLONLAT longitude
+/// latitude, BYRADIUS radius unit | BYBOX width height unit, [ASC | DESC]
+/// [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]. GEOSEARCHSTORE
+/// takes STOREDIST instead of the WITH options.
+fn parse_search(args: &mut CommandArgs, store: bool) -> Result<(GeoSearch, bool), CommandError> {
+    let (mut origin, mut shape, mut unit, mut order, mut count) = (None, None, None, None, None);
+    let (mut any, mut with_coord, mut with_dist, mut with_hash, mut store_dist) =
+        (false, false, false, false, false);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "FROMMEMBER" if origin.is_none() => {
+                origin = Some(GeoOrigin::Member(args.next_bytes()?));
+            }
+            "FROMLONLAT" if origin.is_none() && args.remaining() >= 2 => {
+                let (longitude, latitude) = (args.next_bytes()?, args.next_bytes()?);
+                origin = Some(GeoOrigin::Coordinates(parse_coordinates(
+                    &longitude, &latitude,
+                )?));
+            }
+            "BYRADIUS" if shape.is_none() && args.remaining() >= 2 => {
+                shape = Some(GeoShape::Radius(parse_distance(args, "radius")?));
+                unit = Some(parse_unit(&args.next_bytes()?)?);
+            }
+            "BYBOX" if shape.is_none() && args.remaining() >= 3 => {
+                let width = parse_distance(args, "width")?;
+                let height = parse_distance(args, "height")?;
+                shape = Some(GeoShape::Box { width, height });
+                unit = Some(parse_unit(&args.next_bytes()?)?);
+            }
+            "ASC" => order = Some(Order::Asc),
+            "DESC" => order = Some(Order::Desc),
+            "COUNT" => {
+                let n = args.next_integer()?;
+                if n <= 0 {
+                    return Err(CommandError::Other("ERR COUNT must be > 0".into()));
+                }
+                count = Some(n as usize);
+            }
+            "ANY" => any = true,
+            "WITHCOORD" => with_coord = true,
+            "WITHDIST" => with_dist = true,
+            "WITHHASH" => with_hash = true,
+            "STOREDIST" if store => store_dist = true,
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    let name = if store { "GEOSEARCHSTORE" } else { "GEOSEARCH" };
+    if store && (with_coord || with_dist || with_hash) {
+        return Err(CommandError::Other(format!(
+            "ERR {name} is not compatible with WITHDIST, WITHHASH and WITHCOORD options"
+        )));
+    }
+    let Some(origin) = origin else {
+        return Err(CommandError::Other(format!(
+            "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {name}"
+        )));
+    };
+    let (Some(shape), Some(unit)) = (shape, unit) else {
+        return Err(CommandError::Other(format!(
+            "ERR exactly one of BYRADIUS and BYBOX can be specified for {name}"
+        )));
+    };
+    if any && count.is_none() {
+        return Err(CommandError::Other(
+            "ERR the ANY argument requires COUNT argument".into(),
+        ));
+    }
+    let search = GeoSearch {
+        origin,
+        shape,
+        unit,
+        order,
+        count,
+        any,
+        with_coord,
+        with_dist,
+        with_hash,
+    };
+    return Ok((search, store_dist));
+}
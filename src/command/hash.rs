@@ -0,0 +1,530 @@
+//! Commands that operate on hash values
+use super::key::{single_key, ExpireCondition, Expiry};
+use super::string::{parse_expiry, parse_pairs, push_expiry};
+use super::{Command, CommandArgs, CommandError, CommandFlag, CommandSpec, DEFAULT_SCAN_COUNT};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HashCommand {
+    /// HSET, which replies with the number of new fields
+    Set {
+        key: Bytes,
+        pairs: Vec<(Bytes, Bytes)>,
+    },
+    /// HMSET, the older form of HSET which replies with OK
+    MSet {
+        key: Bytes,
+        pairs: Vec<(Bytes, Bytes)>,
+    },
+    SetNx {
+        key: Bytes,
+        field: Bytes,
+        val: Bytes,
+    },
+    Get {
+        key: Bytes,
+        field: Bytes,
+    },
+    MGet {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    Del {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    Exists {
+        key: Bytes,
+        field: Bytes,
+    },
+    Len {
+        key: Bytes,
+    },
+    StrLen {
+        key: Bytes,
+        field: Bytes,
+    },
+    Keys {
+        key: Bytes,
+    },
+    Vals {
+        key: Bytes,
+    },
+    GetAll {
+        key: Bytes,
+    },
+    IncrBy {
+        key: Bytes,
+        field: Bytes,
+        delta: i64,
+    },
+    IncrByFloat {
+        key: Bytes,
+        field: Bytes,
+        delta: f64,
+    },
+    /// Return random fields. Without a count, the reply is a single field
+    /// rather than an array. A positive count returns distinct fields, a
+    /// negative one may return the same field several times.
+    RandField {
+        key: Bytes,
+        count: Option<i64>,
+        with_values: bool,
+    },
+    Scan {
+        key: Bytes,
+        cursor: u64,
+        pattern: Option<Bytes>,
+        count: usize,
+        no_values: bool,
+    },
+    /// HEXPIRE, HPEXPIRE, HEXPIREAT and HPEXPIREAT, which set the expiry of
+    /// each field
+    Expire {
+        key: Bytes,
+        expiry: Expiry,
+        condition: Option<ExpireCondition>,
+        fields: Vec<Bytes>,
+    },
+    Ttl {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    Pttl {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    ExpireTime {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    PexpireTime {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    Persist {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    /// Get the values of fields, and change their expiry if given EX, PX,
+    /// EXAT, PXAT or PERSIST
+    GetEx {
+        key: Bytes,
+        expiry: Option<Expiry>,
+        persist: bool,
+        fields: Vec<Bytes>,
+    },
+}
+
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("HSET", -4, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let pairs = parse_pairs(args, "HSET")?;
+        return Ok(Command::Hash(HashCommand::Set { key, pairs }));
+    }),
+    single_key("HMSET", -4, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let pairs = parse_pairs(args, "HMSET")?;
+        return Ok(Command::Hash(HashCommand::MSet { key, pairs }));
+    }),
+    single_key("HSETNX", 4, WRITE_FAST, |args| {
+        let (key, field, val) = (args.next_bytes()?, args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::Hash(HashCommand::SetNx { key, field, val }));
+    }),
+    single_key("HGET", 3, READONLY_FAST, |args| {
+        let (key, field) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::Hash(HashCommand::Get { key, field }));
+    }),
+    single_key("HMGET", -3, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        let fields = args.rest();
+        return Ok(Command::Hash(HashCommand::MGet { key, fields }));
+    }),
+    single_key("HDEL", -3, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let fields = args.rest();
+        return Ok(Command::Hash(HashCommand::Del { key, fields }));
+    }),
+    single_key("HEXISTS", 3, READONLY_FAST, |args| {
+        let (key, field) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::Hash(HashCommand::Exists { key, field }));
+    }),
+    single_key("HLEN", 2, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::Hash(HashCommand::Len { key }));
+    }),
+    single_key("HSTRLEN", 3, READONLY_FAST, |args| {
+        let (key, field) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::Hash(HashCommand::StrLen { key, field }));
+    }),
+    single_key("HKEYS", 2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::Hash(HashCommand::Keys { key }));
+    }),
+    single_key("HVALS", 2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::Hash(HashCommand::Vals { key }));
+    }),
+    single_key("HGETALL", 2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::Hash(HashCommand::GetAll { key }));
+    }),
+    single_key("HINCRBY", 4, WRITE_FAST, |args| {
+        let (key, field) = (args.next_bytes()?, args.next_bytes()?);
+        let delta = args.next_integer()?;
+        return Ok(Command::Hash(HashCommand::IncrBy { key, field, delta }));
+    }),
+    single_key("HINCRBYFLOAT", 4, WRITE_FAST, |args| {
+        let (key, field) = (args.next_bytes()?, args.next_bytes()?);
+        let delta = args.next_float()?;
+        return Ok(Command::Hash(HashCommand::IncrByFloat {
+            key,
+            field,
+            delta,
+        }));
+    }),
+    single_key("HRANDFIELD", -2, READONLY, parse_randfield),
+    single_key("HSCAN", -3, READONLY, parse_scan),
+    single_key("HEXPIRE", -6, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::Ex)
+    }),
+    single_key("HPEXPIRE", -6, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::Px)
+    }),
+    single_key("HEXPIREAT", -6, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::ExAt)
+    }),
+    single_key("HPEXPIREAT", -6, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::PxAt)
+    }),
+    single_key("HTTL", -5, READONLY_FAST, |args| {
+        let (key, fields) = parse_key_fields(args)?;
+        return Ok(Command::Hash(HashCommand::Ttl { key, fields }));
+    }),
+    single_key("HPTTL", -5, READONLY_FAST, |args| {
+        let (key, fields) = parse_key_fields(args)?;
+        return Ok(Command::Hash(HashCommand::Pttl { key, fields }));
+    }),
+    single_key("HEXPIRETIME", -5, READONLY_FAST, |args| {
+        let (key, fields) = parse_key_fields(args)?;
+        return Ok(Command::Hash(HashCommand::ExpireTime { key, fields }));
+    }),
+    single_key("HPEXPIRETIME", -5, READONLY_FAST, |args| {
+        let (key, fields) = parse_key_fields(args)?;
+        return Ok(Command::Hash(HashCommand::PexpireTime { key, fields }));
+    }),
+    single_key("HPERSIST", -5, WRITE_FAST, |args| {
+        let (key, fields) = parse_key_fields(args)?;
+        return Ok(Command::Hash(HashCommand::Persist { key, fields }));
+    }),
+    single_key("HGETEX", -5, WRITE_FAST, parse_getex),
+];
+
+impl HashCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let name = |name: &'static str, key: &Bytes| vec![Bytes::from(name), key.clone()];
+        return match self {
+            Self::Set { key, pairs } | Self::MSet { key, pairs } => {
+                let name = match self {
+                    Self::Set { .. } => "HSET",
+                    _ => "HMSET",
+                };
+                let mut args = vec![Bytes::from(name), key.clone()];
+                for (field, val) in pairs {
+                    args.extend([field.clone(), val.clone()]);
+                }
+                args
+            }
+            Self::SetNx { key, field, val } => {
+                vec![
+                    Bytes::from("HSETNX"),
+                    key.clone(),
+                    field.clone(),
+                    val.clone(),
+                ]
+            }
+            Self::Get { key, field } => vec![Bytes::from("HGET"), key.clone(), field.clone()],
+            Self::MGet { key, fields } => {
+                let mut args = name("HMGET", key);
+                args.extend(fields.iter().cloned());
+                args
+            }
+            Self::Del { key, fields } => {
+                let mut args = name("HDEL", key);
+                args.extend(fields.iter().cloned());
+                args
+            }
+            Self::Exists { key, field } => {
+                vec![Bytes::from("HEXISTS"), key.clone(), field.clone()]
+            }
+            Self::Len { key } => name("HLEN", key),
+            Self::StrLen { key, field } => {
+                vec![Bytes::from("HSTRLEN"), key.clone(), field.clone()]
+            }
+            Self::Keys { key } => name("HKEYS", key),
+            Self::Vals { key } => name("HVALS", key),
+            Self::GetAll { key } => name("HGETALL", key),
+            Self::IncrBy { key, field, delta } => vec![
+                Bytes::from("HINCRBY"),
+                key.clone(),
+                field.clone(),
+                Bytes::from(delta.to_string()),
+            ],
+            Self::IncrByFloat { key, field, delta } => vec![
+                Bytes::from("HINCRBYFLOAT"),
+                key.clone(),
+                field.clone(),
+                Bytes::from(crate::format_double(*delta)),
+            ],
+            Self::RandField {
+                key,
+                count,
+                with_values,
+            } => {
+                let mut args = name("HRANDFIELD", key);
+                if let Some(count) = count {
+                    args.push(Bytes::from(count.to_string()));
+                }
+                if *with_values {
+                    args.push(Bytes::from("WITHVALUES"));
+                }
+                args
+            }
+            Self::Scan {
+                key,
+                cursor,
+                pattern,
+                count,
+                no_values,
+            } => {
+                let mut args = name("HSCAN", key);
+                args.push(Bytes::from(cursor.to_string()));
+                if let Some(pattern) = pattern {
+                    args.extend([Bytes::from("MATCH"), pattern.clone()]);
+                }
+                if *count != DEFAULT_SCAN_COUNT {
+                    args.extend([Bytes::from("COUNT"), Bytes::from(count.to_string())]);
+                }
+                if *no_values {
+                    args.push(Bytes::from("NOVALUES"));
+                }
+                args
+            }
+            Self::Expire {
+                key,
+                expiry,
+                condition,
+                fields,
+            } => {
+                let name = format!("H{}", expiry.command_name());
+                let mut args = vec![
+                    Bytes::from(name),
+                    key.clone(),
+                    Bytes::from(expiry.value().to_string()),
+                ];
+                if let Some(condition) = condition {
+                    let condition = match condition {
+                        ExpireCondition::Nx => "NX",
+                        ExpireCondition::Xx => "XX",
+                        ExpireCondition::Gt => "GT",
+                        ExpireCondition::Lt => "LT",
+                    };
+                    args.push(Bytes::from(condition));
+                }
+                push_fields(&mut args, fields);
+                args
+            }
+            Self::Ttl { key, fields }
+            | Self::Pttl { key, fields }
+            | Self::ExpireTime { key, fields }
+            | Self::PexpireTime { key, fields }
+            | Self::Persist { key, fields } => {
+                let mut args = name(
+                    match self {
+                        Self::Ttl { .. } => "HTTL",
+                        Self::Pttl { .. } => "HPTTL",
+                        Self::ExpireTime { .. } => "HEXPIRETIME",
+                        Self::PexpireTime { .. } => "HPEXPIRETIME",
+                        _ => "HPERSIST",
+                    },
+                    key,
+                );
+                push_fields(&mut args, fields);
+                args
+            }
+            Self::GetEx {
+                key,
+                expiry,
+                persist,
+                fields,
+            } => {
+                let mut args = name("HGETEX", key);
+                push_expiry(&mut args, expiry);
+                if *persist {
+                    args.push(Bytes::from("PERSIST"));
+                }
+                push_fields(&mut args, fields);
+                args
+            }
+        };
+    }
+}
+
+/// HRANDFIELD key [count [WITHVALUES]]
+fn parse_randfield(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let mut count = None;
+    let mut with_values = false;
+    if args.remaining() > 0 {
+        let n = args.next_integer()?;
+        // The magnitude of the count must fit, as it may be negated
+        if n == i64::MIN {
+            return Err(CommandError::Other("ERR value is out of range".into()));
+        }
+        count = Some(n);
+        if args.remaining() > 0 {
+            if args.next_keyword()? != "WITHVALUES" {
+                return Err(CommandError::Syntax);
+            }
+            with_values = true;
+        }
+    }
+    args.finish()?;
+    return Ok(Command::Hash(HashCommand::RandField {
+        key,
+        count,
+        with_values,
+    }));
+}
+
+/// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+fn parse_scan(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let cursor = parse_cursor(&args.next_bytes()?)?;
+    let (mut pattern, mut count, mut no_values) = (None, DEFAULT_SCAN_COUNT, false);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "MATCH" => pattern = Some(args.next_bytes()?),
+            "COUNT" => {
+                count = match args.next_integer()? {
+                    n if n > 0 => n as usize,
+                    _ => return Err(CommandError::Syntax),
+                };
+            }
+            "NOVALUES" => no_values = true,
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::Hash(HashCommand::Scan {
+        key,
+        cursor,
+        pattern,
+        count,
+        no_values,
+    }));
+}
+
+/// Parse the cursor of a SCAN-like command, an unsigned 64-bit integer
+pub(super) fn parse_cursor(arg: &[u8]) -> Result<u64, CommandError> {
+    let cursor = std::str::from_utf8(arg).ok().and_then(|s| s.parse().ok());
+    return cursor.ok_or_else(|| CommandError::Other("ERR invalid cursor".into()));
+}
+
+/// HEXPIRE key seconds [NX | XX | GT | LT] FIELDS numfields field
+/// [field ...], and the same for HPEXPIRE, HEXPIREAT and HPEXPIREAT
+fn parse_expire(
+    args: &mut CommandArgs,
+    expiry: fn(i64) -> Expiry,
+) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let time = args.next_integer()?;
+    if time < 0 {
+        return Err(CommandError::Other(
+            "ERR invalid expire time, must be >= 0".into(),
+        ));
+    }
+    let mut keyword = args.next_keyword()?;
+    let condition = match keyword.as_str() {
+        "NX" => Some(ExpireCondition::Nx),
+        "XX" => Some(ExpireCondition::Xx),
+        "GT" => Some(ExpireCondition::Gt),
+        "LT" => Some(ExpireCondition::Lt),
+        _ => None,
+    };
+    if condition.is_some() {
+        keyword = args.next_keyword()?;
+    }
+    let fields = parse_fields(args, &keyword)?;
+    return Ok(Command::Hash(HashCommand::Expire {
+        key,
+        expiry: expiry(time),
+        condition,
+        fields,
+    }));
+}
+
+/// key FIELDS numfields field [field ...], the arguments of HTTL, HPTTL,
+/// HEXPIRETIME, HPEXPIRETIME and HPERSIST
+fn parse_key_fields(args: &mut CommandArgs) -> Result<(Bytes, Vec<Bytes>), CommandError> {
+    let key = args.next_bytes()?;
+    let keyword = args.next_keyword()?;
+    return Ok((key, parse_fields(args, &keyword)?));
+}
+
+/// HGETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+/// PXAT unix-time-milliseconds | PERSIST] FIELDS numfields field [field ...]
+fn parse_getex(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let (mut expiry, mut persist) = (None, false);
+    loop {
+        let option = args.next_keyword()?;
+        match option.as_str() {
+            "PERSIST" if expiry.is_none() && !persist => persist = true,
+            "EX" | "PX" | "EXAT" | "PXAT" if expiry.is_none() && !persist => {
+                expiry = Some(parse_expiry(args, &option, "hgetex")?);
+            }
+            _ => {
+                let fields = parse_fields(args, &option)?;
+                return Ok(Command::Hash(HashCommand::GetEx {
+                    key,
+                    expiry,
+                    persist,
+                    fields,
+                }));
+            }
+        }
+    }
+}
+
+/// FIELDS numfields field [field ...], which ends the commands that set or
+/// inspect the expiry of fields. `keyword` is the argument that should be
+/// FIELDS, which has already been consumed.
+fn parse_fields(args: &mut CommandArgs, keyword: &str) -> Result<Vec<Bytes>, CommandError> {
+    if keyword != "FIELDS" {
+        return Err(CommandError::Other(
+            "ERR Mandatory argument FIELDS is missing or not at the right position".into(),
+        ));
+    }
+    let numfields = args.next_integer()?;
+    if numfields <= 0 {
+        return Err(CommandError::Other(
+            "ERR Parameter `numFields` should be greater than 0".into(),
+        ));
+    }
+    if numfields as usize != args.remaining() {
+        return Err(CommandError::Other(
+            "ERR The `numfields` parameter must match the number of arguments".into(),
+        ));
+    }
+    return Ok(args.rest());
+}
+
+fn push_fields(args: &mut Vec<Bytes>, fields: &[Bytes]) {
+    args.extend([Bytes::from("FIELDS"), Bytes::from(fields.len().to_string())]);
+    args.extend(fields.iter().cloned());
+}
@@ -0,0 +1,63 @@
+//! Commands that operate on HyperLogLog values
+use super::key::{multi_key, single_key};
+use super::{Command, CommandFlag, CommandSpec};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HyperLogLogCommand {
+    /// PFADD, which replies with 1 if the estimate may have changed
+    Add { key: Bytes, elements: Vec<Bytes> },
+    /// PFCOUNT, which estimates the number of distinct elements added to any
+    /// of the keys
+    Count { keys: Vec<Bytes> },
+    /// PFMERGE, which stores the union of the sources and the destination
+    /// under the destination
+    Merge {
+        destination: Bytes,
+        sources: Vec<Bytes>,
+    },
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("PFADD", -2, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let elements = args.rest();
+        return Ok(Command::HyperLogLog(HyperLogLogCommand::Add {
+            key,
+            elements,
+        }));
+    }),
+    // PFCOUNT is readonly although it caches the estimate in the value, like
+    // in Redis
+    multi_key("PFCOUNT", -2, READONLY, 1, |args| {
+        let keys = args.rest();
+        return Ok(Command::HyperLogLog(HyperLogLogCommand::Count { keys }));
+    }),
+    multi_key("PFMERGE", -2, WRITE, 1, |args| {
+        let destination = args.next_bytes()?;
+        let sources = args.rest();
+        return Ok(Command::HyperLogLog(HyperLogLogCommand::Merge {
+            destination,
+            sources,
+        }));
+    }),
+];
+
+impl HyperLogLogCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let (name, args) = match self {
+            Self::Add { key, elements } => ("PFADD", [&[key.clone()][..], elements].concat()),
+            Self::Count { keys } => ("PFCOUNT", keys.clone()),
+            Self::Merge {
+                destination,
+                sources,
+            } => ("PFMERGE", [&[destination.clone()][..], sources].concat()),
+        };
+        return [vec![Bytes::from(name)], args].concat();
+    }
+}
@@ -0,0 +1,380 @@
+//! Commands that operate on keys regardless of the type of their values
+use super::{Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyCommand {
+    Del {
+        keys: Vec<Bytes>,
+    },
+    /// DEL that reclaims memory in the background, which makes no difference
+    /// to this server
+    Unlink {
+        keys: Vec<Bytes>,
+    },
+    /// Count how many of the keys exist, counting repeated keys every time
+    Exists {
+        keys: Vec<Bytes>,
+    },
+    /// Mark the keys as accessed, counting how many of them exist
+    Touch {
+        keys: Vec<Bytes>,
+    },
+    /// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT, told apart by the kind of
+    /// expiry
+    Expire {
+        key: Bytes,
+        expiry: Expiry,
+        /// Every condition must allow the new expiry
+        conditions: Vec<ExpireCondition>,
+    },
+    Ttl {
+        key: Bytes,
+    },
+    Pttl {
+        key: Bytes,
+    },
+    ExpireTime {
+        key: Bytes,
+    },
+    PexpireTime {
+        key: Bytes,
+    },
+    Persist {
+        key: Bytes,
+    },
+    Type {
+        key: Bytes,
+    },
+    Object {
+        subcommand: ObjectSubcommand,
+        key: Bytes,
+    },
+    ObjectHelp,
+}
+
+/// What OBJECT reports about the value stored under a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectSubcommand {
+    /// The internal representation of the value
+    Encoding,
+    /// The logarithmic access frequency counter
+    Freq,
+    /// The number of seconds since the key was last accessed
+    IdleTime,
+    /// The number of references to the value, always 1
+    RefCount,
+}
+
+impl ObjectSubcommand {
+    fn name(&self) -> &'static str {
+        return match self {
+            Self::Encoding => "ENCODING",
+            Self::Freq => "FREQ",
+            Self::IdleTime => "IDLETIME",
+            Self::RefCount => "REFCOUNT",
+        };
+    }
+}
+
+/// When a key expires, either relative to the time the command runs or as a
+/// Unix timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// In the given number of seconds
+    Ex(i64),
+    /// In the given number of milliseconds
+    Px(i64),
+    /// At the given Unix time in seconds
+    ExAt(i64),
+    /// At the given Unix time in milliseconds
+    PxAt(i64),
+}
+
+impl Expiry {
+    /// Return the Unix time in milliseconds at which the key expires, given
+    /// the current Unix time in milliseconds. Return None if the time does not
+    /// fit in a signed 64-bit integer.
+    pub fn deadline(&self, now_ms: i64) -> Option<i64> {
+        return match *self {
+            Self::Ex(secs) => secs.checked_mul(1000)?.checked_add(now_ms),
+            Self::Px(ms) => ms.checked_add(now_ms),
+            Self::ExAt(secs) => secs.checked_mul(1000),
+            Self::PxAt(ms) => Some(ms),
+        };
+    }
+
+    /// Return the name of the EXPIRE command that sets this kind of expiry
+    pub fn command_name(&self) -> &'static str {
+        return match self {
+            Self::Ex(_) => "EXPIRE",
+            Self::Px(_) => "PEXPIRE",
+            Self::ExAt(_) => "EXPIREAT",
+            Self::PxAt(_) => "PEXPIREAT",
+        };
+    }
+
+    /// Return the amount of time or the timestamp
+    pub(super) fn value(&self) -> i64 {
+        return match *self {
+            Self::Ex(n) | Self::Px(n) | Self::ExAt(n) | Self::PxAt(n) => n,
+        };
+    }
+}
+
+/// The conditions under which the EXPIRE family replaces a key's expiry. A key
+/// without an expiry counts as expiring infinitely far in the future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCondition {
+    /// Only if the key has no expiry
+    Nx,
+    /// Only if the key has an expiry
+    Xx,
+    /// Only if the new expiry is later than the current one
+    Gt,
+    /// Only if the new expiry is earlier than the current one
+    Lt,
+}
+
+impl ExpireCondition {
+    /// Return whether a key whose current expiry is `current` may be given the
+    /// new expiry `deadline`
+    pub fn allows(&self, current: Option<i64>, deadline: i64) -> bool {
+        return match (self, current) {
+            (Self::Nx, current) => current.is_none(),
+            (Self::Xx, current) => current.is_some(),
+            (Self::Gt, None) => false,
+            (Self::Gt, Some(current)) => deadline > current,
+            (Self::Lt, None) => true,
+            (Self::Lt, Some(current)) => deadline < current,
+        };
+    }
+}
+
+/// Describe a command whose only key is its first argument
+pub(super) const fn single_key(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [CommandFlag],
+    parse: fn(&mut CommandArgs) -> Result<Command, CommandError>,
+) -> CommandSpec {
+    return CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        parse,
+    };
+}
+
+/// Describe a command whose keys run from its first argument to its last one,
+/// `key_step` arguments apart
+pub(super) const fn multi_key(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [CommandFlag],
+    key_step: usize,
+    parse: fn(&mut CommandArgs) -> Result<Command, CommandError>,
+) -> CommandSpec {
+    return CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: 1,
+        last_key: -1,
+        key_step,
+        parse,
+    };
+}
+
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    multi_key("DEL", -2, &[CommandFlag::Write], 1, |args| {
+        return Ok(Command::Key(KeyCommand::Del { keys: args.rest() }));
+    }),
+    multi_key("UNLINK", -2, WRITE_FAST, 1, |args| {
+        return Ok(Command::Key(KeyCommand::Unlink { keys: args.rest() }));
+    }),
+    multi_key("EXISTS", -2, READONLY_FAST, 1, |args| {
+        return Ok(Command::Key(KeyCommand::Exists { keys: args.rest() }));
+    }),
+    multi_key("TOUCH", -2, READONLY_FAST, 1, |args| {
+        return Ok(Command::Key(KeyCommand::Touch { keys: args.rest() }));
+    }),
+    single_key("EXPIRE", -3, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::Ex)
+    }),
+    single_key("PEXPIRE", -3, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::Px)
+    }),
+    single_key("EXPIREAT", -3, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::ExAt)
+    }),
+    single_key("PEXPIREAT", -3, WRITE_FAST, |args| {
+        parse_expire(args, Expiry::PxAt)
+    }),
+    single_key("TTL", 2, READONLY_FAST, |args| {
+        parse_key(args, |key| KeyCommand::Ttl { key })
+    }),
+    single_key("PTTL", 2, READONLY_FAST, |args| {
+        parse_key(args, |key| KeyCommand::Pttl { key })
+    }),
+    single_key("EXPIRETIME", 2, READONLY_FAST, |args| {
+        parse_key(args, |key| KeyCommand::ExpireTime { key })
+    }),
+    single_key("PEXPIRETIME", 2, READONLY_FAST, |args| {
+        parse_key(args, |key| KeyCommand::PexpireTime { key })
+    }),
+    single_key("PERSIST", 2, WRITE_FAST, |args| {
+        parse_key(args, |key| KeyCommand::Persist { key })
+    }),
+    single_key("TYPE", 2, READONLY_FAST, |args| {
+        parse_key(args, |key| KeyCommand::Type { key })
+    }),
+    CommandSpec {
+        name: "OBJECT",
+        arity: -2,
+        flags: &[CommandFlag::Readonly],
+        first_key: 2,
+        last_key: 2,
+        key_step: 1,
+        parse: parse_object,
+    },
+];
+
+impl KeyCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let with_key = |name: &'static str, key: &Bytes| vec![Bytes::from(name), key.clone()];
+        let with_keys = |name: &'static str, keys: &[Bytes]| {
+            return [Bytes::from(name)]
+                .into_iter()
+                .chain(keys.iter().cloned())
+                .collect();
+        };
+        return match self {
+            Self::Del { keys } => with_keys("DEL", keys),
+            Self::Unlink { keys } => with_keys("UNLINK", keys),
+            Self::Exists { keys } => with_keys("EXISTS", keys),
+            Self::Touch { keys } => with_keys("TOUCH", keys),
+            Self::Expire {
+                key,
+                expiry,
+                conditions,
+            } => {
+                let mut args = with_key(expiry.command_name(), key);
+                args.push(Bytes::from(expiry.value().to_string()));
+                for condition in conditions {
+                    args.push(Bytes::from(match condition {
+                        ExpireCondition::Nx => "NX",
+                        ExpireCondition::Xx => "XX",
+                        ExpireCondition::Gt => "GT",
+                        ExpireCondition::Lt => "LT",
+                    }));
+                }
+                args
+            }
+            Self::Ttl { key } => with_key("TTL", key),
+            Self::Pttl { key } => with_key("PTTL", key),
+            Self::ExpireTime { key } => with_key("EXPIRETIME", key),
+            Self::PexpireTime { key } => with_key("PEXPIRETIME", key),
+            Self::Persist { key } => with_key("PERSIST", key),
+            Self::Type { key } => with_key("TYPE", key),
+            Self::Object { subcommand, key } => {
+                vec![
+                    Bytes::from("OBJECT"),
+                    Bytes::from(subcommand.name()),
+                    key.clone(),
+                ]
+            }
+            Self::ObjectHelp => vec![Bytes::from("OBJECT"), Bytes::from("HELP")],
+        };
+    }
+}
+
+/// Any command whose only argument is a key
+fn parse_key(
+    args: &mut CommandArgs,
+    cmd: fn(Bytes) -> KeyCommand,
+) -> Result<Command, CommandError> {
+    return Ok(Command::Key(cmd(args.next_bytes()?)));
+}
+
+/// EXPIRE key seconds [NX | XX | GT | LT], and the same for PEXPIRE, EXPIREAT
+/// and PEXPIREAT
+fn parse_expire(
+    args: &mut CommandArgs,
+    expiry: fn(i64) -> Expiry,
+) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let expiry = expiry(args.next_integer()?);
+    let mut conditions = vec![];
+    while args.remaining() > 0 {
+        let option = args.next_keyword()?;
+        let condition = match option.as_str() {
+            "NX" => ExpireCondition::Nx,
+            "XX" => ExpireCondition::Xx,
+            "GT" => ExpireCondition::Gt,
+            "LT" => ExpireCondition::Lt,
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "ERR Unsupported option {option}"
+                )))
+            }
+        };
+        if !conditions.contains(&condition) {
+            conditions.push(condition);
+        }
+    }
+    let has = |condition| conditions.contains(&condition);
+    if has(ExpireCondition::Nx) && conditions.len() > 1 {
+        return Err(CommandError::Other(
+            "ERR NX and XX, GT or LT options at the same time are not compatible".into(),
+        ));
+    }
+    if has(ExpireCondition::Gt) && has(ExpireCondition::Lt) {
+        return Err(CommandError::Other(
+            "ERR GT and LT options at the same time are not compatible".into(),
+        ));
+    }
+    return Ok(Command::Key(KeyCommand::Expire {
+        key,
+        expiry,
+        conditions,
+    }));
+}
+
+/// OBJECT ENCODING | FREQ | IDLETIME | REFCOUNT key, or OBJECT HELP
+fn parse_object(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let name = args.next_bytes()?;
+    let subcommand = match name.to_ascii_uppercase().as_slice() {
+        b"HELP" if args.remaining() == 0 => return Ok(Command::Key(KeyCommand::ObjectHelp)),
+        b"HELP" => return Err(CommandError::WrongArity("OBJECT|HELP")),
+        b"ENCODING" => ObjectSubcommand::Encoding,
+        b"FREQ" => ObjectSubcommand::Freq,
+        b"IDLETIME" => ObjectSubcommand::IdleTime,
+        b"REFCOUNT" => ObjectSubcommand::RefCount,
+        _ => {
+            return Err(CommandError::Other(format!(
+                "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+                String::from_utf8_lossy(&name)
+            )))
+        }
+    };
+    if args.remaining() != 1 {
+        return Err(CommandError::WrongArity(match subcommand {
+            ObjectSubcommand::Encoding => "OBJECT|ENCODING",
+            ObjectSubcommand::Freq => "OBJECT|FREQ",
+            ObjectSubcommand::IdleTime => "OBJECT|IDLETIME",
+            ObjectSubcommand::RefCount => "OBJECT|REFCOUNT",
+        }));
+    }
+    return Ok(Command::Key(KeyCommand::Object {
+        subcommand,
+        key: args.next_bytes()?,
+    }));
+}
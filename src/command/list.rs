@@ -0,0 +1,617 @@
+//! Commands that operate on list values
+use super::key::{multi_key, single_key};
+use super::{parse_float, Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListCommand {
+    /// LPUSH, RPUSH, and LPUSHX and RPUSHX which only push onto lists that
+    /// already exist
+    Push {
+        key: Bytes,
+        side: Side,
+        elems: Vec<Bytes>,
+        only_if_exists: bool,
+    },
+    /// LPOP and RPOP. Without a count, a single element is popped and the
+    /// reply is not an array.
+    Pop {
+        key: Bytes,
+        side: Side,
+        count: Option<usize>,
+    },
+    Len {
+        key: Bytes,
+    },
+    Range {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    Index {
+        key: Bytes,
+        index: i64,
+    },
+    Set {
+        key: Bytes,
+        index: i64,
+        elem: Bytes,
+    },
+    /// Remove `count` occurrences of the element: from the head if `count` is
+    /// positive, from the tail if it is negative, and all of them if it is 0
+    Rem {
+        key: Bytes,
+        count: i64,
+        elem: Bytes,
+    },
+    Trim {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    Insert {
+        key: Bytes,
+        position: Position,
+        pivot: Bytes,
+        elem: Bytes,
+    },
+    /// LMOVE, and RPOPLPUSH which moves from the right to the left
+    Move {
+        source: Bytes,
+        destination: Bytes,
+        from: Side,
+        to: Side,
+    },
+    Pos {
+        key: Bytes,
+        elem: Bytes,
+        /// Which match to start from, counting from the tail if negative
+        rank: i64,
+        /// How many matches to return. Without a count, the reply is a single
+        /// index rather than an array. A count of 0 returns every match.
+        count: Option<usize>,
+        /// How many elements to compare at most, 0 for the whole list
+        maxlen: usize,
+    },
+    /// LMPOP: pop up to `count` elements from the first of the keys that
+    /// holds a list
+    MPop {
+        keys: Vec<Bytes>,
+        side: Side,
+        count: usize,
+    },
+    /// BLPOP and BRPOP: pop an element from the first of the keys that holds
+    /// a list, waiting for one to be pushed if there is none
+    BPop {
+        keys: Vec<Bytes>,
+        side: Side,
+        /// How long to wait, None to wait forever
+        timeout: Option<Duration>,
+    },
+    /// BLMOVE, and BRPOPLPUSH which moves from the right to the left
+    BMove {
+        source: Bytes,
+        destination: Bytes,
+        from: Side,
+        to: Side,
+        timeout: Option<Duration>,
+    },
+    /// BLMPOP, the blocking version of LMPOP
+    BMPop {
+        keys: Vec<Bytes>,
+        side: Side,
+        count: usize,
+        timeout: Option<Duration>,
+    },
+}
+
+/// An end of a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    fn name(&self) -> &'static str {
+        return match self {
+            Self::Left => "LEFT",
+            Self::Right => "RIGHT",
+        };
+    }
+
+    /// Parse LEFT or RIGHT, ignoring case
+    pub(super) fn parse(args: &mut CommandArgs) -> Result<Self, CommandError> {
+        return match args.next_keyword()?.as_str() {
+            "LEFT" => Ok(Self::Left),
+            "RIGHT" => Ok(Self::Right),
+            _ => Err(CommandError::Syntax),
+        };
+    }
+}
+
+/// Where LINSERT inserts the element relative to the pivot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Before,
+    After,
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+const WRITE_BLOCKING: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Blocking];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("LPUSH", -3, WRITE_FAST, |args| {
+        parse_push(args, Side::Left, false)
+    }),
+    single_key("RPUSH", -3, WRITE_FAST, |args| {
+        parse_push(args, Side::Right, false)
+    }),
+    single_key("LPUSHX", -3, WRITE_FAST, |args| {
+        parse_push(args, Side::Left, true)
+    }),
+    single_key("RPUSHX", -3, WRITE_FAST, |args| {
+        parse_push(args, Side::Right, true)
+    }),
+    single_key("LPOP", -2, WRITE_FAST, |args| parse_pop(args, Side::Left)),
+    single_key("RPOP", -2, WRITE_FAST, |args| parse_pop(args, Side::Right)),
+    single_key("LLEN", 2, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::List(ListCommand::Len { key }));
+    }),
+    single_key("LRANGE", 4, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let (start, stop) = (args.next_integer()?, args.next_integer()?);
+        return Ok(Command::List(ListCommand::Range { key, start, stop }));
+    }),
+    single_key("LINDEX", 3, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let index = args.next_integer()?;
+        return Ok(Command::List(ListCommand::Index { key, index }));
+    }),
+    single_key("LSET", 4, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let index = args.next_integer()?;
+        let elem = args.next_bytes()?;
+        return Ok(Command::List(ListCommand::Set { key, index, elem }));
+    }),
+    single_key("LREM", 4, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let count = args.next_integer()?;
+        let elem = args.next_bytes()?;
+        return Ok(Command::List(ListCommand::Rem { key, count, elem }));
+    }),
+    single_key("LTRIM", 4, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let (start, stop) = (args.next_integer()?, args.next_integer()?);
+        return Ok(Command::List(ListCommand::Trim { key, start, stop }));
+    }),
+    single_key("LINSERT", 5, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let position = match args.next_keyword()?.as_str() {
+            "BEFORE" => Position::Before,
+            "AFTER" => Position::After,
+            _ => return Err(CommandError::Syntax),
+        };
+        let (pivot, elem) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::List(ListCommand::Insert {
+            key,
+            position,
+            pivot,
+            elem,
+        }));
+    }),
+    multi_key("LMOVE", 5, WRITE, 1, |args| {
+        let (source, destination) = (args.next_bytes()?, args.next_bytes()?);
+        let (from, to) = (Side::parse(args)?, Side::parse(args)?);
+        return Ok(Command::List(ListCommand::Move {
+            source,
+            destination,
+            from,
+            to,
+        }));
+    }),
+    multi_key("RPOPLPUSH", 3, WRITE, 1, |args| {
+        let (source, destination) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::List(ListCommand::Move {
+            source,
+            destination,
+            from: Side::Right,
+            to: Side::Left,
+        }));
+    }),
+    single_key("LPOS", -3, READONLY, parse_pos),
+    // The keys of LMPOP and BLMPOP follow their count, so the table cannot
+    // describe where they are
+    CommandSpec {
+        name: "LMPOP",
+        arity: -4,
+        flags: WRITE,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        parse: |args| {
+            let (keys, side, count) = parse_mpop(args)?;
+            return Ok(Command::List(ListCommand::MPop { keys, side, count }));
+        },
+    },
+    CommandSpec {
+        name: "BLPOP",
+        arity: -3,
+        flags: WRITE_BLOCKING,
+        first_key: 1,
+        last_key: -2,
+        key_step: 1,
+        parse: |args| parse_bpop(args, Side::Left),
+    },
+    CommandSpec {
+        name: "BRPOP",
+        arity: -3,
+        flags: WRITE_BLOCKING,
+        first_key: 1,
+        last_key: -2,
+        key_step: 1,
+        parse: |args| parse_bpop(args, Side::Right),
+    },
+    CommandSpec {
+        name: "BLMOVE",
+        arity: 6,
+        flags: WRITE_BLOCKING,
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        parse: |args| {
+            let (source, destination) = (args.next_bytes()?, args.next_bytes()?);
+            let (from, to) = (Side::parse(args)?, Side::parse(args)?);
+            let timeout = parse_timeout(&args.next_bytes()?)?;
+            return Ok(Command::List(ListCommand::BMove {
+                source,
+                destination,
+                from,
+                to,
+                timeout,
+            }));
+        },
+    },
+    CommandSpec {
+        name: "BRPOPLPUSH",
+        arity: 4,
+        flags: WRITE_BLOCKING,
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        parse: |args| {
+            let (source, destination) = (args.next_bytes()?, args.next_bytes()?);
+            let timeout = parse_timeout(&args.next_bytes()?)?;
+            return Ok(Command::List(ListCommand::BMove {
+                source,
+                destination,
+                from: Side::Right,
+                to: Side::Left,
+                timeout,
+            }));
+        },
+    },
+    CommandSpec {
+        name: "BLMPOP",
+        arity: -5,
+        flags: WRITE_BLOCKING,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        parse: |args| {
+            let timeout = parse_timeout(&args.next_bytes()?)?;
+            let (keys, side, count) = parse_mpop(args)?;
+            return Ok(Command::List(ListCommand::BMPop {
+                keys,
+                side,
+                count,
+                timeout,
+            }));
+        },
+    },
+];
+
+impl ListCommand {
+    /// Return whether the command waits for data when none of its keys hold
+    /// any. Such commands reply as if they timed out right away unless they
+    /// are executed through DB::block.
+    pub fn is_blocking(&self) -> bool {
+        return matches!(
+            self,
+            Self::BPop { .. } | Self::BMove { .. } | Self::BMPop { .. }
+        );
+    }
+
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let int = |n: i64| Bytes::from(n.to_string());
+        return match self {
+            Self::Push {
+                key,
+                side,
+                elems,
+                only_if_exists,
+            } => {
+                let name = match (side, only_if_exists) {
+                    (Side::Left, false) => "LPUSH",
+                    (Side::Right, false) => "RPUSH",
+                    (Side::Left, true) => "LPUSHX",
+                    (Side::Right, true) => "RPUSHX",
+                };
+                let mut args = vec![Bytes::from(name), key.clone()];
+                args.extend(elems.iter().cloned());
+                args
+            }
+            Self::Pop { key, side, count } => {
+                let name = match side {
+                    Side::Left => "LPOP",
+                    Side::Right => "RPOP",
+                };
+                let mut args = vec![Bytes::from(name), key.clone()];
+                if let Some(count) = count {
+                    args.push(int(*count as i64));
+                }
+                args
+            }
+            Self::Len { key } => vec![Bytes::from("LLEN"), key.clone()],
+            Self::Range { key, start, stop } => {
+                vec![Bytes::from("LRANGE"), key.clone(), int(*start), int(*stop)]
+            }
+            Self::Index { key, index } => vec![Bytes::from("LINDEX"), key.clone(), int(*index)],
+            Self::Set { key, index, elem } => {
+                vec![Bytes::from("LSET"), key.clone(), int(*index), elem.clone()]
+            }
+            Self::Rem { key, count, elem } => {
+                vec![Bytes::from("LREM"), key.clone(), int(*count), elem.clone()]
+            }
+            Self::Trim { key, start, stop } => {
+                vec![Bytes::from("LTRIM"), key.clone(), int(*start), int(*stop)]
+            }
+            Self::Insert {
+                key,
+                position,
+                pivot,
+                elem,
+            } => {
+                let position = match position {
+                    Position::Before => "BEFORE",
+                    Position::After => "AFTER",
+                };
+                vec![
+                    Bytes::from("LINSERT"),
+                    key.clone(),
+                    Bytes::from(position),
+                    pivot.clone(),
+                    elem.clone(),
+                ]
+            }
+            Self::Move {
+                source,
+                destination,
+                from,
+                to,
+            } => vec![
+                Bytes::from("LMOVE"),
+                source.clone(),
+                destination.clone(),
+                Bytes::from(from.name()),
+                Bytes::from(to.name()),
+            ],
+            Self::Pos {
+                key,
+                elem,
+                rank,
+                count,
+                maxlen,
+            } => {
+                let mut args = vec![Bytes::from("LPOS"), key.clone(), elem.clone()];
+                if *rank != 1 {
+                    args.extend([Bytes::from("RANK"), int(*rank)]);
+                }
+                if let Some(count) = count {
+                    args.extend([Bytes::from("COUNT"), int(*count as i64)]);
+                }
+                if *maxlen != 0 {
+                    args.extend([Bytes::from("MAXLEN"), int(*maxlen as i64)]);
+                }
+                args
+            }
+            Self::MPop { keys, side, count } => {
+                let mut args = vec![Bytes::from("LMPOP")];
+                args.extend(mpop_args(keys, *side, *count));
+                args
+            }
+            Self::BPop {
+                keys,
+                side,
+                timeout,
+            } => {
+                let name = match side {
+                    Side::Left => "BLPOP",
+                    Side::Right => "BRPOP",
+                };
+                let mut args = vec![Bytes::from(name)];
+                args.extend(keys.iter().cloned());
+                args.push(timeout_arg(*timeout));
+                args
+            }
+            Self::BMove {
+                source,
+                destination,
+                from,
+                to,
+                timeout,
+            } => vec![
+                Bytes::from("BLMOVE"),
+                source.clone(),
+                destination.clone(),
+                Bytes::from(from.name()),
+                Bytes::from(to.name()),
+                timeout_arg(*timeout),
+            ],
+            Self::BMPop {
+                keys,
+                side,
+                count,
+                timeout,
+            } => {
+                let mut args = vec![Bytes::from("BLMPOP"), timeout_arg(*timeout)];
+                args.extend(mpop_args(keys, *side, *count));
+                args
+            }
+        };
+    }
+}
+
+/// LPUSH key element [element ...], and the same for RPUSH, LPUSHX and RPUSHX
+fn parse_push(
+    args: &mut CommandArgs,
+    side: Side,
+    only_if_exists: bool,
+) -> Result<Command, CommandError> {
+    return Ok(Command::List(ListCommand::Push {
+        key: args.next_bytes()?,
+        side,
+        elems: args.rest(),
+        only_if_exists,
+    }));
+}
+
+/// LPOP key [count], and the same for RPOP
+fn parse_pop(args: &mut CommandArgs, side: Side) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let mut count = None;
+    if args.remaining() > 0 {
+        count = Some(args.next_count()?);
+    }
+    args.finish()?;
+    return Ok(Command::List(ListCommand::Pop { key, side, count }));
+}
+
+/// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+fn parse_pos(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let (key, elem) = (args.next_bytes()?, args.next_bytes()?);
+    let (mut rank, mut count, mut maxlen) = (1, None, 0);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "RANK" => {
+                rank = args.next_integer()?;
+                if rank == 0 {
+                    return Err(CommandError::Other(
+                        "ERR RANK can't be zero: use 1 to start from the first match, 2 from the \
+                         second ... or use negative to start from the end of the list"
+                            .into(),
+                    ));
+                }
+                if rank == i64::MIN {
+                    return Err(CommandError::NotInteger);
+                }
+            }
+            "COUNT" => {
+                let n = args.next_integer()?;
+                if n < 0 {
+                    return Err(CommandError::Other("ERR COUNT can't be negative".into()));
+                }
+                count = Some(n as usize);
+            }
+            "MAXLEN" => {
+                let n = args.next_integer()?;
+                if n < 0 {
+                    return Err(CommandError::Other("ERR MAXLEN can't be negative".into()));
+                }
+                maxlen = n as usize;
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::List(ListCommand::Pos {
+        key,
+        elem,
+        rank,
+        count,
+        maxlen,
+    }));
+}
+
+/// numkeys key [key ...] LEFT|RIGHT [COUNT count], shared by LMPOP and BLMPOP
+fn parse_mpop(args: &mut CommandArgs) -> Result<(Vec<Bytes>, Side, usize), CommandError> {
+    let numkeys = args.next_integer()?;
+    if numkeys <= 0 {
+        return Err(CommandError::Other(
+            "ERR numkeys should be greater than 0".into(),
+        ));
+    }
+    if numkeys as usize >= args.remaining() {
+        return Err(CommandError::Syntax);
+    }
+    let keys = (0..numkeys)
+        .map(|_| args.next_bytes())
+        .collect::<Result<_, _>>()?;
+    let side = Side::parse(args)?;
+    let mut count = 1;
+    if args.remaining() > 0 {
+        if args.next_keyword()? != "COUNT" {
+            return Err(CommandError::Syntax);
+        }
+        count = match args.next_integer()? {
+            n if n > 0 => n as usize,
+            _ => {
+                return Err(CommandError::Other(
+                    "ERR count should be greater than 0".into(),
+                ))
+            }
+        };
+    }
+    args.finish()?;
+    return Ok((keys, side, count));
+}
+
+/// The arguments of LMPOP and BLMPOP that follow the name and timeout
+fn mpop_args(keys: &[Bytes], side: Side, count: usize) -> Vec<Bytes> {
+    let mut args = vec![Bytes::from(keys.len().to_string())];
+    args.extend(keys.iter().cloned());
+    args.push(Bytes::from(side.name()));
+    if count != 1 {
+        args.extend([Bytes::from("COUNT"), Bytes::from(count.to_string())]);
+    }
+    return args;
+}
+
+/// BLPOP key [key ...] timeout, and the same for BRPOP
+fn parse_bpop(args: &mut CommandArgs, side: Side) -> Result<Command, CommandError> {
+    let mut keys = args.rest();
+    let timeout = parse_timeout(&keys.pop().unwrap())?;
+    return Ok(Command::List(ListCommand::BPop {
+        keys,
+        side,
+        timeout,
+    }));
+}
+
+/// Parse a timeout in seconds, which may have a fractional part. A timeout
+/// of zero means waiting forever.
+pub(super) fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, CommandError> {
+    let Some(secs) = parse_float(arg) else {
+        return Err(CommandError::Other(
+            "ERR timeout is not a float or out of range".into(),
+        ));
+    };
+    if secs < 0.0 {
+        return Err(CommandError::Other("ERR timeout is negative".into()));
+    }
+    return match Duration::try_from_secs_f64(secs) {
+        Ok(timeout) if timeout.is_zero() => Ok(None),
+        Ok(timeout) => Ok(Some(timeout)),
+        Err(_) => Err(CommandError::Other("ERR timeout is out of range".into())),
+    };
+}
+
+pub(super) fn timeout_arg(timeout: Option<Duration>) -> Bytes {
+    let secs = timeout.map_or(0.0, |timeout| timeout.as_secs_f64());
+    return Bytes::from(crate::format_double(secs));
+}
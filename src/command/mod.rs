@@ -0,0 +1,1928 @@
+//! Commands: parsing request frames into typed commands through a table of
+//! command specifications, and converting commands back into frames
+//!
+//! Commands are grouped into families, one module per family, the same way
+//! Redis groups them. Each family module owns an enum of its commands, the
+//! functions that parse them, and the table entries that describe them. A new
+//! command is registered by adding an entry to its family's table.
+mod bitmap;
+mod connection;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod key;
+mod list;
+mod pubsub;
+mod server;
+mod set;
+mod sorted_set;
+mod stream;
+mod string;
+
+pub use bitmap::{BitFieldOp, BitFieldType, BitOp, BitRange, BitUnit, BitmapCommand, Overflow};
+pub use connection::ConnectionCommand;
+pub use geo::{Coordinates, DistanceUnit, GeoCommand, GeoOrigin, GeoSearch, GeoShape, Order};
+pub(crate) use geo::{LATITUDE_MAX, LONGITUDE_MAX};
+pub use hash::HashCommand;
+pub use hyperloglog::HyperLogLogCommand;
+pub use key::{ExpireCondition, Expiry, KeyCommand, ObjectSubcommand};
+pub use list::{ListCommand, Position, Side};
+pub use pubsub::PubSubCommand;
+pub use server::ServerCommand;
+pub use set::{SetCommand, SetOp};
+pub use sorted_set::{
+    Aggregate, Comparison, End, LexBound, Limit, Range, ScoreBound, SortedSetCommand, StoreOp,
+};
+pub use stream::{
+    ClaimOptions, NewId, PendingRange, ReadId, StreamCommand, StreamId, Trim, TrimStrategy,
+};
+pub use string::{SetCondition, StringCommand};
+
+use crate::Frame;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// How many elements SCAN-like commands look at when not given a COUNT
+pub(crate) const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// The Command enum provides abstraction over Frames
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Bitmap(BitmapCommand),
+    Connection(ConnectionCommand),
+    Geo(GeoCommand),
+    Hash(HashCommand),
+    HyperLogLog(HyperLogLogCommand),
+    Key(KeyCommand),
+    List(ListCommand),
+    PubSub(PubSubCommand),
+    Server(ServerCommand),
+    Set(SetCommand),
+    SortedSet(SortedSetCommand),
+    Stream(StreamCommand),
+    String(StringCommand),
+}
+
+impl Command {
+    /// Create a new Set command
+    pub fn set(key: Bytes, val: Bytes) -> Self {
+        return Self::String(StringCommand::Set {
+            key,
+            val,
+            expiry: None,
+            keep_ttl: false,
+            condition: None,
+            get: false,
+        });
+    }
+
+    /// Create a new Get command
+    pub fn get(key: Bytes) -> Self {
+        return Self::String(StringCommand::Get { key });
+    }
+
+    /// Create a new Del command
+    pub fn del(key: Bytes) -> Self {
+        return Self::Key(KeyCommand::Del { keys: vec![key] });
+    }
+
+    /// Create a new Hello command that negotiates the given protocol version
+    pub fn hello(protover: Option<i64>) -> Self {
+        return Self::Connection(ConnectionCommand::Hello {
+            protover,
+            auth: None,
+            setname: None,
+        });
+    }
+
+    /// Return whether the command waits for data when none of its keys hold
+    /// any. Such commands reply as if they timed out right away unless they
+    /// are executed through DB::block.
+    pub fn is_blocking(&self) -> bool {
+        return match self {
+            Self::List(cmd) => cmd.is_blocking(),
+            Self::SortedSet(cmd) => cmd.is_blocking(),
+            Self::Stream(cmd) => cmd.is_blocking(),
+            _ => false,
+        };
+    }
+
+    /// Return whether a RESP2 connection may send the command once it
+    /// subscribed to anything. Like in Redis, it may only change its
+    /// subscriptions, PING, QUIT or RESET, since its replies could not be
+    /// told apart from messages otherwise.
+    pub fn is_allowed_when_subscribed(&self) -> bool {
+        return match self {
+            Self::PubSub(cmd) => cmd.is_subscription(),
+            Self::Connection(cmd) => cmd.is_allowed_when_subscribed(),
+            _ => false,
+        };
+    }
+
+    /// Convert a command into the appropriate Frame: an Array of Bulk frames,
+    /// starting with the command name
+    pub fn to_frame(&self) -> Frame {
+        let args = match self {
+            Self::Bitmap(cmd) => cmd.to_args(),
+            Self::Connection(cmd) => cmd.to_args(),
+            Self::Geo(cmd) => cmd.to_args(),
+            Self::Key(cmd) => cmd.to_args(),
+            Self::Hash(cmd) => cmd.to_args(),
+            Self::HyperLogLog(cmd) => cmd.to_args(),
+            Self::List(cmd) => cmd.to_args(),
+            Self::PubSub(cmd) => cmd.to_args(),
+            Self::Server(cmd) => cmd.to_args(),
+            Self::Set(cmd) => cmd.to_args(),
+            Self::SortedSet(cmd) => cmd.to_args(),
+            Self::Stream(cmd) => cmd.to_args(),
+            Self::String(cmd) => cmd.to_args(),
+        };
+        return Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+    }
+
+    /// Parse a frame back into a command. If the frame does not correspond to
+    /// any of the supported commands, return None
+    ///
+    /// If the frame does not strictly conform to the expected format of the
+    /// command, this method will return None. For example, if the input frame
+    /// has more than three elements, then it will never be parsed into a SET
+    /// command even if the first three elements form a valid SET command.
+    pub fn parse_command(frame: &Frame) -> Option<Self> {
+        return Self::from_frame(frame).ok();
+    }
+
+    /// Parse a frame back into a command. The command name is looked up in
+    /// the command table case-insensitively, then the arguments are checked
+    /// against the command's arity before they are parsed.
+    ///
+    /// The errors are the ones Redis replies with for the same input.
+    pub fn from_frame(frame: &Frame) -> Result<Self, CommandError> {
+        let args = match frame {
+            Frame::Array(frames) => frames
+                .iter()
+                .map(|frame| match frame {
+                    Frame::Bulk(bytes) => Ok(bytes.clone()),
+                    Frame::Simple(s) => Ok(Bytes::copy_from_slice(s.as_bytes())),
+                    _ => Err(CommandError::InvalidRequest),
+                })
+                .collect::<Result<Vec<Bytes>, CommandError>>()?,
+            _ => return Err(CommandError::InvalidRequest),
+        };
+        let name = args.first().ok_or(CommandError::InvalidRequest)?;
+        let spec = CommandSpec::lookup(name)
+            .ok_or_else(|| CommandError::unknown_command(name, &args[1..]))?;
+        if !spec.accepts_argc(args.len()) {
+            return Err(CommandError::WrongArity(spec.name));
+        }
+
+        let mut args = args.into_iter();
+        args.next();
+        return (spec.parse)(&mut CommandArgs::new(args));
+    }
+}
+
+/// Flags that describe how a command behaves, mirroring the flags Redis
+/// reports through "COMMAND INFO"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandFlag {
+    /// The command only reads data
+    Readonly,
+    /// The command may modify data
+    Write,
+    /// The command manages the server rather than the data
+    Admin,
+    /// The command runs in constant or logarithmic time
+    Fast,
+    /// The command may block the client
+    Blocking,
+    /// The command is part of publish/subscribe messaging
+    PubSub,
+    /// The command can be sent before the client negotiated the protocol
+    NoAuth,
+}
+
+/// Describes one command: its name, how many arguments it takes, how it
+/// behaves, where its keys are, and how its arguments are parsed
+pub struct CommandSpec {
+    /// The upper-case name of the command
+    pub name: &'static str,
+    /// The number of arguments, including the command name. A negative arity
+    /// -N means that the command takes at least N arguments.
+    pub arity: i64,
+    pub flags: &'static [CommandFlag],
+    /// The position of the first key argument, 0 if the command takes no keys
+    pub first_key: usize,
+    /// The position of the last key argument. A negative position counts
+    /// from the end: -1 is the last argument.
+    pub last_key: i64,
+    /// The distance between two key arguments
+    pub key_step: usize,
+    /// Parse the arguments after the command name. The number of arguments
+    /// has already been checked against the arity.
+    parse: fn(&mut CommandArgs) -> Result<Command, CommandError>,
+}
+
+impl CommandSpec {
+    /// Look up the command with the given name, ignoring case
+    pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+        static TABLE: OnceLock<HashMap<&'static str, &'static CommandSpec>> = OnceLock::new();
+        let table = TABLE.get_or_init(|| {
+            let families: [&'static [CommandSpec]; 13] = [
+                bitmap::COMMANDS,
+                connection::COMMANDS,
+                geo::COMMANDS,
+                hash::COMMANDS,
+                hyperloglog::COMMANDS,
+                key::COMMANDS,
+                list::COMMANDS,
+                pubsub::COMMANDS,
+                server::COMMANDS,
+                set::COMMANDS,
+                sorted_set::COMMANDS,
+                stream::COMMANDS,
+                string::COMMANDS,
+            ];
+            return families
+                .into_iter()
+                .flatten()
+                .map(|spec| (spec.name, spec))
+                .collect();
+        });
+        let name = std::str::from_utf8(name).ok()?.to_ascii_uppercase();
+        return table.get(name.as_str()).copied();
+    }
+
+    /// Return whether the command accepts the given number of arguments,
+    /// including the command name
+    pub fn accepts_argc(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity < 0 {
+            return argc >= -self.arity;
+        }
+        return argc == self.arity;
+    }
+
+    /// Return whether the command has the flag
+    pub fn has_flag(&self, flag: CommandFlag) -> bool {
+        return self.flags.contains(&flag);
+    }
+
+    /// Return the positions of the key arguments in a request with the given
+    /// number of arguments, including the command name
+    pub fn key_positions(&self, argc: usize) -> impl Iterator<Item = usize> {
+        let last = match self.last_key {
+            last if last < 0 => argc as i64 + last,
+            last => last,
+        };
+        let (first, last) = match self.first_key {
+            0 => (1, 0),
+            first => (first, last.max(0) as usize),
+        };
+        return (first..=last).step_by(self.key_step.max(1));
+    }
+}
+
+/// The number of hash slots that keys are spread over in a cluster
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+/// Return the hash slot of a key, the CRC16 of the key modulo the number of
+/// slots, like in Redis Cluster. If the key contains a non-empty hash tag
+/// between the first `{` and the `}` after it, only the hash tag is hashed,
+/// so that related keys can be kept in the same slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let mut hashed = key;
+    if let Some(open) = key.iter().position(|&byte| byte == b'{') {
+        let tag = &key[open + 1..];
+        if let Some(close) = tag.iter().position(|&byte| byte == b'}') {
+            if close > 0 {
+                hashed = &tag[..close];
+            }
+        }
+    }
+    return crc16(hashed) % CLUSTER_SLOTS;
+}
+
+/// The CRC16 variant Redis Cluster uses: XMODEM, with the 0x1021 polynomial
+/// and an initial value of 0
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    return crc;
+}
+
+/// The reasons why a frame cannot be turned into a command. Displaying the
+/// error gives the message Redis replies with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// The frame is not an Array of Bulk frames
+    InvalidRequest,
+    UnknownCommand {
+        name: String,
+        args: Vec<String>,
+    },
+    /// The number of arguments does not match the command's arity
+    WrongArity(&'static str),
+    Syntax,
+    NotInteger,
+    NotFloat,
+    /// The key holds a value of a type the command does not operate on
+    WrongType,
+    /// Any other error, with its full message
+    Other(String),
+}
+
+impl CommandError {
+    /// Describe a request for a command that is unknown
+    pub fn unknown_command(name: &[u8], args: &[Bytes]) -> Self {
+        return Self::UnknownCommand {
+            name: String::from_utf8_lossy(name).into(),
+            args: args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into())
+                .collect(),
+        };
+    }
+
+    /// Convert the error into the Error frame that is sent to the client
+    pub fn to_frame(&self) -> Frame {
+        return Frame::Error(self.to_string());
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Self::InvalidRequest => {
+                write!(f, "ERR Protocol error: expected an array of bulk strings")
+            }
+            Self::UnknownCommand { name, args } => {
+                write!(
+                    f,
+                    "ERR unknown command '{name}', with args beginning with: "
+                )?;
+                for arg in args {
+                    write!(f, "'{arg}' ")?;
+                }
+                Ok(())
+            }
+            Self::WrongArity(name) => write!(
+                f,
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            ),
+            Self::Syntax => write!(f, "ERR syntax error"),
+            Self::NotInteger => write!(f, "ERR value is not an integer or out of range"),
+            Self::NotFloat => write!(f, "ERR value is not a valid float"),
+            Self::WrongType => write!(
+                f,
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+            Self::Other(msg) => write!(f, "{msg}"),
+        };
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// The arguments of a request after the command name, consumed front to back
+/// by the parse functions in the command table
+pub struct CommandArgs {
+    args: std::vec::IntoIter<Bytes>,
+}
+
+impl CommandArgs {
+    fn new(args: std::vec::IntoIter<Bytes>) -> Self {
+        return Self { args };
+    }
+
+    /// Return the number of arguments that have not been consumed
+    pub fn remaining(&self) -> usize {
+        return self.args.len();
+    }
+
+    /// Consume the next argument, which must exist
+    pub fn next_bytes(&mut self) -> Result<Bytes, CommandError> {
+        return self.args.next().ok_or(CommandError::Syntax);
+    }
+
+    /// Consume the next argument as a signed 64-bit integer
+    pub fn next_integer(&mut self) -> Result<i64, CommandError> {
+        return parse_integer(&self.next_bytes()?).ok_or(CommandError::NotInteger);
+    }
+
+    /// Consume the next argument as a count, which must not be negative
+    pub fn next_count(&mut self) -> Result<usize, CommandError> {
+        let count = self.next_integer()?;
+        if count < 0 {
+            return Err(CommandError::Other(
+                "ERR value is out of range, must be positive".into(),
+            ));
+        }
+        return Ok(count as usize);
+    }
+
+    /// Consume the next argument as a double
+    pub fn next_float(&mut self) -> Result<f64, CommandError> {
+        return parse_float(&self.next_bytes()?).ok_or(CommandError::NotFloat);
+    }
+
+    /// Consume the next argument as an upper-cased keyword, for matching
+    /// against options such as "EX" or "NX"
+    pub fn next_keyword(&mut self) -> Result<String, CommandError> {
+        let arg = self.next_bytes()?;
+        return Ok(String::from_utf8_lossy(&arg).to_ascii_uppercase());
+    }
+
+    /// Consume every remaining argument
+    pub fn rest(&mut self) -> Vec<Bytes> {
+        return self.args.by_ref().collect();
+    }
+
+    /// Fail with a syntax error if there are arguments left
+    pub fn finish(&mut self) -> Result<(), CommandError> {
+        if self.args.len() > 0 {
+            return Err(CommandError::Syntax);
+        }
+        return Ok(());
+    }
+}
+
+/// Parse a signed 64-bit integer the way Redis does: no whitespace, no plus
+/// sign, and no leading zeros
+pub fn parse_integer(bytes: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    if digits.is_empty()
+        || !digits.bytes().all(|b| b.is_ascii_digit())
+        || (digits.len() > 1 && digits.starts_with('0'))
+        || s == "-0"
+    {
+        return None;
+    }
+    return s.parse().ok();
+}
+
+/// Parse a double the way Redis does: no whitespace and no NaN
+pub fn parse_float(bytes: &[u8]) -> Option<f64> {
+    // Rust's parser already rejects whitespace and accepts "inf" and "-inf"
+    let num = std::str::from_utf8(bytes).ok()?.parse::<f64>().ok()?;
+    if num.is_nan() {
+        return None;
+    }
+    return Some(num);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Build a request frame out of the arguments
+    fn request(args: &[&'static str]) -> Frame {
+        return Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::from(*arg)))
+                .collect(),
+        );
+    }
+
+    #[test]
+    fn test_parse_hello_command() {
+        let hello = |args: &[&'static str]| Command::parse_command(&request(args));
+
+        assert_eq!(hello(&["HELLO"]), Some(Command::hello(None)));
+        assert_eq!(hello(&["HELLO", "3"]), Some(Command::hello(Some(3))));
+        assert_eq!(
+            hello(&["HELLO", "3", "auth", "default", "pw", "SETNAME", "me"]),
+            Some(Command::Connection(ConnectionCommand::Hello {
+                protover: Some(3),
+                auth: Some((Bytes::from("default"), Bytes::from("pw"))),
+                setname: Some(Bytes::from("me")),
+            }))
+        );
+        assert_eq!(hello(&["HELLO", "three"]), None);
+        assert_eq!(hello(&["HELLO", "3", "AUTH", "default"]), None);
+        assert_eq!(hello(&["HELLO", "3", "FOO"]), None);
+
+        let cmd = hello(&["HELLO", "2", "SETNAME", "me"]).unwrap();
+        assert_eq!(Command::parse_command(&cmd.to_frame()), Some(cmd));
+    }
+
+    #[test]
+    fn test_parse_ping_quit_reset_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+
+        assert_eq!(
+            parse(&["ping"]),
+            Ok(Command::Connection(ConnectionCommand::Ping {
+                message: None
+            }))
+        );
+        assert_eq!(
+            parse(&["PING", "hi"]),
+            Ok(Command::Connection(ConnectionCommand::Ping {
+                message: Some(Bytes::from("hi"))
+            }))
+        );
+        assert_eq!(
+            parse(&["PING", "a", "b"]),
+            Err(CommandError::WrongArity("PING"))
+        );
+        assert_eq!(
+            parse(&["QUIT", "now"]),
+            Ok(Command::Connection(ConnectionCommand::Quit))
+        );
+        assert_eq!(
+            parse(&["RESET", "all"]),
+            Err(CommandError::WrongArity("RESET"))
+        );
+
+        // Only these and the commands that change the subscriptions are
+        // allowed on a subscribed RESP2 connection
+        for args in [
+            &["PING"][..],
+            &["QUIT"],
+            &["RESET"],
+            &["SUBSCRIBE", "a"],
+            &["PUNSUBSCRIBE"],
+            &["SSUBSCRIBE", "a"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert!(cmd.is_allowed_when_subscribed(), "{args:?}");
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+        for args in [&["GET", "a"][..], &["PUBLISH", "a", "b"], &["HELLO", "3"]] {
+            assert!(
+                !parse(args).unwrap().is_allowed_when_subscribed(),
+                "{args:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::parse_command(&Frame::Simple("OK".into())), None,);
+
+        assert_eq!(
+            Command::parse_command(&Frame::Array(vec![
+                Frame::Bulk(Bytes::from("SET")),
+                Frame::Bulk(Bytes::from("foo")),
+                Frame::Bulk(Bytes::from("bar")),
+            ])),
+            Some(Command::set(Bytes::from("foo"), Bytes::from("bar"))),
+        );
+
+        assert_eq!(
+            Command::parse_command(&Frame::Array(vec![
+                Frame::Bulk(Bytes::from("SET")),
+                Frame::Bulk(Bytes::from("foo")),
+            ])),
+            None,
+        );
+
+        assert_eq!(
+            Command::parse_command(&Frame::Array(vec![
+                Frame::Bulk(Bytes::from("SET")),
+                Frame::Bulk(Bytes::from("foo")),
+                Frame::Bulk(Bytes::from("bar")),
+                Frame::Bulk(Bytes::from("baz")),
+            ])),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_parse_command_case_insensitive() {
+        assert_eq!(
+            Command::from_frame(&request(&["set", "foo", "bar"])),
+            Ok(Command::set(Bytes::from("foo"), Bytes::from("bar")))
+        );
+        assert_eq!(
+            Command::from_frame(&request(&["gEt", "foo"])),
+            Ok(Command::get(Bytes::from("foo")))
+        );
+        // Only the command name is case-insensitive
+        assert_eq!(
+            Command::from_frame(&request(&["Del", "FOO"])),
+            Ok(Command::del(Bytes::from("FOO")))
+        );
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let err = Command::from_frame(&request(&["FOO", "a", "b"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR unknown command 'FOO', with args beginning with: 'a' 'b' "
+        );
+
+        let err = Command::from_frame(&request(&["foo"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR unknown command 'foo', with args beginning with: "
+        );
+
+        let err = Command::from_frame(&request(&["get"])).unwrap_err();
+        assert_eq!(err, CommandError::WrongArity("GET"));
+        assert_eq!(
+            err.to_frame(),
+            Frame::Error("ERR wrong number of arguments for 'get' command".into())
+        );
+
+        let err = Command::from_frame(&request(&["HELLO", "3", "FOO"])).unwrap_err();
+        assert_eq!(err.to_string(), "ERR syntax error");
+
+        let err = Command::from_frame(&Frame::Array(vec![])).unwrap_err();
+        assert_eq!(err, CommandError::InvalidRequest);
+        let err = Command::from_frame(&Frame::Array(vec![Frame::Integer(1)])).unwrap_err();
+        assert_eq!(err, CommandError::InvalidRequest);
+    }
+
+    #[test]
+    fn test_parse_set_options() {
+        let set = |args: &[&'static str]| Command::from_frame(&request(args));
+        let expected = |expiry, keep_ttl, condition, get| {
+            return Ok(Command::String(StringCommand::Set {
+                key: Bytes::from("k"),
+                val: Bytes::from("v"),
+                expiry,
+                keep_ttl,
+                condition,
+                get,
+            }));
+        };
+
+        assert_eq!(
+            set(&["SET", "k", "v", "ex", "10"]),
+            expected(Some(Expiry::Ex(10)), false, None, false)
+        );
+        assert_eq!(
+            set(&["SET", "k", "v", "PXAT", "1700000000000", "NX", "GET"]),
+            expected(
+                Some(Expiry::PxAt(1700000000000)),
+                false,
+                Some(SetCondition::Nx),
+                true
+            )
+        );
+        assert_eq!(
+            set(&["SET", "k", "v", "XX", "KEEPTTL"]),
+            expected(None, true, Some(SetCondition::Xx), false)
+        );
+        assert_eq!(
+            set(&["SET", "k", "v", "NX", "XX"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            set(&["SET", "k", "v", "EX", "10", "PX", "10"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            set(&["SET", "k", "v", "KEEPTTL", "EX", "10"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(set(&["SET", "k", "v", "EX"]), Err(CommandError::Syntax));
+        assert_eq!(
+            set(&["SET", "k", "v", "EX", "ten"]),
+            Err(CommandError::NotInteger)
+        );
+        assert_eq!(
+            set(&["SET", "k", "v", "EX", "0"]).unwrap_err().to_string(),
+            "ERR invalid expire time in 'set' command"
+        );
+
+        let cmd = set(&["SET", "k", "v", "XX", "GET", "EXAT", "17"]).unwrap();
+        assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+    }
+
+    #[test]
+    fn test_parse_string_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+        let key = || Bytes::from("k");
+
+        assert_eq!(
+            parse(&["incr", "k"]),
+            Ok(Command::String(StringCommand::IncrBy {
+                key: key(),
+                delta: 1
+            }))
+        );
+        assert_eq!(
+            parse(&["DECR", "k"]),
+            Ok(Command::String(StringCommand::IncrBy {
+                key: key(),
+                delta: -1
+            }))
+        );
+        assert_eq!(
+            parse(&["DECRBY", "k", "5"]),
+            Ok(Command::String(StringCommand::IncrBy {
+                key: key(),
+                delta: -5
+            }))
+        );
+        assert_eq!(
+            parse(&["DECRBY", "k", "-9223372036854775808"])
+                .unwrap_err()
+                .to_string(),
+            "ERR decrement would overflow"
+        );
+        assert_eq!(
+            parse(&["INCRBY", "k", "1.5"]),
+            Err(CommandError::NotInteger)
+        );
+        assert_eq!(
+            parse(&["INCRBYFLOAT", "k", "1.5"]),
+            Ok(Command::String(StringCommand::IncrByFloat {
+                key: key(),
+                delta: 1.5
+            }))
+        );
+        assert_eq!(
+            parse(&["INCRBYFLOAT", "k", "nan"]),
+            Err(CommandError::NotFloat)
+        );
+        assert_eq!(
+            parse(&["GETRANGE", "k", "0", "-1"]),
+            Ok(Command::String(StringCommand::GetRange {
+                key: key(),
+                start: 0,
+                end: -1
+            }))
+        );
+        assert_eq!(
+            parse(&["GETEX", "k", "PERSIST"]),
+            Ok(Command::String(StringCommand::GetEx {
+                key: key(),
+                expiry: None,
+                persist: true
+            }))
+        );
+        assert_eq!(
+            parse(&["GETEX", "k", "EX", "1", "PERSIST"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            parse(&["GETEX", "k", "PX", "-1"]).unwrap_err().to_string(),
+            "ERR invalid expire time in 'getex' command"
+        );
+
+        for args in [
+            &["SETNX", "k", "v"][..],
+            &["GETDEL", "k"],
+            &["GETEX", "k", "PXAT", "100"],
+            &["APPEND", "k", "v"],
+            &["STRLEN", "k"],
+            &["SETRANGE", "k", "3", "v"],
+            &["INCRBY", "k", "-3"],
+            &["INCRBYFLOAT", "k", "-0.25"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_multi_key_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+        let keys = |keys: &[&'static str]| keys.iter().map(|key| Bytes::from(*key)).collect();
+
+        assert_eq!(
+            parse(&["DEL", "a", "b", "c"]),
+            Ok(Command::Key(KeyCommand::Del {
+                keys: keys(&["a", "b", "c"])
+            }))
+        );
+        assert_eq!(
+            parse(&["exists", "a", "a"]),
+            Ok(Command::Key(KeyCommand::Exists {
+                keys: keys(&["a", "a"])
+            }))
+        );
+        assert_eq!(
+            parse(&["MGET", "a", "b"]),
+            Ok(Command::String(StringCommand::MGet {
+                keys: keys(&["a", "b"])
+            }))
+        );
+        assert_eq!(
+            parse(&["MSET", "a", "1", "b", "2"]),
+            Ok(Command::String(StringCommand::MSet {
+                pairs: vec![
+                    (Bytes::from("a"), Bytes::from("1")),
+                    (Bytes::from("b"), Bytes::from("2"))
+                ]
+            }))
+        );
+        assert_eq!(
+            parse(&["MSETNX", "a", "1", "b"]),
+            Err(CommandError::WrongArity("MSETNX"))
+        );
+        assert_eq!(parse(&["DEL"]), Err(CommandError::WrongArity("DEL")));
+
+        for args in [
+            &["UNLINK", "a", "b"][..],
+            &["TOUCH", "a"],
+            &["MSETNX", "a", "1", "b", "2"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+
+        let mset = CommandSpec::lookup(b"MSET").unwrap();
+        assert_eq!(mset.key_positions(5).collect::<Vec<_>>(), vec![1, 3]);
+        let del = CommandSpec::lookup(b"DEL").unwrap();
+        assert_eq!(del.key_positions(4).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_parse_list_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+
+        assert_eq!(
+            parse(&["rpush", "l", "a", "b"]),
+            Ok(Command::List(ListCommand::Push {
+                key: Bytes::from("l"),
+                side: Side::Right,
+                elems: vec![Bytes::from("a"), Bytes::from("b")],
+                only_if_exists: false,
+            }))
+        );
+        assert_eq!(
+            parse(&["RPOPLPUSH", "a", "b"]),
+            Ok(Command::List(ListCommand::Move {
+                source: Bytes::from("a"),
+                destination: Bytes::from("b"),
+                from: Side::Right,
+                to: Side::Left,
+            }))
+        );
+        assert_eq!(
+            parse(&["LPOP", "l", "-1"]),
+            Err(CommandError::Other(
+                "ERR value is out of range, must be positive".into()
+            ))
+        );
+        assert_eq!(
+            parse(&["LMOVE", "a", "b", "LEFT", "UP"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            parse(&["LINSERT", "l", "AROUND", "a", "b"]),
+            Err(CommandError::Syntax)
+        );
+        assert!(parse(&["LPOS", "l", "a", "RANK", "0"])
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR RANK can't be zero"));
+        assert_eq!(
+            parse(&["LPOS", "l", "a", "COUNT", "-1"])
+                .unwrap_err()
+                .to_string(),
+            "ERR COUNT can't be negative"
+        );
+
+        for args in [
+            &["LPUSHX", "l", "a"][..],
+            &["RPOP", "l", "2"],
+            &["LRANGE", "l", "0", "-1"],
+            &["LSET", "l", "-1", "x"],
+            &["LREM", "l", "-2", "a"],
+            &["LINSERT", "l", "before", "a", "b"],
+            &["LMOVE", "a", "b", "left", "right"],
+            &["LPOS", "l", "a", "RANK", "-2", "COUNT", "0", "MAXLEN", "10"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_blocking_list_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+
+        assert_eq!(
+            parse(&["BLPOP", "a", "b", "1.5"]),
+            Ok(Command::List(ListCommand::BPop {
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+                side: Side::Left,
+                timeout: Some(Duration::from_millis(1500)),
+            }))
+        );
+        assert_eq!(
+            parse(&["blmpop", "0", "2", "a", "b", "right", "count", "3"]),
+            Ok(Command::List(ListCommand::BMPop {
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+                side: Side::Right,
+                count: 3,
+                timeout: None,
+            }))
+        );
+        assert!(matches!(
+            parse(&["BRPOPLPUSH", "a", "b", "0"]),
+            Ok(Command::List(cmd)) if cmd.is_blocking()
+        ));
+        let err = |args| parse(args).unwrap_err().to_string();
+        assert_eq!(err(&["BLPOP", "a", "-1"]), "ERR timeout is negative");
+        assert_eq!(
+            err(&["BRPOP", "a", "soon"]),
+            "ERR timeout is not a float or out of range"
+        );
+        assert_eq!(err(&["BLPOP", "a", "1e300"]), "ERR timeout is out of range");
+        assert_eq!(
+            err(&["LMPOP", "0", "a", "LEFT"]),
+            "ERR numkeys should be greater than 0"
+        );
+        assert_eq!(err(&["LMPOP", "2", "a", "LEFT"]), "ERR syntax error");
+        assert_eq!(
+            err(&["LMPOP", "1", "a", "LEFT", "COUNT", "0"]),
+            "ERR count should be greater than 0"
+        );
+
+        for args in [
+            &["LMPOP", "1", "a", "LEFT"][..],
+            &["LMPOP", "2", "a", "b", "RIGHT", "COUNT", "2"],
+            &["BRPOP", "a", "0"],
+            &["BLMOVE", "a", "b", "RIGHT", "LEFT", "0.25"],
+            &["BLMPOP", "3", "1", "a", "LEFT", "COUNT", "10"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+
+        let blpop = CommandSpec::lookup(b"BLPOP").unwrap();
+        assert_eq!(blpop.key_positions(4).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"somekey"), 11058);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        // An empty hash tag hashes the whole key
+        assert_eq!(
+            key_slot(b"foo{}{bar}"),
+            crc16(b"foo{}{bar}") % CLUSTER_SLOTS
+        );
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+
+    #[test]
+    fn test_parse_hash_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+
+        assert_eq!(
+            parse(&["hset", "h", "a", "1", "b", "2"]),
+            Ok(Command::Hash(HashCommand::Set {
+                key: Bytes::from("h"),
+                pairs: vec![
+                    (Bytes::from("a"), Bytes::from("1")),
+                    (Bytes::from("b"), Bytes::from("2"))
+                ],
+            }))
+        );
+        assert_eq!(
+            parse(&["HSET", "h", "a", "1", "b"]),
+            Err(CommandError::WrongArity("HSET"))
+        );
+        assert_eq!(
+            parse(&["HINCRBY", "h", "a", "x"]),
+            Err(CommandError::NotInteger)
+        );
+        assert_eq!(
+            parse(&["HRANDFIELD", "h", "1", "WITHSCORES"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            parse(&["HSCAN", "h", "-1"]).unwrap_err().to_string(),
+            "ERR invalid cursor"
+        );
+        assert_eq!(
+            parse(&["HSCAN", "h", "0", "COUNT", "0"]),
+            Err(CommandError::Syntax)
+        );
+
+        for args in [
+            &["HMSET", "h", "a", "1"][..],
+            &["HMGET", "h", "a", "b"],
+            &["HDEL", "h", "a"],
+            &["HINCRBYFLOAT", "h", "a", "1.5"],
+            &["HRANDFIELD", "h"],
+            &["HRANDFIELD", "h", "-5", "WITHVALUES"],
+            &[
+                "HSCAN", "h", "42", "MATCH", "a*", "COUNT", "100", "NOVALUES",
+            ],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_hash_field_expiry() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+
+        assert_eq!(
+            parse(&["HEXPIRE", "h", "10", "gt", "FIELDS", "2", "a", "b"]),
+            Ok(Command::Hash(HashCommand::Expire {
+                key: Bytes::from("h"),
+                expiry: Expiry::Ex(10),
+                condition: Some(ExpireCondition::Gt),
+                fields: vec![Bytes::from("a"), Bytes::from("b")],
+            }))
+        );
+        assert_eq!(
+            parse(&["HTTL", "h", "FIELDS", "1", "a"]),
+            Ok(Command::Hash(HashCommand::Ttl {
+                key: Bytes::from("h"),
+                fields: vec![Bytes::from("a")],
+            }))
+        );
+        assert_eq!(
+            parse(&["HEXPIRE", "h", "-1", "FIELDS", "1", "a"])
+                .unwrap_err()
+                .to_string(),
+            "ERR invalid expire time, must be >= 0"
+        );
+        assert_eq!(
+            parse(&["HPEXPIRE", "h", "10", "NX", "a", "1", "a"])
+                .unwrap_err()
+                .to_string(),
+            "ERR Mandatory argument FIELDS is missing or not at the right position"
+        );
+        assert_eq!(
+            parse(&["HPERSIST", "h", "FIELDS", "0", "a"])
+                .unwrap_err()
+                .to_string(),
+            "ERR Parameter `numFields` should be greater than 0"
+        );
+        assert_eq!(
+            parse(&["HTTL", "h", "FIELDS", "2", "a"])
+                .unwrap_err()
+                .to_string(),
+            "ERR The `numfields` parameter must match the number of arguments"
+        );
+        assert_eq!(
+            parse(&["HGETEX", "h", "EX", "0", "FIELDS", "1", "a"])
+                .unwrap_err()
+                .to_string(),
+            "ERR invalid expire time in 'hgetex' command"
+        );
+        assert_eq!(
+            parse(&["HGETEX", "h", "EX", "1", "PERSIST", "FIELDS", "1", "a"])
+                .unwrap_err()
+                .to_string(),
+            "ERR Mandatory argument FIELDS is missing or not at the right position"
+        );
+
+        for args in [
+            &["HPEXPIREAT", "h", "1700000000000", "FIELDS", "1", "a"][..],
+            &["HEXPIRE", "h", "5", "XX", "FIELDS", "2", "a", "b"],
+            &["HPTTL", "h", "FIELDS", "1", "a"],
+            &["HEXPIRETIME", "h", "FIELDS", "1", "a"],
+            &["HPEXPIRETIME", "h", "FIELDS", "1", "a"],
+            &["HPERSIST", "h", "FIELDS", "1", "a"],
+            &["HGETEX", "h", "PXAT", "100", "FIELDS", "1", "a"],
+            &["HGETEX", "h", "PERSIST", "FIELDS", "1", "a"],
+            &["HGETEX", "h", "FIELDS", "1", "a"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_set_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+
+        assert_eq!(
+            parse(&["sinterstore", "dst", "a", "b"]),
+            Ok(Command::Set(SetCommand::Store {
+                op: SetOp::Inter,
+                destination: Bytes::from("dst"),
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+            }))
+        );
+        assert_eq!(
+            parse(&["SINTERCARD", "2", "a", "b", "LIMIT", "5"]),
+            Ok(Command::Set(SetCommand::InterCard {
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+                limit: 5,
+            }))
+        );
+        assert_eq!(
+            parse(&["SINTERCARD", "3", "a", "b"])
+                .unwrap_err()
+                .to_string(),
+            "ERR Number of keys can't be greater than number of args"
+        );
+        assert_eq!(
+            parse(&["SINTERCARD", "1", "a", "LIMIT", "-1"])
+                .unwrap_err()
+                .to_string(),
+            "ERR LIMIT can't be negative"
+        );
+        assert_eq!(
+            parse(&["SPOP", "s", "-1"]).unwrap_err().to_string(),
+            "ERR value is out of range, must be positive"
+        );
+        assert_eq!(parse(&["SPOP", "s", "1", "2"]), Err(CommandError::Syntax));
+        assert_eq!(
+            parse(&["SSCAN", "s", "0", "NOVALUES"]),
+            Err(CommandError::Syntax)
+        );
+
+        for args in [
+            &["SADD", "s", "a", "b"][..],
+            &["SREM", "s", "a"],
+            &["SMISMEMBER", "s", "a", "b"],
+            &["SPOP", "s"],
+            &["SPOP", "s", "3"],
+            &["SRANDMEMBER", "s", "-3"],
+            &["SMOVE", "s", "t", "a"],
+            &["SUNION", "a", "b"],
+            &["SDIFFSTORE", "dst", "a", "b"],
+            &["SINTERCARD", "1", "a"],
+            &["SSCAN", "s", "7", "MATCH", "a*", "COUNT", "20"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_sorted_set_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+        let err = |args: &[&'static str]| parse(args).unwrap_err().to_string();
+
+        assert_eq!(
+            parse(&["zadd", "z", "xx", "ch", "1.5", "a", "-inf", "b"]),
+            Ok(Command::SortedSet(SortedSetCommand::Add {
+                key: Bytes::from("z"),
+                condition: Some(SetCondition::Xx),
+                comparison: None,
+                changed: true,
+                incr: false,
+                pairs: vec![
+                    (1.5, Bytes::from("a")),
+                    (f64::NEG_INFINITY, Bytes::from("b"))
+                ],
+            }))
+        );
+        assert_eq!(
+            err(&["ZADD", "z", "NX", "XX", "1", "a"]),
+            "ERR XX and NX options at the same time are not compatible"
+        );
+        assert_eq!(
+            err(&["ZADD", "z", "NX", "GT", "1", "a"]),
+            "ERR GT, LT, and/or NX options at the same time are not compatible"
+        );
+        assert_eq!(
+            err(&["ZADD", "z", "INCR", "1", "a", "2", "b"]),
+            "ERR INCR option supports a single increment-element pair"
+        );
+        assert_eq!(
+            parse(&["ZADD", "z", "1", "a", "2"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(parse(&["ZADD", "z", "x", "a"]), Err(CommandError::NotFloat));
+
+        // With REV, the bounds of a score range are given highest first
+        assert_eq!(
+            parse(&["ZRANGE", "z", "(5", "-inf", "BYSCORE", "REV", "LIMIT", "1", "2"]),
+            Ok(Command::SortedSet(SortedSetCommand::Range {
+                key: Bytes::from("z"),
+                range: Range::Score {
+                    min: ScoreBound {
+                        score: f64::NEG_INFINITY,
+                        exclusive: false,
+                    },
+                    max: ScoreBound {
+                        score: 5.0,
+                        exclusive: true,
+                    },
+                },
+                rev: true,
+                limit: Some(Limit {
+                    offset: 1,
+                    count: 2
+                }),
+                with_scores: false,
+            }))
+        );
+        assert_eq!(
+            err(&["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"]),
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+        );
+        assert_eq!(
+            err(&["ZRANGE", "z", "-", "+", "BYLEX", "WITHSCORES"]),
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX"
+        );
+        assert_eq!(
+            err(&["ZRANGE", "z", "a", "+", "BYLEX"]),
+            "ERR min or max not valid string range item"
+        );
+        assert_eq!(
+            err(&["ZCOUNT", "z", "(a", "1"]),
+            "ERR min or max is not a float"
+        );
+        assert_eq!(
+            parse(&["ZRANGESTORE", "d", "z", "0", "1", "WITHSCORES"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            parse(&[
+                "ZINTERSTORE",
+                "d",
+                "2",
+                "a",
+                "b",
+                "WEIGHTS",
+                "2",
+                "0.5",
+                "AGGREGATE",
+                "max"
+            ]),
+            Ok(Command::SortedSet(SortedSetCommand::Store {
+                op: StoreOp::Inter,
+                destination: Bytes::from("d"),
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+                weights: vec![2.0, 0.5],
+                aggregate: Aggregate::Max,
+            }))
+        );
+        assert_eq!(
+            err(&["ZUNIONSTORE", "d", "0", "a"]),
+            "ERR at least 1 input key is needed for 'zunionstore' command"
+        );
+        assert_eq!(
+            err(&["ZUNIONSTORE", "d", "1", "a", "WEIGHTS", "x"]),
+            "ERR weight value is not a float"
+        );
+        assert!(matches!(
+            parse(&["BZPOPMIN", "a", "b", "0"]),
+            Ok(cmd) if cmd.is_blocking()
+        ));
+
+        for args in [
+            &["ZADD", "z", "NX", "CH", "1", "a", "2.5", "b"][..],
+            &["ZADD", "z", "GT", "INCR", "1", "a"],
+            &["ZREM", "z", "a", "b"],
+            &["ZMSCORE", "z", "a", "b"],
+            &["ZINCRBY", "z", "-1.5", "a"],
+            &["ZCOUNT", "z", "(1", "+inf"],
+            &["ZREVRANK", "z", "a", "WITHSCORE"],
+            &["ZRANGE", "z", "0", "-1", "REV", "WITHSCORES"],
+            &["ZRANGE", "z", "[b", "-", "BYLEX", "REV", "LIMIT", "0", "-1"],
+            &["ZRANGESTORE", "d", "z", "1", "(2", "BYSCORE"],
+            &["ZPOPMAX", "z", "2"],
+            &["BZPOPMAX", "a", "b", "1.5"],
+            &[
+                "ZUNIONSTORE",
+                "d",
+                "2",
+                "a",
+                "b",
+                "WEIGHTS",
+                "1",
+                "2",
+                "AGGREGATE",
+                "MIN",
+            ],
+            &["ZSCAN", "z", "7", "MATCH", "a*", "COUNT", "20"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_stream_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+        let err = |args: &[&'static str]| parse(args).unwrap_err().to_string();
+        let id = |ms, seq| StreamId { ms, seq };
+
+        assert_eq!(
+            parse(&["xadd", "s", "MAXLEN", "~", "10", "LIMIT", "5", "5-*", "f", "v"]),
+            Ok(Command::Stream(StreamCommand::Add {
+                key: Bytes::from("s"),
+                id: NewId::AutoSeq(5),
+                no_mkstream: false,
+                trim: Some(Trim {
+                    strategy: TrimStrategy::MaxLen(10),
+                    approx: true,
+                    limit: Some(5),
+                }),
+                fields: vec![(Bytes::from("f"), Bytes::from("v"))],
+            }))
+        );
+        assert_eq!(
+            err(&["XADD", "s", "MINID", "1", "LIMIT", "5", "*", "f", "v"]),
+            "ERR syntax error, LIMIT cannot be used without the special ~ option"
+        );
+        assert_eq!(
+            err(&["XADD", "s", "1-x", "f", "v"]),
+            "ERR Invalid stream ID specified as stream command argument"
+        );
+        assert_eq!(
+            parse(&["XADD", "s", "*", "f"]),
+            Err(CommandError::WrongArity("XADD"))
+        );
+
+        // Incomplete IDs cover every sequence number, and ( excludes an ID
+        assert_eq!(
+            parse(&["XREVRANGE", "s", "5", "(3-0", "COUNT", "2"]),
+            Ok(Command::Stream(StreamCommand::Range {
+                key: Bytes::from("s"),
+                start: id(3, 1),
+                end: id(5, u64::MAX),
+                count: Some(2),
+                rev: true,
+            }))
+        );
+        assert_eq!(
+            parse(&["XREAD", "BLOCK", "0", "STREAMS", "a", "b", "$", "1"]),
+            Ok(Command::Stream(StreamCommand::Read {
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+                ids: vec![ReadId::Last, ReadId::After(id(1, 0))],
+                count: None,
+                block: Some(Duration::ZERO),
+            }))
+        );
+        assert_eq!(
+            err(&["XREAD", "STREAMS", "a", "b", "0"]),
+            "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+        );
+        assert!(err(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "a", "$"])
+            .starts_with("ERR The $ ID is meaningless"));
+        assert!(matches!(
+            parse(&["XREADGROUP", "GROUP", "g", "c", "BLOCK", "10", "STREAMS", "a", ">"]),
+            Ok(cmd) if cmd.is_blocking()
+        ));
+        // Reading the pending entries of a consumer never blocks
+        assert!(matches!(
+            parse(&["XREADGROUP", "GROUP", "g", "c", "BLOCK", "10", "STREAMS", "a", "0"]),
+            Ok(cmd) if !cmd.is_blocking()
+        ));
+        assert_eq!(
+            err(&["XGROUP", "NOPE", "s", "g"]),
+            "ERR unknown subcommand 'NOPE'. Try XGROUP HELP."
+        );
+        assert_eq!(
+            parse(&["XGROUP", "DESTROY", "s"]),
+            Err(CommandError::WrongArity("XGROUP|DESTROY"))
+        );
+        assert_eq!(
+            err(&["XCLAIM", "s", "g", "c", "10", "1-1", "BOGUS"]),
+            "ERR Unrecognized XCLAIM option 'BOGUS'"
+        );
+
+        for args in [
+            &[
+                "XADD",
+                "s",
+                "NOMKSTREAM",
+                "MINID",
+                "~",
+                "1-1",
+                "*",
+                "a",
+                "1",
+                "b",
+                "2",
+            ][..],
+            &["XADD", "s", "MAXLEN", "0", "7-1", "a", "1"],
+            &["XRANGE", "s", "-", "+", "COUNT", "10"],
+            &["XDEL", "s", "1-1", "2-2"],
+            &["XTRIM", "s", "MAXLEN", "~", "100", "LIMIT", "10"],
+            &[
+                "XREAD", "COUNT", "2", "BLOCK", "100", "STREAMS", "a", "b", "$", "0-1",
+            ],
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "COUNT",
+                "1",
+                "NOACK",
+                "STREAMS",
+                "a",
+                ">",
+            ],
+            &[
+                "XGROUP",
+                "CREATE",
+                "s",
+                "g",
+                "$",
+                "MKSTREAM",
+                "ENTRIESREAD",
+                "3",
+            ],
+            &["XGROUP", "SETID", "s", "g", "1-1"],
+            &["XGROUP", "DELCONSUMER", "s", "g", "c"],
+            &["XACK", "s", "g", "1-1"],
+            &["XPENDING", "s", "g"],
+            &["XPENDING", "s", "g", "IDLE", "10", "-", "+", "5", "c"],
+            &[
+                "XCLAIM",
+                "s",
+                "g",
+                "c",
+                "10",
+                "1-1",
+                "2-2",
+                "RETRYCOUNT",
+                "3",
+                "JUSTID",
+            ],
+            &[
+                "XAUTOCLAIM",
+                "s",
+                "g",
+                "c",
+                "10",
+                "0-0",
+                "COUNT",
+                "5",
+                "JUSTID",
+            ],
+            &["XINFO", "CONSUMERS", "s", "g"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_geo_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+        let err = |args: &[&'static str]| parse(args).unwrap_err().to_string();
+
+        assert_eq!(
+            parse(&[
+                "geosearch",
+                "k",
+                "frommember",
+                "m",
+                "bybox",
+                "1",
+                "2",
+                "KM",
+                "count",
+                "3",
+                "any",
+                "withcoord"
+            ]),
+            Ok(Command::Geo(GeoCommand::Search {
+                key: Bytes::from("k"),
+                search: GeoSearch {
+                    origin: GeoOrigin::Member(Bytes::from("m")),
+                    shape: GeoShape::Box {
+                        width: 1.0,
+                        height: 2.0
+                    },
+                    unit: DistanceUnit::Kilometers,
+                    order: None,
+                    count: Some(3),
+                    any: true,
+                    with_coord: true,
+                    with_dist: false,
+                    with_hash: false,
+                },
+            }))
+        );
+        assert_eq!(
+            parse(&["GEOADD", "k", "NX", "XX", "1", "2", "m"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            parse(&["GEOADD", "k", "1", "2", "m", "3"]),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            err(&["GEOADD", "k", "0", "86", "m"]),
+            "ERR invalid longitude,latitude pair 0.000000,86.000000"
+        );
+        assert_eq!(
+            err(&["GEODIST", "k", "a", "b", "yd"]),
+            "ERR unsupported unit provided. please use M, KM, FT, MI"
+        );
+        assert_eq!(
+            err(&["GEOSEARCH", "k", "BYRADIUS", "1", "m", "ASC", "WITHDIST"]),
+            "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+        );
+        assert_eq!(
+            err(&["GEOSEARCH", "k", "FROMMEMBER", "m", "FROMLONLAT", "1", "2"]),
+            "ERR syntax error"
+        );
+        assert_eq!(
+            err(&[
+                "GEOSEARCH",
+                "k",
+                "FROMMEMBER",
+                "m",
+                "BYRADIUS",
+                "1",
+                "m",
+                "ANY"
+            ]),
+            "ERR the ANY argument requires COUNT argument"
+        );
+        assert_eq!(
+            err(&["GEOSEARCH", "k", "FROMMEMBER", "m", "BYRADIUS", "-1", "m"]),
+            "ERR radius cannot be negative"
+        );
+        assert_eq!(
+            err(&[
+                "GEOSEARCHSTORE",
+                "d",
+                "k",
+                "FROMMEMBER",
+                "m",
+                "BYRADIUS",
+                "1",
+                "m",
+                "WITHDIST"
+            ]),
+            "ERR GEOSEARCHSTORE is not compatible with WITHDIST, WITHHASH and WITHCOORD options"
+        );
+        assert_eq!(
+            parse(&[
+                "GEOSEARCH",
+                "k",
+                "FROMMEMBER",
+                "m",
+                "BYRADIUS",
+                "1",
+                "m",
+                "STOREDIST"
+            ]),
+            Err(CommandError::Syntax)
+        );
+
+        for args in [
+            &[
+                "GEOADD",
+                "k",
+                "XX",
+                "CH",
+                "13.361389",
+                "38.115556",
+                "a",
+                "-1.5",
+                "0",
+                "b",
+            ][..],
+            &["GEOPOS", "k", "a", "b"],
+            &["GEODIST", "k", "a", "b", "mi"],
+            &["GEOHASH", "k", "a"],
+            &[
+                "GEOSEARCH",
+                "k",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYRADIUS",
+                "200",
+                "km",
+                "DESC",
+                "COUNT",
+                "2",
+                "WITHDIST",
+                "WITHHASH",
+            ],
+            &[
+                "GEOSEARCHSTORE",
+                "d",
+                "k",
+                "FROMMEMBER",
+                "a",
+                "BYBOX",
+                "1.5",
+                "2",
+                "ft",
+                "STOREDIST",
+            ],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_pubsub_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+        let err = |args: &[&'static str]| parse(args).unwrap_err().to_string();
+
+        assert_eq!(
+            parse(&["unsubscribe"]),
+            Ok(Command::PubSub(PubSubCommand::Unsubscribe {
+                channels: vec![]
+            }))
+        );
+        assert_eq!(
+            parse(&["PUBSUB", "channels", "news.*"]),
+            Ok(Command::PubSub(PubSubCommand::Channels {
+                pattern: Some(Bytes::from("news.*"))
+            }))
+        );
+        assert_eq!(
+            parse(&["PUBSUB", "NUMSUB"]),
+            Ok(Command::PubSub(PubSubCommand::NumSub { channels: vec![] }))
+        );
+        assert_eq!(
+            err(&["SUBSCRIBE"]),
+            "ERR wrong number of arguments for 'subscribe' command"
+        );
+        assert_eq!(
+            err(&["PUBSUB", "NUMPAT", "x"]),
+            "ERR wrong number of arguments for 'pubsub|numpat' command"
+        );
+        assert_eq!(
+            err(&["PUBSUB", "CHANNELS", "a", "b"]),
+            "ERR wrong number of arguments for 'pubsub|channels' command"
+        );
+        assert_eq!(
+            err(&["PUBSUB", "LIST"]),
+            "ERR unknown subcommand 'LIST'. Try PUBSUB HELP."
+        );
+        let cmd = PubSubCommand::Publish {
+            channel: Bytes::from("news"),
+            message: Bytes::from("hello"),
+        };
+        let frame = Command::PubSub(cmd.clone()).to_frame();
+        assert_eq!(Command::from_frame(&frame), Ok(Command::PubSub(cmd)));
+
+        // Shard channels are keys, which must hash to the same slot
+        assert_eq!(
+            parse(&["SSUBSCRIBE", "{user}.a", "{user}.b"]),
+            Ok(Command::PubSub(PubSubCommand::SSubscribe {
+                channels: vec![Bytes::from("{user}.a"), Bytes::from("{user}.b")]
+            }))
+        );
+        assert_eq!(
+            err(&["SSUBSCRIBE", "a", "b"]),
+            "CROSSSLOT Keys in request don't hash to the same slot"
+        );
+        assert_eq!(
+            err(&["SUNSUBSCRIBE", "a", "b"]),
+            "CROSSSLOT Keys in request don't hash to the same slot"
+        );
+        assert_eq!(
+            parse(&["PUBSUB", "SHARDNUMSUB", "a"]),
+            Ok(Command::PubSub(PubSubCommand::ShardNumSub {
+                channels: vec![Bytes::from("a")]
+            }))
+        );
+        let ssubscribe = CommandSpec::lookup(b"SSUBSCRIBE").unwrap();
+        assert_eq!(ssubscribe.key_positions(3).collect::<Vec<_>>(), vec![1, 2]);
+        let spublish = CommandSpec::lookup(b"SPUBLISH").unwrap();
+        assert_eq!(spublish.key_positions(3).collect::<Vec<_>>(), vec![1]);
+        let subscribe = CommandSpec::lookup(b"SUBSCRIBE").unwrap();
+        assert_eq!(subscribe.key_positions(3).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_bitmap_commands() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+        let err = |args: &[&'static str]| parse(args).unwrap_err().to_string();
+
+        // Offsets prefixed with # count in multiples of the type's width
+        let i8 = BitFieldType {
+            signed: true,
+            bits: 8,
+        };
+        assert_eq!(
+            parse(&["bitfield", "k", "overflow", "sat", "incrby", "i8", "#2", "5"]),
+            Ok(Command::Bitmap(BitmapCommand::Field {
+                key: Bytes::from("k"),
+                ops: vec![
+                    BitFieldOp::Overflow(Overflow::Sat),
+                    BitFieldOp::IncrBy {
+                        encoding: i8,
+                        offset: 16,
+                        increment: 5,
+                    },
+                ],
+                readonly: false,
+            }))
+        );
+        assert!(err(&["BITFIELD", "k", "GET", "u64", "0"]).starts_with("ERR Invalid bitfield type"));
+        assert_eq!(
+            err(&["BITFIELD_RO", "k", "SET", "u8", "0", "1"]),
+            "ERR BITFIELD_RO only supports the GET subcommand"
+        );
+        assert_eq!(
+            err(&["SETBIT", "k", "4294967296", "1"]),
+            "ERR bit offset is not an integer or out of range"
+        );
+        assert_eq!(
+            err(&["SETBIT", "k", "0", "2"]),
+            "ERR bit is not an integer or out of range"
+        );
+        assert_eq!(
+            err(&["BITOP", "NOT", "d", "a", "b"]),
+            "ERR BITOP NOT must be called with a single source key."
+        );
+        assert_eq!(parse(&["BITCOUNT", "k", "0"]), Err(CommandError::Syntax));
+
+        let bitop = CommandSpec::lookup(b"bitop").unwrap();
+        assert_eq!(bitop.key_positions(5).collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        for args in [
+            &["SETBIT", "k", "7", "1"][..],
+            &["GETBIT", "k", "7"],
+            &["BITCOUNT", "k", "1", "-1", "BIT"],
+            &["BITPOS", "k", "0", "2"],
+            &["BITPOS", "k", "1", "0", "-1"],
+            &["BITOP", "XOR", "d", "a", "b"],
+            &[
+                "BITFIELD", "k", "GET", "u4", "0", "SET", "i64", "8", "-1", "OVERFLOW", "FAIL",
+            ],
+            &["BITFIELD_RO", "k", "GET", "i1", "3"],
+        ] {
+            let cmd = parse(args).unwrap();
+            assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        }
+    }
+
+    #[test]
+    fn test_parse_object() {
+        let parse = |args: &[&'static str]| Command::from_frame(&request(args));
+
+        let cmd = parse(&["object", "encoding", "k"]).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Key(KeyCommand::Object {
+                subcommand: ObjectSubcommand::Encoding,
+                key: Bytes::from("k")
+            })
+        );
+        assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        assert_eq!(
+            parse(&["OBJECT", "help"]),
+            Ok(Command::Key(KeyCommand::ObjectHelp))
+        );
+        assert_eq!(
+            parse(&["OBJECT", "FREQ"]).unwrap_err().to_string(),
+            "ERR wrong number of arguments for 'object|freq' command"
+        );
+        assert_eq!(
+            parse(&["OBJECT", "foo", "k"]).unwrap_err().to_string(),
+            "ERR unknown subcommand 'foo'. Try OBJECT HELP."
+        );
+        let object = CommandSpec::lookup(b"OBJECT").unwrap();
+        assert_eq!(object.key_positions(3).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn test_parse_expire() {
+        let expire = |args: &[&'static str]| Command::from_frame(&request(args));
+        let expected = |expiry, conditions| {
+            return Ok(Command::Key(KeyCommand::Expire {
+                key: Bytes::from("k"),
+                expiry,
+                conditions,
+            }));
+        };
+
+        assert_eq!(
+            expire(&["EXPIRE", "k", "-5"]),
+            expected(Expiry::Ex(-5), vec![])
+        );
+        assert_eq!(
+            expire(&["pexpireat", "k", "100", "xx", "lt"]),
+            expected(
+                Expiry::PxAt(100),
+                vec![ExpireCondition::Xx, ExpireCondition::Lt]
+            )
+        );
+        assert_eq!(
+            expire(&["EXPIRE", "k", "10", "NX", "GT"])
+                .unwrap_err()
+                .to_string(),
+            "ERR NX and XX, GT or LT options at the same time are not compatible"
+        );
+        assert_eq!(
+            expire(&["EXPIRE", "k", "10", "GT", "LT"])
+                .unwrap_err()
+                .to_string(),
+            "ERR GT and LT options at the same time are not compatible"
+        );
+        assert_eq!(
+            expire(&["EXPIRE", "k", "10", "FOO"])
+                .unwrap_err()
+                .to_string(),
+            "ERR Unsupported option FOO"
+        );
+
+        let cmd = expire(&["EXPIREAT", "k", "100", "GT"]).unwrap();
+        assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+        assert_eq!(
+            expire(&["TTL", "k"]),
+            Ok(Command::Key(KeyCommand::Ttl {
+                key: Bytes::from("k")
+            }))
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        assert_eq!(Expiry::Ex(2).deadline(1000), Some(3000));
+        assert_eq!(Expiry::Px(2).deadline(1000), Some(1002));
+        assert_eq!(Expiry::ExAt(2).deadline(1000), Some(2000));
+        assert_eq!(Expiry::PxAt(2).deadline(1000), Some(2));
+        assert_eq!(Expiry::Ex(i64::MAX / 100).deadline(0), None);
+        assert_eq!(Expiry::Px(i64::MAX).deadline(1), None);
+
+        assert!(ExpireCondition::Nx.allows(None, 10));
+        assert!(!ExpireCondition::Nx.allows(Some(5), 10));
+        assert!(ExpireCondition::Xx.allows(Some(5), 10));
+        assert!(!ExpireCondition::Gt.allows(None, 10));
+        assert!(ExpireCondition::Gt.allows(Some(5), 10));
+        assert!(!ExpireCondition::Gt.allows(Some(10), 10));
+        assert!(ExpireCondition::Lt.allows(None, 10));
+        assert!(!ExpireCondition::Lt.allows(Some(5), 10));
+    }
+
+    #[test]
+    fn test_parse_info() {
+        assert_eq!(
+            Command::from_frame(&request(&["INFO"])),
+            Ok(Command::Server(ServerCommand::Info { sections: vec![] }))
+        );
+        let cmd = Command::from_frame(&request(&["info", "stats", "keyspace"])).unwrap();
+        assert_eq!(
+            cmd,
+            Command::Server(ServerCommand::Info {
+                sections: vec![Bytes::from("stats"), Bytes::from("keyspace")]
+            })
+        );
+        assert_eq!(Command::from_frame(&cmd.to_frame()), Ok(cmd));
+    }
+
+    #[test]
+    fn test_command_spec() {
+        let get = CommandSpec::lookup(b"get").unwrap();
+        assert_eq!(get.name, "GET");
+        assert!(get.has_flag(CommandFlag::Readonly));
+        assert!(!get.has_flag(CommandFlag::Write));
+        assert!(get.accepts_argc(2));
+        assert!(!get.accepts_argc(3));
+        assert_eq!(get.key_positions(2).collect::<Vec<_>>(), vec![1]);
+
+        let hello = CommandSpec::lookup(b"HELLO").unwrap();
+        assert!(hello.accepts_argc(1));
+        assert!(hello.accepts_argc(6));
+        assert_eq!(hello.key_positions(2).count(), 0);
+
+        assert!(CommandSpec::lookup(b"NOSUCHCOMMAND").is_none());
+    }
+
+    #[test]
+    fn test_key_positions() {
+        let spec = CommandSpec {
+            name: "MSET",
+            arity: -3,
+            flags: &[CommandFlag::Write],
+            first_key: 1,
+            last_key: -1,
+            key_step: 2,
+            parse: |_| Err(CommandError::Syntax),
+        };
+        assert_eq!(spec.key_positions(7).collect::<Vec<_>>(), vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        assert_eq!(parse_integer(b"0"), Some(0));
+        assert_eq!(parse_integer(b"-42"), Some(-42));
+        assert_eq!(parse_integer(b"9223372036854775807"), Some(i64::MAX));
+        assert_eq!(parse_integer(b"9223372036854775808"), None);
+        assert_eq!(parse_integer(b"007"), None);
+        assert_eq!(parse_integer(b"+1"), None);
+        assert_eq!(parse_integer(b" 1"), None);
+        assert_eq!(parse_integer(b"-0"), None);
+        assert_eq!(parse_integer(b""), None);
+
+        assert_eq!(parse_float(b"1.5"), Some(1.5));
+        assert_eq!(parse_float(b"-inf"), Some(f64::NEG_INFINITY));
+        assert_eq!(parse_float(b"1e3"), Some(1000.0));
+        assert_eq!(parse_float(b"nan"), None);
+        assert_eq!(parse_float(b"1.5 "), None);
+        assert_eq!(parse_float(b"abc"), None);
+    }
+}
@@ -0,0 +1,266 @@
+//! Commands that publish messages to channels and subscribe connections to
+//! channels and patterns
+//!
+//! Shard channels are declared as keys in the command table, so that they are
+//! assigned hash slots the same way keys are.
+use super::key::{multi_key, single_key};
+use super::{key_slot, Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PubSubCommand {
+    Subscribe {
+        channels: Vec<Bytes>,
+    },
+    /// Unsubscribe from the channels, or from every channel if none are given
+    Unsubscribe {
+        channels: Vec<Bytes>,
+    },
+    PSubscribe {
+        patterns: Vec<Bytes>,
+    },
+    /// Unsubscribe from the patterns, or from every pattern if none are given
+    PUnsubscribe {
+        patterns: Vec<Bytes>,
+    },
+    Publish {
+        channel: Bytes,
+        message: Bytes,
+    },
+    /// List the channels with subscribers, limited to those matching the
+    /// pattern if there is one
+    Channels {
+        pattern: Option<Bytes>,
+    },
+    /// Count the subscribers of each channel, leaving out pattern subscribers
+    NumSub {
+        channels: Vec<Bytes>,
+    },
+    /// Count the patterns subscribed to
+    NumPat,
+    /// Subscribe to shard channels, which must all hash to the same slot
+    SSubscribe {
+        channels: Vec<Bytes>,
+    },
+    /// Unsubscribe from the shard channels, which must all hash to the same
+    /// slot, or from every shard channel if none are given
+    SUnsubscribe {
+        channels: Vec<Bytes>,
+    },
+    /// Publish a message to the subscribers of a shard channel. Patterns do
+    /// not apply to shard channels.
+    SPublish {
+        channel: Bytes,
+        message: Bytes,
+    },
+    /// List the shard channels with subscribers, limited to those matching
+    /// the pattern if there is one
+    ShardChannels {
+        pattern: Option<Bytes>,
+    },
+    /// Count the subscribers of each shard channel
+    ShardNumSub {
+        channels: Vec<Bytes>,
+    },
+    Help,
+}
+
+const PUBSUB: &[CommandFlag] = &[CommandFlag::PubSub];
+const PUBSUB_FAST: &[CommandFlag] = &[CommandFlag::PubSub, CommandFlag::Fast];
+
+/// Describe a command that takes no keys: channels are not keys
+const fn no_keys(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [CommandFlag],
+    parse: fn(&mut CommandArgs) -> Result<Command, CommandError>,
+) -> CommandSpec {
+    return CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        parse,
+    };
+}
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    no_keys("PSUBSCRIBE", -2, PUBSUB, parse_psubscribe),
+    no_keys("PUBLISH", 3, PUBSUB_FAST, parse_publish),
+    no_keys("PUBSUB", -2, PUBSUB, parse_pubsub),
+    no_keys("PUNSUBSCRIBE", -1, PUBSUB, parse_punsubscribe),
+    single_key("SPUBLISH", 3, PUBSUB_FAST, parse_spublish),
+    multi_key("SSUBSCRIBE", -2, PUBSUB, 1, parse_ssubscribe),
+    no_keys("SUBSCRIBE", -2, PUBSUB, parse_subscribe),
+    multi_key("SUNSUBSCRIBE", -1, PUBSUB, 1, parse_sunsubscribe),
+    no_keys("UNSUBSCRIBE", -1, PUBSUB, parse_unsubscribe),
+];
+
+impl PubSubCommand {
+    /// Return whether the command changes the subscriptions of the
+    /// connection
+    pub fn is_subscription(&self) -> bool {
+        return matches!(
+            self,
+            Self::Subscribe { .. }
+                | Self::Unsubscribe { .. }
+                | Self::PSubscribe { .. }
+                | Self::PUnsubscribe { .. }
+                | Self::SSubscribe { .. }
+                | Self::SUnsubscribe { .. }
+        );
+    }
+
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let with = |name: &'static str, rest: &[Bytes]| {
+            let mut args = vec![Bytes::from(name)];
+            args.extend(rest.iter().cloned());
+            return args;
+        };
+        return match self {
+            Self::Subscribe { channels } => with("SUBSCRIBE", channels),
+            Self::Unsubscribe { channels } => with("UNSUBSCRIBE", channels),
+            Self::PSubscribe { patterns } => with("PSUBSCRIBE", patterns),
+            Self::PUnsubscribe { patterns } => with("PUNSUBSCRIBE", patterns),
+            Self::Publish { channel, message } => {
+                vec![Bytes::from("PUBLISH"), channel.clone(), message.clone()]
+            }
+            Self::Channels { pattern } => {
+                let mut args = vec![Bytes::from("PUBSUB"), Bytes::from("CHANNELS")];
+                args.extend(pattern.iter().cloned());
+                args
+            }
+            Self::NumSub { channels } => {
+                let mut args = vec![Bytes::from("PUBSUB"), Bytes::from("NUMSUB")];
+                args.extend(channels.iter().cloned());
+                args
+            }
+            Self::NumPat => vec![Bytes::from("PUBSUB"), Bytes::from("NUMPAT")],
+            Self::SSubscribe { channels } => with("SSUBSCRIBE", channels),
+            Self::SUnsubscribe { channels } => with("SUNSUBSCRIBE", channels),
+            Self::SPublish { channel, message } => {
+                vec![Bytes::from("SPUBLISH"), channel.clone(), message.clone()]
+            }
+            Self::ShardChannels { pattern } => {
+                let mut args = vec![Bytes::from("PUBSUB"), Bytes::from("SHARDCHANNELS")];
+                args.extend(pattern.iter().cloned());
+                args
+            }
+            Self::ShardNumSub { channels } => {
+                let mut args = vec![Bytes::from("PUBSUB"), Bytes::from("SHARDNUMSUB")];
+                args.extend(channels.iter().cloned());
+                args
+            }
+            Self::Help => vec![Bytes::from("PUBSUB"), Bytes::from("HELP")],
+        };
+    }
+}
+
+/// SUBSCRIBE channel [channel ...]
+fn parse_subscribe(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::PubSub(PubSubCommand::Subscribe {
+        channels: args.rest(),
+    }));
+}
+
+/// UNSUBSCRIBE [channel [channel ...]]
+fn parse_unsubscribe(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::PubSub(PubSubCommand::Unsubscribe {
+        channels: args.rest(),
+    }));
+}
+
+/// PSUBSCRIBE pattern [pattern ...]
+fn parse_psubscribe(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::PubSub(PubSubCommand::PSubscribe {
+        patterns: args.rest(),
+    }));
+}
+
+/// PUNSUBSCRIBE [pattern [pattern ...]]
+fn parse_punsubscribe(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::PubSub(PubSubCommand::PUnsubscribe {
+        patterns: args.rest(),
+    }));
+}
+
+/// PUBLISH channel message
+fn parse_publish(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::PubSub(PubSubCommand::Publish {
+        channel: args.next_bytes()?,
+        message: args.next_bytes()?,
+    }));
+}
+
+/// Check that shard channels all hash to the same slot, so that a subscriber
+/// only ever hears from the shard that owns the slot
+fn same_slot(channels: &[Bytes]) -> Result<(), CommandError> {
+    let mut slots = channels.iter().map(|channel| key_slot(channel));
+    if let Some(first) = slots.next() {
+        if slots.any(|slot| slot != first) {
+            return Err(CommandError::Other(
+                "CROSSSLOT Keys in request don't hash to the same slot".into(),
+            ));
+        }
+    }
+    return Ok(());
+}
+
+/// SSUBSCRIBE shardchannel [shardchannel ...]
+fn parse_ssubscribe(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let channels = args.rest();
+    same_slot(&channels)?;
+    return Ok(Command::PubSub(PubSubCommand::SSubscribe { channels }));
+}
+
+/// SUNSUBSCRIBE [shardchannel [shardchannel ...]]
+fn parse_sunsubscribe(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let channels = args.rest();
+    same_slot(&channels)?;
+    return Ok(Command::PubSub(PubSubCommand::SUnsubscribe { channels }));
+}
+
+/// SPUBLISH shardchannel message
+fn parse_spublish(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::PubSub(PubSubCommand::SPublish {
+        channel: args.next_bytes()?,
+        message: args.next_bytes()?,
+    }));
+}
+
+/// PUBSUB CHANNELS [pattern] | NUMSUB [channel [channel ...]] | NUMPAT |
+/// SHARDCHANNELS [pattern] | SHARDNUMSUB [shardchannel [shardchannel ...]], or
+/// PUBSUB HELP
+fn parse_pubsub(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let name = args.next_bytes()?;
+    let cmd = match name.to_ascii_uppercase().as_slice() {
+        b"HELP" if args.remaining() == 0 => PubSubCommand::Help,
+        b"HELP" => return Err(CommandError::WrongArity("PUBSUB|HELP")),
+        b"CHANNELS" if args.remaining() <= 1 => PubSubCommand::Channels {
+            pattern: args.rest().pop(),
+        },
+        b"CHANNELS" => return Err(CommandError::WrongArity("PUBSUB|CHANNELS")),
+        b"NUMSUB" => PubSubCommand::NumSub {
+            channels: args.rest(),
+        },
+        b"NUMPAT" if args.remaining() == 0 => PubSubCommand::NumPat,
+        b"NUMPAT" => return Err(CommandError::WrongArity("PUBSUB|NUMPAT")),
+        b"SHARDCHANNELS" if args.remaining() <= 1 => PubSubCommand::ShardChannels {
+            pattern: args.rest().pop(),
+        },
+        b"SHARDCHANNELS" => return Err(CommandError::WrongArity("PUBSUB|SHARDCHANNELS")),
+        b"SHARDNUMSUB" => PubSubCommand::ShardNumSub {
+            channels: args.rest(),
+        },
+        _ => {
+            return Err(CommandError::Other(format!(
+                "ERR unknown subcommand '{}'. Try PUBSUB HELP.",
+                String::from_utf8_lossy(&name)
+            )))
+        }
+    };
+    return Ok(Command::PubSub(cmd));
+}
@@ -0,0 +1,39 @@
+//! Commands that manage and inspect the server rather than the data
+use super::{Command, CommandArgs, CommandError, CommandSpec};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerCommand {
+    /// Describe the server, limited to the named sections if there are any
+    Info { sections: Vec<Bytes> },
+}
+
+pub(super) const COMMANDS: &[CommandSpec] = &[CommandSpec {
+    name: "INFO",
+    arity: -1,
+    flags: &[],
+    first_key: 0,
+    last_key: 0,
+    key_step: 0,
+    parse: parse_info,
+}];
+
+impl ServerCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        return match self {
+            Self::Info { sections } => {
+                let mut args = vec![Bytes::from("INFO")];
+                args.extend(sections.iter().cloned());
+                args
+            }
+        };
+    }
+}
+
+/// INFO [section [section ...]]
+fn parse_info(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::Server(ServerCommand::Info {
+        sections: args.rest(),
+    }));
+}
@@ -0,0 +1,374 @@
+//! Commands that operate on set values
+use super::hash::parse_cursor;
+use super::key::{multi_key, single_key};
+use super::{Command, CommandArgs, CommandError, CommandFlag, CommandSpec, DEFAULT_SCAN_COUNT};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetCommand {
+    /// SADD, which replies with the number of new members
+    Add {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Rem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    IsMember {
+        key: Bytes,
+        member: Bytes,
+    },
+    MIsMember {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Card {
+        key: Bytes,
+    },
+    Members {
+        key: Bytes,
+    },
+    /// Remove and return random members. Without a count, the reply is a
+    /// single member rather than an array.
+    Pop {
+        key: Bytes,
+        count: Option<usize>,
+    },
+    /// Return random members. Without a count, the reply is a single member
+    /// rather than an array. A positive count returns distinct members, a
+    /// negative one may return the same member several times.
+    RandMember {
+        key: Bytes,
+        count: Option<i64>,
+    },
+    Move {
+        source: Bytes,
+        destination: Bytes,
+        member: Bytes,
+    },
+    /// SINTER, SUNION and SDIFF
+    Combine {
+        op: SetOp,
+        keys: Vec<Bytes>,
+    },
+    /// SINTERSTORE, SUNIONSTORE and SDIFFSTORE, which store the result under
+    /// the destination and reply with its size
+    Store {
+        op: SetOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    /// The size of the intersection, counting no further than `limit` unless
+    /// it is 0
+    InterCard {
+        keys: Vec<Bytes>,
+        limit: usize,
+    },
+    Scan {
+        key: Bytes,
+        cursor: u64,
+        pattern: Option<Bytes>,
+        count: usize,
+    },
+}
+
+/// How the sets of SINTER, SUNION, SDIFF and their STORE variants combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    /// The members of every set
+    Inter,
+    /// The members of any set
+    Union,
+    /// The members of the first set that are in none of the others
+    Diff,
+}
+
+impl SetOp {
+    fn name(&self) -> &'static str {
+        return match self {
+            Self::Inter => "SINTER",
+            Self::Union => "SUNION",
+            Self::Diff => "SDIFF",
+        };
+    }
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("SADD", -3, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let members = args.rest();
+        return Ok(Command::Set(SetCommand::Add { key, members }));
+    }),
+    single_key("SREM", -3, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let members = args.rest();
+        return Ok(Command::Set(SetCommand::Rem { key, members }));
+    }),
+    single_key("SISMEMBER", 3, READONLY_FAST, |args| {
+        let (key, member) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::Set(SetCommand::IsMember { key, member }));
+    }),
+    single_key("SMISMEMBER", -3, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        let members = args.rest();
+        return Ok(Command::Set(SetCommand::MIsMember { key, members }));
+    }),
+    single_key("SCARD", 2, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::Set(SetCommand::Card { key }));
+    }),
+    single_key("SMEMBERS", 2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::Set(SetCommand::Members { key }));
+    }),
+    single_key("SPOP", -2, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let count = match args.remaining() {
+            0 => None,
+            _ => Some(args.next_count()?),
+        };
+        args.finish()?;
+        return Ok(Command::Set(SetCommand::Pop { key, count }));
+    }),
+    single_key("SRANDMEMBER", -2, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let mut count = None;
+        if args.remaining() > 0 {
+            let n = args.next_integer()?;
+            // The magnitude of the count must fit, as it may be negated
+            if n == i64::MIN {
+                return Err(CommandError::Other("ERR value is out of range".into()));
+            }
+            count = Some(n);
+        }
+        args.finish()?;
+        return Ok(Command::Set(SetCommand::RandMember { key, count }));
+    }),
+    CommandSpec {
+        name: "SMOVE",
+        arity: 4,
+        flags: WRITE_FAST,
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        parse: |args| {
+            let (source, destination) = (args.next_bytes()?, args.next_bytes()?);
+            let member = args.next_bytes()?;
+            return Ok(Command::Set(SetCommand::Move {
+                source,
+                destination,
+                member,
+            }));
+        },
+    },
+    multi_key("SINTER", -2, READONLY, 1, |args| {
+        let keys = args.rest();
+        return Ok(Command::Set(SetCommand::Combine {
+            op: SetOp::Inter,
+            keys,
+        }));
+    }),
+    multi_key("SUNION", -2, READONLY, 1, |args| {
+        let keys = args.rest();
+        return Ok(Command::Set(SetCommand::Combine {
+            op: SetOp::Union,
+            keys,
+        }));
+    }),
+    multi_key("SDIFF", -2, READONLY, 1, |args| {
+        let keys = args.rest();
+        return Ok(Command::Set(SetCommand::Combine {
+            op: SetOp::Diff,
+            keys,
+        }));
+    }),
+    multi_key("SINTERSTORE", -3, WRITE, 1, |args| {
+        parse_store(args, SetOp::Inter)
+    }),
+    multi_key("SUNIONSTORE", -3, WRITE, 1, |args| {
+        parse_store(args, SetOp::Union)
+    }),
+    multi_key("SDIFFSTORE", -3, WRITE, 1, |args| {
+        parse_store(args, SetOp::Diff)
+    }),
+    // The keys of SINTERCARD follow their count, so the table cannot describe
+    // where they are
+    CommandSpec {
+        name: "SINTERCARD",
+        arity: -3,
+        flags: READONLY,
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        parse: parse_intercard,
+    },
+    single_key("SSCAN", -3, READONLY, parse_scan),
+];
+
+impl SetCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let name = |name: &'static str, key: &Bytes| vec![Bytes::from(name), key.clone()];
+        return match self {
+            Self::Add { key, members }
+            | Self::Rem { key, members }
+            | Self::MIsMember { key, members } => {
+                let mut args = name(
+                    match self {
+                        Self::Add { .. } => "SADD",
+                        Self::Rem { .. } => "SREM",
+                        _ => "SMISMEMBER",
+                    },
+                    key,
+                );
+                args.extend(members.iter().cloned());
+                args
+            }
+            Self::IsMember { key, member } => {
+                vec![Bytes::from("SISMEMBER"), key.clone(), member.clone()]
+            }
+            Self::Card { key } => name("SCARD", key),
+            Self::Members { key } => name("SMEMBERS", key),
+            Self::Pop { key, count } => {
+                let mut args = name("SPOP", key);
+                if let Some(count) = count {
+                    args.push(Bytes::from(count.to_string()));
+                }
+                args
+            }
+            Self::RandMember { key, count } => {
+                let mut args = name("SRANDMEMBER", key);
+                if let Some(count) = count {
+                    args.push(Bytes::from(count.to_string()));
+                }
+                args
+            }
+            Self::Move {
+                source,
+                destination,
+                member,
+            } => vec![
+                Bytes::from("SMOVE"),
+                source.clone(),
+                destination.clone(),
+                member.clone(),
+            ],
+            Self::Combine { op, keys } => {
+                let mut args = vec![Bytes::from(op.name())];
+                args.extend(keys.iter().cloned());
+                args
+            }
+            Self::Store {
+                op,
+                destination,
+                keys,
+            } => {
+                let mut args = vec![
+                    Bytes::from(format!("{}STORE", op.name())),
+                    destination.clone(),
+                ];
+                args.extend(keys.iter().cloned());
+                args
+            }
+            Self::InterCard { keys, limit } => {
+                let mut args = vec![
+                    Bytes::from("SINTERCARD"),
+                    Bytes::from(keys.len().to_string()),
+                ];
+                args.extend(keys.iter().cloned());
+                if *limit != 0 {
+                    args.extend([Bytes::from("LIMIT"), Bytes::from(limit.to_string())]);
+                }
+                args
+            }
+            Self::Scan {
+                key,
+                cursor,
+                pattern,
+                count,
+            } => {
+                let mut args = name("SSCAN", key);
+                args.push(Bytes::from(cursor.to_string()));
+                if let Some(pattern) = pattern {
+                    args.extend([Bytes::from("MATCH"), pattern.clone()]);
+                }
+                if *count != DEFAULT_SCAN_COUNT {
+                    args.extend([Bytes::from("COUNT"), Bytes::from(count.to_string())]);
+                }
+                args
+            }
+        };
+    }
+}
+
+/// SINTERSTORE destination key [key ...], and the same for SUNIONSTORE and
+/// SDIFFSTORE
+fn parse_store(args: &mut CommandArgs, op: SetOp) -> Result<Command, CommandError> {
+    let destination = args.next_bytes()?;
+    let keys = args.rest();
+    return Ok(Command::Set(SetCommand::Store {
+        op,
+        destination,
+        keys,
+    }));
+}
+
+/// SINTERCARD numkeys key [key ...] [LIMIT limit]
+fn parse_intercard(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let numkeys = args.next_integer()?;
+    if numkeys <= 0 {
+        return Err(CommandError::Other(
+            "ERR numkeys should be greater than 0".into(),
+        ));
+    }
+    if numkeys as usize > args.remaining() {
+        return Err(CommandError::Other(
+            "ERR Number of keys can't be greater than number of args".into(),
+        ));
+    }
+    let keys = (0..numkeys)
+        .map(|_| args.next_bytes())
+        .collect::<Result<_, _>>()?;
+    let mut limit = 0;
+    while args.remaining() > 0 {
+        if args.next_keyword()? != "LIMIT" {
+            return Err(CommandError::Syntax);
+        }
+        limit = match args.next_integer()? {
+            n if n >= 0 => n as usize,
+            _ => return Err(CommandError::Other("ERR LIMIT can't be negative".into())),
+        };
+    }
+    return Ok(Command::Set(SetCommand::InterCard { keys, limit }));
+}
+
+/// SSCAN key cursor [MATCH pattern] [COUNT count]
+fn parse_scan(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let cursor = parse_cursor(&args.next_bytes()?)?;
+    let (mut pattern, mut count) = (None, DEFAULT_SCAN_COUNT);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "MATCH" => pattern = Some(args.next_bytes()?),
+            "COUNT" => {
+                count = match args.next_integer()? {
+                    n if n > 0 => n as usize,
+                    _ => return Err(CommandError::Syntax),
+                };
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::Set(SetCommand::Scan {
+        key,
+        cursor,
+        pattern,
+        count,
+    }));
+}
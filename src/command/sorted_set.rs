@@ -0,0 +1,809 @@
+//! Commands that operate on sorted set values
+use super::hash::parse_cursor;
+use super::key::single_key;
+use super::list::{parse_timeout, timeout_arg};
+use super::string::SetCondition;
+use super::{
+    parse_float, parse_integer, Command, CommandArgs, CommandError, CommandFlag, CommandSpec,
+    DEFAULT_SCAN_COUNT,
+};
+use crate::format_double;
+use bytes::Bytes;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortedSetCommand {
+    /// ZADD, which replies with the number of new members, or of changed
+    /// members with CH. With INCR it behaves like ZINCRBY and replies with the
+    /// new score, or Null if a condition prevented the update.
+    Add {
+        key: Bytes,
+        /// NX only adds new members, XX only updates existing ones
+        condition: Option<SetCondition>,
+        /// GT and LT only update a score to a greater or a lesser one
+        comparison: Option<Comparison>,
+        changed: bool,
+        incr: bool,
+        pairs: Vec<(f64, Bytes)>,
+    },
+    Rem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Score {
+        key: Bytes,
+        member: Bytes,
+    },
+    MScore {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    IncrBy {
+        key: Bytes,
+        delta: f64,
+        member: Bytes,
+    },
+    Card {
+        key: Bytes,
+    },
+    Count {
+        key: Bytes,
+        min: ScoreBound,
+        max: ScoreBound,
+    },
+    /// ZRANK, and ZREVRANK which ranks from the highest score
+    Rank {
+        key: Bytes,
+        member: Bytes,
+        rev: bool,
+        with_score: bool,
+    },
+    Range {
+        key: Bytes,
+        range: Range,
+        rev: bool,
+        limit: Option<Limit>,
+        with_scores: bool,
+    },
+    /// ZRANGESTORE, which stores the range under the destination and replies
+    /// with its size
+    RangeStore {
+        destination: Bytes,
+        key: Bytes,
+        range: Range,
+        rev: bool,
+        limit: Option<Limit>,
+    },
+    /// ZPOPMIN and ZPOPMAX. The reply is a flat array of members and scores,
+    /// with a single pair if there is no count.
+    Pop {
+        key: Bytes,
+        end: End,
+        count: Option<usize>,
+    },
+    /// BZPOPMIN and BZPOPMAX: pop from the first of the keys that holds a
+    /// sorted set, waiting for one to be added if there is none
+    BPop {
+        keys: Vec<Bytes>,
+        end: End,
+        /// How long to wait, None to wait forever
+        timeout: Option<Duration>,
+    },
+    /// ZUNIONSTORE and ZINTERSTORE
+    Store {
+        op: StoreOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+        /// The factor each key's scores are multiplied by, one per key
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+    },
+    Scan {
+        key: Bytes,
+        cursor: u64,
+        pattern: Option<Bytes>,
+        count: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Gt,
+    Lt,
+}
+
+/// The members ZRANGE selects, before REV and LIMIT apply
+#[derive(Debug, Clone, PartialEq)]
+pub enum Range {
+    /// Members by position in score order, either of which may count from
+    /// the end
+    Rank {
+        start: i64,
+        stop: i64,
+    },
+    Score {
+        min: ScoreBound,
+        max: ScoreBound,
+    },
+    /// Members between two strings, for sorted sets whose members all have
+    /// the same score
+    Lex {
+        min: LexBound,
+        max: LexBound,
+    },
+}
+
+/// One end of a score range, such as "1.5", "(1.5" or "-inf"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+/// One end of a lexicographical range: "-", "+", "[member" or "(member"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    /// Before every member
+    Min,
+    /// After every member
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+/// The LIMIT of a ZRANGE by score or by member. A negative count returns
+/// every member after the offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub offset: i64,
+    pub count: i64,
+}
+
+/// The end of a sorted set ZPOPMIN and ZPOPMAX pop from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOp {
+    Union,
+    Inter,
+}
+
+/// How ZUNIONSTORE and ZINTERSTORE combine the scores of a member found under
+/// several keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl ScoreBound {
+    /// Parse a score bound, which is exclusive if it starts with "("
+    fn parse(arg: &[u8]) -> Result<Self, CommandError> {
+        let (score, exclusive) = match arg.strip_prefix(b"(") {
+            Some(score) => (score, true),
+            None => (arg, false),
+        };
+        let score = parse_float(score)
+            .ok_or_else(|| CommandError::Other("ERR min or max is not a float".into()))?;
+        return Ok(Self { score, exclusive });
+    }
+
+    fn to_arg(self) -> Bytes {
+        let score = format_double(self.score);
+        return Bytes::from(match self.exclusive {
+            true => format!("({score}"),
+            false => score,
+        });
+    }
+}
+
+impl LexBound {
+    fn parse(arg: &Bytes) -> Result<Self, CommandError> {
+        return match arg.first() {
+            Some(b'-') if arg.len() == 1 => Ok(Self::Min),
+            Some(b'+') if arg.len() == 1 => Ok(Self::Max),
+            Some(b'[') => Ok(Self::Inclusive(arg.slice(1..))),
+            Some(b'(') => Ok(Self::Exclusive(arg.slice(1..))),
+            _ => Err(CommandError::Other(
+                "ERR min or max not valid string range item".into(),
+            )),
+        };
+    }
+
+    fn to_arg(&self) -> Bytes {
+        let (prefix, member) = match self {
+            Self::Min => return Bytes::from("-"),
+            Self::Max => return Bytes::from("+"),
+            Self::Inclusive(member) => (b'[', member),
+            Self::Exclusive(member) => (b'(', member),
+        };
+        let mut arg = vec![prefix];
+        arg.extend_from_slice(member);
+        return Bytes::from(arg);
+    }
+}
+
+impl Aggregate {
+    /// Combine the score found so far with the next one
+    pub fn apply(&self, acc: f64, score: f64) -> f64 {
+        return match self {
+            // inf plus -inf is NaN, which Redis turns into 0
+            Self::Sum if (acc + score).is_nan() => 0.0,
+            Self::Sum => acc + score,
+            Self::Min => acc.min(score),
+            Self::Max => acc.max(score),
+        };
+    }
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+const WRITE_BLOCKING: &[CommandFlag] =
+    &[CommandFlag::Write, CommandFlag::Fast, CommandFlag::Blocking];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("ZADD", -4, WRITE_FAST, parse_add),
+    single_key("ZREM", -3, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let members = args.rest();
+        return Ok(Command::SortedSet(SortedSetCommand::Rem { key, members }));
+    }),
+    single_key("ZSCORE", 3, READONLY_FAST, |args| {
+        let (key, member) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::SortedSet(SortedSetCommand::Score { key, member }));
+    }),
+    single_key("ZMSCORE", -3, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        let members = args.rest();
+        return Ok(Command::SortedSet(SortedSetCommand::MScore {
+            key,
+            members,
+        }));
+    }),
+    single_key("ZINCRBY", 4, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let delta = args.next_float()?;
+        let member = args.next_bytes()?;
+        return Ok(Command::SortedSet(SortedSetCommand::IncrBy {
+            key,
+            delta,
+            member,
+        }));
+    }),
+    single_key("ZCARD", 2, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::SortedSet(SortedSetCommand::Card { key }));
+    }),
+    single_key("ZCOUNT", 4, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        let min = ScoreBound::parse(&args.next_bytes()?)?;
+        let max = ScoreBound::parse(&args.next_bytes()?)?;
+        return Ok(Command::SortedSet(SortedSetCommand::Count {
+            key,
+            min,
+            max,
+        }));
+    }),
+    single_key("ZRANK", -3, READONLY_FAST, |args| parse_rank(args, false)),
+    single_key("ZREVRANK", -3, READONLY_FAST, |args| parse_rank(args, true)),
+    single_key("ZRANGE", -4, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let (range, rev, limit, with_scores) = parse_range(args, true)?;
+        return Ok(Command::SortedSet(SortedSetCommand::Range {
+            key,
+            range,
+            rev,
+            limit,
+            with_scores,
+        }));
+    }),
+    CommandSpec {
+        name: "ZRANGESTORE",
+        arity: -5,
+        flags: WRITE,
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        parse: |args| {
+            let (destination, key) = (args.next_bytes()?, args.next_bytes()?);
+            let (range, rev, limit, _) = parse_range(args, false)?;
+            return Ok(Command::SortedSet(SortedSetCommand::RangeStore {
+                destination,
+                key,
+                range,
+                rev,
+                limit,
+            }));
+        },
+    },
+    single_key("ZPOPMIN", -2, WRITE_FAST, |args| parse_pop(args, End::Min)),
+    single_key("ZPOPMAX", -2, WRITE_FAST, |args| parse_pop(args, End::Max)),
+    CommandSpec {
+        name: "BZPOPMIN",
+        arity: -3,
+        flags: WRITE_BLOCKING,
+        first_key: 1,
+        last_key: -2,
+        key_step: 1,
+        parse: |args| parse_bpop(args, End::Min),
+    },
+    CommandSpec {
+        name: "BZPOPMAX",
+        arity: -3,
+        flags: WRITE_BLOCKING,
+        first_key: 1,
+        last_key: -2,
+        key_step: 1,
+        parse: |args| parse_bpop(args, End::Max),
+    },
+    // Like Redis, the table only describes the destination, as the other keys
+    // follow their count
+    single_key("ZUNIONSTORE", -4, WRITE, |args| {
+        parse_store(args, StoreOp::Union)
+    }),
+    single_key("ZINTERSTORE", -4, WRITE, |args| {
+        parse_store(args, StoreOp::Inter)
+    }),
+    single_key("ZSCAN", -3, READONLY, parse_scan),
+];
+
+impl SortedSetCommand {
+    /// Return whether the command waits for data when none of its keys hold
+    /// any. Such commands reply as if they timed out right away unless they
+    /// are executed through DB::block.
+    pub fn is_blocking(&self) -> bool {
+        return matches!(self, Self::BPop { .. });
+    }
+
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let name = |name: &'static str, key: &Bytes| vec![Bytes::from(name), key.clone()];
+        let score = |score: f64| Bytes::from(format_double(score));
+        return match self {
+            Self::Add {
+                key,
+                condition,
+                comparison,
+                changed,
+                incr,
+                pairs,
+            } => {
+                let mut args = name("ZADD", key);
+                match condition {
+                    Some(SetCondition::Nx) => args.push(Bytes::from("NX")),
+                    Some(SetCondition::Xx) => args.push(Bytes::from("XX")),
+                    None => {}
+                }
+                match comparison {
+                    Some(Comparison::Gt) => args.push(Bytes::from("GT")),
+                    Some(Comparison::Lt) => args.push(Bytes::from("LT")),
+                    None => {}
+                }
+                if *changed {
+                    args.push(Bytes::from("CH"));
+                }
+                if *incr {
+                    args.push(Bytes::from("INCR"));
+                }
+                for (s, member) in pairs {
+                    args.extend([score(*s), member.clone()]);
+                }
+                args
+            }
+            Self::Rem { key, members } | Self::MScore { key, members } => {
+                let mut args = match self {
+                    Self::Rem { .. } => name("ZREM", key),
+                    _ => name("ZMSCORE", key),
+                };
+                args.extend(members.iter().cloned());
+                args
+            }
+            Self::Score { key, member } => {
+                vec![Bytes::from("ZSCORE"), key.clone(), member.clone()]
+            }
+            Self::IncrBy { key, delta, member } => {
+                vec![
+                    Bytes::from("ZINCRBY"),
+                    key.clone(),
+                    score(*delta),
+                    member.clone(),
+                ]
+            }
+            Self::Card { key } => name("ZCARD", key),
+            Self::Count { key, min, max } => {
+                let mut args = name("ZCOUNT", key);
+                args.extend([min.to_arg(), max.to_arg()]);
+                args
+            }
+            Self::Rank {
+                key,
+                member,
+                rev,
+                with_score,
+            } => {
+                let mut args = match rev {
+                    false => name("ZRANK", key),
+                    true => name("ZREVRANK", key),
+                };
+                args.push(member.clone());
+                if *with_score {
+                    args.push(Bytes::from("WITHSCORE"));
+                }
+                args
+            }
+            Self::Range {
+                key,
+                range,
+                rev,
+                limit,
+                with_scores,
+            } => {
+                let mut args = name("ZRANGE", key);
+                args.extend(range_args(range, *rev, limit));
+                if *with_scores {
+                    args.push(Bytes::from("WITHSCORES"));
+                }
+                args
+            }
+            Self::RangeStore {
+                destination,
+                key,
+                range,
+                rev,
+                limit,
+            } => {
+                let mut args = vec![Bytes::from("ZRANGESTORE"), destination.clone(), key.clone()];
+                args.extend(range_args(range, *rev, limit));
+                args
+            }
+            Self::Pop { key, end, count } => {
+                let mut args = match end {
+                    End::Min => name("ZPOPMIN", key),
+                    End::Max => name("ZPOPMAX", key),
+                };
+                if let Some(count) = count {
+                    args.push(Bytes::from(count.to_string()));
+                }
+                args
+            }
+            Self::BPop { keys, end, timeout } => {
+                let name = match end {
+                    End::Min => "BZPOPMIN",
+                    End::Max => "BZPOPMAX",
+                };
+                let mut args = vec![Bytes::from(name)];
+                args.extend(keys.iter().cloned());
+                args.push(timeout_arg(*timeout));
+                args
+            }
+            Self::Store {
+                op,
+                destination,
+                keys,
+                weights,
+                aggregate,
+            } => {
+                let name = match op {
+                    StoreOp::Union => "ZUNIONSTORE",
+                    StoreOp::Inter => "ZINTERSTORE",
+                };
+                let mut args = vec![
+                    Bytes::from(name),
+                    destination.clone(),
+                    Bytes::from(keys.len().to_string()),
+                ];
+                args.extend(keys.iter().cloned());
+                if weights.iter().any(|weight| *weight != 1.0) {
+                    args.push(Bytes::from("WEIGHTS"));
+                    args.extend(weights.iter().map(|weight| score(*weight)));
+                }
+                match aggregate {
+                    Aggregate::Sum => {}
+                    Aggregate::Min => args.extend([Bytes::from("AGGREGATE"), Bytes::from("MIN")]),
+                    Aggregate::Max => args.extend([Bytes::from("AGGREGATE"), Bytes::from("MAX")]),
+                }
+                args
+            }
+            Self::Scan {
+                key,
+                cursor,
+                pattern,
+                count,
+            } => {
+                let mut args = name("ZSCAN", key);
+                args.push(Bytes::from(cursor.to_string()));
+                if let Some(pattern) = pattern {
+                    args.extend([Bytes::from("MATCH"), pattern.clone()]);
+                }
+                if *count != DEFAULT_SCAN_COUNT {
+                    args.extend([Bytes::from("COUNT"), Bytes::from(count.to_string())]);
+                }
+                args
+            }
+        };
+    }
+}
+
+/// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+fn parse_add(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let (mut nx, mut xx, mut gt, mut lt, mut changed, mut incr) =
+        (false, false, false, false, false, false);
+    let mut rest = args.rest().into_iter().peekable();
+    while let Some(arg) = rest.peek() {
+        match String::from_utf8_lossy(arg).to_ascii_uppercase().as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            "CH" => changed = true,
+            "INCR" => incr = true,
+            _ => break,
+        }
+        rest.next();
+    }
+    let rest: Vec<Bytes> = rest.collect();
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err(CommandError::Syntax);
+    }
+    if nx && xx {
+        return Err(CommandError::Other(
+            "ERR XX and NX options at the same time are not compatible".into(),
+        ));
+    }
+    if [nx, gt, lt].iter().filter(|option| **option).count() > 1 {
+        return Err(CommandError::Other(
+            "ERR GT, LT, and/or NX options at the same time are not compatible".into(),
+        ));
+    }
+    if incr && rest.len() > 2 {
+        return Err(CommandError::Other(
+            "ERR INCR option supports a single increment-element pair".into(),
+        ));
+    }
+    let pairs = rest
+        .chunks(2)
+        .map(|pair| {
+            let score = parse_float(&pair[0]).ok_or(CommandError::NotFloat)?;
+            return Ok((score, pair[1].clone()));
+        })
+        .collect::<Result<Vec<_>, CommandError>>()?;
+    let condition = match (nx, xx) {
+        (true, _) => Some(SetCondition::Nx),
+        (_, true) => Some(SetCondition::Xx),
+        _ => None,
+    };
+    let comparison = match (gt, lt) {
+        (true, _) => Some(Comparison::Gt),
+        (_, true) => Some(Comparison::Lt),
+        _ => None,
+    };
+    return Ok(Command::SortedSet(SortedSetCommand::Add {
+        key,
+        condition,
+        comparison,
+        changed,
+        incr,
+        pairs,
+    }));
+}
+
+/// ZRANK key member [WITHSCORE], and the same for ZREVRANK
+fn parse_rank(args: &mut CommandArgs, rev: bool) -> Result<Command, CommandError> {
+    let (key, member) = (args.next_bytes()?, args.next_bytes()?);
+    let with_score = match args.remaining() {
+        0 => false,
+        _ if args.next_keyword()? == "WITHSCORE" => true,
+        _ => return Err(CommandError::Syntax),
+    };
+    args.finish()?;
+    return Ok(Command::SortedSet(SortedSetCommand::Rank {
+        key,
+        member,
+        rev,
+        with_score,
+    }));
+}
+
+/// The part of ZRANGE and ZRANGESTORE after the key:
+/// start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES].
+/// With REV, the bounds of a score or lex range are given highest first.
+fn parse_range(
+    args: &mut CommandArgs,
+    allow_scores: bool,
+) -> Result<(Range, bool, Option<Limit>, bool), CommandError> {
+    let (start, stop) = (args.next_bytes()?, args.next_bytes()?);
+    let (mut by_score, mut by_lex, mut rev, mut limit, mut with_scores) =
+        (false, false, false, None, false);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "BYSCORE" => by_score = true,
+            "BYLEX" => by_lex = true,
+            "REV" => rev = true,
+            "LIMIT" => {
+                let offset = args.next_integer()?;
+                let count = args.next_integer()?;
+                limit = Some(Limit { offset, count });
+            }
+            "WITHSCORES" if allow_scores => with_scores = true,
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    if by_score && by_lex {
+        return Err(CommandError::Syntax);
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(CommandError::Other(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .into(),
+        ));
+    }
+    if with_scores && by_lex {
+        return Err(CommandError::Other(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX".into(),
+        ));
+    }
+    let (min, max) = match rev && (by_score || by_lex) {
+        true => (stop.clone(), start.clone()),
+        false => (start.clone(), stop.clone()),
+    };
+    let range = if by_score {
+        Range::Score {
+            min: ScoreBound::parse(&min)?,
+            max: ScoreBound::parse(&max)?,
+        }
+    } else if by_lex {
+        Range::Lex {
+            min: LexBound::parse(&min)?,
+            max: LexBound::parse(&max)?,
+        }
+    } else {
+        let start = parse_integer(&start).ok_or(CommandError::NotInteger)?;
+        let stop = parse_integer(&stop).ok_or(CommandError::NotInteger)?;
+        Range::Rank { start, stop }
+    };
+    return Ok((range, rev, limit, with_scores));
+}
+
+/// Convert a range back into the arguments parse_range reads
+fn range_args(range: &Range, rev: bool, limit: &Option<Limit>) -> Vec<Bytes> {
+    let mut args = match range {
+        Range::Rank { start, stop } => vec![
+            Bytes::from(start.to_string()),
+            Bytes::from(stop.to_string()),
+        ],
+        Range::Score { min, max } => {
+            let (min, max) = (min.to_arg(), max.to_arg());
+            match rev {
+                true => vec![max, min, Bytes::from("BYSCORE")],
+                false => vec![min, max, Bytes::from("BYSCORE")],
+            }
+        }
+        Range::Lex { min, max } => {
+            let (min, max) = (min.to_arg(), max.to_arg());
+            match rev {
+                true => vec![max, min, Bytes::from("BYLEX")],
+                false => vec![min, max, Bytes::from("BYLEX")],
+            }
+        }
+    };
+    if rev {
+        args.push(Bytes::from("REV"));
+    }
+    if let Some(Limit { offset, count }) = limit {
+        args.extend([
+            Bytes::from("LIMIT"),
+            Bytes::from(offset.to_string()),
+            Bytes::from(count.to_string()),
+        ]);
+    }
+    return args;
+}
+
+/// ZPOPMIN key [count], and the same for ZPOPMAX
+fn parse_pop(args: &mut CommandArgs, end: End) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let count = match args.remaining() {
+        0 => None,
+        _ => Some(args.next_count()?),
+    };
+    args.finish()?;
+    return Ok(Command::SortedSet(SortedSetCommand::Pop {
+        key,
+        end,
+        count,
+    }));
+}
+
+/// BZPOPMIN key [key ...] timeout, and the same for BZPOPMAX
+fn parse_bpop(args: &mut CommandArgs, end: End) -> Result<Command, CommandError> {
+    let mut keys = args.rest();
+    let timeout = parse_timeout(&keys.pop().ok_or(CommandError::Syntax)?)?;
+    return Ok(Command::SortedSet(SortedSetCommand::BPop {
+        keys,
+        end,
+        timeout,
+    }));
+}
+
+/// ZUNIONSTORE destination numkeys key [key ...] [WEIGHTS weight [weight ...]]
+/// [AGGREGATE SUM | MIN | MAX], and the same for ZINTERSTORE
+fn parse_store(args: &mut CommandArgs, op: StoreOp) -> Result<Command, CommandError> {
+    let destination = args.next_bytes()?;
+    let numkeys = args.next_integer()?;
+    if numkeys <= 0 {
+        let name = match op {
+            StoreOp::Union => "zunionstore",
+            StoreOp::Inter => "zinterstore",
+        };
+        return Err(CommandError::Other(format!(
+            "ERR at least 1 input key is needed for '{name}' command"
+        )));
+    }
+    if numkeys as usize > args.remaining() {
+        return Err(CommandError::Syntax);
+    }
+    let keys: Vec<Bytes> = (0..numkeys)
+        .map(|_| args.next_bytes())
+        .collect::<Result<_, _>>()?;
+    let (mut weights, mut aggregate) = (vec![1.0; keys.len()], Aggregate::Sum);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "WEIGHTS" if args.remaining() >= keys.len() => {
+                for weight in weights.iter_mut() {
+                    *weight = parse_float(&args.next_bytes()?).ok_or_else(|| {
+                        CommandError::Other("ERR weight value is not a float".into())
+                    })?;
+                }
+            }
+            "AGGREGATE" => {
+                aggregate = match args.next_keyword()?.as_str() {
+                    "SUM" => Aggregate::Sum,
+                    "MIN" => Aggregate::Min,
+                    "MAX" => Aggregate::Max,
+                    _ => return Err(CommandError::Syntax),
+                };
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::SortedSet(SortedSetCommand::Store {
+        op,
+        destination,
+        keys,
+        weights,
+        aggregate,
+    }));
+}
+
+/// ZSCAN key cursor [MATCH pattern] [COUNT count]
+fn parse_scan(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let cursor = parse_cursor(&args.next_bytes()?)?;
+    let (mut pattern, mut count) = (None, DEFAULT_SCAN_COUNT);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "MATCH" => pattern = Some(args.next_bytes()?),
+            "COUNT" => {
+                count = match args.next_integer()? {
+                    n if n > 0 => n as usize,
+                    _ => return Err(CommandError::Syntax),
+                };
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::SortedSet(SortedSetCommand::Scan {
+        key,
+        cursor,
+        pattern,
+        count,
+    }));
+}
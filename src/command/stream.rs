@@ -0,0 +1,1219 @@
+//! Commands that operate on stream values and their consumer groups
+use super::key::single_key;
+use super::{Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamCommand {
+    /// XADD, which replies with the ID of the new entry, or Null if the stream
+    /// does not exist and NOMKSTREAM was given
+    Add {
+        key: Bytes,
+        id: NewId,
+        /// NOMKSTREAM: do not create the stream if it does not exist
+        no_mkstream: bool,
+        trim: Option<Trim>,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    /// XRANGE, and XREVRANGE which returns the entries from the end, both
+    /// with inclusive bounds
+    Range {
+        key: Bytes,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    },
+    Len {
+        key: Bytes,
+    },
+    Del {
+        key: Bytes,
+        ids: Vec<StreamId>,
+    },
+    /// XTRIM, which replies with the number of entries removed
+    Trim {
+        key: Bytes,
+        trim: Trim,
+    },
+    /// XREAD, which replies with the entries after the given IDs, or Null if
+    /// there are none
+    Read {
+        keys: Vec<Bytes>,
+        ids: Vec<ReadId>,
+        count: Option<usize>,
+        /// BLOCK: how long to wait for entries if there are none, where zero
+        /// waits forever
+        block: Option<Duration>,
+    },
+    /// XREADGROUP, which delivers new entries to a consumer of a group, or
+    /// returns the entries already delivered to it and not acknowledged yet
+    ReadGroup {
+        group: Bytes,
+        consumer: Bytes,
+        keys: Vec<Bytes>,
+        ids: Vec<ReadId>,
+        count: Option<usize>,
+        block: Option<Duration>,
+        /// NOACK: do not add the delivered entries to the pending entries
+        no_ack: bool,
+    },
+    GroupCreate {
+        key: Bytes,
+        group: Bytes,
+        /// The last entry the group has seen: an ID, or $ for the last entry
+        /// of the stream
+        id: ReadId,
+        /// MKSTREAM: create an empty stream if the key does not exist
+        mkstream: bool,
+        entries_read: Option<u64>,
+    },
+    GroupSetId {
+        key: Bytes,
+        group: Bytes,
+        id: ReadId,
+        entries_read: Option<u64>,
+    },
+    GroupDestroy {
+        key: Bytes,
+        group: Bytes,
+    },
+    GroupCreateConsumer {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+    },
+    /// XGROUP DELCONSUMER, which replies with the number of pending entries
+    /// the consumer had
+    GroupDelConsumer {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+    },
+    Ack {
+        key: Bytes,
+        group: Bytes,
+        ids: Vec<StreamId>,
+    },
+    /// XPENDING. Without a range, the reply sums up the pending entries of
+    /// the group.
+    Pending {
+        key: Bytes,
+        group: Bytes,
+        range: Option<PendingRange>,
+    },
+    /// XCLAIM: take over pending entries that have been idle for at least
+    /// `min_idle` milliseconds
+    Claim {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        options: ClaimOptions,
+    },
+    /// XAUTOCLAIM: like XCLAIM, for up to `count` pending entries from
+    /// `start` on. The reply starts with the ID to continue from.
+    AutoClaim {
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        just_id: bool,
+    },
+    InfoStream {
+        key: Bytes,
+    },
+    InfoGroups {
+        key: Bytes,
+    },
+    InfoConsumers {
+        key: Bytes,
+        group: Bytes,
+    },
+}
+
+/// The ID of a stream entry: the Unix time in milliseconds it was added at,
+/// and a sequence number for entries added in the same millisecond
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parse an ID, either "<ms>-<seq>" or "<ms>" with the given sequence
+    /// number
+    pub fn parse(arg: &[u8], seq: u64) -> Option<Self> {
+        let arg = std::str::from_utf8(arg).ok()?;
+        let digits = |s: &str| -> Option<u64> {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            return s.parse().ok();
+        };
+        return match arg.split_once('-') {
+            Some((ms, seq)) => Some(Self {
+                ms: digits(ms)?,
+                seq: digits(seq)?,
+            }),
+            None => Some(Self {
+                ms: digits(arg)?,
+                seq,
+            }),
+        };
+    }
+
+    /// Return the smallest ID after this one
+    pub fn next(self) -> Option<Self> {
+        return match self.seq.checked_add(1) {
+            Some(seq) => Some(Self { ms: self.ms, seq }),
+            None => Some(Self {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        };
+    }
+
+    /// Return the greatest ID before this one
+    pub fn prev(self) -> Option<Self> {
+        return match self.seq.checked_sub(1) {
+            Some(seq) => Some(Self { ms: self.ms, seq }),
+            None => Some(Self {
+                ms: self.ms.checked_sub(1)?,
+                seq: u64::MAX,
+            }),
+        };
+    }
+
+    pub fn to_bytes(self) -> Bytes {
+        return Bytes::from(self.to_string());
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}-{}", self.ms, self.seq);
+    }
+}
+
+/// The ID XADD gives the new entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewId {
+    /// *: generated from the current time
+    Auto,
+    /// <ms>-*: the given time, with the next sequence number
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/// Which entries XADD and XTRIM remove from the start of a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trim {
+    pub strategy: TrimStrategy,
+    /// ~: trim no more than needed, which Redis does in whole nodes of
+    /// entries. Entries are trimmed one by one here, so this only allows
+    /// LIMIT.
+    pub approx: bool,
+    /// The most entries to remove at once
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// Keep at most this many entries
+    MaxLen(usize),
+    /// Remove the entries with smaller IDs
+    MinId(StreamId),
+}
+
+/// Where XREAD and XREADGROUP start reading a stream from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadId {
+    /// The entries after this ID
+    After(StreamId),
+    /// $: only the entries added from now on
+    Last,
+    /// >: the entries never delivered to any consumer of the group
+    New,
+}
+
+/// The range of pending entries the extended form of XPENDING returns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRange {
+    /// Only entries idle for at least this many milliseconds
+    pub min_idle: Option<u64>,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    /// Only the entries pending for this consumer
+    pub consumer: Option<Bytes>,
+}
+
+/// The options of XCLAIM
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClaimOptions {
+    /// IDLE: set the idle time of the claimed entries, in milliseconds
+    pub idle: Option<u64>,
+    /// TIME: set the idle time as if the entries were delivered at this Unix
+    /// time in milliseconds
+    pub time: Option<i64>,
+    /// RETRYCOUNT: set the delivery count of the claimed entries
+    pub retry_count: Option<u64>,
+    /// FORCE: claim the entries even if they are not pending, as long as they
+    /// are in the stream
+    pub force: bool,
+    /// JUSTID: return just the IDs of the claimed entries, without counting
+    /// a delivery
+    pub just_id: bool,
+    /// LASTID: move the last delivered ID of the group forward to this one
+    pub last_id: Option<StreamId>,
+}
+
+fn invalid_id() -> CommandError {
+    return CommandError::Other(
+        "ERR Invalid stream ID specified as stream command argument".into(),
+    );
+}
+
+/// Parse a complete or incomplete ID, where an incomplete ID has the given
+/// sequence number
+fn parse_id(arg: &[u8], seq: u64) -> Result<StreamId, CommandError> {
+    return StreamId::parse(arg, seq).ok_or_else(invalid_id);
+}
+
+/// Parse the start of an XRANGE: "-", an ID, or an ID after "(" to exclude it
+fn parse_start(arg: &[u8]) -> Result<StreamId, CommandError> {
+    return match arg {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        [b'(', id @ ..] => parse_id(id, 0)?
+            .next()
+            .ok_or_else(|| CommandError::Other("ERR invalid start ID for the interval".into())),
+        id => parse_id(id, 0),
+    };
+}
+
+/// Parse the end of an XRANGE: "+", an ID, or an ID after "(" to exclude it
+fn parse_end(arg: &[u8]) -> Result<StreamId, CommandError> {
+    return match arg {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        [b'(', id @ ..] => parse_id(id, u64::MAX)?
+            .prev()
+            .ok_or_else(|| CommandError::Other("ERR invalid end ID for the interval".into())),
+        id => parse_id(id, u64::MAX),
+    };
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("XADD", -5, WRITE_FAST, parse_add),
+    single_key("XRANGE", -4, READONLY, |args| parse_range(args, false)),
+    single_key("XREVRANGE", -4, READONLY, |args| parse_range(args, true)),
+    single_key("XLEN", 2, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::Stream(StreamCommand::Len { key }));
+    }),
+    single_key("XDEL", -3, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let ids = args
+            .rest()
+            .iter()
+            .map(|id| parse_id(id, 0))
+            .collect::<Result<_, _>>()?;
+        return Ok(Command::Stream(StreamCommand::Del { key, ids }));
+    }),
+    single_key("XTRIM", -4, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let rest = args.rest();
+        let mut options = TrimOptions::default();
+        let mut i = 0;
+        while i < rest.len() {
+            if !options.parse(&rest, &mut i)? {
+                return Err(CommandError::Syntax);
+            }
+        }
+        let trim = options.finish()?.ok_or(CommandError::Syntax)?;
+        return Ok(Command::Stream(StreamCommand::Trim { key, trim }));
+    }),
+    // The keys of XREAD and XREADGROUP follow STREAMS, so the table cannot
+    // describe where they are
+    CommandSpec {
+        name: "XREAD",
+        arity: -4,
+        flags: &[CommandFlag::Readonly, CommandFlag::Blocking],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        parse: parse_read,
+    },
+    CommandSpec {
+        name: "XREADGROUP",
+        arity: -7,
+        flags: &[CommandFlag::Write, CommandFlag::Blocking],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        parse: parse_read_group,
+    },
+    CommandSpec {
+        name: "XGROUP",
+        arity: -2,
+        flags: WRITE,
+        first_key: 2,
+        last_key: 2,
+        key_step: 1,
+        parse: parse_group,
+    },
+    single_key("XACK", -4, WRITE_FAST, |args| {
+        let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+        let ids = args
+            .rest()
+            .iter()
+            .map(|id| parse_id(id, 0))
+            .collect::<Result<_, _>>()?;
+        return Ok(Command::Stream(StreamCommand::Ack { key, group, ids }));
+    }),
+    single_key("XPENDING", -3, READONLY, parse_pending),
+    single_key("XCLAIM", -6, WRITE_FAST, parse_claim),
+    single_key("XAUTOCLAIM", -6, WRITE_FAST, parse_autoclaim),
+    CommandSpec {
+        name: "XINFO",
+        arity: -2,
+        flags: READONLY,
+        first_key: 2,
+        last_key: 2,
+        key_step: 1,
+        parse: parse_info,
+    },
+];
+
+impl StreamCommand {
+    /// Return whether the command waits for entries when there are none,
+    /// which XREAD and XREADGROUP do with BLOCK. XREADGROUP only waits when
+    /// it reads new entries from every stream.
+    pub fn is_blocking(&self) -> bool {
+        return match self {
+            Self::Read { block, .. } => block.is_some(),
+            Self::ReadGroup { block, ids, .. } => {
+                block.is_some() && ids.iter().all(|id| *id == ReadId::New)
+            }
+            _ => false,
+        };
+    }
+
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        let name = |name: &'static str, key: &Bytes| vec![Bytes::from(name), key.clone()];
+        let number = |n: u64| Bytes::from(n.to_string());
+        return match self {
+            Self::Add {
+                key,
+                id,
+                no_mkstream,
+                trim,
+                fields,
+            } => {
+                let mut args = name("XADD", key);
+                if *no_mkstream {
+                    args.push(Bytes::from("NOMKSTREAM"));
+                }
+                if let Some(trim) = trim {
+                    push_trim(&mut args, trim);
+                }
+                args.push(match id {
+                    NewId::Auto => Bytes::from("*"),
+                    NewId::AutoSeq(ms) => Bytes::from(format!("{ms}-*")),
+                    NewId::Explicit(id) => id.to_bytes(),
+                });
+                for (field, val) in fields {
+                    args.extend([field.clone(), val.clone()]);
+                }
+                args
+            }
+            Self::Range {
+                key,
+                start,
+                end,
+                count,
+                rev,
+            } => {
+                let mut args = match rev {
+                    false => vec![Bytes::from("XRANGE"), key.clone(), start.to_bytes()],
+                    true => vec![Bytes::from("XREVRANGE"), key.clone(), end.to_bytes()],
+                };
+                args.push(match rev {
+                    false => end.to_bytes(),
+                    true => start.to_bytes(),
+                });
+                if let Some(count) = count {
+                    args.extend([Bytes::from("COUNT"), number(*count as u64)]);
+                }
+                args
+            }
+            Self::Len { key } => name("XLEN", key),
+            Self::Del { key, ids } => {
+                let mut args = name("XDEL", key);
+                args.extend(ids.iter().map(|id| id.to_bytes()));
+                args
+            }
+            Self::Trim { key, trim } => {
+                let mut args = name("XTRIM", key);
+                push_trim(&mut args, trim);
+                args
+            }
+            Self::Read {
+                keys,
+                ids,
+                count,
+                block,
+            } => {
+                let mut args = vec![Bytes::from("XREAD")];
+                push_read_options(&mut args, *count, *block);
+                push_streams(&mut args, keys, ids);
+                args
+            }
+            Self::ReadGroup {
+                group,
+                consumer,
+                keys,
+                ids,
+                count,
+                block,
+                no_ack,
+            } => {
+                let mut args = vec![
+                    Bytes::from("XREADGROUP"),
+                    Bytes::from("GROUP"),
+                    group.clone(),
+                    consumer.clone(),
+                ];
+                push_read_options(&mut args, *count, *block);
+                if *no_ack {
+                    args.push(Bytes::from("NOACK"));
+                }
+                push_streams(&mut args, keys, ids);
+                args
+            }
+            Self::GroupCreate {
+                key,
+                group,
+                id,
+                mkstream,
+                entries_read,
+            } => {
+                let mut args = vec![
+                    Bytes::from("XGROUP"),
+                    Bytes::from("CREATE"),
+                    key.clone(),
+                    group.clone(),
+                    read_id_arg(id),
+                ];
+                if *mkstream {
+                    args.push(Bytes::from("MKSTREAM"));
+                }
+                if let Some(entries_read) = entries_read {
+                    args.extend([Bytes::from("ENTRIESREAD"), number(*entries_read)]);
+                }
+                args
+            }
+            Self::GroupSetId {
+                key,
+                group,
+                id,
+                entries_read,
+            } => {
+                let mut args = vec![
+                    Bytes::from("XGROUP"),
+                    Bytes::from("SETID"),
+                    key.clone(),
+                    group.clone(),
+                    read_id_arg(id),
+                ];
+                if let Some(entries_read) = entries_read {
+                    args.extend([Bytes::from("ENTRIESREAD"), number(*entries_read)]);
+                }
+                args
+            }
+            Self::GroupDestroy { key, group } => vec![
+                Bytes::from("XGROUP"),
+                Bytes::from("DESTROY"),
+                key.clone(),
+                group.clone(),
+            ],
+            Self::GroupCreateConsumer {
+                key,
+                group,
+                consumer,
+            }
+            | Self::GroupDelConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let subcommand = match self {
+                    Self::GroupCreateConsumer { .. } => "CREATECONSUMER",
+                    _ => "DELCONSUMER",
+                };
+                vec![
+                    Bytes::from("XGROUP"),
+                    Bytes::from(subcommand),
+                    key.clone(),
+                    group.clone(),
+                    consumer.clone(),
+                ]
+            }
+            Self::Ack { key, group, ids } => {
+                let mut args = vec![Bytes::from("XACK"), key.clone(), group.clone()];
+                args.extend(ids.iter().map(|id| id.to_bytes()));
+                args
+            }
+            Self::Pending { key, group, range } => {
+                let mut args = vec![Bytes::from("XPENDING"), key.clone(), group.clone()];
+                if let Some(range) = range {
+                    if let Some(min_idle) = range.min_idle {
+                        args.extend([Bytes::from("IDLE"), number(min_idle)]);
+                    }
+                    args.extend([
+                        range.start.to_bytes(),
+                        range.end.to_bytes(),
+                        number(range.count as u64),
+                    ]);
+                    args.extend(range.consumer.clone());
+                }
+                args
+            }
+            Self::Claim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                ids,
+                options,
+            } => {
+                let mut args = vec![
+                    Bytes::from("XCLAIM"),
+                    key.clone(),
+                    group.clone(),
+                    consumer.clone(),
+                    number(*min_idle),
+                ];
+                args.extend(ids.iter().map(|id| id.to_bytes()));
+                if let Some(idle) = options.idle {
+                    args.extend([Bytes::from("IDLE"), number(idle)]);
+                }
+                if let Some(time) = options.time {
+                    args.extend([Bytes::from("TIME"), Bytes::from(time.to_string())]);
+                }
+                if let Some(retry_count) = options.retry_count {
+                    args.extend([Bytes::from("RETRYCOUNT"), number(retry_count)]);
+                }
+                if options.force {
+                    args.push(Bytes::from("FORCE"));
+                }
+                if options.just_id {
+                    args.push(Bytes::from("JUSTID"));
+                }
+                if let Some(last_id) = options.last_id {
+                    args.extend([Bytes::from("LASTID"), last_id.to_bytes()]);
+                }
+                args
+            }
+            Self::AutoClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                start,
+                count,
+                just_id,
+            } => {
+                let mut args = vec![
+                    Bytes::from("XAUTOCLAIM"),
+                    key.clone(),
+                    group.clone(),
+                    consumer.clone(),
+                    number(*min_idle),
+                    start.to_bytes(),
+                    Bytes::from("COUNT"),
+                    number(*count as u64),
+                ];
+                if *just_id {
+                    args.push(Bytes::from("JUSTID"));
+                }
+                args
+            }
+            Self::InfoStream { key } => {
+                vec![Bytes::from("XINFO"), Bytes::from("STREAM"), key.clone()]
+            }
+            Self::InfoGroups { key } => {
+                vec![Bytes::from("XINFO"), Bytes::from("GROUPS"), key.clone()]
+            }
+            Self::InfoConsumers { key, group } => vec![
+                Bytes::from("XINFO"),
+                Bytes::from("CONSUMERS"),
+                key.clone(),
+                group.clone(),
+            ],
+        };
+    }
+}
+
+fn push_trim(args: &mut Vec<Bytes>, trim: &Trim) {
+    let (strategy, threshold) = match trim.strategy {
+        TrimStrategy::MaxLen(len) => ("MAXLEN", Bytes::from(len.to_string())),
+        TrimStrategy::MinId(id) => ("MINID", id.to_bytes()),
+    };
+    args.push(Bytes::from(strategy));
+    if trim.approx {
+        args.push(Bytes::from("~"));
+    }
+    args.push(threshold);
+    if let Some(limit) = trim.limit {
+        args.extend([Bytes::from("LIMIT"), Bytes::from(limit.to_string())]);
+    }
+}
+
+fn push_read_options(args: &mut Vec<Bytes>, count: Option<usize>, block: Option<Duration>) {
+    if let Some(count) = count {
+        args.extend([Bytes::from("COUNT"), Bytes::from(count.to_string())]);
+    }
+    if let Some(block) = block {
+        args.extend([
+            Bytes::from("BLOCK"),
+            Bytes::from(block.as_millis().to_string()),
+        ]);
+    }
+}
+
+fn push_streams(args: &mut Vec<Bytes>, keys: &[Bytes], ids: &[ReadId]) {
+    args.push(Bytes::from("STREAMS"));
+    args.extend(keys.iter().cloned());
+    args.extend(ids.iter().map(read_id_arg));
+}
+
+fn read_id_arg(id: &ReadId) -> Bytes {
+    return match id {
+        ReadId::After(id) => id.to_bytes(),
+        ReadId::Last => Bytes::from("$"),
+        ReadId::New => Bytes::from(">"),
+    };
+}
+
+/// The trimming options of XADD and XTRIM as they are parsed
+#[derive(Default)]
+struct TrimOptions {
+    strategy: Option<TrimStrategy>,
+    approx: bool,
+    limit: Option<usize>,
+}
+
+impl TrimOptions {
+    /// Parse the trimming option at position `i` of the arguments, if there
+    /// is one, moving `i` past it. Return whether there was one.
+    fn parse(&mut self, args: &[Bytes], i: &mut usize) -> Result<bool, CommandError> {
+        let keyword = args[*i].to_ascii_uppercase();
+        let arg = |i: usize| args.get(i).ok_or(CommandError::Syntax);
+        match keyword.as_slice() {
+            b"MAXLEN" | b"MINID" => {
+                *i += 1;
+                self.approx = false;
+                match arg(*i)?.as_ref() {
+                    b"~" => {
+                        self.approx = true;
+                        *i += 1;
+                    }
+                    b"=" => *i += 1,
+                    _ => {}
+                }
+                let threshold = arg(*i)?;
+                self.strategy = Some(match keyword.as_slice() {
+                    b"MAXLEN" => match super::parse_integer(threshold) {
+                        Some(len) if len >= 0 => TrimStrategy::MaxLen(len as usize),
+                        Some(_) => {
+                            return Err(CommandError::Other(
+                                "ERR The MAXLEN argument must be >= 0.".into(),
+                            ))
+                        }
+                        None => return Err(CommandError::NotInteger),
+                    },
+                    _ => TrimStrategy::MinId(parse_id(threshold, 0)?),
+                });
+            }
+            b"LIMIT" => {
+                *i += 1;
+                self.limit = match super::parse_integer(arg(*i)?) {
+                    Some(limit) if limit >= 0 => Some(limit as usize),
+                    Some(_) => {
+                        return Err(CommandError::Other(
+                            "ERR The LIMIT argument must be >= 0.".into(),
+                        ))
+                    }
+                    None => return Err(CommandError::NotInteger),
+                };
+            }
+            _ => return Ok(false),
+        }
+        *i += 1;
+        return Ok(true);
+    }
+
+    fn finish(self) -> Result<Option<Trim>, CommandError> {
+        let Some(strategy) = self.strategy else {
+            return match self.limit {
+                Some(_) => Err(CommandError::Other(
+                    "ERR syntax error, LIMIT cannot be used without specifying a trimming strategy"
+                        .into(),
+                )),
+                None => Ok(None),
+            };
+        };
+        if self.limit.is_some() && !self.approx {
+            return Err(CommandError::Other(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option".into(),
+            ));
+        }
+        return Ok(Some(Trim {
+            strategy,
+            approx: self.approx,
+            limit: self.limit,
+        }));
+    }
+}
+
+/// XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]]
+/// * | id field value [field value ...]
+fn parse_add(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let rest = args.rest();
+    let (mut no_mkstream, mut options, mut i) = (false, TrimOptions::default(), 0);
+    while i < rest.len() {
+        if rest[i].eq_ignore_ascii_case(b"NOMKSTREAM") {
+            no_mkstream = true;
+            i += 1;
+        } else if !options.parse(&rest, &mut i)? {
+            break;
+        }
+    }
+    let trim = options.finish()?;
+    let Some((id, fields)) = rest[i.min(rest.len())..].split_first() else {
+        return Err(CommandError::WrongArity("XADD"));
+    };
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return Err(CommandError::WrongArity("XADD"));
+    }
+    let id = match id.as_ref() {
+        b"*" => NewId::Auto,
+        [ms @ .., b'-', b'*'] => NewId::AutoSeq(parse_id(ms, 0)?.ms),
+        id => NewId::Explicit(parse_id(id, 0)?),
+    };
+    let fields = fields
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    return Ok(Command::Stream(StreamCommand::Add {
+        key,
+        id,
+        no_mkstream,
+        trim,
+        fields,
+    }));
+}
+
+/// XRANGE key start end [COUNT count], and XREVRANGE key end start
+/// [COUNT count]
+fn parse_range(args: &mut CommandArgs, rev: bool) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let (first, second) = (args.next_bytes()?, args.next_bytes()?);
+    let (start, end) = match rev {
+        false => (parse_start(&first)?, parse_end(&second)?),
+        true => (parse_start(&second)?, parse_end(&first)?),
+    };
+    let mut count = None;
+    while args.remaining() > 0 {
+        if args.next_keyword()? != "COUNT" {
+            return Err(CommandError::Syntax);
+        }
+        // Like Redis, a negative count returns nothing
+        count = Some(args.next_integer()?.max(0) as usize);
+    }
+    return Ok(Command::Stream(StreamCommand::Range {
+        key,
+        start,
+        end,
+        count,
+        rev,
+    }));
+}
+
+/// Parse the COUNT and BLOCK options of XREAD and XREADGROUP, returning
+/// false at an argument that is neither
+fn parse_read_option(
+    keyword: &str,
+    args: &mut CommandArgs,
+    count: &mut Option<usize>,
+    block: &mut Option<Duration>,
+) -> Result<bool, CommandError> {
+    match keyword {
+        "COUNT" => *count = Some(args.next_integer()?.max(0) as usize),
+        "BLOCK" => {
+            let ms = args.next_integer()?;
+            if ms < 0 {
+                return Err(CommandError::Other("ERR timeout is negative".into()));
+            }
+            *block = Some(Duration::from_millis(ms as u64));
+        }
+        _ => return Ok(false),
+    }
+    return Ok(true);
+}
+
+/// Parse the keys and IDs after STREAMS, checking each ID is allowed
+fn parse_streams(
+    args: &mut CommandArgs,
+    command: &str,
+    parse: fn(&Bytes) -> Result<ReadId, CommandError>,
+) -> Result<(Vec<Bytes>, Vec<ReadId>), CommandError> {
+    let mut rest = args.rest();
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err(CommandError::Other(format!(
+            "ERR Unbalanced '{command}' list of streams: for each stream key an ID or '$' must be specified."
+        )));
+    }
+    let ids = rest.split_off(rest.len() / 2);
+    let ids = ids.iter().map(parse).collect::<Result<_, _>>()?;
+    return Ok((rest, ids));
+}
+
+/// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+fn parse_read(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let (mut count, mut block) = (None, None);
+    loop {
+        let keyword = args.next_keyword()?;
+        if keyword == "STREAMS" {
+            break;
+        }
+        if !parse_read_option(&keyword, args, &mut count, &mut block)? {
+            return Err(CommandError::Syntax);
+        }
+    }
+    let (keys, ids) = parse_streams(args, "xread", |id| match id.as_ref() {
+        b"$" => Ok(ReadId::Last),
+        id => Ok(ReadId::After(parse_id(id, 0)?)),
+    })?;
+    return Ok(Command::Stream(StreamCommand::Read {
+        keys,
+        ids,
+        count,
+        block,
+    }));
+}
+
+/// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds]
+/// [NOACK] STREAMS key [key ...] id [id ...]
+fn parse_read_group(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    if args.next_keyword()? != "GROUP" {
+        return Err(CommandError::Syntax);
+    }
+    let (group, consumer) = (args.next_bytes()?, args.next_bytes()?);
+    let (mut count, mut block, mut no_ack) = (None, None, false);
+    loop {
+        let keyword = args.next_keyword()?;
+        match keyword.as_str() {
+            "STREAMS" => break,
+            "NOACK" => no_ack = true,
+            keyword => {
+                if !parse_read_option(keyword, args, &mut count, &mut block)? {
+                    return Err(CommandError::Syntax);
+                }
+            }
+        }
+    }
+    let (keys, ids) = parse_streams(args, "xreadgroup", |id| {
+        match id.as_ref() {
+        b">" => Ok(ReadId::New),
+        b"$" => Err(CommandError::Other(
+            "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.".into(),
+        )),
+        id => Ok(ReadId::After(parse_id(id, 0)?)),
+    }
+    })?;
+    return Ok(Command::Stream(StreamCommand::ReadGroup {
+        group,
+        consumer,
+        keys,
+        ids,
+        count,
+        block,
+        no_ack,
+    }));
+}
+
+/// XGROUP CREATE, SETID, DESTROY, CREATECONSUMER or DELCONSUMER
+fn parse_group(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let name = args.next_bytes()?;
+    let subcommand = String::from_utf8_lossy(&name).to_ascii_uppercase();
+    let arity = |args: &CommandArgs, min: usize, max: usize, name: &'static str| {
+        return match (min..=max).contains(&args.remaining()) {
+            true => Ok(()),
+            false => Err(CommandError::WrongArity(name)),
+        };
+    };
+    let cmd = match subcommand.as_str() {
+        "CREATE" | "SETID" => {
+            let create = subcommand == "CREATE";
+            match create {
+                true => arity(args, 3, 6, "XGROUP|CREATE")?,
+                false => arity(args, 3, 5, "XGROUP|SETID")?,
+            }
+            let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+            let id = match args.next_bytes()?.as_ref() {
+                b"$" => ReadId::Last,
+                id => ReadId::After(parse_id(id, 0)?),
+            };
+            let (mut mkstream, mut entries_read) = (false, None);
+            while args.remaining() > 0 {
+                match args.next_keyword()?.as_str() {
+                    "MKSTREAM" if create => mkstream = true,
+                    "ENTRIESREAD" => {
+                        entries_read = match args.next_integer()? {
+                            n if n >= 0 => Some(n as u64),
+                            _ => {
+                                return Err(CommandError::Other(
+                                    "ERR value for ENTRIESREAD must be positive or -1".into(),
+                                ))
+                            }
+                        };
+                    }
+                    _ => return Err(CommandError::Syntax),
+                }
+            }
+            match create {
+                true => StreamCommand::GroupCreate {
+                    key,
+                    group,
+                    id,
+                    mkstream,
+                    entries_read,
+                },
+                false => StreamCommand::GroupSetId {
+                    key,
+                    group,
+                    id,
+                    entries_read,
+                },
+            }
+        }
+        "DESTROY" => {
+            arity(args, 2, 2, "XGROUP|DESTROY")?;
+            let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+            StreamCommand::GroupDestroy { key, group }
+        }
+        "CREATECONSUMER" | "DELCONSUMER" => {
+            let create = subcommand == "CREATECONSUMER";
+            match create {
+                true => arity(args, 3, 3, "XGROUP|CREATECONSUMER")?,
+                false => arity(args, 3, 3, "XGROUP|DELCONSUMER")?,
+            }
+            let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+            let consumer = args.next_bytes()?;
+            match create {
+                true => StreamCommand::GroupCreateConsumer {
+                    key,
+                    group,
+                    consumer,
+                },
+                false => StreamCommand::GroupDelConsumer {
+                    key,
+                    group,
+                    consumer,
+                },
+            }
+        }
+        _ => {
+            return Err(CommandError::Other(format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                String::from_utf8_lossy(&name)
+            )))
+        }
+    };
+    return Ok(Command::Stream(cmd));
+}
+
+/// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+fn parse_pending(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+    let mut range = None;
+    if args.remaining() > 0 {
+        let mut first = args.next_bytes()?;
+        let mut min_idle = None;
+        if first.eq_ignore_ascii_case(b"IDLE") {
+            min_idle = Some(args.next_integer()?.max(0) as u64);
+            first = args.next_bytes()?;
+        }
+        let start = parse_start(&first)?;
+        let end = parse_end(&args.next_bytes()?)?;
+        let count = args.next_integer()?.max(0) as usize;
+        let consumer = match args.remaining() {
+            0 => None,
+            _ => Some(args.next_bytes()?),
+        };
+        args.finish()?;
+        range = Some(PendingRange {
+            min_idle,
+            start,
+            end,
+            count,
+            consumer,
+        });
+    }
+    return Ok(Command::Stream(StreamCommand::Pending {
+        key,
+        group,
+        range,
+    }));
+}
+
+/// Parse a minimum idle time in milliseconds, where a negative one counts as 0
+fn parse_min_idle(args: &mut CommandArgs) -> Result<u64, CommandError> {
+    return match super::parse_integer(&args.next_bytes()?) {
+        Some(ms) => Ok(ms.max(0) as u64),
+        None => Err(CommandError::Other(
+            "ERR Invalid min-idle-time argument for XCLAIM".into(),
+        )),
+    };
+}
+
+/// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+/// [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID]
+/// [LASTID lastid]
+fn parse_claim(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+    let consumer = args.next_bytes()?;
+    let min_idle = parse_min_idle(args)?;
+    let rest = args.rest();
+    // The IDs run up to the first argument that is not one
+    let count = rest
+        .iter()
+        .take_while(|arg| StreamId::parse(arg, 0).is_some())
+        .count();
+    if count == 0 {
+        return Err(invalid_id());
+    }
+    let ids = rest[..count]
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<_, _>>()?;
+    let mut options = ClaimOptions::default();
+    let mut rest = rest[count..].iter();
+    let number = |rest: &mut std::slice::Iter<Bytes>| {
+        let arg = rest.next().ok_or(CommandError::Syntax)?;
+        return super::parse_integer(arg).ok_or(CommandError::NotInteger);
+    };
+    while let Some(arg) = rest.next() {
+        match String::from_utf8_lossy(arg).to_ascii_uppercase().as_str() {
+            "IDLE" => options.idle = Some(number(&mut rest)?.max(0) as u64),
+            "TIME" => options.time = Some(number(&mut rest)?),
+            "RETRYCOUNT" => options.retry_count = Some(number(&mut rest)?.max(0) as u64),
+            "FORCE" => options.force = true,
+            "JUSTID" => options.just_id = true,
+            "LASTID" => {
+                let id = rest.next().ok_or(CommandError::Syntax)?;
+                options.last_id = Some(parse_id(id, 0)?);
+            }
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "ERR Unrecognized XCLAIM option '{}'",
+                    String::from_utf8_lossy(arg)
+                )))
+            }
+        }
+    }
+    return Ok(Command::Stream(StreamCommand::Claim {
+        key,
+        group,
+        consumer,
+        min_idle,
+        ids,
+        options,
+    }));
+}
+
+/// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+fn parse_autoclaim(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+    let consumer = args.next_bytes()?;
+    let min_idle = parse_min_idle(args)?;
+    let start = parse_start(&args.next_bytes()?)?;
+    let (mut count, mut just_id) = (100, false);
+    while args.remaining() > 0 {
+        match args.next_keyword()?.as_str() {
+            "COUNT" => {
+                count = match args.next_integer()? {
+                    n if n > 0 => n as usize,
+                    _ => return Err(CommandError::Other("ERR COUNT must be > 0".into())),
+                };
+            }
+            "JUSTID" => just_id = true,
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::Stream(StreamCommand::AutoClaim {
+        key,
+        group,
+        consumer,
+        min_idle,
+        start,
+        count,
+        just_id,
+    }));
+}
+
+/// XINFO STREAM key, XINFO GROUPS key or XINFO CONSUMERS key group
+fn parse_info(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let name = args.next_bytes()?;
+    let cmd = match String::from_utf8_lossy(&name).to_ascii_uppercase().as_str() {
+        "STREAM" => {
+            let key = args.next_bytes()?;
+            args.finish()?;
+            StreamCommand::InfoStream { key }
+        }
+        "GROUPS" => {
+            if args.remaining() != 1 {
+                return Err(CommandError::WrongArity("XINFO|GROUPS"));
+            }
+            StreamCommand::InfoGroups {
+                key: args.next_bytes()?,
+            }
+        }
+        "CONSUMERS" => {
+            if args.remaining() != 2 {
+                return Err(CommandError::WrongArity("XINFO|CONSUMERS"));
+            }
+            let (key, group) = (args.next_bytes()?, args.next_bytes()?);
+            StreamCommand::InfoConsumers { key, group }
+        }
+        _ => {
+            return Err(CommandError::Other(format!(
+                "ERR unknown subcommand '{}'. Try XINFO HELP.",
+                String::from_utf8_lossy(&name)
+            )))
+        }
+    };
+    return Ok(Command::Stream(cmd));
+}
@@ -0,0 +1,362 @@
+//! Commands that operate on string values
+use super::key::{multi_key, single_key, Expiry};
+use super::{Command, CommandArgs, CommandError, CommandFlag, CommandSpec};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringCommand {
+    Set {
+        key: Bytes,
+        val: Bytes,
+        /// When the key expires. Without an expiry, SET removes the key's
+        /// current expiry unless `keep_ttl` is set.
+        expiry: Option<Expiry>,
+        keep_ttl: bool,
+        condition: Option<SetCondition>,
+        /// Reply with the value the key held before, instead of OK
+        get: bool,
+    },
+    Get {
+        key: Bytes,
+    },
+    /// SET NX that replies with whether the value was set
+    SetNx {
+        key: Bytes,
+        val: Bytes,
+    },
+    GetDel {
+        key: Bytes,
+    },
+    GetEx {
+        key: Bytes,
+        /// The new expiry. Without one, the expiry is left alone unless
+        /// `persist` is set.
+        expiry: Option<Expiry>,
+        persist: bool,
+    },
+    Append {
+        key: Bytes,
+        val: Bytes,
+    },
+    Strlen {
+        key: Bytes,
+    },
+    /// GETRANGE, where negative offsets count from the end of the string
+    GetRange {
+        key: Bytes,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        key: Bytes,
+        offset: i64,
+        val: Bytes,
+    },
+    /// INCR, DECR, INCRBY and DECRBY, which all add a possibly negative
+    /// integer to the value
+    IncrBy {
+        key: Bytes,
+        delta: i64,
+    },
+    IncrByFloat {
+        key: Bytes,
+        delta: f64,
+    },
+    MGet {
+        keys: Vec<Bytes>,
+    },
+    MSet {
+        pairs: Vec<(Bytes, Bytes)>,
+    },
+    /// MSET that sets either every key or, if any of them exists, none
+    MSetNx {
+        pairs: Vec<(Bytes, Bytes)>,
+    },
+}
+
+/// The conditions under which SET writes the value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    /// Only if the key does not exist
+    Nx,
+    /// Only if the key already exists
+    Xx,
+}
+
+const WRITE: &[CommandFlag] = &[CommandFlag::Write];
+const WRITE_FAST: &[CommandFlag] = &[CommandFlag::Write, CommandFlag::Fast];
+const READONLY: &[CommandFlag] = &[CommandFlag::Readonly];
+const READONLY_FAST: &[CommandFlag] = &[CommandFlag::Readonly, CommandFlag::Fast];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    single_key("SET", -3, WRITE, parse_set),
+    single_key("GET", 2, READONLY_FAST, parse_get),
+    single_key("SETNX", 3, WRITE_FAST, |args| {
+        let (key, val) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::String(StringCommand::SetNx { key, val }));
+    }),
+    single_key("GETDEL", 2, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::String(StringCommand::GetDel { key }));
+    }),
+    single_key("GETEX", -2, WRITE_FAST, parse_getex),
+    single_key("APPEND", 3, WRITE_FAST, |args| {
+        let (key, val) = (args.next_bytes()?, args.next_bytes()?);
+        return Ok(Command::String(StringCommand::Append { key, val }));
+    }),
+    single_key("STRLEN", 2, READONLY_FAST, |args| {
+        let key = args.next_bytes()?;
+        return Ok(Command::String(StringCommand::Strlen { key }));
+    }),
+    single_key("GETRANGE", 4, READONLY, |args| {
+        let key = args.next_bytes()?;
+        let (start, end) = (args.next_integer()?, args.next_integer()?);
+        return Ok(Command::String(StringCommand::GetRange { key, start, end }));
+    }),
+    single_key("SETRANGE", 4, WRITE, |args| {
+        let key = args.next_bytes()?;
+        let offset = args.next_integer()?;
+        let val = args.next_bytes()?;
+        return Ok(Command::String(StringCommand::SetRange {
+            key,
+            offset,
+            val,
+        }));
+    }),
+    single_key("INCR", 2, WRITE_FAST, |args| parse_incr(args, Some(1))),
+    single_key("DECR", 2, WRITE_FAST, |args| parse_incr(args, Some(-1))),
+    single_key("INCRBY", 3, WRITE_FAST, |args| parse_incr(args, None)),
+    single_key("DECRBY", 3, WRITE_FAST, parse_decrby),
+    single_key("INCRBYFLOAT", 3, WRITE_FAST, |args| {
+        let key = args.next_bytes()?;
+        let delta = args.next_float()?;
+        return Ok(Command::String(StringCommand::IncrByFloat { key, delta }));
+    }),
+    multi_key("MGET", -2, READONLY_FAST, 1, |args| {
+        return Ok(Command::String(StringCommand::MGet { keys: args.rest() }));
+    }),
+    multi_key("MSET", -3, WRITE, 2, |args| {
+        let pairs = parse_pairs(args, "MSET")?;
+        return Ok(Command::String(StringCommand::MSet { pairs }));
+    }),
+    multi_key("MSETNX", -3, WRITE, 2, |args| {
+        let pairs = parse_pairs(args, "MSETNX")?;
+        return Ok(Command::String(StringCommand::MSetNx { pairs }));
+    }),
+];
+
+impl StringCommand {
+    /// Convert the command into its arguments, starting with its name
+    pub(super) fn to_args(&self) -> Vec<Bytes> {
+        return match self {
+            Self::Set {
+                key,
+                val,
+                expiry,
+                keep_ttl,
+                condition,
+                get,
+            } => {
+                let mut args = vec![Bytes::from("SET"), key.clone(), val.clone()];
+                match condition {
+                    Some(SetCondition::Nx) => args.push(Bytes::from("NX")),
+                    Some(SetCondition::Xx) => args.push(Bytes::from("XX")),
+                    None => {}
+                }
+                if *get {
+                    args.push(Bytes::from("GET"));
+                }
+                push_expiry(&mut args, expiry);
+                if *keep_ttl {
+                    args.push(Bytes::from("KEEPTTL"));
+                }
+                args
+            }
+            Self::Get { key } => vec![Bytes::from("GET"), key.clone()],
+            Self::SetNx { key, val } => vec![Bytes::from("SETNX"), key.clone(), val.clone()],
+            Self::GetDel { key } => vec![Bytes::from("GETDEL"), key.clone()],
+            Self::GetEx {
+                key,
+                expiry,
+                persist,
+            } => {
+                let mut args = vec![Bytes::from("GETEX"), key.clone()];
+                push_expiry(&mut args, expiry);
+                if *persist {
+                    args.push(Bytes::from("PERSIST"));
+                }
+                args
+            }
+            Self::Append { key, val } => vec![Bytes::from("APPEND"), key.clone(), val.clone()],
+            Self::Strlen { key } => vec![Bytes::from("STRLEN"), key.clone()],
+            Self::GetRange { key, start, end } => vec![
+                Bytes::from("GETRANGE"),
+                key.clone(),
+                Bytes::from(start.to_string()),
+                Bytes::from(end.to_string()),
+            ],
+            Self::SetRange { key, offset, val } => vec![
+                Bytes::from("SETRANGE"),
+                key.clone(),
+                Bytes::from(offset.to_string()),
+                val.clone(),
+            ],
+            Self::IncrBy { key, delta } => vec![
+                Bytes::from("INCRBY"),
+                key.clone(),
+                Bytes::from(delta.to_string()),
+            ],
+            Self::IncrByFloat { key, delta } => vec![
+                Bytes::from("INCRBYFLOAT"),
+                key.clone(),
+                Bytes::from(delta.to_string()),
+            ],
+            Self::MGet { keys } => {
+                let mut args = vec![Bytes::from("MGET")];
+                args.extend(keys.iter().cloned());
+                args
+            }
+            Self::MSet { pairs } | Self::MSetNx { pairs } => {
+                let name = match self {
+                    Self::MSet { .. } => "MSET",
+                    _ => "MSETNX",
+                };
+                let mut args = vec![Bytes::from(name)];
+                for (key, val) in pairs {
+                    args.push(key.clone());
+                    args.push(val.clone());
+                }
+                args
+            }
+        };
+    }
+}
+
+/// Append the option that sets the expiry, if there is one
+pub(super) fn push_expiry(args: &mut Vec<Bytes>, expiry: &Option<Expiry>) {
+    if let Some(expiry) = expiry {
+        let (option, time) = match *expiry {
+            Expiry::Ex(secs) => ("EX", secs),
+            Expiry::Px(ms) => ("PX", ms),
+            Expiry::ExAt(secs) => ("EXAT", secs),
+            Expiry::PxAt(ms) => ("PXAT", ms),
+        };
+        args.push(Bytes::from(option));
+        args.push(Bytes::from(time.to_string()));
+    }
+}
+
+/// Parse the time that follows one of the EX, PX, EXAT or PXAT options of the
+/// named command. The time must be positive.
+pub(super) fn parse_expiry(
+    args: &mut CommandArgs,
+    option: &str,
+    command: &str,
+) -> Result<Expiry, CommandError> {
+    let time = args.next_integer()?;
+    if time <= 0 {
+        return Err(CommandError::Other(format!(
+            "ERR invalid expire time in '{command}' command"
+        )));
+    }
+    return Ok(match option {
+        "EX" => Expiry::Ex(time),
+        "PX" => Expiry::Px(time),
+        "EXAT" => Expiry::ExAt(time),
+        _ => Expiry::PxAt(time),
+    });
+}
+
+/// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
+/// EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]
+fn parse_set(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let val = args.next_bytes()?;
+    let (mut expiry, mut keep_ttl, mut condition, mut get) = (None, false, None, false);
+    while args.remaining() > 0 {
+        let option = args.next_keyword()?;
+        match option.as_str() {
+            "NX" if condition != Some(SetCondition::Xx) => condition = Some(SetCondition::Nx),
+            "XX" if condition != Some(SetCondition::Nx) => condition = Some(SetCondition::Xx),
+            "GET" => get = true,
+            "KEEPTTL" if expiry.is_none() => keep_ttl = true,
+            "EX" | "PX" | "EXAT" | "PXAT" if expiry.is_none() && !keep_ttl => {
+                expiry = Some(parse_expiry(args, &option, "set")?);
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::String(StringCommand::Set {
+        key,
+        val,
+        expiry,
+        keep_ttl,
+        condition,
+        get,
+    }));
+}
+
+/// GET key
+fn parse_get(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    return Ok(Command::String(StringCommand::Get {
+        key: args.next_bytes()?,
+    }));
+}
+
+/// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+/// PXAT unix-time-milliseconds | PERSIST]
+fn parse_getex(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let (mut expiry, mut persist) = (None, false);
+    while args.remaining() > 0 {
+        let option = args.next_keyword()?;
+        match option.as_str() {
+            "PERSIST" if expiry.is_none() => persist = true,
+            "EX" | "PX" | "EXAT" | "PXAT" if expiry.is_none() && !persist => {
+                expiry = Some(parse_expiry(args, &option, "getex")?);
+            }
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    return Ok(Command::String(StringCommand::GetEx {
+        key,
+        expiry,
+        persist,
+    }));
+}
+
+/// INCR key and DECR key, which pass their fixed delta, and INCRBY key delta
+fn parse_incr(args: &mut CommandArgs, delta: Option<i64>) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let delta = match delta {
+        Some(delta) => delta,
+        None => args.next_integer()?,
+    };
+    return Ok(Command::String(StringCommand::IncrBy { key, delta }));
+}
+
+/// DECRBY key decrement
+fn parse_decrby(args: &mut CommandArgs) -> Result<Command, CommandError> {
+    let key = args.next_bytes()?;
+    let delta = args
+        .next_integer()?
+        .checked_neg()
+        .ok_or_else(|| CommandError::Other("ERR decrement would overflow".into()))?;
+    return Ok(Command::String(StringCommand::IncrBy { key, delta }));
+}
+
+/// The key value pairs of MSET and MSETNX, or the field value pairs of HSET,
+/// which must come in whole pairs
+pub(super) fn parse_pairs(
+    args: &mut CommandArgs,
+    name: &'static str,
+) -> Result<Vec<(Bytes, Bytes)>, CommandError> {
+    if !args.remaining().is_multiple_of(2) {
+        return Err(CommandError::WrongArity(name));
+    }
+    let mut pairs = Vec::with_capacity(args.remaining() / 2);
+    while args.remaining() > 0 {
+        pairs.push((args.next_bytes()?, args.next_bytes()?));
+    }
+    return Ok(pairs);
+}
@@ -0,0 +1,397 @@
+//! Execution of the commands that operate on string values as arrays of bits
+//!
+//! Bits are numbered from the most significant bit of the first byte, like in
+//! Redis, and the bits past the end of a string read as zeros. Commands that
+//! write past the end grow the string with zero bytes.
+use super::{Keyspace, Value};
+use crate::command::{
+    BitFieldOp, BitFieldType, BitOp, BitRange, BitUnit, BitmapCommand, CommandError, Overflow,
+};
+use crate::Frame;
+use bytes::{Bytes, BytesMut};
+
+fn get_bit(s: &[u8], offset: u64) -> bool {
+    return s
+        .get((offset >> 3) as usize)
+        .is_some_and(|byte| byte & (0x80 >> (offset & 7)) != 0);
+}
+
+/// Set a bit, which must be within the string
+fn set_bit(s: &mut [u8], offset: u64, bit: bool) {
+    let mask = 0x80 >> (offset & 7);
+    let byte = &mut s[(offset >> 3) as usize];
+    match bit {
+        true => *byte |= mask,
+        false => *byte &= !mask,
+    }
+}
+
+/// Grow a string with zero bytes so that it holds the given bit
+fn grow(s: &mut BytesMut, offset: u64) {
+    let len = (offset >> 3) as usize + 1;
+    if s.len() < len {
+        s.resize(len, 0);
+    }
+}
+
+/// Resolve a range of a string of `len` bytes into the first and last bit it
+/// covers, or None if it is empty
+fn bit_range(range: Option<BitRange>, len: usize) -> Option<(u64, u64)> {
+    let (start, end, unit) = match range {
+        None => (0, -1, BitUnit::Byte),
+        Some(range) => (range.start, range.end.unwrap_or(-1), range.unit),
+    };
+    let total = match unit {
+        BitUnit::Byte => len as i64,
+        BitUnit::Bit => len as i64 * 8,
+    };
+    let start = if start < 0 { start + total } else { start }.max(0);
+    let end = if end < 0 { end + total } else { end }
+        .max(0)
+        .min(total - 1);
+    if start > end {
+        return None;
+    }
+    return match unit {
+        BitUnit::Byte => Some((start as u64 * 8, end as u64 * 8 + 7)),
+        BitUnit::Bit => Some((start as u64, end as u64)),
+    };
+}
+
+/// Iterate over the bytes that hold the bits from `first` to `last`, each
+/// with a mask of the bits of the byte within the range
+fn masked_bytes(s: &[u8], first: u64, last: u64) -> impl Iterator<Item = (usize, u8, u8)> + '_ {
+    let (first_byte, last_byte) = ((first >> 3) as usize, (last >> 3) as usize);
+    return (first_byte..=last_byte).map(move |i| {
+        let mut mask = 0xff;
+        if i == first_byte {
+            mask &= 0xff >> (first & 7);
+        }
+        if i == last_byte {
+            mask &= 0xff << (7 - (last & 7));
+        }
+        return (i, s[i], mask);
+    });
+}
+
+/// Read a BITFIELD integer
+fn get_int(s: &[u8], offset: u64, encoding: BitFieldType) -> i64 {
+    let bits = encoding.bits as u64;
+    let mut val: u64 = 0;
+    for i in 0..bits {
+        val = (val << 1) | get_bit(s, offset + i) as u64;
+    }
+    if encoding.signed && bits < 64 && val >> (bits - 1) == 1 {
+        // Extend the sign
+        val |= u64::MAX << bits;
+    }
+    return val as i64;
+}
+
+/// Write a BITFIELD integer, which must be within the string
+fn set_int(s: &mut [u8], offset: u64, encoding: BitFieldType, val: i64) {
+    let bits = encoding.bits as u64;
+    for i in 0..bits {
+        set_bit(s, offset + i, (val as u64 >> (bits - 1 - i)) & 1 == 1);
+    }
+}
+
+/// Fit a value into a BITFIELD integer, returning None if it does not fit
+/// and overflows fail
+fn fit(val: i128, encoding: BitFieldType, overflow: Overflow) -> Option<i64> {
+    let bits = encoding.bits;
+    let (min, max) = match encoding.signed {
+        true => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+        false => (0, (1i128 << bits) - 1),
+    };
+    if (min..=max).contains(&val) {
+        return Some(val as i64);
+    }
+    return match overflow {
+        Overflow::Fail => None,
+        Overflow::Sat => Some(val.clamp(min, max) as i64),
+        Overflow::Wrap => {
+            let wrapped = val.rem_euclid(1 << bits);
+            match wrapped > max {
+                true => Some((wrapped - (1 << bits)) as i64),
+                false => Some(wrapped as i64),
+            }
+        }
+    };
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: BitmapCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        BitmapCommand::SetBit { key, offset, value } => {
+            let old_bit = keyspace.update_string(key, now, |s| {
+                grow(s, offset);
+                let old_bit = get_bit(s, offset);
+                set_bit(s, offset, value);
+                return old_bit;
+            })?;
+            Ok(Frame::Integer(old_bit as i64))
+        }
+        BitmapCommand::GetBit { key, offset } => {
+            let s = keyspace.get_string(&key, now)?.cloned().unwrap_or_default();
+            Ok(Frame::Integer(get_bit(&s, offset) as i64))
+        }
+        BitmapCommand::Count { key, range } => {
+            let s = keyspace.get_string(&key, now)?.cloned().unwrap_or_default();
+            // Like Redis, a range whose both ends count from the end of the
+            // string is empty when they are backwards, even before clamping
+            if let Some(BitRange {
+                start,
+                end: Some(end),
+                ..
+            }) = range
+            {
+                if start < 0 && end < 0 && start > end {
+                    return Ok(Frame::Integer(0));
+                }
+            }
+            let Some((first, last)) = bit_range(range, s.len()) else {
+                return Ok(Frame::Integer(0));
+            };
+            let count: u32 = masked_bytes(&s, first, last)
+                .map(|(_, byte, mask)| (byte & mask).count_ones())
+                .sum();
+            Ok(Frame::Integer(count as i64))
+        }
+        BitmapCommand::Pos { key, bit, range } => {
+            let Some(s) = keyspace.get_string(&key, now)?.cloned() else {
+                // A missing key is an empty string, followed by zeros
+                return Ok(Frame::Integer(if bit { -1 } else { 0 }));
+            };
+            let Some((first, last)) = bit_range(range, s.len()) else {
+                return Ok(Frame::Integer(-1));
+            };
+            for (i, byte, mask) in masked_bytes(&s, first, last) {
+                let found = if bit { byte & mask } else { !byte & mask };
+                if found != 0 {
+                    return Ok(Frame::Integer(i as i64 * 8 + found.leading_zeros() as i64));
+                }
+            }
+            // Without an end, the string is considered padded with zeros
+            let end_given = range.is_some_and(|range| range.end.is_some());
+            if !bit && !end_given {
+                return Ok(Frame::Integer(last as i64 + 1));
+            }
+            Ok(Frame::Integer(-1))
+        }
+        BitmapCommand::Op {
+            op,
+            destination,
+            keys,
+        } => {
+            let mut sources = Vec::with_capacity(keys.len());
+            for key in &keys {
+                sources.push(keyspace.get_string(key, now)?.cloned().unwrap_or_default());
+            }
+            let len = sources.iter().map(Bytes::len).max().unwrap_or(0);
+            let mut result = vec![if op == BitOp::And { 0xff } else { 0 }; len];
+            for source in &sources {
+                for (i, byte) in result.iter_mut().enumerate() {
+                    // Shorter strings are padded with zeros
+                    let other = source.get(i).copied().unwrap_or(0);
+                    match op {
+                        BitOp::And => *byte &= other,
+                        BitOp::Or => *byte |= other,
+                        BitOp::Xor => *byte ^= other,
+                        BitOp::Not => *byte = !other,
+                    }
+                }
+            }
+            match result.is_empty() {
+                true => {
+                    keyspace.remove(&destination, now);
+                }
+                false => {
+                    let val = Value::String(Bytes::from(result));
+                    keyspace.insert(destination, val, None, now);
+                }
+            }
+            Ok(Frame::Integer(len as i64))
+        }
+        BitmapCommand::Field { key, ops, .. } => {
+            let end = ops
+                .iter()
+                .filter_map(|op| match op {
+                    BitFieldOp::Set {
+                        encoding, offset, ..
+                    }
+                    | BitFieldOp::IncrBy {
+                        encoding, offset, ..
+                    } => Some(offset + encoding.bits as u64 - 1),
+                    _ => None,
+                })
+                .max();
+            let Some(end) = end else {
+                // Only reads, which leave a missing key missing
+                let old = keyspace.get_string(&key, now)?.cloned().unwrap_or_default();
+                let replies = ops
+                    .iter()
+                    .filter_map(|op| match op {
+                        BitFieldOp::Get { encoding, offset } => {
+                            Some(Frame::Integer(get_int(&old, *offset, *encoding)))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                return Ok(Frame::Array(replies));
+            };
+            // Like Redis, the string grows to fit every write up front, even
+            // those that fail on an overflow
+            let replies = keyspace.update_string(key, now, |s| {
+                grow(s, end);
+                return bit_field(s, ops);
+            })?;
+            Ok(Frame::Array(replies))
+        }
+    };
+}
+
+/// Run the operations of a BITFIELD that writes, on a string large enough for
+/// all of them
+fn bit_field(s: &mut BytesMut, ops: Vec<BitFieldOp>) -> Vec<Frame> {
+    let mut overflow = Overflow::default();
+    let mut replies = Vec::with_capacity(ops.len());
+    for op in ops {
+        match op {
+            BitFieldOp::Get { encoding, offset } => {
+                replies.push(Frame::Integer(get_int(s, offset, encoding)));
+            }
+            BitFieldOp::Set {
+                encoding,
+                offset,
+                value,
+            } => {
+                // Unsigned values are taken as their 64 bit pattern
+                let value = match encoding.signed {
+                    true => value as i128,
+                    false => value as u64 as i128,
+                };
+                let old = get_int(s, offset, encoding);
+                replies.push(match fit(value, encoding, overflow) {
+                    Some(new) => {
+                        set_int(s, offset, encoding, new);
+                        Frame::Integer(old)
+                    }
+                    None => Frame::Null,
+                });
+            }
+            BitFieldOp::IncrBy {
+                encoding,
+                offset,
+                increment,
+            } => {
+                let old = get_int(s, offset, encoding);
+                let value = old as i128 + increment as i128;
+                replies.push(match fit(value, encoding, overflow) {
+                    Some(new) => {
+                        set_int(s, offset, encoding, new);
+                        Frame::Integer(new)
+                    }
+                    None => Frame::Null,
+                });
+            }
+            BitFieldOp::Overflow(new) => overflow = new,
+        }
+    }
+    return replies;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        let i8 = BitFieldType {
+            signed: true,
+            bits: 8,
+        };
+        let u4 = BitFieldType {
+            signed: false,
+            bits: 4,
+        };
+        assert_eq!(fit(127, i8, Overflow::Fail), Some(127));
+        assert_eq!(fit(128, i8, Overflow::Wrap), Some(-128));
+        assert_eq!(fit(-129, i8, Overflow::Wrap), Some(127));
+        assert_eq!(fit(300, i8, Overflow::Sat), Some(127));
+        assert_eq!(fit(128, i8, Overflow::Fail), None);
+        assert_eq!(fit(16, u4, Overflow::Wrap), Some(0));
+        assert_eq!(fit(-1, u4, Overflow::Wrap), Some(15));
+        assert_eq!(fit(-1, u4, Overflow::Sat), Some(0));
+        let i64 = BitFieldType {
+            signed: true,
+            bits: 64,
+        };
+        assert_eq!(
+            fit(i64::MAX as i128 + 1, i64, Overflow::Wrap),
+            Some(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn test_set_bits_in_place() {
+        let mut keyspace = Keyspace::new();
+        let key = Bytes::from("bitmap");
+        let setbit = |keyspace: &mut Keyspace, offset| {
+            let cmd = BitmapCommand::SetBit {
+                key: key.clone(),
+                offset,
+                value: true,
+            };
+            return execute(keyspace, cmd, 0).unwrap();
+        };
+        // A 64MiB string, which would take minutes to copy on every write
+        let bits = 1u64 << 29;
+        setbit(&mut keyspace, bits - 1);
+        let buffer = keyspace.get_string(&key, 0).unwrap().unwrap().as_ptr();
+        for i in 0..10_000 {
+            setbit(&mut keyspace, i * 7919 % bits);
+        }
+        let s = keyspace.get_string(&key, 0).unwrap().unwrap();
+        assert_eq!(s.as_ptr(), buffer);
+        assert_eq!(s.len() as u64, bits / 8);
+        assert!(get_bit(s, 7919 * 42));
+
+        let ops = vec![BitFieldOp::IncrBy {
+            encoding: BitFieldType {
+                signed: false,
+                bits: 8,
+            },
+            offset: 8,
+            increment: 3,
+        }];
+        let cmd = BitmapCommand::Field {
+            key: key.clone(),
+            ops,
+            readonly: false,
+        };
+        assert_eq!(
+            execute(&mut keyspace, cmd, 0),
+            Ok(Frame::Array(vec![Frame::Integer(3)]))
+        );
+        assert_eq!(
+            keyspace.get_string(&key, 0).unwrap().unwrap().as_ptr(),
+            buffer
+        );
+    }
+
+    #[test]
+    fn test_int_roundtrip() {
+        let i5 = BitFieldType {
+            signed: true,
+            bits: 5,
+        };
+        let mut s = [0u8; 2];
+        set_int(&mut s, 6, i5, -3);
+        assert_eq!(s, [0b0000_0011, 0b1010_0000]);
+        assert_eq!(get_int(&s, 6, i5), -3);
+    }
+}
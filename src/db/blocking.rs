@@ -0,0 +1,175 @@
+//! Clients blocked on keys by commands such as BLPOP and BZPOPMIN, and
+//! serving them in the order they blocked once the keys receive data
+//!
+//! Like Redis, a push does not hand its elements to blocked clients directly.
+//! It marks the key as ready, and the blocked clients are served right after
+//! the command that pushed, before the keyspace is unlocked, so that no other
+//! client can take the elements first.
+//!
+//! XREAD and XREADGROUP block the same way. Reading a stream does not consume
+//! its entries, so every client blocked on a stream may be served by a single
+//! XADD.
+use super::{list, sorted_set, stream, Keyspace, Value};
+use crate::command::{Command, CommandError};
+use crate::Frame;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// A client blocked by a command, waiting for the reply to it
+struct Waiter {
+    cmd: Command,
+    keys: Vec<Bytes>,
+    reply: oneshot::Sender<Frame>,
+}
+
+/// The blocked clients, and the keys they are blocked on
+#[derive(Default)]
+pub(super) struct Blocked {
+    next_id: u64,
+    waiters: HashMap<u64, Waiter>,
+    /// For each key, the clients blocked on it in the order they blocked
+    queues: HashMap<Bytes, VecDeque<u64>>,
+    /// Keys that received data since the blocked clients were last served
+    ready: VecDeque<Bytes>,
+}
+
+impl Keyspace {
+    /// Return the number of clients blocked on keys
+    pub fn blocked_len(&self) -> usize {
+        return self.blocked.waiters.len();
+    }
+
+    /// Block a client on the keys of a command until the command is served.
+    /// Return the ID to unblock the client with, and the receiving end of the
+    /// reply.
+    pub(super) fn block(
+        &mut self,
+        cmd: Command,
+        keys: Vec<Bytes>,
+    ) -> (u64, oneshot::Receiver<Frame>) {
+        let blocked = &mut self.blocked;
+        let id = blocked.next_id;
+        blocked.next_id += 1;
+        for key in &keys {
+            blocked.queues.entry(key.clone()).or_default().push_back(id);
+        }
+        let (reply, receiver) = oneshot::channel();
+        blocked.waiters.insert(id, Waiter { cmd, keys, reply });
+        return (id, receiver);
+    }
+
+    /// Stop waiting on the keys of a blocked client. Does nothing if the
+    /// client was already served.
+    pub(super) fn unblock(&mut self, id: u64) {
+        self.take_waiter(id);
+    }
+
+    fn take_waiter(&mut self, id: u64) -> Option<Waiter> {
+        let blocked = &mut self.blocked;
+        let waiter = blocked.waiters.remove(&id)?;
+        for key in &waiter.keys {
+            let queue = blocked.queues.get_mut(key).unwrap();
+            queue.retain(|waiting| *waiting != id);
+            if queue.is_empty() {
+                blocked.queues.remove(key);
+            }
+        }
+        return Some(waiter);
+    }
+
+    /// Record that a key received data. Clients blocked on it are served by
+    /// the next call to serve_blocked.
+    pub(super) fn signal_ready(&mut self, key: &Bytes) {
+        let blocked = &mut self.blocked;
+        if blocked.queues.contains_key(key) && !blocked.ready.contains(key) {
+            blocked.ready.push_back(key.clone());
+        }
+    }
+
+    /// Serve the clients blocked on the keys that received data, oldest first,
+    /// for as long as the keys hold data. Serving a client may push onto
+    /// another key, whose clients are served in turn. Clients waiting for
+    /// another type than the key holds stay blocked, like in Redis, and so do
+    /// clients whose command still finds nothing, such as an XREAD after an
+    /// ID greater than the new entries.
+    pub(super) fn serve_blocked(&mut self, now: i64) {
+        while let Some(key) = self.blocked.ready.pop_front() {
+            let Some(queue) = self.blocked.queues.get(&key) else {
+                continue;
+            };
+            for id in queue.clone() {
+                if self.peek(&key, now).is_none() {
+                    break;
+                }
+                let val = &self.entries[&key].val;
+                // The client was served through another key
+                let Some(waiter) = self.blocked.waiters.get(&id) else {
+                    continue;
+                };
+                if !waits_for(&waiter.cmd, val) {
+                    continue;
+                }
+                // The client disconnected without unblocking yet
+                if waiter.reply.is_closed() {
+                    self.unblock(id);
+                    continue;
+                }
+                let reply = match execute(self, waiter.cmd.clone(), now) {
+                    Ok(Frame::Null) => continue,
+                    Ok(reply) => reply,
+                    Err(err) => err.to_frame(),
+                };
+                let waiter = self.take_waiter(id).unwrap();
+                let _ = waiter.reply.send(reply);
+            }
+        }
+    }
+}
+
+/// Return whether a blocked command can be served by a key holding the value
+fn waits_for(cmd: &Command, val: &Value) -> bool {
+    return matches!(
+        (cmd, val),
+        (Command::List(_), Value::List(_))
+            | (Command::SortedSet(_), Value::SortedSet(_))
+            | (Command::Stream(_), Value::Stream(_))
+    );
+}
+
+/// Return the keys a blocking command waits on and how long it waits, None
+/// meaning forever
+pub(super) fn blocking_keys(cmd: &Command) -> (Vec<Bytes>, Option<Duration>) {
+    return match cmd {
+        Command::List(cmd) => list::blocking_keys(cmd),
+        Command::SortedSet(cmd) => sorted_set::blocking_keys(cmd),
+        Command::Stream(cmd) => stream::blocking_keys(cmd),
+        cmd => panic!("{cmd:?} does not block"),
+    };
+}
+
+/// Execute a blocking command once, which replies with Null if none of its
+/// keys hold data
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: Command,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        Command::List(cmd) => list::execute(keyspace, cmd, now),
+        Command::SortedSet(cmd) => sorted_set::execute(keyspace, cmd, now),
+        Command::Stream(cmd) => stream::execute(keyspace, cmd, now),
+        cmd => panic!("{cmd:?} does not block"),
+    };
+}
+
+/// Prepare a blocking command for waiting, once it found nothing to reply
+/// with. XREAD resolves its $ IDs to the last entries of the streams, so that
+/// it is served the entries added from now on.
+pub(super) fn pin(keyspace: &mut Keyspace, cmd: Command, now: i64) -> Command {
+    return match cmd {
+        Command::Stream(cmd) => Command::Stream(stream::pin_last_ids(keyspace, cmd, now)),
+        cmd => cmd,
+    };
+}
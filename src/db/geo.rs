@@ -0,0 +1,265 @@
+//! Execution of the commands that operate on sorted sets as geospatial
+//! indexes, whose members are scored with the geohash of their coordinates
+use super::geohash::{self, GeoHash};
+use super::{sorted_set, Keyspace, SortedSet};
+use crate::command::{
+    CommandError, GeoCommand, GeoOrigin, GeoSearch, GeoShape, Order, Range, ScoreBound,
+    SortedSetCommand,
+};
+use crate::Frame;
+use bytes::Bytes;
+
+/// A member found by a search
+struct Match {
+    member: Bytes,
+    score: f64,
+    longitude: f64,
+    latitude: f64,
+    /// The distance from the center of the search, in meters
+    distance: f64,
+}
+
+/// Reply with coordinates as a pair of doubles
+fn coordinates_frame(longitude: f64, latitude: f64) -> Frame {
+    return Frame::Array(vec![Frame::Double(longitude), Frame::Double(latitude)]);
+}
+
+/// Reply with a distance the way Redis does, as a string with four decimals
+fn distance_frame(distance: f64) -> Frame {
+    return Frame::Bulk(Bytes::from(format!("{distance:.4}")));
+}
+
+/// Find the members of an index within the area of a search, centered on
+/// the given coordinates
+fn search(zset: &SortedSet, search: &GeoSearch, longitude: f64, latitude: f64) -> Vec<Match> {
+    let conversion = search.unit.meters();
+    // How far the area reaches east and west, and north and south, and its
+    // furthest point
+    let (width, height, radius) = match search.shape {
+        GeoShape::Radius(radius) => {
+            let radius = radius * conversion;
+            (radius, radius, radius)
+        }
+        GeoShape::Box { width, height } => (
+            conversion * (width / 2.0),
+            conversion * (height / 2.0),
+            ((width / 2.0) * (width / 2.0) + (height / 2.0) * (height / 2.0)).sqrt() * conversion,
+        ),
+    };
+    let within = |lon: f64, lat: f64| match search.shape {
+        GeoShape::Radius(radius) => {
+            let distance = geohash::distance(longitude, latitude, lon, lat);
+            return (distance <= radius * conversion).then_some(distance);
+        }
+        GeoShape::Box {
+            width: box_width,
+            height: box_height,
+        } => {
+            if geohash::latitude_distance(lat, latitude) > box_height * conversion / 2.0
+                || geohash::distance(lon, lat, longitude, lat) > box_width * conversion / 2.0
+            {
+                return None;
+            }
+            return Some(geohash::distance(longitude, latitude, lon, lat));
+        }
+    };
+    let limit = match search.any {
+        true => search.count,
+        false => None,
+    };
+
+    let mut matches = vec![];
+    for hash in geohash::search_boxes(longitude, latitude, width, height, radius) {
+        let (min, max) = hash.score_range();
+        let range = Range::Score {
+            min: ScoreBound {
+                score: min,
+                exclusive: false,
+            },
+            max: ScoreBound {
+                score: max,
+                exclusive: true,
+            },
+        };
+        for (member, score) in zset.range(&range, false, None) {
+            if limit.is_some_and(|limit| matches.len() >= limit) {
+                return matches;
+            }
+            let (lon, lat) = GeoHash::from_score(score).coordinates();
+            if let Some(distance) = within(lon, lat) {
+                matches.push(Match {
+                    member: member.clone(),
+                    score,
+                    longitude: lon,
+                    latitude: lat,
+                    distance,
+                });
+            }
+        }
+    }
+    return matches;
+}
+
+/// Run a search, resolving its center and applying its order and count.
+/// Return None if the key does not exist.
+fn run_search(
+    keyspace: &mut Keyspace,
+    key: &[u8],
+    search_args: &GeoSearch,
+    now: i64,
+) -> Result<Option<Vec<Match>>, CommandError> {
+    let Some(zset) = keyspace.get_zset(key, now)? else {
+        return Ok(None);
+    };
+    let (longitude, latitude) = match &search_args.origin {
+        GeoOrigin::Member(member) => match zset.score(member) {
+            Some(score) => GeoHash::from_score(score).coordinates(),
+            None => {
+                return Err(CommandError::Other(
+                    "ERR could not decode requested zset member".into(),
+                ))
+            }
+        },
+        GeoOrigin::Coordinates(coordinates) => (coordinates.longitude, coordinates.latitude),
+    };
+    let mut matches = search(zset, search_args, longitude, latitude);
+    // A COUNT picks the closest members unless any will do
+    let order = match search_args.order {
+        None if search_args.count.is_some() && !search_args.any => Some(Order::Asc),
+        order => order,
+    };
+    match order {
+        Some(Order::Asc) => matches.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        Some(Order::Desc) => matches.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+        None => {}
+    }
+    if let Some(count) = search_args.count {
+        matches.truncate(count);
+    }
+    return Ok(Some(matches));
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: GeoCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        GeoCommand::Add {
+            key,
+            condition,
+            changed,
+            items,
+        } => {
+            // Like in Redis, GEOADD is a ZADD of the geohashes
+            let pairs = items
+                .into_iter()
+                .map(|(coordinates, member)| {
+                    let hash = GeoHash::encode(
+                        coordinates.longitude,
+                        coordinates.latitude,
+                        geohash::STEP_MAX,
+                    );
+                    return (hash.score(), member);
+                })
+                .collect();
+            let cmd = SortedSetCommand::Add {
+                key,
+                condition,
+                comparison: None,
+                changed,
+                incr: false,
+                pairs,
+            };
+            sorted_set::execute(keyspace, cmd, now)
+        }
+        GeoCommand::Pos { key, members } => {
+            let zset = keyspace.get_zset(&key, now)?;
+            let positions = members
+                .iter()
+                .map(|member| match zset.and_then(|zset| zset.score(member)) {
+                    Some(score) => {
+                        let (longitude, latitude) = GeoHash::from_score(score).coordinates();
+                        coordinates_frame(longitude, latitude)
+                    }
+                    None => Frame::Null,
+                })
+                .collect();
+            Ok(Frame::Array(positions))
+        }
+        GeoCommand::Dist {
+            key,
+            member1,
+            member2,
+            unit,
+        } => {
+            let Some(zset) = keyspace.get_zset(&key, now)? else {
+                return Ok(Frame::Null);
+            };
+            let (Some(score1), Some(score2)) = (zset.score(&member1), zset.score(&member2)) else {
+                return Ok(Frame::Null);
+            };
+            let (lon1, lat1) = GeoHash::from_score(score1).coordinates();
+            let (lon2, lat2) = GeoHash::from_score(score2).coordinates();
+            let distance = geohash::distance(lon1, lat1, lon2, lat2);
+            Ok(distance_frame(distance / unit.meters()))
+        }
+        GeoCommand::Hash { key, members } => {
+            let zset = keyspace.get_zset(&key, now)?;
+            let hashes = members
+                .iter()
+                .map(|member| match zset.and_then(|zset| zset.score(member)) {
+                    Some(score) => Frame::Bulk(Bytes::from(GeoHash::from_score(score).standard())),
+                    None => Frame::Null,
+                })
+                .collect();
+            Ok(Frame::Array(hashes))
+        }
+        GeoCommand::Search { key, search } => {
+            let Some(matches) = run_search(keyspace, &key, &search, now)? else {
+                return Ok(Frame::Array(vec![]));
+            };
+            let conversion = search.unit.meters();
+            let replies = matches
+                .into_iter()
+                .map(|found| {
+                    if !(search.with_dist || search.with_hash || search.with_coord) {
+                        return Frame::Bulk(found.member);
+                    }
+                    let mut reply = vec![Frame::Bulk(found.member)];
+                    if search.with_dist {
+                        reply.push(distance_frame(found.distance / conversion));
+                    }
+                    if search.with_hash {
+                        reply.push(Frame::Integer(found.score as i64));
+                    }
+                    if search.with_coord {
+                        reply.push(coordinates_frame(found.longitude, found.latitude));
+                    }
+                    return Frame::Array(reply);
+                })
+                .collect();
+            Ok(Frame::Array(replies))
+        }
+        GeoCommand::SearchStore {
+            destination,
+            source,
+            search,
+            store_dist,
+        } => {
+            let matches = run_search(keyspace, &source, &search, now)?.unwrap_or_default();
+            let conversion = search.unit.meters();
+            let mut zset = SortedSet::new();
+            for found in matches {
+                let score = match store_dist {
+                    true => found.distance / conversion,
+                    false => found.score,
+                };
+                zset.insert(found.member, score);
+            }
+            Ok(Frame::Integer(
+                keyspace.store_zset(destination, zset, now) as i64
+            ))
+        }
+    };
+}
@@ -0,0 +1,386 @@
+//! Geohashes, which index coordinates in sorted sets the way Redis does
+//!
+//! A geohash halves the range of the longitude and of the latitude `step`
+//! times and interleaves the resulting bits, latitude in the even bits and
+//! longitude in the odd ones, so that nearby points share a prefix. Members
+//! of a geospatial index are scored with their geohash at 26 steps, which
+//! makes 52 bits that a double holds exactly. A search covers its area with
+//! the geohash box of the center and its eight neighbors, at a step that
+//! makes them at least as large as the area, and scans the range of scores
+//! of each box.
+use crate::command::{LATITUDE_MAX, LONGITUDE_MAX};
+
+/// The number of times the ranges are halved in the scores of members
+pub(super) const STEP_MAX: u32 = 26;
+
+/// The radius of the Earth Redis uses, in meters
+const EARTH_RADIUS: f64 = 6372797.560856;
+
+/// Half the circumference of the Earth at the equator in the Web Mercator
+/// projection, in meters
+const MERCATOR_MAX: f64 = 20037726.37;
+
+/// A range of coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Range {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The range of longitudes of the index, and of standard geohashes
+const LONGITUDES: Range = Range {
+    min: -LONGITUDE_MAX,
+    max: LONGITUDE_MAX,
+};
+
+/// The range of latitudes of the index
+const LATITUDES: Range = Range {
+    min: -LATITUDE_MAX,
+    max: LATITUDE_MAX,
+};
+
+/// The range of latitudes of standard geohashes
+const STANDARD_LATITUDES: Range = Range {
+    min: -90.0,
+    max: 90.0,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct GeoHash {
+    bits: u64,
+    step: u32,
+}
+
+/// The box of coordinates a geohash covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Area {
+    pub longitude: Range,
+    pub latitude: Range,
+}
+
+/// Spread the 32 bits of `x` over the even bits of the result
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000ffff0000ffff;
+    x = (x | (x << 8)) & 0x00ff00ff00ff00ff;
+    x = (x | (x << 4)) & 0x0f0f0f0f0f0f0f0f;
+    x = (x | (x << 2)) & 0x3333333333333333;
+    x = (x | (x << 1)) & 0x5555555555555555;
+    return x;
+}
+
+/// Gather the even bits of `x`, the reverse of `spread`
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555555555555555;
+    x = (x | (x >> 1)) & 0x3333333333333333;
+    x = (x | (x >> 2)) & 0x0f0f0f0f0f0f0f0f;
+    x = (x | (x >> 4)) & 0x00ff00ff00ff00ff;
+    x = (x | (x >> 8)) & 0x0000ffff0000ffff;
+    x = (x | (x >> 16)) & 0x00000000ffffffff;
+    return x as u32;
+}
+
+impl GeoHash {
+    /// Compute the geohash of coordinates within the given ranges
+    fn encode_in(
+        longitudes: Range,
+        latitudes: Range,
+        longitude: f64,
+        latitude: f64,
+        step: u32,
+    ) -> Self {
+        let scale = (1u64 << step) as f64;
+        let lat = (latitude - latitudes.min) / (latitudes.max - latitudes.min) * scale;
+        let lon = (longitude - longitudes.min) / (longitudes.max - longitudes.min) * scale;
+        return Self {
+            bits: spread(lat as u32) | (spread(lon as u32) << 1),
+            step,
+        };
+    }
+
+    /// Compute the geohash of coordinates within the index
+    pub fn encode(longitude: f64, latitude: f64, step: u32) -> Self {
+        return Self::encode_in(LONGITUDES, LATITUDES, longitude, latitude, step);
+    }
+
+    /// Return the geohash of a member of the index from its score
+    pub fn from_score(score: f64) -> Self {
+        return Self {
+            bits: score as u64,
+            step: STEP_MAX,
+        };
+    }
+
+    /// Return the geohash as the score of a member of the index, which only
+    /// makes sense at the largest step
+    pub fn score(&self) -> f64 {
+        return self.bits as f64;
+    }
+
+    /// Return the range of scores of the members within the box, from the
+    /// first score to the one past the last
+    pub fn score_range(&self) -> (f64, f64) {
+        let shift = 2 * (STEP_MAX - self.step);
+        return (
+            (self.bits << shift) as f64,
+            ((self.bits + 1) << shift) as f64,
+        );
+    }
+
+    fn decode_in(&self, longitudes: Range, latitudes: Range) -> Area {
+        let scale = (1u64 << self.step) as f64;
+        let (lat, lon) = (squash(self.bits) as f64, squash(self.bits >> 1) as f64);
+        let lat_width = latitudes.max - latitudes.min;
+        let lon_width = longitudes.max - longitudes.min;
+        return Area {
+            latitude: Range {
+                min: latitudes.min + lat / scale * lat_width,
+                max: latitudes.min + (lat + 1.0) / scale * lat_width,
+            },
+            longitude: Range {
+                min: longitudes.min + lon / scale * lon_width,
+                max: longitudes.min + (lon + 1.0) / scale * lon_width,
+            },
+        };
+    }
+
+    /// Return the box the geohash covers within the index
+    pub fn decode(&self) -> Area {
+        return self.decode_in(LONGITUDES, LATITUDES);
+    }
+
+    /// Return the coordinates at the center of the box the geohash covers
+    pub fn coordinates(&self) -> (f64, f64) {
+        let area = self.decode();
+        let longitude =
+            ((area.longitude.min + area.longitude.max) / 2.0).clamp(-LONGITUDE_MAX, LONGITUDE_MAX);
+        let latitude =
+            ((area.latitude.min + area.latitude.max) / 2.0).clamp(-LATITUDE_MAX, LATITUDE_MAX);
+        return (longitude, latitude);
+    }
+
+    /// Return the standard 11 character geohash of the coordinates this
+    /// geohash stands for, whose ranges cover the poles. Like Redis, only 52
+    /// bits are encoded and the last character is always "0".
+    pub fn standard(&self) -> String {
+        const ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+        let (longitude, latitude) = self.coordinates();
+        let hash = Self::encode_in(
+            LONGITUDES,
+            STANDARD_LATITUDES,
+            longitude,
+            latitude,
+            STEP_MAX,
+        );
+        return (0..11)
+            .map(|i| match i {
+                10 => '0',
+                _ => ALPHABET[((hash.bits >> (52 - (i + 1) * 5)) & 0x1f) as usize] as char,
+            })
+            .collect();
+    }
+
+    /// Move to the next box east, or west when `d` is negative
+    fn move_x(&self, d: i8) -> Self {
+        let width = 64 - 2 * self.step;
+        let mut x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let y = self.bits & 0x5555555555555555;
+        let zz = 0x5555555555555555u64 >> width;
+        if d > 0 {
+            x = x.wrapping_add(zz + 1);
+        } else {
+            x = (x | zz).wrapping_sub(zz + 1);
+        }
+        x &= 0xaaaaaaaaaaaaaaaa >> width;
+        return Self {
+            bits: x | y,
+            step: self.step,
+        };
+    }
+
+    /// Move to the next box north, or south when `d` is negative
+    fn move_y(&self, d: i8) -> Self {
+        let width = 64 - 2 * self.step;
+        let x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let mut y = self.bits & 0x5555555555555555;
+        let zz = 0xaaaaaaaaaaaaaaaau64 >> width;
+        if d > 0 {
+            y = y.wrapping_add(zz + 1);
+        } else {
+            y = (y | zz).wrapping_sub(zz + 1);
+        }
+        y &= 0x5555555555555555 >> width;
+        return Self {
+            bits: x | y,
+            step: self.step,
+        };
+    }
+}
+
+/// Radians per degree
+const RADIANS: f64 = std::f64::consts::PI / 180.0;
+
+fn radians(degrees: f64) -> f64 {
+    return degrees * RADIANS;
+}
+
+fn degrees(radians: f64) -> f64 {
+    return radians / RADIANS;
+}
+
+/// Return the distance between two latitudes along a meridian, in meters
+pub(super) fn latitude_distance(lat1: f64, lat2: f64) -> f64 {
+    return EARTH_RADIUS * (radians(lat2) - radians(lat1)).abs();
+}
+
+/// Return the great-circle distance between two points in meters, with the
+/// haversine formula
+pub(super) fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((radians(lon2) - radians(lon1)) / 2.0).sin();
+    if v == 0.0 {
+        return latitude_distance(lat1, lat2);
+    }
+    let (lat1, lat2) = (radians(lat1), radians(lat2));
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    return 2.0 * EARTH_RADIUS * a.sqrt().asin();
+}
+
+/// Estimate the step at which a geohash box is about as large as a radius
+fn estimate_step(radius: f64, latitude: f64) -> u32 {
+    if radius == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    let mut range = radius;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    // Make sure the radius is covered in most cases
+    step -= 2;
+    // Boxes get narrower towards the poles
+    if latitude.abs() > 66.0 {
+        step -= 1;
+        if latitude.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    return step.clamp(1, STEP_MAX as i32) as u32;
+}
+
+/// Return the geohash boxes that cover the area around a point reaching
+/// `width` meters east and west and `height` meters north and south, for a
+/// search whose furthest point is `radius` meters away. Boxes that cannot
+/// hold any point of the area are left out.
+pub(super) fn search_boxes(
+    longitude: f64,
+    latitude: f64,
+    width: f64,
+    height: f64,
+    radius: f64,
+) -> Vec<GeoHash> {
+    // The bounding box of the area, which is wider on the side closer to
+    // the pole
+    let lat_delta = degrees(height / EARTH_RADIUS);
+    let lon_delta_top = degrees(width / EARTH_RADIUS / radians(latitude + lat_delta).cos());
+    let lon_delta_bottom = degrees(width / EARTH_RADIUS / radians(latitude - lat_delta).cos());
+    let lon_delta = match latitude < 0.0 {
+        true => lon_delta_bottom,
+        false => lon_delta_top,
+    };
+    let (min_lon, max_lon) = (longitude - lon_delta, longitude + lon_delta);
+    let (min_lat, max_lat) = (latitude - lat_delta, latitude + lat_delta);
+
+    let neighbors = |hash: GeoHash| {
+        return [
+            hash,
+            hash.move_y(1),
+            hash.move_y(-1),
+            hash.move_x(1),
+            hash.move_x(-1),
+            hash.move_x(1).move_y(1),
+            hash.move_x(-1).move_y(1),
+            hash.move_x(1).move_y(-1),
+            hash.move_x(-1).move_y(-1),
+        ];
+    };
+    let mut step = estimate_step(radius, latitude);
+    let mut boxes = neighbors(GeoHash::encode(longitude, latitude, step));
+    // The estimate may be too fine when the point is close to the edge of
+    // its box, in which case the neighbors do not reach far enough
+    let [_, north, south, east, west, ..] = boxes.map(|hash| hash.decode());
+    if step > 1
+        && (north.latitude.max < max_lat
+            || south.latitude.min > min_lat
+            || east.longitude.max < max_lon
+            || west.longitude.min > min_lon)
+    {
+        step -= 1;
+        boxes = neighbors(GeoHash::encode(longitude, latitude, step));
+    }
+
+    // Leave out the neighbors on the sides the center box already covers
+    let area = boxes[0].decode();
+    let mut useless = [false; 9];
+    if step >= 2 {
+        let [_, n, s, e, w, ne, nw, se, sw] = &mut useless;
+        if area.latitude.min < min_lat {
+            (*s, *sw, *se) = (true, true, true);
+        }
+        if area.latitude.max > max_lat {
+            (*n, *ne, *nw) = (true, true, true);
+        }
+        if area.longitude.min < min_lon {
+            (*w, *sw, *nw) = (true, true, true);
+        }
+        if area.longitude.max > max_lon {
+            (*e, *se, *ne) = (true, true, true);
+        }
+    }
+    let mut result: Vec<GeoHash> = vec![];
+    for (hash, useless) in boxes.into_iter().zip(useless) {
+        // Large areas wrap around, and neighbors can be the same box
+        if !useless && !result.contains(&hash) {
+            result.push(hash);
+        }
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geohash() {
+        // The scores and geohashes Redis gives Palermo and Catania
+        let palermo = GeoHash::encode(13.361389, 38.115556, STEP_MAX);
+        assert_eq!(palermo.score(), 3479099956230698.0);
+        let catania = GeoHash::encode(15.087269, 37.502669, STEP_MAX);
+        assert_eq!(catania.score(), 3479447370796909.0);
+        assert_eq!(palermo.standard(), "sqc8b49rny0");
+        assert_eq!(catania.standard(), "sqdtr74hyu0");
+
+        let (longitude, latitude) = GeoHash::from_score(palermo.score()).coordinates();
+        assert_eq!(format!("{longitude:.17}"), "13.36138933897018433");
+        assert_eq!(format!("{latitude:.17}"), "38.11555639549629859");
+        let (lon2, lat2) = catania.coordinates();
+        assert_eq!(
+            format!("{:.4}", distance(longitude, latitude, lon2, lat2)),
+            "166274.1516"
+        );
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let hash = GeoHash::encode(0.0, 0.0, 4);
+        let area = hash.decode();
+        let north = hash.move_y(1).decode();
+        assert_eq!(north.latitude.min, area.latitude.max);
+        assert_eq!(north.longitude, area.longitude);
+        let west = hash.move_x(-1).decode();
+        assert_eq!(west.longitude.max, area.longitude.min);
+        assert_eq!(west.latitude, area.latitude);
+    }
+}
@@ -0,0 +1,601 @@
+//! The hash value type, and the execution of the commands that operate on it
+use super::{glob_match, Config, Entry, Keyspace, ScanTable, Value};
+use crate::command::{parse_float, parse_integer, CommandError, HashCommand};
+use crate::Frame;
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap};
+
+/// A hash value. Small hashes are kept as a flat list of field value pairs,
+/// like the listpack Redis uses, which is smaller and about as fast as a hash
+/// table for a handful of short fields. A hash that outgrows the limits in the
+/// Config is converted into a hash table for good.
+///
+/// Fields may expire on their own. Expired fields are removed by
+/// `purge_expired`, which the keyspace calls before any command reads the
+/// hash, so they are never visible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hash {
+    fields: Fields,
+    /// The fields that have an expiry, and the time they expire at
+    expires: HashMap<Bytes, i64>,
+    /// The same expiries, ordered by time
+    expiry_order: BTreeSet<(i64, Bytes)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Fields {
+    Listpack(Vec<(Bytes, Bytes)>),
+    Table(ScanTable<Bytes>),
+}
+
+impl Hash {
+    fn new() -> Self {
+        return Self {
+            fields: Fields::Listpack(Vec::new()),
+            expires: HashMap::new(),
+            expiry_order: BTreeSet::new(),
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return match &self.fields {
+            Fields::Listpack(pairs) => pairs.len(),
+            Fields::Table(table) => table.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Return the value of a field
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        return match &self.fields {
+            Fields::Listpack(pairs) => pairs.iter().find(|(f, _)| f == field).map(|(_, v)| v),
+            Fields::Table(table) => table.get(field),
+        };
+    }
+
+    /// Iterate over the fields and their values. Listpacks keep the order the
+    /// fields were added in.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&Bytes, &Bytes)> + '_> {
+        return match &self.fields {
+            Fields::Listpack(pairs) => Box::new(pairs.iter().map(|(f, v)| (f, v))),
+            Fields::Table(table) => Box::new(table.iter()),
+        };
+    }
+
+    /// Return a page of a scan over the fields and their values, and the
+    /// cursor to continue from. Listpacks are small enough to return in one
+    /// go, like in Redis.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &Bytes)>) {
+        return match &self.fields {
+            Fields::Listpack(_) => (0, self.iter().collect()),
+            Fields::Table(table) => table.page(cursor, count),
+        };
+    }
+
+    /// Return the name of the encoding, as reported by OBJECT ENCODING
+    pub fn encoding(&self) -> &'static str {
+        return match &self.fields {
+            Fields::Listpack(_) if self.expires.is_empty() => "listpack",
+            Fields::Listpack(_) => "listpackex",
+            Fields::Table(_) => "hashtable",
+        };
+    }
+
+    /// Return the time a field expires at, if it has an expiry
+    pub fn expiry(&self, field: &[u8]) -> Option<i64> {
+        return self.expires.get(field).copied();
+    }
+
+    /// Return the time the first field to expire expires at
+    pub fn next_expiry(&self) -> Option<i64> {
+        return self.expiry_order.first().map(|(expires_at, _)| *expires_at);
+    }
+
+    /// Set a field and remove its expiry, like HSET does. Return whether the
+    /// field is new.
+    fn insert(&mut self, field: Bytes, val: Bytes, config: &Config) -> bool {
+        self.set_expiry(&field, None);
+        return self.update(field, val, config);
+    }
+
+    /// Set a field, keeping its expiry, converting the hash into a hash table
+    /// if it outgrows the listpack limits. Return whether the field is new.
+    fn update(&mut self, field: Bytes, val: Bytes, config: &Config) -> bool {
+        if let Fields::Listpack(pairs) = &mut self.fields {
+            let too_long = field.len().max(val.len()) > config.hash_max_listpack_value;
+            let too_many = pairs.len() >= config.hash_max_listpack_entries
+                && !pairs.iter().any(|(f, _)| *f == field);
+            if too_long || too_many {
+                self.fields = Fields::Table(pairs.drain(..).collect());
+            }
+        }
+        return match &mut self.fields {
+            Fields::Listpack(pairs) => match pairs.iter_mut().find(|(f, _)| *f == field) {
+                Some((_, old)) => {
+                    *old = val;
+                    false
+                }
+                None => {
+                    pairs.push((field, val));
+                    true
+                }
+            },
+            Fields::Table(table) => table.insert(field, val).is_none(),
+        };
+    }
+
+    /// Remove a field along with its expiry, returning its value
+    fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        self.set_expiry(field, None);
+        return match &mut self.fields {
+            Fields::Listpack(pairs) => {
+                let index = pairs.iter().position(|(f, _)| f == field)?;
+                Some(pairs.remove(index).1)
+            }
+            Fields::Table(table) => table.remove(field),
+        };
+    }
+
+    /// Change the expiry of a field, None meaning it never expires
+    fn set_expiry(&mut self, field: &[u8], expires_at: Option<i64>) {
+        if let Some((field, old)) = self.expires.remove_entry(field) {
+            self.expiry_order.remove(&(old, field));
+        }
+        if let Some(expires_at) = expires_at {
+            let field = Bytes::copy_from_slice(field);
+            self.expires.insert(field.clone(), expires_at);
+            self.expiry_order.insert((expires_at, field));
+        }
+    }
+
+    /// Remove the fields that expired by `now`, and return how many there were
+    fn purge_expired(&mut self, now: i64) -> usize {
+        let mut removed = 0;
+        while let Some((expires_at, field)) = self.expiry_order.first().cloned() {
+            if expires_at > now {
+                break;
+            }
+            self.remove(&field);
+            removed += 1;
+        }
+        return removed;
+    }
+}
+
+impl Keyspace {
+    /// Remove the fields of the hash stored under a key that expired by `now`,
+    /// and the key itself if no fields are left. Return how many fields were
+    /// removed.
+    pub(super) fn expire_fields(&mut self, key: &[u8], now: i64) -> usize {
+        let removed = match self.entries.get_mut(key) {
+            Some(Entry {
+                val: Value::Hash(hash),
+                ..
+            }) if hash
+                .next_expiry()
+                .is_some_and(|expires_at| expires_at <= now) =>
+            {
+                hash.purge_expired(now)
+            }
+            _ => 0,
+        };
+        if removed > 0 {
+            self.stats.expired_fields += removed as u64;
+            self.remove_if_empty(key, now);
+            self.reindex_fields(key);
+        }
+        return removed;
+    }
+
+    /// Bring the entry of a key in the index of hashes with expiring fields up
+    /// to date. The entry may be left too early when fields lose their expiry,
+    /// which only costs `remove_expired_fields` a wasted visit, but it must be
+    /// updated whenever a field gains an earlier expiry.
+    pub(super) fn reindex_fields(&mut self, key: &[u8]) {
+        let next_expiry = match self.entries.get(key) {
+            Some(Entry {
+                val: Value::Hash(hash),
+                ..
+            }) => hash.next_expiry(),
+            _ => None,
+        };
+        if self.field_expires_at.get(key).copied() == next_expiry {
+            return;
+        }
+        let key = Bytes::copy_from_slice(key);
+        if let Some(old) = self.field_expires_at.remove(&key) {
+            self.field_expires.remove(&(old, key.clone()));
+        }
+        if let Some(new) = next_expiry {
+            self.field_expires_at.insert(key.clone(), new);
+            self.field_expires.insert((new, key));
+        }
+    }
+
+    /// Remove expired hash fields, the hashes with the earliest expiries
+    /// first, until at least `limit` fields were removed or none are left.
+    /// Return how many were removed.
+    pub(super) fn remove_expired_fields(&mut self, now: i64, limit: usize) -> usize {
+        let mut removed = 0;
+        while removed < limit {
+            let key = match self.field_expires.first() {
+                Some((expires_at, key)) if *expires_at <= now => key.clone(),
+                _ => break,
+            };
+            removed += self.expire_fields(&key, now);
+            // The entry was too early, no field expired yet
+            self.reindex_fields(&key);
+        }
+        return removed;
+    }
+
+    /// Return the hash stored under a key, if there is one
+    fn get_hash(&mut self, key: &[u8], now: i64) -> Result<Option<&Hash>, CommandError> {
+        self.expire_fields(key, now);
+        return match self.get(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the hash stored under a key for changing it, if there is one.
+    /// The key must be removed if the hash ends up empty.
+    fn get_hash_mut(&mut self, key: &[u8], now: i64) -> Result<Option<&mut Hash>, CommandError> {
+        self.expire_fields(key, now);
+        return match self.get_mut(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the hash stored under a key for changing it, creating an empty
+    /// hash if the key does not exist
+    fn get_or_insert_hash(&mut self, key: &Bytes, now: i64) -> Result<&mut Hash, CommandError> {
+        if self.get_hash(key, now)?.is_none() {
+            self.insert(key.clone(), Value::Hash(Hash::new()), None, now);
+        }
+        return Ok(self.get_hash_mut(key, now)?.unwrap());
+    }
+}
+
+/// Reply with the value of a field if there is one, or Null otherwise
+fn bulk_or_null(val: Option<&Bytes>) -> Frame {
+    return match val {
+        Some(val) => Frame::Bulk(val.clone()),
+        None => Frame::Null,
+    };
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: HashCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    let config = keyspace.config.clone();
+    return match cmd {
+        HashCommand::Set { key, pairs } => {
+            let hash = keyspace.get_or_insert_hash(&key, now)?;
+            let mut added = 0;
+            for (field, val) in pairs {
+                added += hash.insert(field, val, &config) as i64;
+            }
+            Ok(Frame::Integer(added))
+        }
+        HashCommand::MSet { key, pairs } => {
+            let hash = keyspace.get_or_insert_hash(&key, now)?;
+            for (field, val) in pairs {
+                hash.insert(field, val, &config);
+            }
+            Ok(Frame::Simple("OK".into()))
+        }
+        HashCommand::SetNx { key, field, val } => {
+            let hash = keyspace.get_or_insert_hash(&key, now)?;
+            if hash.get(&field).is_some() {
+                return Ok(Frame::Integer(0));
+            }
+            hash.insert(field, val, &config);
+            Ok(Frame::Integer(1))
+        }
+        HashCommand::Get { key, field } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            Ok(bulk_or_null(hash.and_then(|hash| hash.get(&field))))
+        }
+        HashCommand::MGet { key, fields } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let vals = fields
+                .iter()
+                .map(|field| bulk_or_null(hash.and_then(|hash| hash.get(field))));
+            Ok(Frame::Array(vals.collect()))
+        }
+        HashCommand::Del { key, fields } => {
+            let Some(hash) = keyspace.get_hash_mut(&key, now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            let removed = fields
+                .iter()
+                .filter(|field| hash.remove(field).is_some())
+                .count();
+            keyspace.remove_if_empty(&key, now);
+            Ok(Frame::Integer(removed as i64))
+        }
+        HashCommand::Exists { key, field } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let exists = hash.is_some_and(|hash| hash.get(&field).is_some());
+            Ok(Frame::Integer(exists as i64))
+        }
+        HashCommand::Len { key } => {
+            let len = keyspace.get_hash(&key, now)?.map_or(0, |hash| hash.len());
+            Ok(Frame::Integer(len as i64))
+        }
+        HashCommand::StrLen { key, field } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let len = hash
+                .and_then(|hash| hash.get(&field))
+                .map_or(0, |val| val.len());
+            Ok(Frame::Integer(len as i64))
+        }
+        HashCommand::Keys { key } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let fields = hash
+                .into_iter()
+                .flat_map(|hash| hash.iter())
+                .map(|(field, _)| Frame::Bulk(field.clone()));
+            Ok(Frame::Array(fields.collect()))
+        }
+        HashCommand::Vals { key } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let vals = hash
+                .into_iter()
+                .flat_map(|hash| hash.iter())
+                .map(|(_, val)| Frame::Bulk(val.clone()));
+            Ok(Frame::Array(vals.collect()))
+        }
+        HashCommand::GetAll { key } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let pairs = hash
+                .into_iter()
+                .flat_map(|hash| hash.iter())
+                .map(|(field, val)| (Frame::Bulk(field.clone()), Frame::Bulk(val.clone())));
+            Ok(Frame::Map(pairs.collect()))
+        }
+        HashCommand::IncrBy { key, field, delta } => {
+            let hash = keyspace.get_or_insert_hash(&key, now)?;
+            let old = match hash.get(&field) {
+                Some(val) => parse_integer(val).ok_or_else(|| {
+                    CommandError::Other("ERR hash value is not an integer".into())
+                })?,
+                None => 0,
+            };
+            let new = old.checked_add(delta).ok_or_else(|| {
+                CommandError::Other("ERR increment or decrement would overflow".into())
+            })?;
+            hash.update(field, Bytes::from(new.to_string()), &config);
+            Ok(Frame::Integer(new))
+        }
+        HashCommand::IncrByFloat { key, field, delta } => {
+            let hash = keyspace.get_or_insert_hash(&key, now)?;
+            let old = match hash.get(&field) {
+                Some(val) => parse_float(val)
+                    .ok_or_else(|| CommandError::Other("ERR hash value is not a float".into()))?,
+                None => 0.0,
+            };
+            let new = old + delta;
+            if !new.is_finite() {
+                return Err(CommandError::Other(
+                    "ERR increment would produce NaN or Infinity".into(),
+                ));
+            }
+            let new = Bytes::from(new.to_string());
+            hash.update(field, new.clone(), &config);
+            Ok(Frame::Bulk(new))
+        }
+        HashCommand::RandField {
+            key,
+            count,
+            with_values,
+        } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let mut pairs: Vec<(Bytes, Bytes)> = hash
+                .into_iter()
+                .flat_map(|hash| hash.iter())
+                .map(|(field, val)| (field.clone(), val.clone()))
+                .collect();
+            let Some(count) = count else {
+                if pairs.is_empty() {
+                    return Ok(Frame::Null);
+                }
+                let index = (keyspace.random() * pairs.len() as f64) as usize;
+                return Ok(Frame::Bulk(pairs.swap_remove(index).0));
+            };
+            let picked = match count {
+                _ if pairs.is_empty() => vec![],
+                // The same field may be picked several times
+                count if count < 0 => (0..count.unsigned_abs())
+                    .map(|_| {
+                        let index = (keyspace.random() * pairs.len() as f64) as usize;
+                        return pairs[index].clone();
+                    })
+                    .collect(),
+                // Shuffle just enough of the fields to pick distinct ones
+                count => {
+                    let count = (count as usize).min(pairs.len());
+                    for i in 0..count {
+                        let j = i + (keyspace.random() * (pairs.len() - i) as f64) as usize;
+                        pairs.swap(i, j);
+                    }
+                    pairs.truncate(count);
+                    pairs
+                }
+            };
+            let reply = picked
+                .into_iter()
+                .flat_map(|(field, val)| match with_values {
+                    true => vec![Frame::Bulk(field), Frame::Bulk(val)],
+                    false => vec![Frame::Bulk(field)],
+                });
+            Ok(Frame::Array(reply.collect()))
+        }
+        HashCommand::Scan {
+            key,
+            cursor,
+            pattern,
+            count,
+            no_values,
+        } => {
+            let hash = keyspace.get_hash(&key, now)?;
+            let (cursor, page) = match hash {
+                None => (0, vec![]),
+                Some(hash) => hash.scan(cursor, count),
+            };
+            let mut elems = vec![];
+            for (field, val) in page {
+                if pattern
+                    .as_ref()
+                    .is_some_and(|pattern| !glob_match(pattern, field))
+                {
+                    continue;
+                }
+                elems.push(Frame::Bulk(field.clone()));
+                if !no_values {
+                    elems.push(Frame::Bulk(val.clone()));
+                }
+            }
+            Ok(Frame::Array(vec![
+                Frame::Bulk(Bytes::from(cursor.to_string())),
+                Frame::Array(elems),
+            ]))
+        }
+        HashCommand::Expire {
+            key,
+            expiry,
+            condition,
+            fields,
+        } => {
+            let deadline = expiry.deadline(now).ok_or_else(|| {
+                CommandError::Other(format!(
+                    "ERR invalid expire time in 'h{}' command",
+                    expiry.command_name().to_ascii_lowercase()
+                ))
+            })?;
+            let Some(hash) = keyspace.get_hash_mut(&key, now)? else {
+                return Ok(Frame::Array(vec![Frame::Integer(-2); fields.len()]));
+            };
+            let mut replies = Vec::with_capacity(fields.len());
+            for field in &fields {
+                let reply = if hash.get(field).is_none() {
+                    -2
+                } else if condition
+                    .is_some_and(|condition| !condition.allows(hash.expiry(field), deadline))
+                {
+                    0
+                } else if deadline <= now {
+                    hash.remove(field);
+                    2
+                } else {
+                    hash.set_expiry(field, Some(deadline));
+                    1
+                };
+                replies.push(Frame::Integer(reply));
+            }
+            keyspace.remove_if_empty(&key, now);
+            keyspace.reindex_fields(&key);
+            Ok(Frame::Array(replies))
+        }
+        HashCommand::Ttl { key, fields } => {
+            field_ttls(keyspace, &key, &fields, now, |ms| (ms - now + 999) / 1000)
+        }
+        HashCommand::Pttl { key, fields } => {
+            field_ttls(keyspace, &key, &fields, now, |ms| ms - now)
+        }
+        HashCommand::ExpireTime { key, fields } => {
+            field_ttls(keyspace, &key, &fields, now, |ms| (ms + 999) / 1000)
+        }
+        HashCommand::PexpireTime { key, fields } => {
+            field_ttls(keyspace, &key, &fields, now, |ms| ms)
+        }
+        HashCommand::Persist { key, fields } => {
+            let Some(hash) = keyspace.get_hash_mut(&key, now)? else {
+                return Ok(Frame::Array(vec![Frame::Integer(-2); fields.len()]));
+            };
+            let replies = fields.iter().map(|field| {
+                let reply = match (hash.get(field), hash.expiry(field)) {
+                    (None, _) => -2,
+                    (Some(_), None) => -1,
+                    (Some(_), Some(_)) => {
+                        hash.set_expiry(field, None);
+                        1
+                    }
+                };
+                return Frame::Integer(reply);
+            });
+            Ok(Frame::Array(replies.collect()))
+        }
+        HashCommand::GetEx {
+            key,
+            expiry,
+            persist,
+            fields,
+        } => {
+            let deadline = match expiry {
+                Some(expiry) => Some(expiry.deadline(now).ok_or_else(|| {
+                    CommandError::Other("ERR invalid expire time in 'hgetex' command".into())
+                })?),
+                None => None,
+            };
+            let Some(hash) = keyspace.get_hash_mut(&key, now)? else {
+                return Ok(Frame::Array(vec![Frame::Null; fields.len()]));
+            };
+            let mut vals = Vec::with_capacity(fields.len());
+            for field in &fields {
+                let val = hash.get(field).cloned();
+                if val.is_some() {
+                    match deadline {
+                        Some(deadline) if deadline <= now => {
+                            hash.remove(field);
+                        }
+                        Some(deadline) => hash.set_expiry(field, Some(deadline)),
+                        None if persist => hash.set_expiry(field, None),
+                        None => {}
+                    }
+                }
+                vals.push(val.map_or(Frame::Null, Frame::Bulk));
+            }
+            keyspace.remove_if_empty(&key, now);
+            keyspace.reindex_fields(&key);
+            Ok(Frame::Array(vals))
+        }
+    };
+}
+
+/// Reply to the HTTL family with an integer for each field: -2 if the field
+/// does not exist, -1 if it never expires, and otherwise its expiry converted
+/// by `convert`
+fn field_ttls(
+    keyspace: &mut Keyspace,
+    key: &[u8],
+    fields: &[Bytes],
+    now: i64,
+    convert: impl Fn(i64) -> i64,
+) -> Result<Frame, CommandError> {
+    let hash = keyspace.get_hash(key, now)?;
+    let replies = fields.iter().map(|field| {
+        let reply = match hash.map(|hash| (hash.get(field), hash.expiry(field))) {
+            None | Some((None, _)) => -2,
+            Some((Some(_), None)) => -1,
+            Some((Some(_), Some(expires_at))) => convert(expires_at).max(0),
+        };
+        return Frame::Integer(reply);
+    });
+    return Ok(Frame::Array(replies.collect()));
+}
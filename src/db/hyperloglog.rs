@@ -0,0 +1,462 @@
+//! HyperLogLog values, and the execution of the commands that operate on them
+//!
+//! A HyperLogLog is stored as a string in the format Redis uses, so that its
+//! bytes can be moved between this server and Redis with GET and SET. The
+//! string starts with a 16 byte header: the magic "HYLL", the encoding, three
+//! unused bytes and the cached estimate as a little endian integer, whose
+//! most significant bit is set when the cache is stale. The 16384 registers
+//! of 6 bits follow, either packed one after the other in the dense encoding,
+//! or run-length encoded in the sparse one, which is used until the string
+//! grows past `hll_sparse_max_bytes` or a register goes past 32.
+use super::{Config, Keyspace};
+use crate::command::{CommandError, HyperLogLogCommand};
+use crate::Frame;
+use bytes::Bytes;
+
+const MAGIC: &[u8] = b"HYLL";
+
+const HEADER_LEN: usize = 16;
+
+/// The number of bits of a hash that pick its register
+const P: u32 = 14;
+
+const REGISTERS: usize = 1 << P;
+
+/// The number of bits of a hash whose run of zeros is counted
+const Q: u32 = 64 - P;
+
+/// The width of a register in the dense encoding
+const REGISTER_BITS: usize = 6;
+
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+/// The largest register value the sparse VAL opcode can hold
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+
+/// The most registers the sparse VAL, ZERO and XZERO opcodes can cover
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+
+/// The bias correction of the estimator for a large number of registers
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// The seed Redis hashes elements with
+const HASH_SEED: u64 = 0xadc8_3b19;
+
+/// A HyperLogLog decoded from its string
+#[derive(Debug, Clone, PartialEq)]
+struct HyperLogLog {
+    registers: Vec<u8>,
+    /// Whether the string uses the dense encoding. A HyperLogLog never goes
+    /// back to the sparse encoding once dense.
+    dense: bool,
+    /// The cached estimate, if it is up to date
+    cached: Option<u64>,
+}
+
+fn not_hll() -> CommandError {
+    return CommandError::Other("WRONGTYPE Key is not a valid HyperLogLog string value.".into());
+}
+
+fn corrupted() -> CommandError {
+    return CommandError::Other("INVALIDOBJ Corrupted HLL object detected".into());
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        return Self {
+            registers: vec![0; REGISTERS],
+            dense: false,
+            cached: Some(0),
+        };
+    }
+
+    /// Decode the string of a HyperLogLog
+    fn decode(s: &[u8]) -> Result<Self, CommandError> {
+        if s.len() < HEADER_LEN || &s[..4] != MAGIC {
+            return Err(not_hll());
+        }
+        let cached = match s[15] & 0x80 {
+            0 => Some(u64::from_le_bytes(s[8..16].try_into().unwrap())),
+            _ => None,
+        };
+        let registers = match s[4] {
+            DENSE if s.len() == DENSE_LEN => (0..REGISTERS)
+                .map(|i| dense_register(&s[HEADER_LEN..], i))
+                .collect(),
+            SPARSE => decode_sparse(&s[HEADER_LEN..]).ok_or_else(corrupted)?,
+            _ => return Err(not_hll()),
+        };
+        return Ok(Self {
+            registers,
+            dense: s[4] == DENSE,
+            cached,
+        });
+    }
+
+    /// Encode the HyperLogLog into a string, switching to the dense encoding
+    /// if the sparse one does not fit
+    fn encode(&mut self, config: &Config) -> Bytes {
+        let sparse = match self.dense {
+            true => None,
+            false => encode_sparse(&self.registers)
+                .filter(|data| HEADER_LEN + data.len() <= config.hll_sparse_max_bytes),
+        };
+        self.dense = sparse.is_none();
+        let mut s = Vec::with_capacity(DENSE_LEN);
+        s.extend_from_slice(MAGIC);
+        s.extend_from_slice(&[if self.dense { DENSE } else { SPARSE }, 0, 0, 0]);
+        match self.cached {
+            Some(count) => s.extend_from_slice(&count.to_le_bytes()),
+            None => s.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80]),
+        }
+        match sparse {
+            Some(data) => s.extend_from_slice(&data),
+            None => {
+                s.resize(DENSE_LEN, 0);
+                for (i, &val) in self.registers.iter().enumerate() {
+                    set_dense_register(&mut s[HEADER_LEN..], i, val);
+                }
+            }
+        }
+        return Bytes::from(s);
+    }
+
+    /// Add an element, returning whether a register changed
+    fn add(&mut self, elem: &[u8]) -> bool {
+        let (index, count) = hash_element(elem);
+        if self.registers[index] >= count {
+            return false;
+        }
+        self.registers[index] = count;
+        self.cached = None;
+        return true;
+    }
+
+    /// Raise the registers to those of another HyperLogLog
+    fn merge(&mut self, other: &Self) {
+        for (reg, &val) in self.registers.iter_mut().zip(&other.registers) {
+            *reg = (*reg).max(val);
+        }
+        self.dense |= other.dense;
+        self.cached = None;
+    }
+
+    fn count(&self) -> u64 {
+        return estimate(&self.registers);
+    }
+}
+
+/// Read a register of the dense encoding, where register `i` starts at bit
+/// `6 * i`, least significant bits first
+fn dense_register(data: &[u8], i: usize) -> u8 {
+    let (byte, bit) = (i * REGISTER_BITS / 8, i * REGISTER_BITS % 8);
+    let low = data[byte] >> bit;
+    let high = match bit > 8 - REGISTER_BITS {
+        true => data[byte + 1] << (8 - bit),
+        false => 0,
+    };
+    return (low | high) & REGISTER_MAX;
+}
+
+fn set_dense_register(data: &mut [u8], i: usize, val: u8) {
+    let (byte, bit) = (i * REGISTER_BITS / 8, i * REGISTER_BITS % 8);
+    data[byte] &= !(REGISTER_MAX << bit);
+    data[byte] |= val << bit;
+    if bit > 8 - REGISTER_BITS {
+        data[byte + 1] &= !(REGISTER_MAX >> (8 - bit));
+        data[byte + 1] |= val >> (8 - bit);
+    }
+}
+
+/// Decode the opcodes of the sparse encoding:
+/// - ZERO, 00xxxxxx: 1 to 64 registers set to 0
+/// - XZERO, 01xxxxxx yyyyyyyy: 1 to 16384 registers set to 0
+/// - VAL, 1vvvvvxx: 1 to 4 registers set to 1 to 32
+///
+/// Return None if the opcodes do not cover every register exactly.
+fn decode_sparse(data: &[u8]) -> Option<Vec<u8>> {
+    let mut registers = Vec::with_capacity(REGISTERS);
+    let mut data = data.iter();
+    while let Some(&op) = data.next() {
+        match op >> 6 {
+            0b00 => registers.resize(registers.len() + (op & 0x3f) as usize + 1, 0),
+            0b01 => {
+                let len = ((op & 0x3f) as usize) << 8 | *data.next()? as usize;
+                registers.resize(registers.len() + len + 1, 0);
+            }
+            _ => {
+                let (val, len) = ((op >> 2) & 0x1f, (op & 0x03) as usize);
+                registers.resize(registers.len() + len + 1, val + 1);
+            }
+        }
+        if registers.len() > REGISTERS {
+            return None;
+        }
+    }
+    return (registers.len() == REGISTERS).then_some(registers);
+}
+
+/// Encode registers with the opcodes of the sparse encoding, or return None
+/// if a register is too large for it
+fn encode_sparse(registers: &[u8]) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    let mut i = 0;
+    while i < registers.len() {
+        let val = registers[i];
+        let run = registers[i..].iter().take_while(|&&reg| reg == val).count();
+        i += run;
+        let mut left = run;
+        while left > 0 {
+            if val > SPARSE_VAL_MAX_VALUE {
+                return None;
+            }
+            let len = match val {
+                0 if left > SPARSE_ZERO_MAX_LEN => {
+                    let len = left.min(SPARSE_XZERO_MAX_LEN);
+                    data.extend([0x40 | ((len - 1) >> 8) as u8, (len - 1) as u8]);
+                    len
+                }
+                0 => {
+                    data.push((left - 1) as u8);
+                    left
+                }
+                _ => {
+                    let len = left.min(SPARSE_VAL_MAX_LEN);
+                    data.push(0x80 | (val - 1) << 2 | (len - 1) as u8);
+                    len
+                }
+            };
+            left -= len;
+        }
+    }
+    return Some(data);
+}
+
+/// MurmurHash64A, the 64 bit hash of MurmurHash2, reading blocks as little
+/// endian integers
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut blocks = data.chunks_exact(8);
+    for block in &mut blocks {
+        let mut k = u64::from_le_bytes(block.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    return h;
+}
+
+/// Return the register an element falls into, and the length of the run of
+/// zeros in its hash plus one, which is what the register records at most
+fn hash_element(elem: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(elem, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // The extra bit caps the count at Q + 1
+    let hash = (hash >> P) | (1 << Q);
+    return (index, hash.trailing_zeros() as u8 + 1);
+}
+
+/// Estimate the number of distinct elements from the registers, with the
+/// estimator of Otmar Ertl that Redis uses, which needs no bias correction
+/// for small or large cardinalities
+fn estimate(registers: &[u8]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut histogram = [0u32; Q as usize + 2];
+    for &reg in registers {
+        histogram[reg as usize] += 1;
+    }
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for &count in histogram[1..=Q as usize].iter().rev() {
+        z += count as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    return (ALPHA_INF * m * m / z).round() as u64;
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if z == prev {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == prev {
+            return z / 3.0;
+        }
+    }
+}
+
+impl Keyspace {
+    /// Return the HyperLogLog stored under a key, if there is one
+    fn get_hll(&mut self, key: &[u8], now: i64) -> Result<Option<HyperLogLog>, CommandError> {
+        return match self.get_string(key, now)? {
+            Some(s) => Ok(Some(HyperLogLog::decode(s)?)),
+            None => Ok(None),
+        };
+    }
+
+    /// Store a HyperLogLog under a key, keeping the expiry if the key exists
+    fn set_hll(&mut self, key: Bytes, mut hll: HyperLogLog, now: i64) {
+        let s = hll.encode(&self.config);
+        self.set_value(key, super::Value::String(s), now);
+    }
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: HyperLogLogCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        HyperLogLogCommand::Add { key, elements } => {
+            let (mut hll, mut changed) = match keyspace.get_hll(&key, now)? {
+                Some(hll) => (hll, false),
+                None => (HyperLogLog::new(), true),
+            };
+            for elem in &elements {
+                changed |= hll.add(elem);
+            }
+            if changed {
+                keyspace.set_hll(key, hll, now);
+            }
+            Ok(Frame::Integer(changed as i64))
+        }
+        HyperLogLogCommand::Count { keys } if keys.len() == 1 => {
+            let Some(mut hll) = keyspace.get_hll(&keys[0], now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            if let Some(count) = hll.cached {
+                return Ok(Frame::Integer(count as i64));
+            }
+            // Cache the estimate in the string, like Redis
+            let count = hll.count();
+            hll.cached = Some(count);
+            let key = keys.into_iter().next().unwrap();
+            keyspace.set_hll(key, hll, now);
+            Ok(Frame::Integer(count as i64))
+        }
+        HyperLogLogCommand::Count { keys } => {
+            let mut union = HyperLogLog::new();
+            for key in keys {
+                if let Some(hll) = keyspace.get_hll(&key, now)? {
+                    union.merge(&hll);
+                }
+            }
+            Ok(Frame::Integer(union.count() as i64))
+        }
+        HyperLogLogCommand::Merge {
+            destination,
+            sources,
+        } => {
+            let mut union = HyperLogLog::new();
+            for key in std::iter::once(&destination).chain(&sources) {
+                if let Some(hll) = keyspace.get_hll(key, now)? {
+                    union.merge(&hll);
+                }
+            }
+            union.cached = None;
+            keyspace.set_hll(destination, union, now);
+            Ok(Frame::Simple("OK".into()))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_accuracy() {
+        let mut hll = HyperLogLog::new();
+        let mut added = 0;
+        for cardinality in [10, 100, 1000, 10_000, 100_000] {
+            while added < cardinality {
+                hll.add(format!("element:{added}").as_bytes());
+                added += 1;
+            }
+            let count = hll.count() as f64;
+            // The standard error is 0.81%, so this allows for more than
+            // four standard errors
+            let error = (count - cardinality as f64).abs() / cardinality as f64;
+            assert!(error < 0.035, "estimated {count} for {cardinality}");
+        }
+        // Adding the same elements again changes nothing
+        assert!(!hll.add(b"element:42"));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let config = Config::default();
+        let mut hll = HyperLogLog::new();
+        let s = hll.encode(&config);
+        // An empty sparse HyperLogLog is a single XZERO opcode for all
+        // 16384 registers, with a valid cache of 0
+        assert_eq!(&s[..], b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff");
+        assert_eq!(HyperLogLog::decode(&s), Ok(hll.clone()));
+
+        for n in 0..100 {
+            hll.add(format!("{n}").as_bytes());
+        }
+        let s = hll.encode(&config);
+        assert_eq!(s[4], SPARSE);
+        assert_eq!(s[15] & 0x80, 0x80);
+        assert_eq!(HyperLogLog::decode(&s).unwrap().registers, hll.registers);
+
+        for n in 100..5000 {
+            hll.add(format!("{n}").as_bytes());
+        }
+        let s = hll.encode(&config);
+        assert_eq!((s.len(), s[4]), (DENSE_LEN, DENSE));
+        let decoded = HyperLogLog::decode(&s).unwrap();
+        assert_eq!(decoded.registers, hll.registers);
+        assert!(decoded.dense);
+
+        assert_eq!(HyperLogLog::decode(b"HYLL"), Err(not_hll()));
+        assert_eq!(HyperLogLog::decode(&s[..100]), Err(not_hll()));
+        assert_eq!(
+            HyperLogLog::decode(b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x7f"),
+            Err(corrupted())
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Execution of the commands that operate on keys regardless of the type of
+//! their values
+use super::Keyspace;
+use crate::command::{CommandError, KeyCommand, ObjectSubcommand};
+use crate::Frame;
+use bytes::Bytes;
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: KeyCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        KeyCommand::Del { keys } | KeyCommand::Unlink { keys } => {
+            let removed = keys
+                .iter()
+                .filter(|key| keyspace.remove(key, now).is_some())
+                .count();
+            Ok(Frame::Integer(removed as i64))
+        }
+        KeyCommand::Exists { keys } => {
+            let found = keys
+                .iter()
+                .filter(|key| keyspace.peek(key, now).is_some())
+                .count();
+            Ok(Frame::Integer(found as i64))
+        }
+        KeyCommand::Touch { keys } => {
+            let found = keys
+                .iter()
+                .filter(|key| keyspace.get(key, now).is_some())
+                .count();
+            Ok(Frame::Integer(found as i64))
+        }
+        KeyCommand::Expire {
+            key,
+            expiry,
+            conditions,
+        } => {
+            let deadline = expiry.deadline(now).ok_or_else(|| {
+                CommandError::Other(format!(
+                    "ERR invalid expire time in '{}' command",
+                    expiry.command_name().to_ascii_lowercase()
+                ))
+            })?;
+            let Some(entry) = keyspace.get(&key, now) else {
+                return Ok(Frame::Integer(0));
+            };
+            let current = entry.expires_at;
+            if !conditions
+                .iter()
+                .all(|condition| condition.allows(current, deadline))
+            {
+                return Ok(Frame::Integer(0));
+            }
+            if deadline <= now {
+                keyspace.remove(&key, now);
+            } else {
+                keyspace.set_expiry(&key, Some(deadline));
+            }
+            Ok(Frame::Integer(1))
+        }
+        KeyCommand::Ttl { key } => Ok(ttl(keyspace, &key, now, |ms| (ms - now + 500) / 1000)),
+        KeyCommand::Pttl { key } => Ok(ttl(keyspace, &key, now, |ms| ms - now)),
+        KeyCommand::ExpireTime { key } => Ok(ttl(keyspace, &key, now, |ms| (ms + 500) / 1000)),
+        KeyCommand::PexpireTime { key } => Ok(ttl(keyspace, &key, now, |ms| ms)),
+        KeyCommand::Persist { key } => match keyspace.get(&key, now) {
+            Some(entry) if entry.expires_at.is_some() => {
+                keyspace.set_expiry(&key, None);
+                Ok(Frame::Integer(1))
+            }
+            _ => Ok(Frame::Integer(0)),
+        },
+        KeyCommand::Type { key } => {
+            let name = match keyspace.peek(&key, now) {
+                Some(entry) => entry.val.type_name(),
+                None => "none",
+            };
+            Ok(Frame::Simple(name.into()))
+        }
+        KeyCommand::Object { subcommand, key } => {
+            let Some(entry) = keyspace.peek(&key, now) else {
+                return Ok(Frame::Null);
+            };
+            Ok(match subcommand {
+                ObjectSubcommand::Encoding => Frame::Bulk(Bytes::from(entry.val.encoding())),
+                ObjectSubcommand::Freq => Frame::Integer(entry.decayed_freq(now) as i64),
+                ObjectSubcommand::IdleTime => {
+                    Frame::Integer((now - entry.accessed_at).max(0) / 1000)
+                }
+                ObjectSubcommand::RefCount => Frame::Integer(1),
+            })
+        }
+        KeyCommand::ObjectHelp => {
+            let lines = [
+                "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "ENCODING <key>",
+                "    Return the kind of internal representation used in order to store the value",
+                "    associated with a <key>.",
+                "FREQ <key>",
+                "    Return the access frequency index of the <key>. The returned integer is",
+                "    proportional to the logarithm of the recent access frequency of the key.",
+                "IDLETIME <key>",
+                "    Return the idle time of the <key>, that is the approximated number of",
+                "    seconds elapsed since the last access to the key.",
+                "REFCOUNT <key>",
+                "    Return the number of references of the value associated with the specified",
+                "    <key>.",
+                "HELP",
+                "    Print this help.",
+            ];
+            Ok(Frame::Array(
+                lines
+                    .iter()
+                    .map(|line| Frame::Simple(line.to_string()))
+                    .collect(),
+            ))
+        }
+    };
+}
+
+/// Reply to the TTL family: -2 if the key does not exist, -1 if it never
+/// expires, and otherwise its expiry converted by `convert`
+fn ttl(keyspace: &mut Keyspace, key: &[u8], now: i64, convert: impl Fn(i64) -> i64) -> Frame {
+    return match keyspace.peek(key, now).map(|entry| entry.expires_at) {
+        None => Frame::Integer(-2),
+        Some(None) => Frame::Integer(-1),
+        Some(Some(expires_at)) => Frame::Integer(convert(expires_at).max(0)),
+    };
+}
@@ -0,0 +1,360 @@
+//! Execution of the commands that operate on list values
+use super::{Entry, Keyspace, Value};
+use crate::command::{CommandError, ListCommand, Position, Side};
+use crate::Frame;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+impl Keyspace {
+    /// Return the list stored under a key, if there is one
+    fn get_list(&mut self, key: &[u8], now: i64) -> Result<Option<&VecDeque<Bytes>>, CommandError> {
+        return match self.get(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::List(list),
+                ..
+            }) => Ok(Some(list)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the list stored under a key for changing it, if there is one.
+    /// The key must be removed if the list ends up empty.
+    fn get_list_mut(
+        &mut self,
+        key: &[u8],
+        now: i64,
+    ) -> Result<Option<&mut VecDeque<Bytes>>, CommandError> {
+        return match self.get_mut(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::List(list),
+                ..
+            }) => Ok(Some(list)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the list stored under a key for changing it, creating an empty
+    /// list if the key does not exist. Clients blocked on the key are served
+    /// once the command is done.
+    fn get_or_insert_list(
+        &mut self,
+        key: &Bytes,
+        now: i64,
+    ) -> Result<&mut VecDeque<Bytes>, CommandError> {
+        if self.get_list(key, now)?.is_none() {
+            self.insert(key.clone(), Value::List(VecDeque::new()), None, now);
+            self.signal_ready(key);
+        }
+        return Ok(self.get_list_mut(key, now)?.unwrap());
+    }
+}
+
+/// Convert a start and stop index, either of which may count from the end,
+/// into an inclusive range of positions in a list of the given length. Return
+/// None if the range is empty.
+pub(super) fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 { len + stop } else { stop };
+    if start > stop || start >= len {
+        return None;
+    }
+    return Some((start as usize, stop.min(len - 1) as usize));
+}
+
+/// Convert an index that may count from the end into a position in a list of
+/// the given length
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index < 0 || index >= len as i64 {
+        return None;
+    }
+    return Some(index as usize);
+}
+
+fn pop(list: &mut VecDeque<Bytes>, side: Side) -> Option<Bytes> {
+    return match side {
+        Side::Left => list.pop_front(),
+        Side::Right => list.pop_back(),
+    };
+}
+
+fn push(list: &mut VecDeque<Bytes>, side: Side, elem: Bytes) {
+    match side {
+        Side::Left => list.push_front(elem),
+        Side::Right => list.push_back(elem),
+    }
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: ListCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        ListCommand::Push {
+            key,
+            side,
+            elems,
+            only_if_exists,
+        } => {
+            if only_if_exists && keyspace.get_list(&key, now)?.is_none() {
+                return Ok(Frame::Integer(0));
+            }
+            let list = keyspace.get_or_insert_list(&key, now)?;
+            for elem in elems {
+                push(list, side, elem);
+            }
+            Ok(Frame::Integer(list.len() as i64))
+        }
+        ListCommand::Pop { key, side, count } => {
+            let Some(list) = keyspace.get_list_mut(&key, now)? else {
+                return Ok(Frame::Null);
+            };
+            let reply = match count {
+                None => Frame::Bulk(pop(list, side).unwrap()),
+                Some(count) => {
+                    let count = count.min(list.len());
+                    let elems = (0..count).map(|_| Frame::Bulk(pop(list, side).unwrap()));
+                    Frame::Array(elems.collect())
+                }
+            };
+            keyspace.remove_if_empty(&key, now);
+            Ok(reply)
+        }
+        ListCommand::Len { key } => {
+            let len = keyspace.get_list(&key, now)?.map_or(0, |list| list.len());
+            Ok(Frame::Integer(len as i64))
+        }
+        ListCommand::Range { key, start, stop } => {
+            let list = keyspace.get_list(&key, now)?;
+            let Some(list) = list else {
+                return Ok(Frame::Array(vec![]));
+            };
+            let Some((start, stop)) = normalize_range(start, stop, list.len()) else {
+                return Ok(Frame::Array(vec![]));
+            };
+            let elems = list.range(start..=stop).cloned().map(Frame::Bulk);
+            Ok(Frame::Array(elems.collect()))
+        }
+        ListCommand::Index { key, index } => {
+            let list = keyspace.get_list(&key, now)?;
+            let elem = list.and_then(|list| {
+                let index = normalize_index(index, list.len())?;
+                return Some(list[index].clone());
+            });
+            Ok(elem.map_or(Frame::Null, Frame::Bulk))
+        }
+        ListCommand::Set { key, index, elem } => {
+            let Some(list) = keyspace.get_list_mut(&key, now)? else {
+                return Err(CommandError::Other("ERR no such key".into()));
+            };
+            let Some(index) = normalize_index(index, list.len()) else {
+                return Err(CommandError::Other("ERR index out of range".into()));
+            };
+            list[index] = elem;
+            Ok(Frame::Simple("OK".into()))
+        }
+        ListCommand::Rem { key, count, elem } => {
+            let Some(list) = keyspace.get_list_mut(&key, now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            let limit = match count {
+                0 => usize::MAX,
+                count => count.unsigned_abs() as usize,
+            };
+            let mut removed = 0;
+            if count >= 0 {
+                list.retain(|e| {
+                    let remove = removed < limit && *e == elem;
+                    removed += remove as usize;
+                    return !remove;
+                });
+            } else {
+                // Walk from the tail, keeping the survivors in reverse order
+                let mut kept: VecDeque<Bytes> = VecDeque::with_capacity(list.len());
+                while let Some(e) = list.pop_back() {
+                    if removed < limit && e == elem {
+                        removed += 1;
+                    } else {
+                        kept.push_front(e);
+                    }
+                }
+                *list = kept;
+            }
+            keyspace.remove_if_empty(&key, now);
+            Ok(Frame::Integer(removed as i64))
+        }
+        ListCommand::Trim { key, start, stop } => {
+            let Some(list) = keyspace.get_list_mut(&key, now)? else {
+                return Ok(Frame::Simple("OK".into()));
+            };
+            match normalize_range(start, stop, list.len()) {
+                Some((start, stop)) => {
+                    list.truncate(stop + 1);
+                    list.drain(..start);
+                }
+                None => list.clear(),
+            }
+            keyspace.remove_if_empty(&key, now);
+            Ok(Frame::Simple("OK".into()))
+        }
+        ListCommand::Insert {
+            key,
+            position,
+            pivot,
+            elem,
+        } => {
+            let Some(list) = keyspace.get_list_mut(&key, now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            let Some(index) = list.iter().position(|e| *e == pivot) else {
+                return Ok(Frame::Integer(-1));
+            };
+            match position {
+                Position::Before => list.insert(index, elem),
+                Position::After => list.insert(index + 1, elem),
+            }
+            Ok(Frame::Integer(list.len() as i64))
+        }
+        ListCommand::MPop { keys, side, count }
+        | ListCommand::BMPop {
+            keys, side, count, ..
+        } => {
+            let Some(key) = first_list(keyspace, &keys, now)? else {
+                return Ok(Frame::Null);
+            };
+            let list = keyspace.get_list_mut(&key, now)?.unwrap();
+            let count = count.min(list.len());
+            let elems = (0..count).map(|_| Frame::Bulk(pop(list, side).unwrap()));
+            let reply = Frame::Array(vec![
+                Frame::Bulk(key.clone()),
+                Frame::Array(elems.collect()),
+            ]);
+            keyspace.remove_if_empty(&key, now);
+            Ok(reply)
+        }
+        ListCommand::BPop { keys, side, .. } => {
+            let Some(key) = first_list(keyspace, &keys, now)? else {
+                return Ok(Frame::Null);
+            };
+            let list = keyspace.get_list_mut(&key, now)?.unwrap();
+            let elem = pop(list, side).unwrap();
+            keyspace.remove_if_empty(&key, now);
+            Ok(Frame::Array(vec![Frame::Bulk(key), Frame::Bulk(elem)]))
+        }
+        ListCommand::Move {
+            source,
+            destination,
+            from,
+            to,
+        }
+        | ListCommand::BMove {
+            source,
+            destination,
+            from,
+            to,
+            ..
+        } => Ok(move_elem(keyspace, &source, &destination, from, to, now)?
+            .map_or(Frame::Null, Frame::Bulk)),
+        ListCommand::Pos {
+            key,
+            elem,
+            rank,
+            count,
+            maxlen,
+        } => {
+            let list = keyspace.get_list(&key, now)?;
+            let Some(list) = list else {
+                return Ok(count.map_or(Frame::Null, |_| Frame::Array(vec![])));
+            };
+            let maxlen = if maxlen == 0 { list.len() } else { maxlen };
+            let skip = rank.unsigned_abs() as usize - 1;
+            let indices: Box<dyn Iterator<Item = usize>> = match rank > 0 {
+                true => Box::new(0..list.len()),
+                false => Box::new((0..list.len()).rev()),
+            };
+            let mut matches = indices
+                .take(maxlen)
+                .filter(|index| list[*index] == elem)
+                .skip(skip);
+            match count {
+                None => Ok(matches
+                    .next()
+                    .map_or(Frame::Null, |index| Frame::Integer(index as i64))),
+                Some(count) => {
+                    let count = if count == 0 { usize::MAX } else { count };
+                    let matches = matches
+                        .take(count)
+                        .map(|index| Frame::Integer(index as i64));
+                    Ok(Frame::Array(matches.collect()))
+                }
+            }
+        }
+    };
+}
+
+/// Return the first of the keys that holds a list. Fail if a key before it
+/// holds another type.
+fn first_list(
+    keyspace: &mut Keyspace,
+    keys: &[Bytes],
+    now: i64,
+) -> Result<Option<Bytes>, CommandError> {
+    for key in keys {
+        if keyspace.get_list(key, now)?.is_some() {
+            return Ok(Some(key.clone()));
+        }
+    }
+    return Ok(None);
+}
+
+/// Return the keys a blocking command waits on and how long it waits, None
+/// meaning forever
+pub(super) fn blocking_keys(cmd: &ListCommand) -> (Vec<Bytes>, Option<Duration>) {
+    return match cmd {
+        ListCommand::BPop { keys, timeout, .. } | ListCommand::BMPop { keys, timeout, .. } => {
+            (keys.clone(), *timeout)
+        }
+        ListCommand::BMove {
+            source, timeout, ..
+        } => (vec![source.clone()], *timeout),
+        cmd => panic!("{cmd:?} does not block"),
+    };
+}
+
+/// Pop an element from one end of the source list and push it onto one end
+/// of the destination list, which may be the same list. Return the element,
+/// or None if the source does not exist.
+fn move_elem(
+    keyspace: &mut Keyspace,
+    source: &Bytes,
+    destination: &Bytes,
+    from: Side,
+    to: Side,
+    now: i64,
+) -> Result<Option<Bytes>, CommandError> {
+    if keyspace.get_list(source, now)?.is_none() {
+        return Ok(None);
+    }
+    // Check the destination before changing anything, so that a WRONGTYPE
+    // error leaves the source alone
+    keyspace.get_list(destination, now)?;
+
+    let list = keyspace.get_list_mut(source, now)?.unwrap();
+    let elem = pop(list, from).unwrap();
+    keyspace.remove_if_empty(source, now);
+    push(
+        keyspace.get_or_insert_list(destination, now)?,
+        to,
+        elem.clone(),
+    );
+    return Ok(Some(elem));
+}
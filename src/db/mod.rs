@@ -0,0 +1,2594 @@
+//! The keyspace: typed values stored under keys, with expiries and access
+//! statistics, and the execution of data commands against it
+//!
+//! Execution is grouped into the same families as the commands, one module
+//! per family. Both server binaries share a `DB`, which serializes access to
+//! the keyspace through a mutex so that every command is atomic.
+mod bitmap;
+mod blocking;
+mod geo;
+mod geohash;
+mod hash;
+mod hyperloglog;
+mod key;
+mod list;
+mod pubsub;
+mod scan;
+mod set;
+mod skiplist;
+mod sorted_set;
+mod stream;
+mod string;
+
+pub use hash::Hash;
+pub use pubsub::Subscriptions;
+pub use scan::ScanTable;
+pub use set::Set;
+pub use sorted_set::SortedSet;
+pub use stream::Stream;
+
+use crate::command::{
+    BitmapCommand, Command, CommandError, GeoCommand, HashCommand, HyperLogLogCommand, KeyCommand,
+    ListCommand, PubSubCommand, SetCommand, SortedSetCommand, StreamCommand, StringCommand,
+};
+use crate::Frame;
+use bytes::{Bytes, BytesMut};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A value stored under a key. Commands only operate on values of the type
+/// they were made for, and fail with WRONGTYPE otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(Hash),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// Return whether the value is an empty collection. Redis never stores
+    /// empty collections, the key is removed along with the last element.
+    pub fn is_empty(&self) -> bool {
+        return match self {
+            Self::String(_) => false,
+            Self::List(list) => list.is_empty(),
+            Self::Hash(hash) => hash.is_empty(),
+            Self::Set(set) => set.is_empty(),
+            Self::SortedSet(zset) => zset.is_empty(),
+            // Streams are kept when empty, along with their last ID and groups
+            Self::Stream(_) => false,
+        };
+    }
+
+    /// Return the name of the type, as reported by the TYPE command
+    pub fn type_name(&self) -> &'static str {
+        return match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Stream(_) => "stream",
+        };
+    }
+
+    /// Return the name of the encoding Redis would use for the value, as
+    /// reported by the OBJECT ENCODING command
+    pub fn encoding(&self) -> &'static str {
+        return match self {
+            Self::String(s) if s.len() <= 20 && crate::command::parse_integer(s).is_some() => "int",
+            Self::String(s) if s.len() <= MAX_EMBSTR_LEN => "embstr",
+            Self::String(_) => "raw",
+            Self::List(list) if fits_listpack(list.len(), list) => "listpack",
+            Self::List(_) => "quicklist",
+            Self::Hash(hash) => hash.encoding(),
+            Self::Set(set) => set.encoding(),
+            Self::SortedSet(zset) => zset.encoding(),
+            Self::Stream(_) => "stream",
+        };
+    }
+}
+
+/// Return whether a collection is small enough for a compact listpack
+fn fits_listpack<'a>(len: usize, elems: impl IntoIterator<Item = &'a Bytes>) -> bool {
+    return len <= MAX_LISTPACK_ENTRIES
+        && elems
+            .into_iter()
+            .all(|elem| elem.len() <= MAX_LISTPACK_VALUE);
+}
+
+/// The longest string stored together with its object header
+const MAX_EMBSTR_LEN: usize = 44;
+
+/// The most elements a collection may hold in a compact listpack
+const MAX_LISTPACK_ENTRIES: usize = 128;
+
+/// The longest element a collection may hold in a compact listpack
+const MAX_LISTPACK_VALUE: usize = 64;
+
+/// The most members a set of integers may hold in a compact intset
+const MAX_INTSET_ENTRIES: usize = 512;
+
+/// The longest string a HyperLogLog may take up in the sparse encoding
+const HLL_SPARSE_MAX_BYTES: usize = 3000;
+
+/// Settings that change how values are stored, named after the Redis
+/// configuration directives they mirror
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The most fields a hash may have and still be kept as a listpack
+    pub hash_max_listpack_entries: usize,
+    /// The longest field or value a hash may hold and still be kept as a
+    /// listpack
+    pub hash_max_listpack_value: usize,
+    /// The most members a set of integers may have and still be kept as an
+    /// intset
+    pub set_max_intset_entries: usize,
+    /// The most members a set may have and still be kept as a listpack
+    pub set_max_listpack_entries: usize,
+    /// The longest member a set may hold and still be kept as a listpack
+    pub set_max_listpack_value: usize,
+    /// The longest string a HyperLogLog may take up and still use the sparse
+    /// encoding
+    pub hll_sparse_max_bytes: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Self {
+            hash_max_listpack_entries: MAX_LISTPACK_ENTRIES,
+            hash_max_listpack_value: MAX_LISTPACK_VALUE,
+            set_max_intset_entries: MAX_INTSET_ENTRIES,
+            set_max_listpack_entries: MAX_LISTPACK_ENTRIES,
+            set_max_listpack_value: MAX_LISTPACK_VALUE,
+            hll_sparse_max_bytes: HLL_SPARSE_MAX_BYTES,
+        };
+    }
+}
+
+/// The frequency counter of a newly created key, so that new keys are not
+/// the first to be considered rarely used
+const LFU_INIT_VAL: u8 = 5;
+
+/// How much harder it gets to increment a higher frequency counter
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// How long it takes for the frequency counter to decay by one
+const LFU_DECAY_MS: i64 = 60 * 1000;
+
+/// A value along with its expiry and how it has been accessed
+struct Entry {
+    val: Value,
+    /// Unix time in milliseconds, or None if the key never expires
+    expires_at: Option<i64>,
+    /// Unix time in milliseconds of the last access
+    accessed_at: i64,
+    /// A logarithmic counter of accesses that decays over time, like the one
+    /// Redis keeps for its LFU eviction policy
+    freq: u8,
+}
+
+impl Entry {
+    fn new(val: Value, expires_at: Option<i64>, now: i64) -> Self {
+        return Self {
+            val,
+            expires_at,
+            accessed_at: now,
+            freq: LFU_INIT_VAL,
+        };
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        return self.expires_at.is_some_and(|expires_at| expires_at <= now);
+    }
+
+    /// Return the frequency counter after decaying it for the time since the
+    /// last access
+    fn decayed_freq(&self, now: i64) -> u8 {
+        let periods = (now - self.accessed_at).max(0) / LFU_DECAY_MS;
+        return (self.freq as i64 - periods).max(0) as u8;
+    }
+
+    /// Record an access, given a random number between 0 and 1
+    fn touch(&mut self, now: i64, random: f64) {
+        let freq = self.decayed_freq(now);
+        let base = freq.saturating_sub(LFU_INIT_VAL) as f64;
+        self.freq = match freq {
+            u8::MAX => freq,
+            freq if random < 1.0 / (base * LFU_LOG_FACTOR + 1.0) => freq + 1,
+            freq => freq,
+        };
+        self.accessed_at = now;
+    }
+}
+
+/// Counters describing how keys expire, reported by "INFO stats"
+#[derive(Debug, Default)]
+pub struct ExpireStats {
+    /// Keys removed because they expired, whether on access or by the
+    /// background cycle
+    pub expired_keys: u64,
+    /// Hash fields removed because they expired
+    pub expired_fields: u64,
+    /// Background cycles run so far
+    pub cycles: u64,
+    /// Time spent in background cycles
+    pub cycle_time: Duration,
+    /// The duration of the most recent background cycle
+    pub last_cycle_time: Duration,
+    /// Background cycles that stopped because they ran out of time
+    pub time_cap_reached: u64,
+}
+
+/// The keys and their values. Expired keys stay in the map until they are
+/// accessed or reclaimed by the background cycle, but are never visible.
+pub struct Keyspace {
+    entries: HashMap<Bytes, Entry>,
+    /// The keys that have an expiry, ordered by the time they expire at
+    expires: BTreeSet<(i64, Bytes)>,
+    /// The hashes that have fields with an expiry, ordered by the time their
+    /// first field expires at
+    field_expires: BTreeSet<(i64, Bytes)>,
+    field_expires_at: HashMap<Bytes, i64>,
+    stats: ExpireStats,
+    /// The state of the xorshift generator behind the frequency counters
+    rng: u64,
+    blocked: blocking::Blocked,
+    config: Config,
+}
+
+impl Keyspace {
+    fn new() -> Self {
+        return Self {
+            entries: HashMap::new(),
+            expires: BTreeSet::new(),
+            field_expires: BTreeSet::new(),
+            field_expires_at: HashMap::new(),
+            stats: ExpireStats::default(),
+            rng: (now_ms() as u64) | 1,
+            blocked: blocking::Blocked::default(),
+            config: Config::default(),
+        };
+    }
+
+    /// Return the number of keys, including the expired keys that have not
+    /// been reclaimed yet
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    /// Return whether there are no keys at all
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    /// Return the number of keys with an expiry
+    pub fn expires_len(&self) -> usize {
+        return self.expires.len();
+    }
+
+    pub fn stats(&self) -> &ExpireStats {
+        return &self.stats;
+    }
+
+    /// Return a random number between 0 and 1
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        return (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+    }
+
+    /// Return the entry of a key that has not expired without recording an
+    /// access, deleting the key if it has expired
+    fn peek(&mut self, key: &[u8], now: i64) -> Option<&Entry> {
+        if self
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(now))
+        {
+            self.remove(key, now);
+        }
+        return self.entries.get(key);
+    }
+
+    /// Return the entry of a key that has not expired and record an access
+    fn get(&mut self, key: &[u8], now: i64) -> Option<&Entry> {
+        return self.get_mut(key, now).map(|entry| &*entry);
+    }
+
+    /// Return the entry of a key that has not expired and record an access.
+    /// The expiry must only be changed through `set_expiry`.
+    fn get_mut(&mut self, key: &[u8], now: i64) -> Option<&mut Entry> {
+        self.peek(key, now)?;
+        let random = self.random();
+        let entry = self.entries.get_mut(key)?;
+        entry.touch(now, random);
+        return Some(entry);
+    }
+
+    /// Return the string stored under a key, if there is one
+    fn get_string(&mut self, key: &[u8], now: i64) -> Result<Option<&Bytes>, CommandError> {
+        return match self.get(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::String(s),
+                ..
+            }) => Ok(Some(s)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Modify the string stored under a key in place, creating an empty string
+    /// if the key does not exist. The buffer is only copied if it is shared,
+    /// for instance with a reply that is still being written, so that small
+    /// edits to large strings stay cheap.
+    fn update_string<R>(
+        &mut self,
+        key: Bytes,
+        now: i64,
+        update: impl FnOnce(&mut BytesMut) -> R,
+    ) -> Result<R, CommandError> {
+        if self.get_string(&key, now)?.is_none() {
+            self.insert(key.clone(), Value::String(Bytes::new()), None, now);
+        }
+        let Some(Entry {
+            val: Value::String(s),
+            ..
+        }) = self.entries.get_mut(&key)
+        else {
+            unreachable!("the key holds a string");
+        };
+        let mut buf = std::mem::take(s)
+            .try_into_mut()
+            .unwrap_or_else(|shared| BytesMut::from(&shared[..]));
+        let result = update(&mut buf);
+        *s = buf.freeze();
+        return Ok(result);
+    }
+
+    /// Store a value under a key, replacing any value of any type and keeping
+    /// the expiry if the key exists
+    fn set_value(&mut self, key: Bytes, val: Value, now: i64) {
+        match self.get_mut(&key, now) {
+            Some(entry) => {
+                entry.val = val;
+                self.reindex_fields(&key);
+            }
+            None => self.insert(key, val, None, now),
+        }
+    }
+
+    /// Store a value under a key with the given expiry, replacing the key
+    fn insert(&mut self, key: Bytes, val: Value, expires_at: Option<i64>, now: i64) {
+        // Unindex the old expiry first, it may be the same as the new one
+        if let Some(expires_at) = self.entries.get(&key).and_then(|old| old.expires_at) {
+            self.expires.remove(&(expires_at, key.clone()));
+        }
+        if let Some(expires_at) = expires_at {
+            self.expires.insert((expires_at, key.clone()));
+        }
+        self.entries
+            .insert(key.clone(), Entry::new(val, expires_at, now));
+        self.reindex_fields(&key);
+    }
+
+    /// Remove a key, returning its value if it had not expired
+    fn remove(&mut self, key: &[u8], now: i64) -> Option<Value> {
+        let (key, entry) = self.entries.remove_entry(key)?;
+        self.reindex_fields(&key);
+        if let Some(expires_at) = entry.expires_at {
+            self.expires.remove(&(expires_at, key));
+        }
+        if entry.is_expired(now) {
+            self.stats.expired_keys += 1;
+            return None;
+        }
+        return Some(entry.val);
+    }
+
+    /// Remove a key if its value is an empty collection
+    fn remove_if_empty(&mut self, key: &[u8], now: i64) {
+        if self
+            .peek(key, now)
+            .is_some_and(|entry| entry.val.is_empty())
+        {
+            self.remove(key, now);
+        }
+    }
+
+    /// Change the expiry of an existing key
+    fn set_expiry(&mut self, key: &[u8], expires_at: Option<i64>) {
+        let Some((key, entry)) = self.entries.get_key_value(key) else {
+            return;
+        };
+        let key = key.clone();
+        if let Some(old) = entry.expires_at {
+            self.expires.remove(&(old, key.clone()));
+        }
+        if let Some(new) = expires_at {
+            self.expires.insert((new, key.clone()));
+        }
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.expires_at = expires_at;
+    }
+
+    /// Remove up to `limit` expired keys, earliest first, and return how many
+    /// were removed
+    fn remove_expired(&mut self, now: i64, limit: usize) -> usize {
+        let mut removed = 0;
+        while removed < limit {
+            match self.expires.first() {
+                Some((expires_at, _)) if *expires_at <= now => {}
+                _ => break,
+            }
+            let (_, key) = self.expires.pop_first().unwrap();
+            self.entries.remove(&key);
+            self.reindex_fields(&key);
+            removed += 1;
+        }
+        self.stats.expired_keys += removed as u64;
+        return removed;
+    }
+}
+
+/// Return whether a string matches a glob-style pattern, the way Redis
+/// matches keys and fields: `*` matches any run of bytes, `?` any single byte,
+/// `[abc]`, `[^abc]` and `[a-z]` one byte out of a set, and `\` escapes the
+/// byte after it
+pub fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // Where to resume after the last star if the rest fails to match: the
+    // pattern after the star, and the next byte of the string it can eat
+    let mut star = None;
+    while i < s.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, i));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], s[i]) {
+            p += len;
+            i += 1;
+            continue;
+        }
+        let Some((star_p, star_i)) = star else {
+            return false;
+        };
+        p = star_p;
+        i = star_i + 1;
+        star = Some((star_p, star_i + 1));
+    }
+    return pattern[p..].iter().all(|c| *c == b'*');
+}
+
+/// Match a single byte against the token at the start of a pattern, which is
+/// not a star. Return the length of the token if it matches.
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    return match pattern {
+        [] => None,
+        [b'?', ..] => Some(1),
+        [b'\\', escaped, ..] => (*escaped == c).then_some(2),
+        [b'[', ..] => {
+            let negate = pattern.get(1) == Some(&b'^');
+            let mut j = if negate { 2 } else { 1 };
+            let mut matched = false;
+            // An unterminated set runs to the end of the pattern
+            while j < pattern.len() && pattern[j] != b']' {
+                if pattern[j] == b'\\' && j + 1 < pattern.len() {
+                    matched |= pattern[j + 1] == c;
+                    j += 2;
+                } else if pattern.get(j + 1) == Some(&b'-') && j + 2 < pattern.len() {
+                    let (lo, hi) = (pattern[j], pattern[j + 2]);
+                    matched |= lo.min(hi) <= c && c <= lo.max(hi);
+                    j += 3;
+                } else {
+                    matched |= pattern[j] == c;
+                    j += 1;
+                }
+            }
+            (matched != negate).then_some((j + 1).min(pattern.len()))
+        }
+        [token, ..] => (*token == c).then_some(1),
+    };
+}
+
+/// The keyspace shared by every connection of a server, along with their
+/// publish/subscribe subscriptions
+pub struct DB {
+    keyspace: Mutex<Keyspace>,
+    pubsub: Mutex<pubsub::Registry>,
+}
+
+impl DB {
+    pub fn new() -> Self {
+        return Self::with_config(Config::default());
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let mut keyspace = Keyspace::new();
+        keyspace.config = config;
+        return Self {
+            keyspace: Mutex::new(keyspace),
+            pubsub: Mutex::new(pubsub::Registry::default()),
+        };
+    }
+
+    /// Lock the keyspace. No command can run until the guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, Keyspace> {
+        return self.keyspace.lock().unwrap();
+    }
+
+    /// Execute a command that operates on string values as arrays of bits
+    pub fn execute_bitmap(&self, cmd: BitmapCommand) -> Frame {
+        let reply = bitmap::execute(&mut self.lock(), cmd, now_ms());
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on sorted sets as geospatial indexes,
+    /// then serve the clients blocked on the sorted sets it added to
+    pub fn execute_geo(&self, cmd: GeoCommand) -> Frame {
+        let mut keyspace = self.lock();
+        let now = now_ms();
+        let reply = geo::execute(&mut keyspace, cmd, now);
+        keyspace.serve_blocked(now);
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on hash values
+    pub fn execute_hash(&self, cmd: HashCommand) -> Frame {
+        let reply = hash::execute(&mut self.lock(), cmd, now_ms());
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on HyperLogLog values
+    pub fn execute_hyperloglog(&self, cmd: HyperLogLogCommand) -> Frame {
+        let reply = hyperloglog::execute(&mut self.lock(), cmd, now_ms());
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on keys
+    pub fn execute_key(&self, cmd: KeyCommand) -> Frame {
+        let reply = key::execute(&mut self.lock(), cmd, now_ms());
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on list values, then serve the clients
+    /// blocked on the lists it pushed onto. Blocking commands reply as if they
+    /// timed out right away if they cannot be served.
+    pub fn execute_list(&self, cmd: ListCommand) -> Frame {
+        let mut keyspace = self.lock();
+        let now = now_ms();
+        let reply = list::execute(&mut keyspace, cmd, now);
+        keyspace.serve_blocked(now);
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a blocking command such as BLPOP, BZPOPMIN or XREAD. If none of its
+    /// keys hold data, wait until a write serves the command, or until the
+    /// timeout runs out and the reply is Null. Clients blocked on the same key
+    /// are served in the order they blocked.
+    ///
+    /// Dropping the future unblocks the client, which is how a server stops
+    /// waiting on behalf of a client that disconnected.
+    pub async fn block(&self, cmd: Command) -> Frame {
+        let (keys, timeout) = blocking::blocking_keys(&cmd);
+        let (id, mut receiver) = {
+            let mut keyspace = self.lock();
+            let now = now_ms();
+            match blocking::execute(&mut keyspace, cmd.clone(), now) {
+                Ok(Frame::Null) => (),
+                reply => {
+                    keyspace.serve_blocked(now);
+                    return reply.unwrap_or_else(|err| err.to_frame());
+                }
+            }
+            let cmd = blocking::pin(&mut keyspace, cmd, now);
+            keyspace.block(cmd, keys)
+        };
+        let unblock = Unblock { db: self, id };
+        let reply = match timeout {
+            None => (&mut receiver).await.ok(),
+            Some(timeout) => tokio::time::timeout(timeout, &mut receiver)
+                .await
+                .ok()
+                .and_then(Result::ok),
+        };
+        // Once unblocked, the client can no longer be served, but it may have
+        // been served just as the timeout ran out
+        drop(unblock);
+        return reply
+            .or_else(|| receiver.try_recv().ok())
+            .unwrap_or(Frame::Null);
+    }
+
+    /// Start tracking the publish/subscribe subscriptions of a connection. A
+    /// connection must drop its subscriptions through `unsubscribe_all` once
+    /// it goes away.
+    pub fn subscriptions(&self) -> Subscriptions {
+        return self.pubsub.lock().unwrap().subscriptions();
+    }
+
+    /// Execute a publish/subscribe command on behalf of a connection.
+    /// Commands that change the subscriptions reply with one confirmation per
+    /// channel or pattern, preceded by the messages published before the
+    /// change that the connection has yet to receive.
+    pub fn execute_pubsub(
+        &self,
+        subscriptions: &mut Subscriptions,
+        cmd: PubSubCommand,
+    ) -> Vec<Frame> {
+        return pubsub::execute(&mut self.pubsub.lock().unwrap(), subscriptions, cmd);
+    }
+
+    /// Drop every subscription of a connection, returning the messages
+    /// published to them that the connection has yet to receive, which must
+    /// go out before anything else it is sent
+    pub fn unsubscribe_all(&self, subscriptions: &mut Subscriptions) -> Vec<Frame> {
+        return self.pubsub.lock().unwrap().unsubscribe_all(subscriptions);
+    }
+
+    /// Execute a command that operates on set values
+    pub fn execute_set(&self, cmd: SetCommand) -> Frame {
+        let reply = set::execute(&mut self.lock(), cmd, now_ms());
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on sorted set values, then serve the
+    /// clients blocked on the sorted sets it created. Blocking commands reply
+    /// as if they timed out right away if they cannot be served.
+    pub fn execute_sorted_set(&self, cmd: SortedSetCommand) -> Frame {
+        let mut keyspace = self.lock();
+        let now = now_ms();
+        let reply = sorted_set::execute(&mut keyspace, cmd, now);
+        keyspace.serve_blocked(now);
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on stream values, then serve the
+    /// clients blocked on the streams it added to. XREAD and XREADGROUP with
+    /// BLOCK reply as if they timed out right away if they find nothing.
+    pub fn execute_stream(&self, cmd: StreamCommand) -> Frame {
+        let mut keyspace = self.lock();
+        let now = now_ms();
+        let reply = stream::execute(&mut keyspace, cmd, now);
+        keyspace.serve_blocked(now);
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    /// Execute a command that operates on string values
+    pub fn execute_string(&self, cmd: StringCommand) -> Frame {
+        let reply = string::execute(&mut self.lock(), cmd, now_ms());
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+}
+
+/// Unblocks a client when dropped, whether its command was served, timed out
+/// or abandoned
+struct Unblock<'a> {
+    db: &'a DB,
+    id: u64,
+}
+
+impl Drop for Unblock<'_> {
+    fn drop(&mut self) {
+        self.db.lock().unblock(self.id);
+    }
+}
+
+impl Default for DB {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// The current Unix time in milliseconds
+pub fn now_ms() -> i64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    return since_epoch.as_millis() as i64;
+}
+
+/// How often the background cycle reclaims expired keys
+const EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long one background cycle may run. Like Redis, the cycle uses at most
+/// a quarter of the time between two cycles.
+const EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(25);
+
+/// How many keys, and how many hash fields, the background cycle removes
+/// before releasing the lock, so that other clients are never kept waiting
+/// for long
+const EXPIRE_KEYS_PER_LOCK: usize = 20;
+
+/// Periodically reclaim the memory held by keys and hash fields that expired
+/// but were never accessed again. Runs until the task is dropped.
+pub async fn expire_keys(db: Arc<DB>) {
+    let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let start = Instant::now();
+        let mut time_cap_reached = false;
+        loop {
+            let (keys, fields) = {
+                let mut keyspace = db.lock();
+                let now = now_ms();
+                (
+                    keyspace.remove_expired(now, EXPIRE_KEYS_PER_LOCK),
+                    keyspace.remove_expired_fields(now, EXPIRE_KEYS_PER_LOCK),
+                )
+            };
+            if keys < EXPIRE_KEYS_PER_LOCK && fields < EXPIRE_KEYS_PER_LOCK {
+                break;
+            }
+            if start.elapsed() >= EXPIRE_CYCLE_BUDGET {
+                time_cap_reached = true;
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        let elapsed = start.elapsed();
+        let stats = &mut db.lock().stats;
+        stats.cycles += 1;
+        stats.cycle_time += elapsed;
+        stats.last_cycle_time = elapsed;
+        stats.time_cap_reached += time_cap_reached as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+    use std::collections::HashSet;
+
+    /// Parse and execute a command at the given time
+    fn run(keyspace: &mut Keyspace, args: &[&str], now: i64) -> Frame {
+        let frame = Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        );
+        let reply = match Command::from_frame(&frame).unwrap() {
+            Command::Bitmap(cmd) => bitmap::execute(keyspace, cmd, now),
+            Command::Geo(cmd) => geo::execute(keyspace, cmd, now),
+            Command::Hash(cmd) => hash::execute(keyspace, cmd, now),
+            Command::HyperLogLog(cmd) => hyperloglog::execute(keyspace, cmd, now),
+            Command::Key(cmd) => key::execute(keyspace, cmd, now),
+            Command::List(cmd) => list::execute(keyspace, cmd, now),
+            Command::Set(cmd) => set::execute(keyspace, cmd, now),
+            Command::SortedSet(cmd) => sorted_set::execute(keyspace, cmd, now),
+            Command::Stream(cmd) => stream::execute(keyspace, cmd, now),
+            Command::String(cmd) => string::execute(keyspace, cmd, now),
+            cmd => panic!("{cmd:?} is not a data command"),
+        };
+        return reply.unwrap_or_else(|err| err.to_frame());
+    }
+
+    fn bulk(s: &'static str) -> Frame {
+        return Frame::Bulk(Bytes::from(s));
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(
+            Bytes::from("list"),
+            Value::List(VecDeque::from([Bytes::from("a")])),
+            None,
+            0,
+        );
+        let wrong_type = CommandError::WrongType.to_frame();
+
+        assert_eq!(run(&mut keyspace, &["GET", "list"], 0), wrong_type);
+        assert_eq!(run(&mut keyspace, &["INCR", "list"], 0), wrong_type);
+        assert_eq!(run(&mut keyspace, &["APPEND", "list", "x"], 0), wrong_type);
+        assert_eq!(
+            run(&mut keyspace, &["SET", "list", "x", "GET"], 0),
+            wrong_type
+        );
+        assert_eq!(
+            run(&mut keyspace, &["MGET", "list", "nope"], 0),
+            Frame::Array(vec![Frame::Null, Frame::Null])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["TYPE", "list"], 0),
+            Frame::Simple("list".into())
+        );
+
+        // SET replaces a value of any type
+        assert_eq!(
+            run(&mut keyspace, &["SET", "list", "x"], 0),
+            Frame::Simple("OK".into())
+        );
+        assert_eq!(run(&mut keyspace, &["GET", "list"], 0), bulk("x"));
+        assert_eq!(
+            run(&mut keyspace, &["TYPE", "list"], 0),
+            Frame::Simple("string".into())
+        );
+        assert_eq!(
+            run(&mut keyspace, &["TYPE", "nope"], 0),
+            Frame::Simple("none".into())
+        );
+    }
+
+    #[test]
+    fn test_object_encoding() {
+        let mut keyspace = Keyspace::new();
+        let encoding = |keyspace: &mut Keyspace, key| {
+            return run(keyspace, &["OBJECT", "ENCODING", key], 0);
+        };
+
+        run(&mut keyspace, &["SET", "int", "12345"], 0);
+        run(&mut keyspace, &["SET", "embstr", "hello"], 0);
+        run(&mut keyspace, &["SET", "raw", &"x".repeat(45)], 0);
+        assert_eq!(encoding(&mut keyspace, "int"), bulk("int"));
+        assert_eq!(encoding(&mut keyspace, "embstr"), bulk("embstr"));
+        assert_eq!(encoding(&mut keyspace, "raw"), bulk("raw"));
+        assert_eq!(encoding(&mut keyspace, "nope"), Frame::Null);
+
+        run(&mut keyspace, &["SADD", "ints", "1", "2"], 0);
+        run(&mut keyspace, &["SADD", "strings", "1", "a"], 0);
+        assert_eq!(encoding(&mut keyspace, "ints"), bulk("intset"));
+        assert_eq!(encoding(&mut keyspace, "strings"), bulk("listpack"));
+        run(&mut keyspace, &["HSET", "small", "f", "v"], 0);
+        run(&mut keyspace, &["HSET", "long", "f", &"v".repeat(65)], 0);
+        assert_eq!(encoding(&mut keyspace, "small"), bulk("listpack"));
+        assert_eq!(encoding(&mut keyspace, "long"), bulk("hashtable"));
+    }
+
+    #[test]
+    fn test_object_freq_idletime() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["SET", "k", "v"], 0);
+
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "FREQ", "k"], 0),
+            Frame::Integer(LFU_INIT_VAL as i64)
+        );
+        // The first accesses always increment the counter
+        run(&mut keyspace, &["GET", "k"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "FREQ", "k"], 0),
+            Frame::Integer(LFU_INIT_VAL as i64 + 1)
+        );
+        for _ in 0..1000 {
+            run(&mut keyspace, &["GET", "k"], 0);
+        }
+        let Frame::Integer(freq) = run(&mut keyspace, &["OBJECT", "FREQ", "k"], 0) else {
+            panic!("FREQ is an integer");
+        };
+        assert!(freq > 10 && freq < 50, "{freq}");
+        // The counter decays by one every minute without access
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "FREQ", "k"], 3 * LFU_DECAY_MS),
+            Frame::Integer(freq - 3)
+        );
+
+        // Neither OBJECT, TYPE, TTL nor EXISTS count as accesses
+        run(&mut keyspace, &["TYPE", "k"], 5000);
+        run(&mut keyspace, &["TTL", "k"], 5000);
+        run(&mut keyspace, &["EXISTS", "k"], 5000);
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "IDLETIME", "k"], 7000),
+            Frame::Integer(7)
+        );
+        run(&mut keyspace, &["TOUCH", "k"], 7000);
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "IDLETIME", "k"], 7000),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "REFCOUNT", "k"], 7000),
+            Frame::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut keyspace = Keyspace::new();
+        run(&mut keyspace, &["SET", "a", "1", "PX", "100"], 0);
+        run(&mut keyspace, &["SET", "b", "1", "PX", "200"], 0);
+        run(&mut keyspace, &["SET", "c", "1"], 0);
+        assert_eq!(keyspace.expires_len(), 2);
+        assert_eq!(run(&mut keyspace, &["PTTL", "a"], 50), Frame::Integer(50));
+        assert_eq!(run(&mut keyspace, &["TTL", "c"], 50), Frame::Integer(-1));
+
+        // Expired keys are invisible before they are reclaimed
+        assert_eq!(run(&mut keyspace, &["GET", "a"], 100), Frame::Null);
+        assert_eq!(run(&mut keyspace, &["TTL", "a"], 100), Frame::Integer(-2));
+        assert_eq!(keyspace.stats().expired_keys, 1);
+
+        // Keeping the same expiry keeps the key indexed
+        run(&mut keyspace, &["SET", "b", "2", "KEEPTTL"], 100);
+        assert_eq!(keyspace.expires_len(), 1);
+        assert_eq!(keyspace.remove_expired(150, 10), 0);
+        assert_eq!(keyspace.remove_expired(200, 10), 1);
+        assert_eq!(keyspace.len(), 1);
+        assert_eq!(keyspace.expires_len(), 0);
+        assert_eq!(keyspace.stats().expired_keys, 2);
+
+        run(&mut keyspace, &["EXPIRE", "c", "10"], 200);
+        assert_eq!(
+            run(&mut keyspace, &["PERSIST", "c"], 300),
+            Frame::Integer(1)
+        );
+        assert_eq!(keyspace.expires_len(), 0);
+        assert_eq!(keyspace.remove_expired(i64::MAX, 10), 0);
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut keyspace = Keyspace::new();
+        let range = |keyspace: &mut Keyspace, key| run(keyspace, &["LRANGE", key, "0", "-1"], 0);
+        let array = |elems: &[&'static str]| Frame::Array(elems.iter().map(|e| bulk(e)).collect());
+
+        run(&mut keyspace, &["RPUSH", "l", "a", "b", "c", "b", "a"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["LRANGE", "l", "-3", "100"], 0),
+            array(&["c", "b", "a"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["LRANGE", "l", "3", "1"], 0),
+            array(&[])
+        );
+        assert_eq!(run(&mut keyspace, &["LINDEX", "l", "-1"], 0), bulk("a"));
+        assert_eq!(run(&mut keyspace, &["LINDEX", "l", "5"], 0), Frame::Null);
+
+        assert_eq!(
+            run(&mut keyspace, &["LPOS", "l", "b"], 0),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["LPOS", "l", "a", "RANK", "-1", "COUNT", "0"],
+                0
+            ),
+            Frame::Array(vec![Frame::Integer(4), Frame::Integer(0)])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["LPOS", "l", "a", "RANK", "2", "MAXLEN", "4"],
+                0
+            ),
+            Frame::Null
+        );
+
+        assert_eq!(
+            run(&mut keyspace, &["LREM", "l", "-1", "b"], 0),
+            Frame::Integer(1)
+        );
+        assert_eq!(range(&mut keyspace, "l"), array(&["a", "b", "c", "a"]));
+
+        // Moving within the same list rotates it
+        assert_eq!(
+            run(&mut keyspace, &["LMOVE", "l", "l", "RIGHT", "LEFT"], 0),
+            bulk("a")
+        );
+        assert_eq!(range(&mut keyspace, "l"), array(&["a", "a", "b", "c"]));
+
+        // A destination of the wrong type leaves the source alone
+        run(&mut keyspace, &["SET", "s", "x"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["RPOPLPUSH", "l", "s"], 0),
+            CommandError::WrongType.to_frame()
+        );
+        assert_eq!(run(&mut keyspace, &["LLEN", "l"], 0), Frame::Integer(4));
+
+        // Lists that become empty are removed
+        assert_eq!(
+            run(&mut keyspace, &["LPOP", "l", "10"], 0),
+            array(&["a", "a", "b", "c"])
+        );
+        assert_eq!(run(&mut keyspace, &["EXISTS", "l"], 0), Frame::Integer(0));
+        assert_eq!(
+            run(&mut keyspace, &["RPUSHX", "l", "a"], 0),
+            Frame::Integer(0)
+        );
+        run(&mut keyspace, &["RPUSH", "l", "a"], 0);
+        run(&mut keyspace, &["LTRIM", "l", "1", "-1"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["TYPE", "l"], 0),
+            Frame::Simple("none".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_list() {
+        let db = Arc::new(DB::new());
+        let parse = |args: &[&str]| {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                    .collect(),
+            );
+            return match Command::from_frame(&frame).unwrap() {
+                Command::List(cmd) => cmd,
+                cmd => panic!("{cmd:?} is not a list command"),
+            };
+        };
+        let block = |args: &[&str]| {
+            let (db, cmd) = (Arc::clone(&db), parse(args));
+            return tokio::spawn(async move { db.block(Command::List(cmd)).await });
+        };
+        let pair = |key, elem| Frame::Array(vec![bulk(key), bulk(elem)]);
+
+        // Clients are served in the order they blocked, whichever key they
+        // listed first
+        let first = block(&["BLPOP", "a", "b", "0"]);
+        wait_blocked(&db, 1).await;
+        let second = block(&["BRPOP", "b", "a", "0"]);
+        wait_blocked(&db, 2).await;
+        let third = block(&["BLMOVE", "a", "c", "LEFT", "LEFT", "0"]);
+        wait_blocked(&db, 3).await;
+        db.execute_list(parse(&["RPUSH", "a", "1", "2", "3"]));
+        assert_eq!(first.await.unwrap(), pair("a", "1"));
+        assert_eq!(second.await.unwrap(), pair("a", "3"));
+        assert_eq!(third.await.unwrap(), bulk("2"));
+        assert_eq!(db.lock().blocked_len(), 0);
+
+        // A moved element serves the clients blocked on the destination
+        let moved = block(&["BLPOP", "d", "0"]);
+        wait_blocked(&db, 1).await;
+        db.execute_list(parse(&["LMOVE", "c", "d", "LEFT", "LEFT"]));
+        assert_eq!(moved.await.unwrap(), pair("d", "2"));
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            db.block(Command::List(parse(&["BLPOP", "e", "0.05"])))
+                .await,
+            Frame::Null
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // An abandoned client is unblocked and leaves the data alone
+        let abandoned = block(&["BLPOP", "e", "0"]);
+        wait_blocked(&db, 1).await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        assert_eq!(db.lock().blocked_len(), 0);
+        db.execute_list(parse(&["RPUSH", "e", "1"]));
+        assert_eq!(db.execute_list(parse(&["LLEN", "e"])), Frame::Integer(1));
+    }
+
+    #[test]
+    fn test_hashes() {
+        let mut keyspace = Keyspace::new();
+        keyspace.config.hash_max_listpack_entries = 4;
+        let array = |elems: &[&'static str]| Frame::Array(elems.iter().map(|e| bulk(e)).collect());
+
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["HSET", "h", "a", "1", "b", "2", "a", "3"],
+                0
+            ),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["HSETNX", "h", "a", "4"], 0),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["HMGET", "h", "a", "nope"], 0),
+            Frame::Array(vec![bulk("3"), Frame::Null])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["HGETALL", "h"], 0),
+            Frame::Map(vec![(bulk("a"), bulk("3")), (bulk("b"), bulk("2"))])
+        );
+        assert_eq!(run(&mut keyspace, &["HKEYS", "h"], 0), array(&["a", "b"]));
+        assert_eq!(
+            run(&mut keyspace, &["HSTRLEN", "h", "a"], 0),
+            Frame::Integer(1)
+        );
+
+        assert_eq!(
+            run(&mut keyspace, &["HINCRBY", "h", "a", "-5"], 0),
+            Frame::Integer(-2)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["HINCRBYFLOAT", "h", "a", "0.5"], 0),
+            bulk("-1.5")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["HINCRBY", "h", "a", "1"], 0),
+            Frame::Error("ERR hash value is not an integer".into())
+        );
+
+        // The hash becomes a hash table once it has too many fields, and
+        // stays one
+        run(&mut keyspace, &["HSET", "h", "c", "1", "d", "1"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "ENCODING", "h"], 0),
+            bulk("listpack")
+        );
+        run(&mut keyspace, &["HSET", "h", "e", "1"], 0);
+        run(&mut keyspace, &["HDEL", "h", "d", "e"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "ENCODING", "h"], 0),
+            bulk("hashtable")
+        );
+
+        let Frame::Array(fields) = run(&mut keyspace, &["HRANDFIELD", "h", "10"], 0) else {
+            panic!("HRANDFIELD replies with an array");
+        };
+        let mut fields: Vec<_> = fields.into_iter().collect();
+        fields.sort_by_key(|field| format!("{field:?}"));
+        assert_eq!(Frame::Array(fields), array(&["a", "b", "c"]));
+        let Frame::Array(fields) = run(&mut keyspace, &["HRANDFIELD", "h", "-10"], 0) else {
+            panic!("HRANDFIELD replies with an array");
+        };
+        assert_eq!(fields.len(), 10);
+
+        assert_eq!(
+            run(&mut keyspace, &["HDEL", "h", "a", "b", "c"], 0),
+            Frame::Integer(3)
+        );
+        assert_eq!(run(&mut keyspace, &["EXISTS", "h"], 0), Frame::Integer(0));
+        assert_eq!(run(&mut keyspace, &["HRANDFIELD", "h"], 0), Frame::Null);
+    }
+
+    #[test]
+    fn test_hash_field_expiry() {
+        let mut keyspace = Keyspace::new();
+        let ints = |ints: &[i64]| Frame::Array(ints.iter().map(|n| Frame::Integer(*n)).collect());
+        run(
+            &mut keyspace,
+            &["HSET", "h", "a", "1", "b", "2", "c", "3"],
+            0,
+        );
+
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["HPEXPIRE", "h", "100", "FIELDS", "3", "a", "b", "nope"],
+                0
+            ),
+            ints(&[1, 1, -2])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "ENCODING", "h"], 0),
+            bulk("listpackex")
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["HPEXPIRE", "h", "200", "LT", "FIELDS", "2", "a", "c"],
+                0
+            ),
+            ints(&[0, 1])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["HTTL", "h", "FIELDS", "3", "a", "b", "nope"],
+                0
+            ),
+            ints(&[1, 1, -2])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["HPERSIST", "h", "FIELDS", "2", "b", "b"],
+                0
+            ),
+            ints(&[1, -1])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["HPTTL", "nope", "FIELDS", "1", "a"], 0),
+            ints(&[-2])
+        );
+
+        // HSET removes the expiry, HINCRBY keeps it
+        run(&mut keyspace, &["HINCRBY", "h", "a", "1"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["HPTTL", "h", "FIELDS", "1", "a"], 50),
+            ints(&[50])
+        );
+
+        // Expired fields are gone, the hash goes with its last field
+        assert_eq!(run(&mut keyspace, &["HGET", "h", "a"], 100), Frame::Null);
+        assert_eq!(run(&mut keyspace, &["HLEN", "h"], 100), Frame::Integer(2));
+        assert_eq!(keyspace.stats().expired_fields, 1);
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["HGETEX", "h", "PX", "10", "FIELDS", "2", "b", "nope"],
+                100
+            ),
+            Frame::Array(vec![bulk("2"), Frame::Null])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["HEXPIRE", "h", "0", "FIELDS", "1", "b"],
+                100
+            ),
+            ints(&[2])
+        );
+        assert_eq!(keyspace.remove_expired_fields(150, 10), 0);
+        assert_eq!(keyspace.remove_expired_fields(200, 10), 1);
+        assert_eq!(run(&mut keyspace, &["EXISTS", "h"], 200), Frame::Integer(0));
+        assert!(keyspace.field_expires.is_empty());
+
+        // The index follows the hash when it is replaced
+        run(&mut keyspace, &["HSET", "h", "a", "1"], 0);
+        run(
+            &mut keyspace,
+            &["HPEXPIRE", "h", "100", "FIELDS", "1", "a"],
+            0,
+        );
+        run(&mut keyspace, &["SET", "h", "x"], 0);
+        assert_eq!(keyspace.remove_expired_fields(100, 10), 0);
+        assert!(keyspace.field_expires.is_empty());
+    }
+
+    #[test]
+    fn test_hscan() {
+        let mut keyspace = Keyspace::new();
+        let fields: Vec<String> = (0..1000).map(|i| format!("f{i}")).collect();
+        for field in &fields {
+            run(&mut keyspace, &["HSET", "h", field, "v"], 0);
+        }
+
+        // Every field is returned once, even when fields are added and
+        // removed during the scan
+        let mut seen = HashSet::new();
+        let mut cursor = "0".to_string();
+        let mut pages = 0;
+        loop {
+            let reply = run(&mut keyspace, &["HSCAN", "h", &cursor, "NOVALUES"], 0);
+            let Frame::Array(reply) = reply else {
+                panic!("HSCAN replies with an array");
+            };
+            let [Frame::Bulk(next), Frame::Array(page)] = &reply[..] else {
+                panic!("HSCAN replies with a cursor and a page");
+            };
+            for field in page {
+                let Frame::Bulk(field) = field else {
+                    panic!("fields are bulk strings");
+                };
+                assert!(seen.insert(field.clone()), "{field:?} was returned twice");
+            }
+            run(&mut keyspace, &["HDEL", "h", &format!("f{pages}")], 0);
+            run(&mut keyspace, &["HSET", "h", &format!("g{pages}"), "v"], 0);
+            pages += 1;
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        for field in fields.iter().skip(pages) {
+            assert!(seen.contains(field.as_bytes()), "{field} was missed");
+        }
+
+        let reply = run(
+            &mut keyspace,
+            &["HSCAN", "h", "0", "MATCH", "f99?", "COUNT", "5000"],
+            0,
+        );
+        let expected: Vec<Frame> = (990..1000)
+            .flat_map(|i| [Frame::Bulk(Bytes::from(format!("f{i}"))), bulk("v")])
+            .collect();
+        let Frame::Array(reply) = reply else {
+            panic!("HSCAN replies with an array");
+        };
+        let Frame::Array(mut page) = reply[1].clone() else {
+            panic!("HSCAN replies with a page");
+        };
+        let mut pairs: Vec<_> = page.chunks(2).map(|pair| pair.to_vec()).collect();
+        pairs.sort_by_key(|pair| format!("{pair:?}"));
+        page = pairs.concat();
+        assert_eq!(reply[0], bulk("0"));
+        assert_eq!(page, expected);
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut keyspace = Keyspace::new();
+        keyspace.config.set_max_intset_entries = 4;
+        keyspace.config.set_max_listpack_entries = 3;
+        let encoding = |keyspace: &mut Keyspace, key| {
+            return run(keyspace, &["OBJECT", "ENCODING", key], 0);
+        };
+        let sorted = |frame: Frame| {
+            let (Frame::Set(mut elems) | Frame::Array(mut elems)) = frame else {
+                panic!("{frame:?} is not a collection");
+            };
+            elems.sort_by_key(|elem| format!("{elem:?}"));
+            return elems;
+        };
+
+        // Integers stay an intset until there are too many of them
+        assert_eq!(
+            run(&mut keyspace, &["SADD", "ints", "3", "1", "2", "1"], 0),
+            Frame::Integer(3)
+        );
+        assert_eq!(encoding(&mut keyspace, "ints"), bulk("intset"));
+        assert_eq!(
+            run(&mut keyspace, &["SMEMBERS", "ints"], 0),
+            Frame::Set(vec![bulk("1"), bulk("2"), bulk("3")])
+        );
+        run(&mut keyspace, &["SADD", "ints", "4", "5"], 0);
+        assert_eq!(encoding(&mut keyspace, "ints"), bulk("hashtable"));
+
+        // A string turns a small intset into a listpack, which becomes a
+        // hash table once it outgrows the limits
+        run(&mut keyspace, &["SADD", "s", "1", "2"], 0);
+        run(&mut keyspace, &["SADD", "s", "a"], 0);
+        assert_eq!(encoding(&mut keyspace, "s"), bulk("listpack"));
+        assert_eq!(
+            run(&mut keyspace, &["SMISMEMBER", "s", "1", "a", "01"], 0),
+            Frame::Array(vec![
+                Frame::Integer(1),
+                Frame::Integer(1),
+                Frame::Integer(0)
+            ])
+        );
+        run(&mut keyspace, &["SADD", "s", "b"], 0);
+        assert_eq!(encoding(&mut keyspace, "s"), bulk("hashtable"));
+
+        assert_eq!(
+            sorted(run(&mut keyspace, &["SINTER", "ints", "s"], 0)),
+            vec![bulk("1"), bulk("2")]
+        );
+        assert_eq!(
+            run(&mut keyspace, &["SINTER", "ints", "nope"], 0),
+            Frame::Set(vec![])
+        );
+        assert_eq!(
+            sorted(run(&mut keyspace, &["SDIFF", "s", "ints", "nope"], 0)),
+            vec![bulk("a"), bulk("b")]
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["SINTERCARD", "2", "ints", "s", "LIMIT", "1"],
+                0
+            ),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["SUNIONSTORE", "u", "ints", "s"], 0),
+            Frame::Integer(7)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["SDIFFSTORE", "d", "s", "ints"], 0),
+            Frame::Integer(2)
+        );
+        assert_eq!(encoding(&mut keyspace, "d"), bulk("listpack"));
+        assert_eq!(
+            run(&mut keyspace, &["SINTERSTORE", "d", "s", "nope"], 0),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&mut keyspace, &["EXISTS", "d"], 0), Frame::Integer(0));
+
+        run(&mut keyspace, &["SET", "str", "x"], 0);
+        let wrong_type = CommandError::WrongType.to_frame();
+        assert_eq!(run(&mut keyspace, &["SUNION", "s", "str"], 0), wrong_type);
+        assert_eq!(
+            run(&mut keyspace, &["SMOVE", "s", "str", "a"], 0),
+            wrong_type
+        );
+        assert_eq!(
+            run(&mut keyspace, &["SMOVE", "s", "t", "a"], 0),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["SMOVE", "s", "t", "a"], 0),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&mut keyspace, &["SCARD", "t"], 0), Frame::Integer(1));
+
+        // Popping every member removes the key
+        assert_eq!(sorted(run(&mut keyspace, &["SPOP", "u", "10"], 0)).len(), 7);
+        assert_eq!(run(&mut keyspace, &["EXISTS", "u"], 0), Frame::Integer(0));
+        assert_eq!(run(&mut keyspace, &["SPOP", "u"], 0), Frame::Null);
+        let Frame::Array(picked) = run(&mut keyspace, &["SRANDMEMBER", "t", "-5"], 0) else {
+            panic!("SRANDMEMBER replies with an array");
+        };
+        assert_eq!(picked, vec![bulk("a"); 5]);
+
+        let (mut cursor, mut members) = (0, vec![]);
+        loop {
+            let cursor_arg = cursor.to_string();
+            let reply = run(
+                &mut keyspace,
+                &["SSCAN", "ints", &cursor_arg, "COUNT", "2"],
+                0,
+            );
+            let Frame::Array(reply) = reply else {
+                panic!("SSCAN replies with an array");
+            };
+            let [Frame::Bulk(next), Frame::Array(page)] = &reply[..] else {
+                panic!("unexpected SSCAN reply {reply:?}");
+            };
+            members.extend(page.iter().cloned());
+            cursor = std::str::from_utf8(next).unwrap().parse::<u64>().unwrap();
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert_eq!(sorted(Frame::Array(members)).len(), 5);
+    }
+
+    #[test]
+    fn test_sorted_sets() {
+        let mut keyspace = Keyspace::new();
+        let array = |elems: &[&'static str]| Frame::Array(elems.iter().map(|e| bulk(e)).collect());
+        let scored = |pairs: &[(&'static str, f64)]| {
+            let elems = pairs
+                .iter()
+                .flat_map(|(member, score)| [bulk(member), Frame::Double(*score)]);
+            return Frame::Array(elems.collect());
+        };
+
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZADD", "z", "1", "a", "2", "b", "3", "c"],
+                0
+            ),
+            Frame::Integer(3)
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZADD", "z", "CH", "1", "a", "5", "b", "4", "d"],
+                0
+            ),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZADD", "z", "GT", "CH", "0", "a", "9", "c"],
+                0
+            ),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZADD", "z", "NX", "INCR", "1", "a"], 0),
+            Frame::Null
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZADD", "z", "XX", "INCR", "1", "a"], 0),
+            Frame::Double(2.0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZADD", "nope", "XX", "1", "a"], 0),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["EXISTS", "nope"], 0),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "ENCODING", "z"], 0),
+            bulk("listpack")
+        );
+        assert_eq!(
+            run(&mut keyspace, &["TYPE", "z"], 0),
+            Frame::Simple("zset".into())
+        );
+
+        // a:2 d:4 b:5 c:9
+        assert_eq!(
+            run(&mut keyspace, &["ZRANGE", "z", "0", "-1", "WITHSCORES"], 0),
+            scored(&[("a", 2.0), ("d", 4.0), ("b", 5.0), ("c", 9.0)])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZRANGE", "z", "0", "1", "REV"], 0),
+            array(&["c", "b"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZRANGE", "z", "(2", "5", "BYSCORE"], 0),
+            array(&["d", "b"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZRANGE", "z", "+inf", "-inf", "BYSCORE", "REV", "LIMIT", "1", "2"],
+                0
+            ),
+            array(&["b", "d"])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZCOUNT", "z", "-inf", "(5"], 0),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZRANK", "z", "b"], 0),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZREVRANK", "z", "b", "WITHSCORE"], 0),
+            Frame::Array(vec![Frame::Integer(1), Frame::Double(5.0)])
+        );
+        assert_eq!(run(&mut keyspace, &["ZRANK", "z", "x"], 0), Frame::Null);
+        assert_eq!(
+            run(&mut keyspace, &["ZMSCORE", "z", "c", "x"], 0),
+            Frame::Array(vec![Frame::Double(9.0), Frame::Null])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZINCRBY", "z", "-10", "c"], 0),
+            Frame::Double(-1.0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZADD", "z", "INCR", "-inf", "c"], 0),
+            Frame::Double(f64::NEG_INFINITY)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZADD", "z", "INCR", "+inf", "c"], 0),
+            CommandError::Other("ERR resulting score is not a number (NaN)".into()).to_frame()
+        );
+
+        // Members with the same score are ordered by their bytes
+        run(
+            &mut keyspace,
+            &["ZADD", "lex", "0", "b", "0", "a", "0", "c", "0", "d"],
+            0,
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZRANGE", "lex", "(a", "[c", "BYLEX"], 0),
+            array(&["b", "c"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZRANGE", "lex", "+", "(b", "BYLEX", "REV"],
+                0
+            ),
+            array(&["d", "c"])
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZRANGESTORE", "dst", "lex", "-", "[b", "BYLEX"],
+                0
+            ),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZPOPMIN", "lex", "3"], 0),
+            scored(&[("a", 0.0), ("b", 0.0), ("c", 0.0)])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZPOPMAX", "lex"], 0),
+            scored(&[("d", 0.0)])
+        );
+        assert_eq!(run(&mut keyspace, &["EXISTS", "lex"], 0), Frame::Integer(0));
+        assert_eq!(
+            run(&mut keyspace, &["ZPOPMAX", "lex"], 0),
+            Frame::Array(vec![])
+        );
+
+        // Plain sets count as sorted sets whose members all score 1
+        run(&mut keyspace, &["SADD", "s", "a", "x"], 0);
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZUNIONSTORE", "u", "2", "z", "s", "WEIGHTS", "2", "3"],
+                0
+            ),
+            Frame::Integer(5)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZSCORE", "u", "a"], 0),
+            Frame::Double(7.0)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZSCORE", "u", "c"], 0),
+            Frame::Double(f64::NEG_INFINITY)
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZINTERSTORE", "i", "2", "z", "s", "AGGREGATE", "MAX"],
+                0
+            ),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZRANGE", "i", "0", "-1", "WITHSCORES"], 0),
+            scored(&[("a", 2.0)])
+        );
+        assert_eq!(
+            run(&mut keyspace, &["ZINTERSTORE", "i", "2", "z", "nope"], 0),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&mut keyspace, &["EXISTS", "i"], 0), Frame::Integer(0));
+        run(&mut keyspace, &["SET", "str", "x"], 0);
+        assert_eq!(
+            run(&mut keyspace, &["ZUNIONSTORE", "u", "2", "z", "str"], 0),
+            CommandError::WrongType.to_frame()
+        );
+
+        // Large sorted sets are scanned a page at a time
+        for n in 0..200 {
+            let (score, member) = (n.to_string(), format!("m{n}"));
+            run(&mut keyspace, &["ZADD", "big", &score, &member], 0);
+        }
+        assert_eq!(
+            run(&mut keyspace, &["OBJECT", "ENCODING", "big"], 0),
+            bulk("skiplist")
+        );
+        assert_eq!(
+            run(
+                &mut keyspace,
+                &["ZRANGE", "big", "150", "(152", "BYSCORE"],
+                0
+            ),
+            array(&["m150", "m151"])
+        );
+        let (mut cursor, mut seen) = (0u64, 0);
+        loop {
+            let cursor_arg = cursor.to_string();
+            let reply = run(
+                &mut keyspace,
+                &["ZSCAN", "big", &cursor_arg, "COUNT", "50"],
+                0,
+            );
+            let Frame::Array(reply) = reply else {
+                panic!("ZSCAN replies with an array");
+            };
+            let (Frame::Bulk(next), Frame::Array(elems)) = (&reply[0], &reply[1]) else {
+                panic!("ZSCAN replies with a cursor and an array");
+            };
+            seen += elems.len() / 2;
+            cursor = std::str::from_utf8(next).unwrap().parse().unwrap();
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert_eq!(seen, 200);
+    }
+
+    #[tokio::test]
+    async fn test_block_sorted_set() {
+        let db = Arc::new(DB::new());
+        let parse = |args: &[&str]| {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                    .collect(),
+            );
+            return Command::from_frame(&frame).unwrap();
+        };
+        let execute = |args: &[&str]| match parse(args) {
+            Command::List(cmd) => db.execute_list(cmd),
+            Command::SortedSet(cmd) => db.execute_sorted_set(cmd),
+            cmd => panic!("{cmd:?} is not a list or sorted set command"),
+        };
+
+        let list = {
+            let db = Arc::clone(&db);
+            tokio::spawn(async move { db.block(parse(&["BLPOP", "k", "0"])).await })
+        };
+        wait_blocked(&db, 1).await;
+        let zset = {
+            let db = Arc::clone(&db);
+            tokio::spawn(async move { db.block(parse(&["BZPOPMIN", "k", "0"])).await })
+        };
+        wait_blocked(&db, 2).await;
+
+        // The sorted set is served first although the list client blocked
+        // earlier, as a list command cannot pop from a sorted set
+        execute(&["ZADD", "k", "2", "b", "1", "a"]);
+        assert_eq!(
+            zset.await.unwrap(),
+            Frame::Array(vec![bulk("k"), bulk("a"), Frame::Double(1.0)])
+        );
+        assert_eq!(db.lock().blocked_len(), 1);
+        execute(&["ZREM", "k", "b"]);
+        execute(&["RPUSH", "k", "x"]);
+        assert_eq!(
+            list.await.unwrap(),
+            Frame::Array(vec![bulk("k"), bulk("x")])
+        );
+        assert_eq!(
+            db.block(parse(&["BZPOPMAX", "k", "0.01"])).await,
+            Frame::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_geo() {
+        let db = Arc::new(DB::new());
+        let parse = |args: &[&str]| {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                    .collect(),
+            );
+            return Command::from_frame(&frame).unwrap();
+        };
+        let execute = |args: &[&str]| match parse(args) {
+            Command::Geo(cmd) => db.execute_geo(cmd),
+            cmd => panic!("{cmd:?} is not a geo command"),
+        };
+        let popped = |reply: Frame| match reply {
+            Frame::Array(elems) => elems[..2].to_vec(),
+            reply => panic!("{reply:?} is not a popped member"),
+        };
+
+        // Geo commands write sorted sets, which wake clients blocked on them
+        let zset = {
+            let db = Arc::clone(&db);
+            tokio::spawn(async move { db.block(parse(&["BZPOPMIN", "geo", "0"])).await })
+        };
+        wait_blocked(&db, 1).await;
+        assert_eq!(
+            execute(&["GEOADD", "geo", "13", "38", "m"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(popped(zset.await.unwrap()), vec![bulk("geo"), bulk("m")]);
+
+        let zset = {
+            let db = Arc::clone(&db);
+            tokio::spawn(async move { db.block(parse(&["BZPOPMAX", "near", "0"])).await })
+        };
+        wait_blocked(&db, 1).await;
+        execute(&["GEOADD", "geo", "13", "38", "p"]);
+        let search = ["GEOSEARCHSTORE", "near", "geo", "FROMLONLAT", "13", "38"];
+        assert_eq!(
+            execute(&[&search[..], &["BYRADIUS", "1", "km"]].concat()),
+            Frame::Integer(1)
+        );
+        assert_eq!(popped(zset.await.unwrap()), vec![bulk("near"), bulk("p")]);
+        assert_eq!(db.lock().blocked_len(), 0);
+    }
+
+    #[test]
+    fn test_bitmaps() {
+        let mut keyspace = Keyspace::new();
+        let mut run = |args: &[&str]| run(&mut keyspace, args, 0);
+        let ints = |ints: &[i64]| Frame::Array(ints.iter().map(|n| Frame::Integer(*n)).collect());
+
+        assert_eq!(run(&["SETBIT", "k", "7", "1"]), Frame::Integer(0));
+        assert_eq!(run(&["SETBIT", "k", "7", "1"]), Frame::Integer(1));
+        assert_eq!(run(&["GET", "k"]), Frame::Bulk(Bytes::from("\x01")));
+        assert_eq!(run(&["GETBIT", "k", "7"]), Frame::Integer(1));
+        assert_eq!(run(&["GETBIT", "k", "100"]), Frame::Integer(0));
+        assert_eq!(run(&["GETBIT", "nope", "0"]), Frame::Integer(0));
+        run(&["SETBIT", "k", "17", "0"]);
+        assert_eq!(run(&["STRLEN", "k"]), Frame::Integer(3));
+
+        run(&["SET", "s", "foobar"]);
+        assert_eq!(run(&["BITCOUNT", "s"]), Frame::Integer(26));
+        assert_eq!(run(&["BITCOUNT", "s", "1", "1"]), Frame::Integer(6));
+        assert_eq!(
+            run(&["BITCOUNT", "s", "5", "30", "BIT"]),
+            Frame::Integer(17)
+        );
+        assert_eq!(run(&["BITCOUNT", "s", "-1", "-2"]), Frame::Integer(0));
+        assert_eq!(run(&["BITCOUNT", "nope"]), Frame::Integer(0));
+
+        // "f" is 01100110
+        assert_eq!(run(&["BITPOS", "s", "1"]), Frame::Integer(1));
+        assert_eq!(
+            run(&["BITPOS", "s", "0", "2", "3", "BIT"]),
+            Frame::Integer(3)
+        );
+        assert_eq!(run(&["BITPOS", "nope", "0"]), Frame::Integer(0));
+        assert_eq!(run(&["BITPOS", "nope", "1"]), Frame::Integer(-1));
+        for offset in 0..8 {
+            run(&["SETBIT", "ones", &offset.to_string(), "1"]);
+        }
+        // Without an end, a string is followed by zeros
+        assert_eq!(run(&["BITPOS", "ones", "0"]), Frame::Integer(8));
+        assert_eq!(run(&["BITPOS", "ones", "0", "0", "-1"]), Frame::Integer(-1));
+
+        run(&["SET", "a", "foo"]);
+        run(&["SET", "b", "fo"]);
+        assert_eq!(run(&["BITOP", "AND", "d", "a", "b"]), Frame::Integer(3));
+        assert_eq!(run(&["GET", "d"]), Frame::Bulk(Bytes::from("fo\0")));
+        assert_eq!(
+            run(&["BITOP", "OR", "d", "a", "b", "nope"]),
+            Frame::Integer(3)
+        );
+        assert_eq!(run(&["GET", "d"]), Frame::Bulk(Bytes::from("foo")));
+        assert_eq!(run(&["BITOP", "NOT", "d", "b"]), Frame::Integer(2));
+        assert_eq!(
+            run(&["GET", "d"]),
+            Frame::Bulk(Bytes::from(vec![!b'f', !b'o']))
+        );
+        // An empty result deletes the destination
+        assert_eq!(run(&["BITOP", "XOR", "d", "nope"]), Frame::Integer(0));
+        assert_eq!(run(&["EXISTS", "d"]), Frame::Integer(0));
+
+        assert_eq!(
+            run(&[
+                "BITFIELD", "f", "SET", "u8", "0", "255", "GET", "u4", "0", "INCRBY", "u8", "0",
+                "1"
+            ]),
+            ints(&[0, 15, 0])
+        );
+        assert_eq!(
+            run(&["BITFIELD", "f", "OVERFLOW", "FAIL", "INCRBY", "i8", "#1", "200"]),
+            Frame::Array(vec![Frame::Null])
+        );
+        assert_eq!(
+            run(&[
+                "BITFIELD", "f", "OVERFLOW", "SAT", "INCRBY", "i8", "8", "200", "GET", "i4", "8"
+            ]),
+            ints(&[127, 7])
+        );
+        assert_eq!(run(&["GET", "f"]), Frame::Bulk(Bytes::from("\0\x7f")));
+        // Reads leave a missing key missing
+        assert_eq!(run(&["BITFIELD_RO", "nope", "GET", "i8", "0"]), ints(&[0]));
+        assert_eq!(run(&["EXISTS", "nope"]), Frame::Integer(0));
+
+        run(&["RPUSH", "l", "x"]);
+        let wrong_type = Frame::Error(CommandError::WrongType.to_string());
+        assert_eq!(run(&["SETBIT", "l", "0", "1"]), wrong_type);
+        assert_eq!(run(&["BITOP", "OR", "d", "a", "l"]), wrong_type);
+        assert_eq!(run(&["BITFIELD", "l", "GET", "u8", "0"]), wrong_type);
+    }
+
+    #[test]
+    fn test_geo() {
+        let mut keyspace = Keyspace::new();
+        let mut run = |args: &[&str]| run(&mut keyspace, args, 0);
+        let bulks =
+            |members: &[&'static str]| Frame::Array(members.iter().map(|m| bulk(m)).collect());
+
+        let add = ["GEOADD", "Sicily", "13.361389", "38.115556", "Palermo"];
+        assert_eq!(run(&add), Frame::Integer(1));
+        assert_eq!(run(&add), Frame::Integer(0));
+        assert_eq!(
+            run(&[
+                "GEOADD",
+                "Sicily",
+                "CH",
+                "15.087269",
+                "37.502669",
+                "Catania",
+                "13.361389",
+                "38",
+                "Palermo"
+            ]),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&[
+                "GEOADD",
+                "Sicily",
+                "XX",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "0",
+                "0",
+                "nope"
+            ]),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&["ZCARD", "Sicily"]), Frame::Integer(2));
+
+        assert_eq!(
+            run(&["GEODIST", "Sicily", "Palermo", "Catania"]),
+            bulk("166274.1516")
+        );
+        assert_eq!(
+            run(&["GEODIST", "Sicily", "Palermo", "Catania", "KM"]),
+            bulk("166.2742")
+        );
+        assert_eq!(run(&["GEODIST", "Sicily", "Palermo", "nope"]), Frame::Null);
+        assert_eq!(
+            run(&["GEOHASH", "Sicily", "Palermo", "nope"]),
+            Frame::Array(vec![bulk("sqc8b49rny0"), Frame::Null])
+        );
+        let Frame::Array(positions) = run(&["GEOPOS", "Sicily", "Palermo", "nope"]) else {
+            panic!("not an array");
+        };
+        assert!(matches!(
+            &positions[..],
+            [Frame::Array(pair), Frame::Null]
+                if pair == &[Frame::Double(13.361389338970184), Frame::Double(38.1155563954963)]
+        ));
+
+        run(&[
+            "GEOADD",
+            "Sicily",
+            "12.758489",
+            "38.788135",
+            "edge1",
+            "17.241510",
+            "38.788135",
+            "edge2",
+        ]);
+        assert_eq!(
+            run(&[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYRADIUS",
+                "200",
+                "km",
+                "ASC"
+            ]),
+            bulks(&["Catania", "Palermo"])
+        );
+        assert_eq!(
+            run(&[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYBOX",
+                "400",
+                "400",
+                "km",
+                "DESC",
+                "WITHDIST"
+            ]),
+            Frame::Array(
+                [
+                    ("edge1", "279.7405"),
+                    ("edge2", "279.7403"),
+                    ("Palermo", "190.4424"),
+                    ("Catania", "56.4413")
+                ]
+                .into_iter()
+                .map(|(member, dist)| Frame::Array(vec![bulk(member), bulk(dist)]))
+                .collect()
+            )
+        );
+        // A COUNT picks the closest members
+        assert_eq!(
+            run(&[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMMEMBER",
+                "Palermo",
+                "BYRADIUS",
+                "500",
+                "km",
+                "COUNT",
+                "2"
+            ]),
+            bulks(&["Palermo", "edge1"])
+        );
+        assert_eq!(
+            run(&[
+                "GEOSEARCH",
+                "Sicily",
+                "FROMMEMBER",
+                "nope",
+                "BYRADIUS",
+                "1",
+                "m"
+            ]),
+            Frame::Error("ERR could not decode requested zset member".into())
+        );
+        assert_eq!(
+            run(&[
+                "GEOSEARCH",
+                "nope",
+                "FROMMEMBER",
+                "nope",
+                "BYRADIUS",
+                "1",
+                "m"
+            ]),
+            Frame::Array(vec![])
+        );
+
+        assert_eq!(
+            run(&[
+                "GEOSEARCHSTORE",
+                "near",
+                "Sicily",
+                "FROMLONLAT",
+                "15",
+                "37",
+                "BYRADIUS",
+                "100",
+                "km",
+                "STOREDIST"
+            ]),
+            Frame::Integer(1)
+        );
+        assert!(matches!(
+            run(&["ZSCORE", "near", "Catania"]),
+            Frame::Double(dist) if (dist - 56.4413).abs() < 1e-4
+        ));
+        // Nothing found removes the destination
+        assert_eq!(
+            run(&[
+                "GEOSEARCHSTORE",
+                "near",
+                "Sicily",
+                "FROMLONLAT",
+                "0",
+                "0",
+                "BYRADIUS",
+                "1",
+                "km"
+            ]),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&["EXISTS", "near"]), Frame::Integer(0));
+    }
+
+    #[test]
+    fn test_hyperloglog() {
+        let mut keyspace = Keyspace::new();
+        let mut run = |args: &[&str], now| run(&mut keyspace, args, now);
+
+        assert_eq!(run(&["PFADD", "a"], 0), Frame::Integer(1));
+        assert_eq!(run(&["PFADD", "a"], 0), Frame::Integer(0));
+        assert_eq!(run(&["PFADD", "a", "x", "y", "z"], 0), Frame::Integer(1));
+        assert_eq!(run(&["PFADD", "a", "x", "y"], 0), Frame::Integer(0));
+        assert_eq!(run(&["PFCOUNT", "a"], 0), Frame::Integer(3));
+        assert_eq!(run(&["PFCOUNT", "nope"], 0), Frame::Integer(0));
+        // HyperLogLogs are strings, which start with their header
+        assert!(matches!(
+            run(&["GET", "a"], 0),
+            Frame::Bulk(s) if s.starts_with(b"HYLL\x01")
+        ));
+        assert_eq!(run(&["TYPE", "a"], 0), Frame::Simple("string".into()));
+
+        run(&["PFADD", "b", "y", "z", "w"], 0);
+        assert_eq!(run(&["PFCOUNT", "a", "b", "nope"], 0), Frame::Integer(4));
+        assert_eq!(
+            run(&["PFMERGE", "c", "a", "b"], 0),
+            Frame::Simple("OK".into())
+        );
+        assert_eq!(run(&["PFCOUNT", "c"], 0), Frame::Integer(4));
+        // The destination is part of the union
+        assert_eq!(run(&["PFMERGE", "b"], 0), Frame::Simple("OK".into()));
+        assert_eq!(run(&["PFCOUNT", "b"], 0), Frame::Integer(3));
+
+        run(&["SET", "s", "not an hll"], 0);
+        let not_hll = Frame::Error("WRONGTYPE Key is not a valid HyperLogLog string value.".into());
+        assert_eq!(run(&["PFADD", "s", "x"], 0), not_hll);
+        assert_eq!(run(&["PFCOUNT", "a", "s"], 0), not_hll);
+        assert_eq!(run(&["PFMERGE", "a", "s"], 0), not_hll);
+        run(&["RPUSH", "l", "x"], 0);
+        assert_eq!(
+            run(&["PFCOUNT", "l"], 0),
+            Frame::Error(CommandError::WrongType.to_string())
+        );
+
+        // A HyperLogLog copied with GET and SET is still one
+        let Frame::Bulk(copy) = run(&["GET", "c"], 0) else {
+            panic!("not a string");
+        };
+        keyspace.set_value(Bytes::from("d"), Value::String(copy), 0);
+        assert_eq!(
+            self::run(&mut keyspace, &["PFCOUNT", "d"], 0),
+            Frame::Integer(4)
+        );
+    }
+
+    #[test]
+    fn test_streams() {
+        let mut keyspace = Keyspace::new();
+        let mut run = |args: &[&str], now| run(&mut keyspace, args, now);
+        let entry = |id: &'static str, field: &'static str, val: &'static str| {
+            return Frame::Array(vec![bulk(id), Frame::Array(vec![bulk(field), bulk(val)])]);
+        };
+
+        assert_eq!(run(&["XADD", "s", "*", "a", "1"], 1000), bulk("1000-0"));
+        // The clock went back, so the sequence number goes up instead
+        assert_eq!(run(&["XADD", "s", "*", "b", "2"], 900), bulk("1000-1"));
+        assert_eq!(run(&["XADD", "s", "2000-*", "c", "3"], 900), bulk("2000-0"));
+        assert_eq!(run(&["XADD", "s", "2000-5", "d", "4"], 900), bulk("2000-5"));
+        assert_eq!(
+            run(&["XADD", "s", "2000-5", "e", "5"], 900),
+            Frame::Error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .into()
+            )
+        );
+        assert_eq!(
+            run(&["XADD", "n", "NOMKSTREAM", "*", "a", "1"], 900),
+            Frame::Null
+        );
+        assert_eq!(
+            run(&["XADD", "n", "0-0", "a", "1"], 900),
+            Frame::Error("ERR The ID specified in XADD must be greater than 0-0".into())
+        );
+        assert_eq!(run(&["TYPE", "s"], 900), Frame::Simple("stream".into()));
+        assert_eq!(run(&["XLEN", "s"], 900), Frame::Integer(4));
+
+        assert_eq!(
+            run(&["XRANGE", "s", "1000", "(2000-5"], 900),
+            Frame::Array(vec![
+                entry("1000-0", "a", "1"),
+                entry("1000-1", "b", "2"),
+                entry("2000-0", "c", "3"),
+            ])
+        );
+        assert_eq!(
+            run(&["XREVRANGE", "s", "+", "-", "COUNT", "1"], 900),
+            Frame::Array(vec![entry("2000-5", "d", "4")])
+        );
+        assert_eq!(
+            run(&["XRANGE", "s", "3000", "1000"], 900),
+            Frame::Array(vec![])
+        );
+        assert_eq!(
+            run(
+                &["XREAD", "COUNT", "1", "STREAMS", "s", "n", "1000-0", "0"],
+                900
+            ),
+            Frame::Array(vec![Frame::Array(vec![
+                bulk("s"),
+                Frame::Array(vec![entry("1000-1", "b", "2")]),
+            ])])
+        );
+        assert_eq!(run(&["XREAD", "STREAMS", "s", "$"], 900), Frame::Null);
+
+        assert_eq!(run(&["XDEL", "s", "1000-1", "9-9"], 900), Frame::Integer(1));
+        assert_eq!(
+            run(&["XTRIM", "s", "MINID", "2000"], 900),
+            Frame::Integer(1)
+        );
+        assert_eq!(run(&["XTRIM", "s", "MAXLEN", "5"], 900), Frame::Integer(0));
+        assert_eq!(
+            run(&["XADD", "s", "MAXLEN", "1", "*", "e", "5"], 900),
+            bulk("2000-6")
+        );
+        // A stream is kept once it is empty, along with its last ID
+        assert_eq!(run(&["XTRIM", "s", "MAXLEN", "0"], 900), Frame::Integer(1));
+        assert_eq!(run(&["EXISTS", "s"], 900), Frame::Integer(1));
+        assert_eq!(run(&["XADD", "s", "*", "f", "6"], 900), bulk("2000-7"));
+
+        assert_eq!(
+            run(&["XGROUP", "CREATE", "t", "g", "$"], 900),
+            Frame::Error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.".into())
+        );
+        assert_eq!(
+            run(&["XGROUP", "CREATE", "t", "g", "$", "MKSTREAM"], 900),
+            Frame::Simple("OK".into())
+        );
+        assert_eq!(
+            run(&["XGROUP", "CREATE", "t", "g", "0"], 900),
+            Frame::Error("BUSYGROUP Consumer Group name already exists".into())
+        );
+        assert_eq!(
+            run(&["XINFO", "STREAM", "nope"], 900),
+            Frame::Error("ERR no such key".into())
+        );
+    }
+
+    #[test]
+    fn test_stream_groups() {
+        let mut keyspace = Keyspace::new();
+        let mut run = |args: &[&str], now| run(&mut keyspace, args, now);
+        let entry = |id: &'static str, val: &'static str| {
+            return Frame::Array(vec![bulk(id), Frame::Array(vec![bulk("f"), bulk(val)])]);
+        };
+        let read = |key: &'static str, entries: Vec<Frame>| {
+            return Frame::Array(vec![Frame::Array(vec![bulk(key), Frame::Array(entries)])]);
+        };
+
+        for id in ["1-0", "2-0", "3-0"] {
+            run(&["XADD", "s", id, "f", id], 0);
+        }
+        run(&["XGROUP", "CREATE", "s", "g", "0"], 0);
+        assert_eq!(
+            run(&["XREADGROUP", "GROUP", "x", "c", "STREAMS", "s", ">"], 0),
+            Frame::Error(
+                "NOGROUP No such key 's' or consumer group 'x' in XREADGROUP with GROUP option"
+                    .into()
+            )
+        );
+        assert_eq!(
+            run(
+                &[
+                    "XREADGROUP",
+                    "GROUP",
+                    "g",
+                    "alice",
+                    "COUNT",
+                    "2",
+                    "STREAMS",
+                    "s",
+                    ">"
+                ],
+                100
+            ),
+            read("s", vec![entry("1-0", "1-0"), entry("2-0", "2-0")])
+        );
+        assert_eq!(
+            run(
+                &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"],
+                200
+            ),
+            read("s", vec![entry("3-0", "3-0")])
+        );
+        // Nothing is left to deliver
+        assert_eq!(
+            run(
+                &["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"],
+                200
+            ),
+            Frame::Null
+        );
+        // The history of a consumer is its pending entries
+        assert_eq!(
+            run(
+                &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"],
+                300
+            ),
+            read("s", vec![entry("1-0", "1-0"), entry("2-0", "2-0")])
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g"], 300),
+            Frame::Array(vec![
+                Frame::Integer(3),
+                bulk("1-0"),
+                bulk("3-0"),
+                Frame::Array(vec![
+                    Frame::Array(vec![bulk("alice"), bulk("2")]),
+                    Frame::Array(vec![bulk("bob"), bulk("1")]),
+                ]),
+            ])
+        );
+        assert_eq!(
+            run(&["XACK", "s", "g", "1-0", "1-0", "9-0"], 300),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g", "-", "+", "10", "alice"], 300),
+            Frame::Array(vec![Frame::Array(vec![
+                bulk("2-0"),
+                bulk("alice"),
+                Frame::Integer(200),
+                Frame::Integer(1),
+            ])])
+        );
+
+        // Bob claims the entry of Alice once it is idle long enough
+        assert_eq!(
+            run(&["XCLAIM", "s", "g", "bob", "500", "2-0"], 300),
+            Frame::Array(vec![])
+        );
+        assert_eq!(
+            run(&["XCLAIM", "s", "g", "bob", "100", "2-0"], 300),
+            Frame::Array(vec![entry("2-0", "2-0")])
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g", "-", "+", "10"], 400),
+            Frame::Array(vec![
+                Frame::Array(vec![
+                    bulk("2-0"),
+                    bulk("bob"),
+                    Frame::Integer(100),
+                    Frame::Integer(2),
+                ]),
+                Frame::Array(vec![
+                    bulk("3-0"),
+                    bulk("bob"),
+                    Frame::Integer(200),
+                    Frame::Integer(1),
+                ]),
+            ])
+        );
+        // Entries removed from the stream are dropped from the pending entries
+        run(&["XDEL", "s", "3-0"], 400);
+        assert_eq!(
+            run(
+                &["XAUTOCLAIM", "s", "g", "alice", "50", "0", "COUNT", "2"],
+                400
+            ),
+            Frame::Array(vec![
+                bulk("0-0"),
+                Frame::Array(vec![entry("2-0", "2-0")]),
+                Frame::Array(vec![bulk("3-0")]),
+            ])
+        );
+
+        assert_eq!(
+            run(&["XINFO", "GROUPS", "s"], 400),
+            Frame::Array(vec![Frame::Map(vec![
+                (bulk("name"), bulk("g")),
+                (bulk("consumers"), Frame::Integer(2)),
+                (bulk("pending"), Frame::Integer(1)),
+                (bulk("last-delivered-id"), bulk("3-0")),
+                (bulk("entries-read"), Frame::Integer(3)),
+                (bulk("lag"), Frame::Integer(0)),
+            ])])
+        );
+        assert_eq!(
+            run(&["XGROUP", "DELCONSUMER", "s", "g", "alice"], 400),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&["XGROUP", "DESTROY", "s", "g"], 400),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g"], 400),
+            Frame::Error("NOGROUP No such key 's' or consumer group 'g'".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_stream() {
+        let db = Arc::new(DB::new());
+        let parse = |args: &[&str]| {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                    .collect(),
+            );
+            return Command::from_frame(&frame).unwrap();
+        };
+        let execute = |args: &[&str]| match parse(args) {
+            Command::Stream(cmd) => db.execute_stream(cmd),
+            cmd => panic!("{cmd:?} is not a stream command"),
+        };
+        let spawn = |args: &'static [&'static str]| {
+            let db = Arc::clone(&db);
+            return tokio::spawn(async move { db.block(parse(args)).await });
+        };
+
+        execute(&["XADD", "s", "1-0", "f", "old"]);
+        execute(&["XGROUP", "CREATE", "s", "g", "$"]);
+        let first = spawn(&["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]);
+        wait_blocked(&db, 1).await;
+        let second = spawn(&["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]);
+        wait_blocked(&db, 2).await;
+        let later = spawn(&["XREAD", "BLOCK", "0", "STREAMS", "s", "5-0"]);
+        wait_blocked(&db, 3).await;
+        let group = spawn(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "BLOCK",
+            "0",
+            "STREAMS",
+            "s",
+            ">",
+        ]);
+        wait_blocked(&db, 4).await;
+
+        // Every reader of the stream is served the new entry, except the one
+        // waiting for entries after a greater ID
+        execute(&["XADD", "s", "2-0", "f", "new"]);
+        let reply = Frame::Array(vec![Frame::Array(vec![
+            bulk("s"),
+            Frame::Array(vec![Frame::Array(vec![
+                bulk("2-0"),
+                Frame::Array(vec![bulk("f"), bulk("new")]),
+            ])]),
+        ])]);
+        assert_eq!(first.await.unwrap(), reply);
+        assert_eq!(second.await.unwrap(), reply);
+        assert_eq!(group.await.unwrap(), reply);
+        assert_eq!(db.lock().blocked_len(), 1);
+        execute(&["XADD", "s", "6-0", "f", "last"]);
+        assert!(matches!(later.await.unwrap(), Frame::Array(_)));
+
+        assert_eq!(
+            db.block(parse(&["XREAD", "BLOCK", "10", "STREAMS", "s", "$"]))
+                .await,
+            Frame::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pubsub() {
+        let db = DB::new();
+        let execute = |subscriptions: &mut Subscriptions, args: &[&str]| {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                    .collect(),
+            );
+            return match Command::from_frame(&frame).unwrap() {
+                Command::PubSub(cmd) => db.execute_pubsub(subscriptions, cmd),
+                cmd => panic!("{cmd:?} is not a pub/sub command"),
+            };
+        };
+        let push = |elems: &[&str], count: Option<i64>| {
+            let mut frames: Vec<Frame> = elems
+                .iter()
+                .map(|elem| Frame::Bulk(Bytes::copy_from_slice(elem.as_bytes())))
+                .collect();
+            frames.extend(count.map(Frame::Integer));
+            return Frame::Push(frames);
+        };
+        let mut news = db.subscriptions();
+        let mut all = db.subscriptions();
+        let mut publisher = db.subscriptions();
+
+        assert_eq!(
+            execute(&mut news, &["SUBSCRIBE", "news", "sports"]),
+            vec![
+                push(&["subscribe", "news"], Some(1)),
+                push(&["subscribe", "sports"], Some(2))
+            ]
+        );
+        assert_eq!(
+            execute(&mut all, &["PSUBSCRIBE", "*"]),
+            vec![push(&["psubscribe", "*"], Some(1))]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["PUBLISH", "news", "hi"]),
+            vec![Frame::Integer(2)]
+        );
+        assert_eq!(
+            news.next_message().await,
+            push(&["message", "news", "hi"], None)
+        );
+        assert_eq!(
+            all.next_message().await,
+            push(&["pmessage", "*", "news", "hi"], None)
+        );
+        assert_eq!(
+            execute(&mut publisher, &["PUBSUB", "NUMSUB", "news", "none"]),
+            vec![Frame::Array(vec![
+                Frame::Bulk(Bytes::from("news")),
+                Frame::Integer(1),
+                Frame::Bulk(Bytes::from("none")),
+                Frame::Integer(0),
+            ])]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["PUBSUB", "CHANNELS", "n*"]),
+            vec![Frame::Array(vec![Frame::Bulk(Bytes::from("news"))])]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["PUBSUB", "NUMPAT"]),
+            vec![Frame::Integer(1)]
+        );
+
+        // Messages published before an unsubscribe go out before its
+        // confirmation
+        execute(&mut publisher, &["PUBLISH", "sports", "goal"]);
+        assert_eq!(
+            execute(&mut news, &["UNSUBSCRIBE"]),
+            vec![
+                push(&["message", "sports", "goal"], None),
+                push(&["unsubscribe", "news"], Some(1)),
+                push(&["unsubscribe", "sports"], Some(0))
+            ]
+        );
+        assert_eq!(
+            execute(&mut news, &["UNSUBSCRIBE"]),
+            vec![Frame::Push(vec![
+                Frame::Bulk(Bytes::from("unsubscribe")),
+                Frame::Null,
+                Frame::Integer(0)
+            ])]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["PUBLISH", "news", "bye"]),
+            vec![Frame::Integer(1)]
+        );
+
+        db.unsubscribe_all(&mut all);
+        assert!(all.is_empty());
+        assert_eq!(
+            execute(&mut publisher, &["PUBLISH", "news", "bye"]),
+            vec![Frame::Integer(0)]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["PUBSUB", "NUMPAT"]),
+            vec![Frame::Integer(0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shard_pubsub() {
+        let db = DB::new();
+        let execute = |subscriptions: &mut Subscriptions, args: &[&str]| {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                    .collect(),
+            );
+            return match Command::from_frame(&frame).unwrap() {
+                Command::PubSub(cmd) => db.execute_pubsub(subscriptions, cmd),
+                cmd => panic!("{cmd:?} is not a pub/sub command"),
+            };
+        };
+        let bulk = |s: &'static str| Frame::Bulk(Bytes::from(s));
+        let mut subscriber = db.subscriptions();
+        let mut publisher = db.subscriptions();
+
+        execute(&mut subscriber, &["SUBSCRIBE", "news"]);
+        // Shard channels are counted apart from the other subscriptions
+        assert_eq!(
+            execute(&mut subscriber, &["SSUBSCRIBE", "{a}1", "{a}2"]),
+            vec![
+                Frame::Push(vec![bulk("ssubscribe"), bulk("{a}1"), Frame::Integer(1)]),
+                Frame::Push(vec![bulk("ssubscribe"), bulk("{a}2"), Frame::Integer(2)])
+            ]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["SPUBLISH", "{a}1", "hi"]),
+            vec![Frame::Integer(1)]
+        );
+        assert_eq!(
+            subscriber.next_message().await,
+            Frame::Push(vec![bulk("smessage"), bulk("{a}1"), bulk("hi")])
+        );
+        // Channels and shard channels of the same name are unrelated
+        assert_eq!(
+            execute(&mut publisher, &["PUBLISH", "{a}1", "hi"]),
+            vec![Frame::Integer(0)]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["SPUBLISH", "news", "hi"]),
+            vec![Frame::Integer(0)]
+        );
+        assert_eq!(
+            execute(&mut publisher, &["PUBSUB", "SHARDNUMSUB", "{a}2", "news"]),
+            vec![Frame::Array(vec![
+                bulk("{a}2"),
+                Frame::Integer(1),
+                bulk("news"),
+                Frame::Integer(0)
+            ])]
+        );
+        let Frame::Array(mut channels) =
+            execute(&mut publisher, &["PUBSUB", "SHARDCHANNELS"]).remove(0)
+        else {
+            panic!("PUBSUB SHARDCHANNELS does not reply with an array");
+        };
+        channels.sort_by_key(|channel| format!("{channel:?}"));
+        assert_eq!(channels, vec![bulk("{a}1"), bulk("{a}2")]);
+
+        assert_eq!(
+            execute(&mut subscriber, &["SUNSUBSCRIBE"]),
+            vec![
+                Frame::Push(vec![bulk("sunsubscribe"), bulk("{a}1"), Frame::Integer(1)]),
+                Frame::Push(vec![bulk("sunsubscribe"), bulk("{a}2"), Frame::Integer(0)])
+            ]
+        );
+        assert!(!subscriber.is_empty());
+        assert_eq!(
+            execute(&mut publisher, &["PUBSUB", "SHARDCHANNELS"]),
+            vec![Frame::Array(vec![])]
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        for (pattern, s, matches) in [
+            ("*", "", true),
+            ("h?llo", "hello", true),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "hellox", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-c]llo", "hbllo", true),
+            ("h[c-a]llo", "hbllo", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("*a*b", "xaxbxab", true),
+            ("a*", "b", false),
+        ] {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), s.as_bytes()),
+                matches,
+                "{pattern} {s}"
+            );
+        }
+    }
+
+    /// Wait until the given number of clients are blocked
+    async fn wait_blocked(db: &DB, n: usize) {
+        while db.lock().blocked_len() != n {
+            tokio::task::yield_now().await;
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Publish/subscribe: the channels and patterns connections subscribed to,
+//! and the delivery of the messages published to them
+//!
+//! Like in Redis, subscriptions live outside of the keyspace. Each connection
+//! receives its messages through an unbounded queue, so that publishing never
+//! waits on a slow subscriber. Shard channels are kept apart from the other
+//! channels and grouped by hash slot, like keys in a cluster.
+use super::glob_match;
+use crate::command::{key_slot, PubSubCommand};
+use crate::Frame;
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap};
+use tokio::sync::mpsc;
+
+type Sender = mpsc::UnboundedSender<Frame>;
+
+/// The connections subscribed to a channel or pattern, by ID
+type Subscribers = HashMap<u64, Sender>;
+
+/// The subscribers of every channel, pattern and shard channel. Those without
+/// subscribers are removed.
+#[derive(Default)]
+pub(super) struct Registry {
+    next_id: u64,
+    channels: HashMap<Bytes, Subscribers>,
+    patterns: HashMap<Bytes, Subscribers>,
+    /// The shard channels by hash slot, then by name
+    shard_channels: HashMap<u16, HashMap<Bytes, Subscribers>>,
+}
+
+/// The subscriptions of a connection, and the queue of the messages published
+/// to them
+pub struct Subscriptions {
+    id: u64,
+    channels: BTreeSet<Bytes>,
+    patterns: BTreeSet<Bytes>,
+    shard_channels: BTreeSet<Bytes>,
+    sender: Sender,
+    receiver: mpsc::UnboundedReceiver<Frame>,
+}
+
+impl Subscriptions {
+    /// Count the channels, patterns and shard channels subscribed to
+    pub fn len(&self) -> usize {
+        return self.channels.len() + self.patterns.len() + self.shard_channels.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Count the channels and patterns subscribed to, which is what the
+    /// confirmations of their changes report. Shard channels are counted on
+    /// their own.
+    fn count(&self) -> usize {
+        return self.channels.len() + self.patterns.len();
+    }
+
+    /// Wait for the next message published to the subscriptions, as a
+    /// `message`, `pmessage` or `smessage` push frame. Never completes while
+    /// there are no subscriptions. Cancel safe: no message is lost if the
+    /// future is dropped.
+    pub async fn next_message(&mut self) -> Frame {
+        // The queue never closes, since the subscriptions hold a sender
+        return self.receiver.recv().await.unwrap();
+    }
+}
+
+impl Registry {
+    /// Start tracking the subscriptions of a new connection
+    pub(super) fn subscriptions(&mut self) -> Subscriptions {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.next_id += 1;
+        return Subscriptions {
+            id: self.next_id,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+            sender,
+            receiver,
+        };
+    }
+
+    /// Drop every subscription of a connection, returning the messages
+    /// published to them that the connection has yet to receive
+    pub(super) fn unsubscribe_all(&mut self, subscriptions: &mut Subscriptions) -> Vec<Frame> {
+        for channel in std::mem::take(&mut subscriptions.channels) {
+            remove(&mut self.channels, &channel, subscriptions.id);
+        }
+        for pattern in std::mem::take(&mut subscriptions.patterns) {
+            remove(&mut self.patterns, &pattern, subscriptions.id);
+        }
+        for channel in std::mem::take(&mut subscriptions.shard_channels) {
+            self.remove_shard(&channel, subscriptions.id);
+        }
+        let mut pending = vec![];
+        while let Ok(message) = subscriptions.receiver.try_recv() {
+            pending.push(message);
+        }
+        return pending;
+    }
+
+    /// Remove a connection from the subscribers of a shard channel
+    fn remove_shard(&mut self, channel: &Bytes, id: u64) {
+        let slot = key_slot(channel);
+        if let Some(channels) = self.shard_channels.get_mut(&slot) {
+            remove(channels, channel, id);
+            if channels.is_empty() {
+                self.shard_channels.remove(&slot);
+            }
+        }
+    }
+
+    /// The subscribers of a shard channel, looked up in its slot
+    fn shard_subscribers(&self, channel: &Bytes) -> Option<&Subscribers> {
+        return self
+            .shard_channels
+            .get(&key_slot(channel))
+            .and_then(|channels| channels.get(channel));
+    }
+
+    /// Deliver a message to the subscribers of a shard channel, returning how
+    /// many received it
+    fn spublish(&self, channel: Bytes, message: Bytes) -> usize {
+        let Some(subscribers) = self.shard_subscribers(&channel) else {
+            return 0;
+        };
+        let frame = Frame::Push(vec![
+            Frame::Bulk(Bytes::from("smessage")),
+            Frame::Bulk(channel),
+            Frame::Bulk(message),
+        ]);
+        return subscribers
+            .values()
+            .filter(|sender| sender.send(frame.clone()).is_ok())
+            .count();
+    }
+
+    /// Deliver a message to the subscribers of the channel and of the
+    /// patterns that match it, returning how many deliveries were made. A
+    /// connection subscribed both to the channel and to a matching pattern
+    /// receives the message twice.
+    fn publish(&self, channel: Bytes, message: Bytes) -> usize {
+        let mut receivers = 0;
+        if let Some(subscribers) = self.channels.get(&channel) {
+            let frame = Frame::Push(vec![
+                Frame::Bulk(Bytes::from("message")),
+                Frame::Bulk(channel.clone()),
+                Frame::Bulk(message.clone()),
+            ]);
+            for sender in subscribers.values() {
+                receivers += sender.send(frame.clone()).is_ok() as usize;
+            }
+        }
+        for (pattern, subscribers) in &self.patterns {
+            if !glob_match(pattern, &channel) {
+                continue;
+            }
+            let frame = Frame::Push(vec![
+                Frame::Bulk(Bytes::from("pmessage")),
+                Frame::Bulk(pattern.clone()),
+                Frame::Bulk(channel.clone()),
+                Frame::Bulk(message.clone()),
+            ]);
+            for sender in subscribers.values() {
+                receivers += sender.send(frame.clone()).is_ok() as usize;
+            }
+        }
+        return receivers;
+    }
+}
+
+/// Remove a connection from the subscribers of a channel or pattern
+fn remove(map: &mut HashMap<Bytes, Subscribers>, name: &Bytes, id: u64) {
+    if let Some(subscribers) = map.get_mut(name) {
+        subscribers.remove(&id);
+        if subscribers.is_empty() {
+            map.remove(name);
+        }
+    }
+}
+
+/// The reply confirming a change to the subscriptions of a connection, with
+/// the number of subscriptions it has left
+fn confirmation(kind: &'static str, name: Frame, count: usize) -> Frame {
+    return Frame::Push(vec![
+        Frame::Bulk(Bytes::from(kind)),
+        name,
+        Frame::Integer(count as i64),
+    ]);
+}
+
+/// Execute a command on behalf of a connection. Commands that change the
+/// subscriptions reply with one confirmation per channel or pattern.
+pub(super) fn execute(
+    registry: &mut Registry,
+    subscriptions: &mut Subscriptions,
+    cmd: PubSubCommand,
+) -> Vec<Frame> {
+    let mut replies = vec![];
+    // Messages published before the subscriptions change go out first, so
+    // that none arrives after its channel was unsubscribed from
+    if cmd.is_subscription() {
+        while let Ok(message) = subscriptions.receiver.try_recv() {
+            replies.push(message);
+        }
+    }
+    let id = subscriptions.id;
+    match cmd {
+        PubSubCommand::Subscribe { channels } => {
+            for channel in channels {
+                if subscriptions.channels.insert(channel.clone()) {
+                    let subscribers = registry.channels.entry(channel.clone()).or_default();
+                    subscribers.insert(id, subscriptions.sender.clone());
+                }
+                let count = subscriptions.count();
+                replies.push(confirmation("subscribe", Frame::Bulk(channel), count));
+            }
+        }
+        PubSubCommand::PSubscribe { patterns } => {
+            for pattern in patterns {
+                if subscriptions.patterns.insert(pattern.clone()) {
+                    let subscribers = registry.patterns.entry(pattern.clone()).or_default();
+                    subscribers.insert(id, subscriptions.sender.clone());
+                }
+                let count = subscriptions.count();
+                replies.push(confirmation("psubscribe", Frame::Bulk(pattern), count));
+            }
+        }
+        PubSubCommand::Unsubscribe { mut channels } => {
+            if channels.is_empty() {
+                channels = subscriptions.channels.iter().cloned().collect();
+                if channels.is_empty() {
+                    let count = subscriptions.count();
+                    replies.push(confirmation("unsubscribe", Frame::Null, count));
+                }
+            }
+            for channel in channels {
+                if subscriptions.channels.remove(&channel) {
+                    remove(&mut registry.channels, &channel, id);
+                }
+                let count = subscriptions.count();
+                replies.push(confirmation("unsubscribe", Frame::Bulk(channel), count));
+            }
+        }
+        PubSubCommand::PUnsubscribe { mut patterns } => {
+            if patterns.is_empty() {
+                patterns = subscriptions.patterns.iter().cloned().collect();
+                if patterns.is_empty() {
+                    let count = subscriptions.count();
+                    replies.push(confirmation("punsubscribe", Frame::Null, count));
+                }
+            }
+            for pattern in patterns {
+                if subscriptions.patterns.remove(&pattern) {
+                    remove(&mut registry.patterns, &pattern, id);
+                }
+                let count = subscriptions.count();
+                replies.push(confirmation("punsubscribe", Frame::Bulk(pattern), count));
+            }
+        }
+        PubSubCommand::SSubscribe { channels } => {
+            for channel in channels {
+                if subscriptions.shard_channels.insert(channel.clone()) {
+                    let slot = registry
+                        .shard_channels
+                        .entry(key_slot(&channel))
+                        .or_default();
+                    let subscribers = slot.entry(channel.clone()).or_default();
+                    subscribers.insert(id, subscriptions.sender.clone());
+                }
+                let count = subscriptions.shard_channels.len();
+                replies.push(confirmation("ssubscribe", Frame::Bulk(channel), count));
+            }
+        }
+        PubSubCommand::SUnsubscribe { mut channels } => {
+            if channels.is_empty() {
+                channels = subscriptions.shard_channels.iter().cloned().collect();
+                if channels.is_empty() {
+                    replies.push(confirmation("sunsubscribe", Frame::Null, 0));
+                }
+            }
+            for channel in channels {
+                if subscriptions.shard_channels.remove(&channel) {
+                    registry.remove_shard(&channel, id);
+                }
+                let count = subscriptions.shard_channels.len();
+                replies.push(confirmation("sunsubscribe", Frame::Bulk(channel), count));
+            }
+        }
+        PubSubCommand::SPublish { channel, message } => {
+            let receivers = registry.spublish(channel, message);
+            replies.push(Frame::Integer(receivers as i64));
+        }
+        PubSubCommand::ShardChannels { pattern } => {
+            let channels = registry
+                .shard_channels
+                .values()
+                .flat_map(HashMap::keys)
+                .filter(|channel| pattern.as_ref().is_none_or(|p| glob_match(p, channel)))
+                .map(|channel| Frame::Bulk(channel.clone()))
+                .collect();
+            replies.push(Frame::Array(channels));
+        }
+        PubSubCommand::ShardNumSub { channels } => {
+            let mut counts = Vec::with_capacity(channels.len() * 2);
+            for channel in channels {
+                let count = registry.shard_subscribers(&channel).map_or(0, HashMap::len);
+                counts.push(Frame::Bulk(channel));
+                counts.push(Frame::Integer(count as i64));
+            }
+            replies.push(Frame::Array(counts));
+        }
+        PubSubCommand::Publish { channel, message } => {
+            let receivers = registry.publish(channel, message);
+            replies.push(Frame::Integer(receivers as i64));
+        }
+        PubSubCommand::Channels { pattern } => {
+            let channels = registry
+                .channels
+                .keys()
+                .filter(|channel| pattern.as_ref().is_none_or(|p| glob_match(p, channel)))
+                .map(|channel| Frame::Bulk(channel.clone()))
+                .collect();
+            replies.push(Frame::Array(channels));
+        }
+        PubSubCommand::NumSub { channels } => {
+            let mut counts = Vec::with_capacity(channels.len() * 2);
+            for channel in channels {
+                let count = registry.channels.get(&channel).map_or(0, HashMap::len);
+                counts.push(Frame::Bulk(channel));
+                counts.push(Frame::Integer(count as i64));
+            }
+            replies.push(Frame::Array(counts));
+        }
+        PubSubCommand::NumPat => {
+            replies.push(Frame::Integer(registry.patterns.len() as i64));
+        }
+        PubSubCommand::Help => {
+            let lines = [
+                "PUBSUB <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "CHANNELS [<pattern>]",
+                "    Return the currently active channels matching a <pattern> (default: '*').",
+                "NUMPAT",
+                "    Return number of subscriptions to patterns.",
+                "NUMSUB [<channel> ...]",
+                "    Return the number of subscribers for the specified channels, excluding",
+                "    pattern subscriptions(default: no channels).",
+                "SHARDCHANNELS [<pattern>]",
+                "    Return the currently active shard level channels matching a <pattern> (default: '*').",
+                "SHARDNUMSUB [<shardchannel> ...]",
+                "    Return the number of subscribers for the specified shard level channel(s)",
+                "HELP",
+                "    Print this help.",
+            ];
+            replies.push(Frame::Array(
+                lines
+                    .iter()
+                    .map(|line| Frame::Simple(line.to_string()))
+                    .collect(),
+            ));
+        }
+    }
+    return replies;
+}
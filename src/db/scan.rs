@@ -0,0 +1,149 @@
+//! Hash tables that can be scanned a page at a time
+//!
+//! Redis walks the buckets of its hash tables with a reverse binary cursor.
+//! The standard hash tables do not expose their buckets, so the elements are
+//! also kept ordered by a hash of their own, and the cursor is a position in
+//! that order. A page of a scan costs as much as its size, and a scan returns
+//! every element that stays in the table from start to end, however the
+//! table changes in between.
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{DefaultHasher, Hash as _, Hasher};
+use std::ops::Bound;
+
+/// Return the position of an element in the order tables are scanned in.
+/// The order only depends on the element itself.
+fn scan_order(elem: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    elem.hash(&mut hasher);
+    return hasher.finish();
+}
+
+/// A hash table keyed by elements, which also keeps them in scan order
+#[derive(Debug, Clone)]
+pub struct ScanTable<V> {
+    entries: HashMap<Bytes, V>,
+    order: BTreeSet<(u64, Bytes)>,
+}
+
+impl<V> Default for ScanTable<V> {
+    fn default() -> Self {
+        return Self {
+            entries: HashMap::new(),
+            order: BTreeSet::new(),
+        };
+    }
+}
+
+impl<V: PartialEq> PartialEq for ScanTable<V> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.entries == other.entries;
+    }
+}
+
+impl<V> ScanTable<V> {
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    pub fn get(&self, elem: &[u8]) -> Option<&V> {
+        return self.entries.get(elem);
+    }
+
+    pub fn contains(&self, elem: &[u8]) -> bool {
+        return self.entries.contains_key(elem);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &V)> {
+        return self.entries.iter();
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        return self.entries.keys();
+    }
+
+    /// Set the value of an element, returning its old value if it was there
+    pub fn insert(&mut self, elem: Bytes, val: V) -> Option<V> {
+        let order = scan_order(&elem);
+        let old = self.entries.insert(elem.clone(), val);
+        if old.is_none() {
+            self.order.insert((order, elem));
+        }
+        return old;
+    }
+
+    /// Remove an element, returning its value if it was there
+    pub fn remove(&mut self, elem: &[u8]) -> Option<V> {
+        let (elem, val) = self.entries.remove_entry(elem)?;
+        self.order.remove(&(scan_order(&elem), elem));
+        return Some(val);
+    }
+
+    /// Return a page of a scan: about `count` of the elements that come at or
+    /// after the cursor in the scan order, with their values, and the cursor
+    /// to continue from, which is 0 once the scan is complete
+    pub fn page(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &V)>) {
+        let start = Bound::Included((cursor, Bytes::new()));
+        let mut elems = self.order.range((start, Bound::Unbounded)).peekable();
+        let mut page = vec![];
+        while let Some((order, elem)) = elems.next() {
+            page.push((elem, &self.entries[elem]));
+            // Elements in the same position must end up on the same page, or
+            // the cursor could not tell them apart
+            match elems.peek() {
+                None => return (0, page),
+                Some((next, _)) if page.len() >= count && next != order => {
+                    return (*next, page);
+                }
+                Some(_) => {}
+            }
+        }
+        return (0, page);
+    }
+}
+
+impl<V> FromIterator<(Bytes, V)> for ScanTable<V> {
+    fn from_iter<I: IntoIterator<Item = (Bytes, V)>>(iter: I) -> Self {
+        let mut table = Self::default();
+        for (elem, val) in iter {
+            table.insert(elem, val);
+        }
+        return table;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_scan_pages() {
+        let mut table: ScanTable<usize> = (0..1000)
+            .map(|i| (Bytes::from(format!("elem:{i}")), i))
+            .collect();
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, page) = table.page(cursor, 10);
+            assert!(page.len() <= 10);
+            seen.extend(page.iter().map(|(_, &i)| i));
+            // Elements removed or added during the scan may or may not be
+            // returned, the others always are
+            let page: Vec<Bytes> = page.into_iter().map(|(elem, _)| elem.clone()).collect();
+            table.remove(&page[0]);
+            table.insert(Bytes::from(format!("new:{next}")), usize::MAX);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        let expected: HashSet<usize> = (0..1000).collect();
+        assert_eq!(seen.intersection(&expected).count(), 1000);
+        assert_eq!(table.page(0, 10_000).1.len(), table.len());
+    }
+}
@@ -0,0 +1,408 @@
+//! The set value type, and the execution of the commands that operate on it
+use super::{glob_match, Config, Entry, Keyspace, ScanTable, Value};
+use crate::command::{parse_integer, CommandError, SetCommand, SetOp};
+use crate::Frame;
+use bytes::Bytes;
+use std::collections::HashSet;
+
+/// A set value. Like Redis, a set of integers is kept as a sorted array of
+/// integers, and a small set of short strings as a flat list, the listpack.
+/// A set that outgrows the limits in the Config moves on to the next encoding
+/// and never moves back: from the intset to the listpack or straight to the
+/// hash table, and from the listpack to the hash table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    Listpack(Vec<Bytes>),
+    Table(ScanTable<()>),
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        return match self {
+            Self::IntSet(ints) => ints.len(),
+            Self::Listpack(members) => members.len(),
+            Self::Table(table) => table.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        return match self {
+            Self::IntSet(ints) => {
+                parse_integer(member).is_some_and(|n| ints.binary_search(&n).is_ok())
+            }
+            Self::Listpack(members) => members.iter().any(|m| m == member),
+            Self::Table(table) => table.contains(member),
+        };
+    }
+
+    /// Iterate over the members. Intsets are in ascending order, and
+    /// listpacks keep the order the members were added in.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        return match self {
+            Self::IntSet(ints) => Box::new(ints.iter().map(|n| Bytes::from(n.to_string()))),
+            Self::Listpack(members) => Box::new(members.iter().cloned()),
+            Self::Table(table) => Box::new(table.keys().cloned()),
+        };
+    }
+
+    /// Return the name of the encoding, as reported by OBJECT ENCODING
+    pub fn encoding(&self) -> &'static str {
+        return match self {
+            Self::IntSet(_) => "intset",
+            Self::Listpack(_) => "listpack",
+            Self::Table(_) => "hashtable",
+        };
+    }
+
+    /// Add a member, converting the set to another encoding if it no longer
+    /// fits its own. Return whether the member is new.
+    fn insert(&mut self, member: Bytes, config: &Config) -> bool {
+        if let Self::IntSet(ints) = self {
+            if let Some(n) = parse_integer(&member) {
+                let Err(index) = ints.binary_search(&n) else {
+                    return false;
+                };
+                ints.insert(index, n);
+                if ints.len() > config.set_max_intset_entries {
+                    *self = Self::Table(self.iter().map(|m| (m, ())).collect());
+                }
+                return true;
+            }
+            let fits = ints.len() < config.set_max_listpack_entries
+                && member.len() <= config.set_max_listpack_value;
+            *self = match fits {
+                true => Self::Listpack(self.iter().collect()),
+                false => Self::Table(self.iter().map(|m| (m, ())).collect()),
+            };
+        }
+        if let Self::Listpack(members) = self {
+            if members.contains(&member) {
+                return false;
+            }
+            if members.len() < config.set_max_listpack_entries
+                && member.len() <= config.set_max_listpack_value
+            {
+                members.push(member);
+                return true;
+            }
+            *self = Self::Table(members.drain(..).map(|m| (m, ())).collect());
+        }
+        let Self::Table(table) = self else {
+            unreachable!("every other encoding was converted");
+        };
+        return table.insert(member, ()).is_none();
+    }
+
+    /// Remove a member, returning whether it was there
+    fn remove(&mut self, member: &[u8]) -> bool {
+        return match self {
+            Self::IntSet(ints) => {
+                let Some(index) = parse_integer(member).and_then(|n| ints.binary_search(&n).ok())
+                else {
+                    return false;
+                };
+                ints.remove(index);
+                true
+            }
+            Self::Listpack(members) => match members.iter().position(|m| m == member) {
+                Some(index) => {
+                    members.swap_remove(index);
+                    true
+                }
+                None => false,
+            },
+            Self::Table(table) => table.remove(member).is_some(),
+        };
+    }
+
+    /// Build a set out of members, in the encoding adding them one by one
+    /// would end up with
+    fn from_members(members: impl IntoIterator<Item = Bytes>, config: &Config) -> Self {
+        let mut set = Self::IntSet(Vec::new());
+        for member in members {
+            set.insert(member, config);
+        }
+        return set;
+    }
+}
+
+impl Keyspace {
+    /// Return the set stored under a key, if there is one
+    fn get_set(&mut self, key: &[u8], now: i64) -> Result<Option<&Set>, CommandError> {
+        return match self.get(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the set stored under a key for changing it, if there is one.
+    /// The key must be removed if the set ends up empty.
+    fn get_set_mut(&mut self, key: &[u8], now: i64) -> Result<Option<&mut Set>, CommandError> {
+        return match self.get_mut(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the set stored under a key for changing it, creating an empty
+    /// set if the key does not exist
+    fn get_or_insert_set(&mut self, key: &Bytes, now: i64) -> Result<&mut Set, CommandError> {
+        if self.get_set(key, now)?.is_none() {
+            self.insert(key.clone(), Value::Set(Set::IntSet(Vec::new())), None, now);
+        }
+        return Ok(self.get_set_mut(key, now)?.unwrap());
+    }
+
+    /// Return the sets stored under several keys, None for the keys that do
+    /// not exist. Fail if any key holds another type.
+    fn get_sets(&mut self, keys: &[Bytes], now: i64) -> Result<Vec<Option<&Set>>, CommandError> {
+        for key in keys {
+            self.get_set(key, now)?;
+        }
+        let sets = keys.iter().map(|key| match self.entries.get(key) {
+            Some(Entry {
+                val: Value::Set(set),
+                ..
+            }) => Some(set),
+            _ => None,
+        });
+        return Ok(sets.collect());
+    }
+}
+
+/// Combine sets the way SINTER, SUNION or SDIFF do, stopping once `limit`
+/// members were found
+fn combine(op: SetOp, sets: &[Option<&Set>], limit: usize) -> Vec<Bytes> {
+    let mut result = vec![];
+    match op {
+        SetOp::Inter => {
+            let Some(mut sets) = sets.iter().copied().collect::<Option<Vec<&Set>>>() else {
+                return result;
+            };
+            // Look up the members of the smallest set in the others
+            sets.sort_by_key(|set| set.len());
+            let Some((smallest, others)) = sets.split_first() else {
+                return result;
+            };
+            for member in smallest.iter() {
+                if others.iter().all(|set| set.contains(&member)) {
+                    result.push(member);
+                    if result.len() == limit {
+                        break;
+                    }
+                }
+            }
+        }
+        SetOp::Union => {
+            let mut seen = HashSet::new();
+            for member in sets.iter().flatten().flat_map(|set| set.iter()) {
+                if seen.insert(member.clone()) {
+                    result.push(member);
+                }
+            }
+        }
+        SetOp::Diff => {
+            let Some(Some(first)) = sets.first() else {
+                return result;
+            };
+            for member in first.iter() {
+                if !sets[1..].iter().flatten().any(|set| set.contains(&member)) {
+                    result.push(member);
+                }
+            }
+        }
+    }
+    return result;
+}
+
+/// Pick `count` random distinct members, or all of them if there are fewer,
+/// by shuffling just enough of them
+fn pick_distinct(keyspace: &mut Keyspace, mut members: Vec<Bytes>, count: usize) -> Vec<Bytes> {
+    let count = count.min(members.len());
+    for i in 0..count {
+        let j = i + (keyspace.random() * (members.len() - i) as f64) as usize;
+        members.swap(i, j);
+    }
+    members.truncate(count);
+    return members;
+}
+
+fn bulks(members: impl IntoIterator<Item = Bytes>) -> Vec<Frame> {
+    return members.into_iter().map(Frame::Bulk).collect();
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: SetCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    let config = keyspace.config.clone();
+    return match cmd {
+        SetCommand::Add { key, members } => {
+            let set = keyspace.get_or_insert_set(&key, now)?;
+            let added = members
+                .into_iter()
+                .filter(|member| set.insert(member.clone(), &config))
+                .count();
+            Ok(Frame::Integer(added as i64))
+        }
+        SetCommand::Rem { key, members } => {
+            let Some(set) = keyspace.get_set_mut(&key, now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            let removed = members.iter().filter(|member| set.remove(member)).count();
+            keyspace.remove_if_empty(&key, now);
+            Ok(Frame::Integer(removed as i64))
+        }
+        SetCommand::IsMember { key, member } => {
+            let set = keyspace.get_set(&key, now)?;
+            Ok(Frame::Integer(
+                set.is_some_and(|set| set.contains(&member)) as i64
+            ))
+        }
+        SetCommand::MIsMember { key, members } => {
+            let set = keyspace.get_set(&key, now)?;
+            let found = members
+                .iter()
+                .map(|member| Frame::Integer(set.is_some_and(|set| set.contains(member)) as i64));
+            Ok(Frame::Array(found.collect()))
+        }
+        SetCommand::Card { key } => {
+            let len = keyspace.get_set(&key, now)?.map_or(0, |set| set.len());
+            Ok(Frame::Integer(len as i64))
+        }
+        SetCommand::Members { key } => {
+            let set = keyspace.get_set(&key, now)?;
+            Ok(Frame::Set(set.map_or(vec![], |set| bulks(set.iter()))))
+        }
+        SetCommand::Pop { key, count } => {
+            let Some(set) = keyspace.get_set(&key, now)? else {
+                return Ok(count.map_or(Frame::Null, |_| Frame::Set(vec![])));
+            };
+            let members: Vec<Bytes> = set.iter().collect();
+            let popped = pick_distinct(keyspace, members, count.unwrap_or(1));
+            let set = keyspace.get_set_mut(&key, now)?.unwrap();
+            for member in &popped {
+                set.remove(member);
+            }
+            keyspace.remove_if_empty(&key, now);
+            Ok(match count {
+                None => Frame::Bulk(popped.into_iter().next().unwrap()),
+                Some(_) => Frame::Set(bulks(popped)),
+            })
+        }
+        SetCommand::RandMember { key, count } => {
+            let set = keyspace.get_set(&key, now)?;
+            let members: Vec<Bytes> = set.into_iter().flat_map(|set| set.iter()).collect();
+            let Some(count) = count else {
+                return Ok(match pick_distinct(keyspace, members, 1).pop() {
+                    Some(member) => Frame::Bulk(member),
+                    None => Frame::Null,
+                });
+            };
+            let picked = match count {
+                _ if members.is_empty() => vec![],
+                // The same member may be picked several times
+                count if count < 0 => (0..count.unsigned_abs())
+                    .map(|_| {
+                        let index = (keyspace.random() * members.len() as f64) as usize;
+                        return members[index].clone();
+                    })
+                    .collect(),
+                count => pick_distinct(keyspace, members, count as usize),
+            };
+            Ok(Frame::Array(bulks(picked)))
+        }
+        SetCommand::Move {
+            source,
+            destination,
+            member,
+        } => {
+            if keyspace.get_set(&source, now)?.is_none() {
+                return Ok(Frame::Integer(0));
+            }
+            // Check the destination before changing anything, so that a
+            // WRONGTYPE error leaves the source alone
+            keyspace.get_set(&destination, now)?;
+            if source == destination {
+                let set = keyspace.get_set(&source, now)?.unwrap();
+                return Ok(Frame::Integer(set.contains(&member) as i64));
+            }
+            if !keyspace.get_set_mut(&source, now)?.unwrap().remove(&member) {
+                return Ok(Frame::Integer(0));
+            }
+            keyspace.remove_if_empty(&source, now);
+            keyspace
+                .get_or_insert_set(&destination, now)?
+                .insert(member, &config);
+            Ok(Frame::Integer(1))
+        }
+        SetCommand::Combine { op, keys } => {
+            let sets = keyspace.get_sets(&keys, now)?;
+            Ok(Frame::Set(bulks(combine(op, &sets, usize::MAX))))
+        }
+        SetCommand::Store {
+            op,
+            destination,
+            keys,
+        } => {
+            let sets = keyspace.get_sets(&keys, now)?;
+            let set = Set::from_members(combine(op, &sets, usize::MAX), &config);
+            let len = set.len();
+            match len {
+                0 => {
+                    keyspace.remove(&destination, now);
+                }
+                _ => keyspace.insert(destination, Value::Set(set), None, now),
+            }
+            Ok(Frame::Integer(len as i64))
+        }
+        SetCommand::InterCard { keys, limit } => {
+            let limit = if limit == 0 { usize::MAX } else { limit };
+            let sets = keyspace.get_sets(&keys, now)?;
+            Ok(Frame::Integer(
+                combine(SetOp::Inter, &sets, limit).len() as i64
+            ))
+        }
+        SetCommand::Scan {
+            key,
+            cursor,
+            pattern,
+            count,
+        } => {
+            let set = keyspace.get_set(&key, now)?;
+            let (cursor, page) = match set {
+                None => (0, vec![]),
+                Some(Set::Table(table)) => {
+                    let (cursor, page) = table.page(cursor, count);
+                    (cursor, page.into_iter().map(|(m, _)| m.clone()).collect())
+                }
+                // Compact sets are small enough to return in one go, like
+                // Redis
+                Some(set) => (0, set.iter().collect()),
+            };
+            let members = page.into_iter().filter(|member| {
+                return pattern
+                    .as_ref()
+                    .is_none_or(|pattern| glob_match(pattern, member));
+            });
+            Ok(Frame::Array(vec![
+                Frame::Bulk(Bytes::from(cursor.to_string())),
+                Frame::Array(bulks(members)),
+            ]))
+        }
+    };
+}
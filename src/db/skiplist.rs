@@ -0,0 +1,351 @@
+//! The skiplist that orders the members of a sorted set, by score and then
+//! by member
+//!
+//! Like the one in Redis, every link records how many nodes it skips over,
+//! its span. Adding up the spans on the way to a node gives its rank, so that
+//! finding the rank of a node, the node at a rank, or the rank where a range
+//! of scores starts all take logarithmic time. Nodes live in a vector and
+//! link to each other by index, with removed nodes kept on a free list for
+//! reuse.
+use bytes::Bytes;
+
+/// The most levels a node may have, enough for 4^32 nodes
+const MAX_LEVEL: usize = 32;
+
+/// The chance that a node on one level is also on the next one
+const LEVEL_P: f64 = 0.25;
+
+/// The index of the head node, which holds no member and is on every level
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone)]
+struct Level {
+    forward: Option<usize>,
+    /// The number of nodes between this node and the forward one, counting
+    /// the forward one. A missing forward node is one past the last node.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+impl Node {
+    /// Return whether the node comes before the given score and member
+    fn precedes(&self, score: f64, member: &[u8]) -> bool {
+        return self.score < score || (self.score == score && self.member[..] < *member);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    /// Indexes of removed nodes that can be reused
+    free: Vec<usize>,
+    /// The number of levels in use
+    level: usize,
+    len: usize,
+    /// The state of the xorshift generator that picks the levels of nodes
+    rng: u64,
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+        };
+        return Self {
+            nodes: vec![head],
+            free: vec![],
+            level: 1,
+            len: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        return self.nodes[node].levels[level].forward;
+    }
+
+    fn span(&self, node: usize, level: usize) -> usize {
+        return self.nodes[node].levels[level].span;
+    }
+
+    /// Pick the number of levels of a new node, each level being a quarter as
+    /// likely as the one below
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+        loop {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            let random = (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+            if level == MAX_LEVEL || random >= LEVEL_P {
+                return level;
+            }
+            level += 1;
+        }
+    }
+
+    /// Add a member with a score. The member must not be in the list yet.
+    pub fn insert(&mut self, score: f64, member: Bytes) {
+        // The last node before the new one on each level, and its rank
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i) {
+                if !self.nodes[next].precedes(score, &member) {
+                    break;
+                }
+                rank[i] += self.span(x, i);
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = self.random_level();
+        if level > self.level {
+            for i in self.level..level {
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+        let node = Node {
+            member,
+            score,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0
+                };
+                level
+            ],
+        };
+        let x = match self.free.pop() {
+            Some(x) => {
+                self.nodes[x] = node;
+                x
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let prev = update[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[x].levels[i] = Level {
+                forward: self.forward(prev, i),
+                span: self.span(prev, i) - skipped,
+            };
+            self.nodes[prev].levels[i] = Level {
+                forward: Some(x),
+                span: skipped + 1,
+            };
+        }
+        // The links above the new node now skip over it as well
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+        if let Some(next) = self.forward(x, 0) {
+            self.nodes[next].backward = Some(x);
+        }
+        self.len += 1;
+    }
+
+    /// Remove a member with the score it was added with, returning whether it
+    /// was there
+    pub fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !self.nodes[next].precedes(score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let Some(x) = self.forward(x, 0) else {
+            return false;
+        };
+        if self.nodes[x].score != score || self.nodes[x].member != *member {
+            return false;
+        }
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == Some(x) {
+                self.nodes[prev].levels[i] = Level {
+                    forward: self.forward(x, i),
+                    span: self.span(prev, i) + self.span(x, i) - 1,
+                };
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        if let Some(next) = self.forward(x, 0) {
+            self.nodes[next].backward = self.nodes[x].backward;
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+        self.nodes[x].member = Bytes::new();
+        self.nodes[x].levels.clear();
+        self.free.push(x);
+        self.len -= 1;
+        return true;
+    }
+
+    /// Return the number of nodes from the start of the list for which the
+    /// predicate holds. The predicate must hold for every node before one it
+    /// holds for, such as "the score is less than 2".
+    pub fn count_while(&self, pred: impl Fn(f64, &Bytes) -> bool) -> usize {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = &self.nodes[next];
+                if !pred(node.score, &node.member) {
+                    break;
+                }
+                rank += self.span(x, i);
+                x = next;
+            }
+        }
+        return rank;
+    }
+
+    /// Return the rank of a member with the score it was added with, 0 for
+    /// the first member
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let rank = self.count_while(|s, m| s < score || (s == score && m[..] <= *member));
+        let node = self.by_rank(rank.checked_sub(1)?)?;
+        return (self.nodes[node].member == *member).then_some(rank - 1);
+    }
+
+    /// Return the node at a rank
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None;
+        }
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.span(x, i) > rank + 1 {
+                    break;
+                }
+                traversed += self.span(x, i);
+                x = next;
+            }
+            if traversed == rank + 1 {
+                return Some(x);
+            }
+        }
+        return None;
+    }
+
+    /// Iterate over the members with ranks from `start` up to but excluding
+    /// `end`, with their scores, from the last of them if `rev` is set
+    pub fn range(&self, start: usize, end: usize, rev: bool) -> Iter<'_> {
+        let end = end.min(self.len);
+        let remaining = end.saturating_sub(start);
+        let next = match (remaining, rev) {
+            (0, _) => None,
+            (_, false) => self.by_rank(start),
+            (_, true) => self.by_rank(end - 1),
+        };
+        return Iter {
+            list: self,
+            next,
+            remaining,
+            rev,
+        };
+    }
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// An iterator over a run of consecutive members of a skiplist
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    next: Option<usize>,
+    remaining: usize,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.next?];
+        self.remaining -= 1;
+        self.next = match self.rev {
+            false => node.levels[0].forward,
+            true => node.backward,
+        };
+        return Some((&node.member, node.score));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skiplist_ranks() {
+        let mut list = SkipList::new();
+        // Insert out of order, with ties broken by member
+        for n in (0..500).rev() {
+            list.insert((n / 2) as f64, Bytes::from(format!("m{n:03}")));
+        }
+        assert_eq!(list.len(), 500);
+        for n in 0..500 {
+            let member = format!("m{n:03}");
+            assert_eq!(list.rank((n / 2) as f64, member.as_bytes()), Some(n));
+        }
+        assert_eq!(list.rank(1.0, b"m000"), None);
+
+        for n in (0..500).step_by(2) {
+            assert!(list.remove((n / 2) as f64, format!("m{n:03}").as_bytes()));
+        }
+        assert!(!list.remove(0.0, b"m000"));
+        assert_eq!(list.len(), 250);
+        assert_eq!(list.rank(0.0, b"m001"), Some(0));
+        assert_eq!(list.rank(249.0, b"m499"), Some(249));
+
+        let members: Vec<&Bytes> = list.range(10, 13, false).map(|(m, _)| m).collect();
+        assert_eq!(members, ["m021", "m023", "m025"]);
+        let members: Vec<&Bytes> = list.range(247, 300, true).map(|(m, _)| m).collect();
+        assert_eq!(members, ["m499", "m497", "m495"]);
+        assert_eq!(list.count_while(|score, _| score < 100.0), 100);
+    }
+}
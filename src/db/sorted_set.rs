@@ -0,0 +1,544 @@
+//! The sorted set value type, and the execution of the commands that operate
+//! on it
+use super::list::normalize_range;
+use super::skiplist::{self, SkipList};
+use super::{fits_listpack, glob_match, Entry, Keyspace, ScanTable, Value};
+use crate::command::{
+    CommandError, Comparison, End, LexBound, Limit, Range, ScoreBound, SetCondition,
+    SortedSetCommand, StoreOp,
+};
+use crate::{format_double, Frame};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A sorted set value: the score of each member, along with a skiplist that
+/// orders the members by score, and members with the same score by their
+/// bytes. Every range query finds the ranks its range starts and ends at in
+/// the skiplist, then walks the members in between.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: ScanTable<f64>,
+    index: SkipList,
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        return self.scores == other.scores;
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.index.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.scores.is_empty();
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        return self.scores.get(member).copied();
+    }
+
+    /// Iterate over the members and their scores, lowest score first
+    pub fn iter(&self) -> skiplist::Iter<'_> {
+        return self.index.range(0, self.len(), false);
+    }
+
+    /// Return the name of the encoding Redis would use, as reported by OBJECT
+    /// ENCODING
+    pub fn encoding(&self) -> &'static str {
+        return match fits_listpack(self.len(), self.scores.keys()) {
+            true => "listpack",
+            false => "skiplist",
+        };
+    }
+
+    /// Set the score of a member, adding it if it is new. Return whether it
+    /// is new.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let old = self.scores.insert(member.clone(), score);
+        match old {
+            Some(old) if old == score => return false,
+            Some(old) => {
+                self.index.remove(old, &member);
+            }
+            None => {}
+        }
+        self.index.insert(score, member);
+        return old.is_none();
+    }
+
+    /// Remove a member, returning its score if it was there
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.index.remove(score, member);
+        return Some(score);
+    }
+
+    /// Return the rank of a member, 0 for the lowest score
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        return self.index.rank(self.score(member)?, member);
+    }
+
+    /// Return the ranks of the first member within a score range and of the
+    /// member right after the last one
+    fn score_range(&self, min: ScoreBound, max: ScoreBound) -> (usize, usize) {
+        let start = self.index.count_while(|score, _| {
+            return score < min.score || (min.exclusive && score == min.score);
+        });
+        let end = self.index.count_while(|score, _| {
+            return score < max.score || (!max.exclusive && score == max.score);
+        });
+        return (start, end.max(start));
+    }
+
+    /// Return the ranks of the first member within a lexicographical range
+    /// and of the member right after the last one. Like Redis, this assumes
+    /// every member has the same score.
+    fn lex_range(&self, min: &LexBound, max: &LexBound) -> (usize, usize) {
+        let before = |bound: &LexBound, member: &Bytes, inclusive_end: bool| match bound {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) if inclusive_end => member <= bound,
+            LexBound::Inclusive(bound) => member < bound,
+            LexBound::Exclusive(bound) if inclusive_end => member < bound,
+            LexBound::Exclusive(bound) => member <= bound,
+        };
+        let start = self
+            .index
+            .count_while(|_, member| before(min, member, false));
+        let end = self
+            .index
+            .count_while(|_, member| before(max, member, true));
+        return (start, end.max(start));
+    }
+
+    /// Iterate over the members a ZRANGE selects, with their scores
+    pub(super) fn range(
+        &self,
+        range: &Range,
+        rev: bool,
+        limit: Option<Limit>,
+    ) -> skiplist::Iter<'_> {
+        let len = self.len();
+        let (mut start, mut end) = match range {
+            // With REV, ranks count from the highest score
+            Range::Rank { start, stop } => match normalize_range(*start, *stop, len) {
+                Some((start, stop)) if rev => (len - 1 - stop, len - start),
+                Some((start, stop)) => (start, stop + 1),
+                None => (0, 0),
+            },
+            Range::Score { min, max } => self.score_range(*min, *max),
+            Range::Lex { min, max } => self.lex_range(min, max),
+        };
+        if let Some(Limit { offset, count }) = limit {
+            let offset = match usize::try_from(offset) {
+                Ok(offset) => offset.min(end - start),
+                // A negative offset selects nothing
+                Err(_) => end - start,
+            };
+            match rev {
+                false => start += offset,
+                true => end -= offset,
+            }
+            if let Ok(count) = usize::try_from(count) {
+                match rev {
+                    false => end = end.min(start + count),
+                    true => start = start.max(end.saturating_sub(count)),
+                }
+            }
+        }
+        return self.index.range(start, end, rev);
+    }
+
+    /// Remove and return up to `count` members from one end
+    fn pop(&mut self, end: End, count: usize) -> Vec<(Bytes, f64)> {
+        let len = self.len();
+        let popped: Vec<(Bytes, f64)> = match end {
+            End::Min => self.index.range(0, count, false),
+            End::Max => self.index.range(len.saturating_sub(count), len, true),
+        }
+        .map(|(member, score)| (member.clone(), score))
+        .collect();
+        for (member, _) in &popped {
+            self.remove(member);
+        }
+        return popped;
+    }
+}
+
+impl Keyspace {
+    /// Return the sorted set stored under a key, if there is one
+    pub(super) fn get_zset(
+        &mut self,
+        key: &[u8],
+        now: i64,
+    ) -> Result<Option<&SortedSet>, CommandError> {
+        return match self.get(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::SortedSet(zset),
+                ..
+            }) => Ok(Some(zset)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the sorted set stored under a key for changing it, if there is
+    /// one. The key must be removed if the sorted set ends up empty.
+    fn get_zset_mut(
+        &mut self,
+        key: &[u8],
+        now: i64,
+    ) -> Result<Option<&mut SortedSet>, CommandError> {
+        return match self.get_mut(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::SortedSet(zset),
+                ..
+            }) => Ok(Some(zset)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the sorted set stored under a key for changing it, creating an
+    /// empty one if the key does not exist. Clients blocked on the key are
+    /// served once the command is done.
+    fn get_or_insert_zset(
+        &mut self,
+        key: &Bytes,
+        now: i64,
+    ) -> Result<&mut SortedSet, CommandError> {
+        if self.get_zset(key, now)?.is_none() {
+            self.insert(key.clone(), Value::SortedSet(SortedSet::new()), None, now);
+            self.signal_ready(key);
+        }
+        return Ok(self.get_zset_mut(key, now)?.unwrap());
+    }
+
+    /// Store a sorted set under a key, replacing the key, or remove the key if
+    /// the sorted set is empty. Return the size of the sorted set.
+    pub(super) fn store_zset(&mut self, key: Bytes, zset: SortedSet, now: i64) -> usize {
+        let len = zset.len();
+        if len == 0 {
+            self.remove(&key, now);
+            return 0;
+        }
+        self.signal_ready(&key);
+        self.insert(key, Value::SortedSet(zset), None, now);
+        return len;
+    }
+
+    /// Return the members and scores of the input of ZUNIONSTORE or
+    /// ZINTERSTORE, where the members of a plain set have a score of 1
+    fn zset_input(&mut self, key: &[u8], now: i64) -> Result<HashMap<Bytes, f64>, CommandError> {
+        return match self.get(key, now) {
+            None => Ok(HashMap::new()),
+            Some(Entry {
+                val: Value::SortedSet(zset),
+                ..
+            }) => Ok(zset
+                .scores
+                .iter()
+                .map(|(member, score)| (member.clone(), *score))
+                .collect()),
+            Some(Entry {
+                val: Value::Set(set),
+                ..
+            }) => Ok(set.iter().map(|member| (member, 1.0)).collect()),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+}
+
+/// Return the keys BZPOPMIN or BZPOPMAX waits on and how long it waits, None
+/// meaning forever
+pub(super) fn blocking_keys(cmd: &SortedSetCommand) -> (Vec<Bytes>, Option<Duration>) {
+    return match cmd {
+        SortedSetCommand::BPop { keys, timeout, .. } => (keys.clone(), *timeout),
+        cmd => panic!("{cmd:?} does not block"),
+    };
+}
+
+/// Convert members and their scores into a flat array
+fn with_scores<'a>(members: impl Iterator<Item = (&'a Bytes, f64)>) -> Frame {
+    let elems =
+        members.flat_map(|(member, score)| [Frame::Bulk(member.clone()), Frame::Double(score)]);
+    return Frame::Array(elems.collect());
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: SortedSetCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        SortedSetCommand::Add {
+            key,
+            condition,
+            comparison,
+            changed,
+            incr,
+            pairs,
+        } => {
+            if condition == Some(SetCondition::Xx) && keyspace.get_zset(&key, now)?.is_none() {
+                return Ok(if incr { Frame::Null } else { Frame::Integer(0) });
+            }
+            let zset = keyspace.get_or_insert_zset(&key, now)?;
+            let (mut added, mut updated, mut last) = (0, 0, None);
+            for (score, member) in pairs {
+                last = None;
+                let new = match zset.score(&member) {
+                    Some(_) if condition == Some(SetCondition::Nx) => continue,
+                    Some(old) => {
+                        let new = if incr { old + score } else { score };
+                        if new.is_nan() {
+                            keyspace.remove_if_empty(&key, now);
+                            return Err(CommandError::Other(
+                                "ERR resulting score is not a number (NaN)".into(),
+                            ));
+                        }
+                        match comparison {
+                            Some(Comparison::Gt) if new <= old => continue,
+                            Some(Comparison::Lt) if new >= old => continue,
+                            _ => {}
+                        }
+                        if new != old {
+                            updated += 1;
+                        }
+                        new
+                    }
+                    None if condition == Some(SetCondition::Xx) => continue,
+                    None => {
+                        added += 1;
+                        score
+                    }
+                };
+                zset.insert(member, new);
+                last = Some(new);
+            }
+            keyspace.remove_if_empty(&key, now);
+            Ok(match incr {
+                true => last.map_or(Frame::Null, Frame::Double),
+                false if changed => Frame::Integer(added + updated),
+                false => Frame::Integer(added),
+            })
+        }
+        SortedSetCommand::Rem { key, members } => {
+            let Some(zset) = keyspace.get_zset_mut(&key, now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            let removed = members
+                .iter()
+                .filter(|member| zset.remove(member).is_some())
+                .count();
+            keyspace.remove_if_empty(&key, now);
+            Ok(Frame::Integer(removed as i64))
+        }
+        SortedSetCommand::Score { key, member } => {
+            let score = keyspace
+                .get_zset(&key, now)?
+                .and_then(|zset| zset.score(&member));
+            Ok(score.map_or(Frame::Null, Frame::Double))
+        }
+        SortedSetCommand::MScore { key, members } => {
+            let zset = keyspace.get_zset(&key, now)?;
+            let scores = members.iter().map(|member| {
+                let score = zset.and_then(|zset| zset.score(member));
+                return score.map_or(Frame::Null, Frame::Double);
+            });
+            Ok(Frame::Array(scores.collect()))
+        }
+        SortedSetCommand::IncrBy { key, delta, member } => {
+            let zset = keyspace.get_or_insert_zset(&key, now)?;
+            let score = zset.score(&member).unwrap_or(0.0) + delta;
+            if score.is_nan() {
+                keyspace.remove_if_empty(&key, now);
+                return Err(CommandError::Other(
+                    "ERR resulting score is not a number (NaN)".into(),
+                ));
+            }
+            zset.insert(member, score);
+            Ok(Frame::Double(score))
+        }
+        SortedSetCommand::Card { key } => {
+            let len = keyspace.get_zset(&key, now)?.map_or(0, |zset| zset.len());
+            Ok(Frame::Integer(len as i64))
+        }
+        SortedSetCommand::Count { key, min, max } => {
+            let count = keyspace.get_zset(&key, now)?.map_or(0, |zset| {
+                let (start, end) = zset.score_range(min, max);
+                return end - start;
+            });
+            Ok(Frame::Integer(count as i64))
+        }
+        SortedSetCommand::Rank {
+            key,
+            member,
+            rev,
+            with_score,
+        } => {
+            let Some(zset) = keyspace.get_zset(&key, now)? else {
+                return Ok(Frame::Null);
+            };
+            let Some(rank) = zset.rank(&member) else {
+                return Ok(Frame::Null);
+            };
+            let rank = if rev { zset.len() - 1 - rank } else { rank };
+            Ok(match with_score {
+                true => Frame::Array(vec![
+                    Frame::Integer(rank as i64),
+                    Frame::Double(zset.score(&member).unwrap()),
+                ]),
+                false => Frame::Integer(rank as i64),
+            })
+        }
+        SortedSetCommand::Range {
+            key,
+            range,
+            rev,
+            limit,
+            with_scores: scores,
+        } => {
+            let Some(zset) = keyspace.get_zset(&key, now)? else {
+                return Ok(Frame::Array(vec![]));
+            };
+            let members = zset.range(&range, rev, limit);
+            Ok(match scores {
+                true => with_scores(members),
+                false => Frame::Array(members.map(|(m, _)| Frame::Bulk(m.clone())).collect()),
+            })
+        }
+        SortedSetCommand::RangeStore {
+            destination,
+            key,
+            range,
+            rev,
+            limit,
+        } => {
+            let mut stored = SortedSet::new();
+            if let Some(zset) = keyspace.get_zset(&key, now)? {
+                for (member, score) in zset.range(&range, rev, limit) {
+                    stored.insert(member.clone(), score);
+                }
+            }
+            let len = keyspace.store_zset(destination, stored, now);
+            Ok(Frame::Integer(len as i64))
+        }
+        SortedSetCommand::Pop { key, end, count } => {
+            let Some(zset) = keyspace.get_zset_mut(&key, now)? else {
+                return Ok(Frame::Array(vec![]));
+            };
+            let popped = zset.pop(end, count.unwrap_or(1));
+            keyspace.remove_if_empty(&key, now);
+            Ok(with_scores(popped.iter().map(|(m, score)| (m, *score))))
+        }
+        SortedSetCommand::BPop { keys, end, .. } => {
+            for key in keys {
+                let Some(zset) = keyspace.get_zset_mut(&key, now)? else {
+                    continue;
+                };
+                let (member, score) = zset.pop(end, 1).pop().unwrap();
+                keyspace.remove_if_empty(&key, now);
+                return Ok(Frame::Array(vec![
+                    Frame::Bulk(key),
+                    Frame::Bulk(member),
+                    Frame::Double(score),
+                ]));
+            }
+            Ok(Frame::Null)
+        }
+        SortedSetCommand::Store {
+            op,
+            destination,
+            keys,
+            weights,
+            aggregate,
+        } => {
+            let mut inputs = vec![];
+            for (key, weight) in keys.iter().zip(weights) {
+                let mut input = keyspace.zset_input(key, now)?;
+                for score in input.values_mut() {
+                    // 0 times inf is NaN, which Redis turns into 0
+                    *score = match weight * *score {
+                        score if score.is_nan() => 0.0,
+                        score => score,
+                    };
+                }
+                inputs.push(input);
+            }
+            let mut result: HashMap<Bytes, f64> = HashMap::new();
+            match op {
+                StoreOp::Union => {
+                    for input in inputs {
+                        for (member, score) in input {
+                            result
+                                .entry(member)
+                                .and_modify(|acc| *acc = aggregate.apply(*acc, score))
+                                .or_insert(score);
+                        }
+                    }
+                }
+                StoreOp::Inter => {
+                    let (first, others) = inputs.split_first().unwrap();
+                    'members: for (member, score) in first {
+                        let mut acc = *score;
+                        for other in others {
+                            let Some(score) = other.get(member) else {
+                                continue 'members;
+                            };
+                            acc = aggregate.apply(acc, *score);
+                        }
+                        result.insert(member.clone(), acc);
+                    }
+                }
+            }
+            let mut stored = SortedSet::new();
+            for (member, score) in result {
+                stored.insert(member, score);
+            }
+            let len = keyspace.store_zset(destination, stored, now);
+            Ok(Frame::Integer(len as i64))
+        }
+        SortedSetCommand::Scan {
+            key,
+            cursor,
+            pattern,
+            count,
+        } => {
+            let zset = keyspace.get_zset(&key, now)?;
+            let (cursor, page) = match zset {
+                None => (0, vec![]),
+                // Small sorted sets are returned in one go, like the listpacks
+                // of Redis
+                Some(zset) if zset.encoding() == "listpack" => (0, zset.iter().collect()),
+                Some(zset) => {
+                    let (cursor, page) = zset.scores.page(cursor, count);
+                    let page = page.into_iter().map(|(m, score)| (m, *score));
+                    (cursor, page.collect())
+                }
+            };
+            let mut elems = vec![];
+            for (member, score) in page {
+                if pattern
+                    .as_ref()
+                    .is_some_and(|pattern| !glob_match(pattern, member))
+                {
+                    continue;
+                }
+                elems.push(Frame::Bulk(member.clone()));
+                elems.push(Frame::Bulk(Bytes::from(format_double(score))));
+            }
+            Ok(Frame::Array(vec![
+                Frame::Bulk(Bytes::from(cursor.to_string())),
+                Frame::Array(elems),
+            ]))
+        }
+    };
+}
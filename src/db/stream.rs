@@ -0,0 +1,967 @@
+//! The stream value type and its consumer groups, and the execution of the
+//! commands that operate on them
+use super::{Entry, Keyspace, Value};
+use crate::command::{
+    ClaimOptions, CommandError, NewId, PendingRange, ReadId, StreamCommand, StreamId, Trim,
+    TrimStrategy,
+};
+use crate::Frame;
+use bytes::Bytes;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::time::Duration;
+
+/// A stream value: entries of field-value pairs ordered by ID, and the
+/// consumer groups reading them. Unlike other collections, a stream is kept
+/// when its last entry is removed, since its last ID and groups still matter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+    /// The ID of the last entry ever added, which new IDs must be greater than
+    last_id: StreamId,
+    /// The greatest ID removed by XDEL
+    max_deleted_id: StreamId,
+    /// The number of entries ever added
+    entries_added: u64,
+    groups: BTreeMap<Bytes, Group>,
+}
+
+/// A consumer group: the last entry delivered to its consumers, and the
+/// entries delivered but not acknowledged yet, which are pending
+#[derive(Debug, Clone, PartialEq)]
+struct Group {
+    last_id: StreamId,
+    /// The number of entries the group has read, if it can be told, used to
+    /// work out how many entries it has yet to read
+    entries_read: Option<u64>,
+    pending: BTreeMap<StreamId, Pending>,
+    consumers: BTreeMap<Bytes, Consumer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Pending {
+    consumer: Bytes,
+    /// When the entry was last delivered, in Unix milliseconds
+    delivery_time: i64,
+    delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Consumer {
+    /// When the consumer last tried to read or claim, in Unix milliseconds
+    seen_time: i64,
+    /// When the consumer last read or claimed an entry, if it ever did
+    active_time: Option<i64>,
+    pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now: i64) -> Self {
+        return Self {
+            seen_time: now,
+            ..Self::default()
+        };
+    }
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        return self.last_id;
+    }
+
+    /// Work out the ID of a new entry, which must be greater than the last
+    fn next_id(&self, id: NewId, now: i64) -> Result<StreamId, CommandError> {
+        let smaller = || {
+            return CommandError::Other(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .into(),
+            );
+        };
+        let id = match id {
+            NewId::Auto if self.last_id.ms < now as u64 => StreamId {
+                ms: now as u64,
+                seq: 0,
+            },
+            NewId::Auto => self.last_id.next().ok_or_else(|| {
+                CommandError::Other(
+                    "ERR The stream has exhausted the last possible ID, unable to add more items"
+                        .into(),
+                )
+            })?,
+            NewId::AutoSeq(ms) if ms > self.last_id.ms => StreamId { ms, seq: 0 },
+            NewId::AutoSeq(ms) if ms == self.last_id.ms => {
+                let seq = self.last_id.seq.checked_add(1).ok_or_else(smaller)?;
+                StreamId { ms, seq }
+            }
+            NewId::AutoSeq(_) => return Err(smaller()),
+            NewId::Explicit(StreamId::MIN) => {
+                return Err(CommandError::Other(
+                    "ERR The ID specified in XADD must be greater than 0-0".into(),
+                ))
+            }
+            NewId::Explicit(id) if id <= self.last_id => return Err(smaller()),
+            NewId::Explicit(id) => id,
+        };
+        // 0-* on a new stream cannot give 0-0
+        return Ok(match id {
+            StreamId::MIN => StreamId { ms: 0, seq: 1 },
+            id => id,
+        });
+    }
+
+    fn add(&mut self, id: StreamId, fields: Vec<(Bytes, Bytes)>) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Remove entries from the start of the stream, returning how many were
+    /// removed
+    fn trim(&mut self, trim: &Trim) -> usize {
+        let limit = trim.limit.unwrap_or(usize::MAX);
+        let mut removed = 0;
+        while removed < limit {
+            let len = self.entries.len();
+            let Some(entry) = self.entries.first_entry() else {
+                break;
+            };
+            let expired = match trim.strategy {
+                TrimStrategy::MaxLen(max) => len > max,
+                TrimStrategy::MinId(id) => *entry.key() < id,
+            };
+            if !expired {
+                break;
+            }
+            entry.remove();
+            removed += 1;
+        }
+        return removed;
+    }
+
+    /// Return the entries with IDs between `start` and `end`, inclusive
+    fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Vec<(Bytes, Bytes)>)> {
+        // BTreeMap panics on a range that ends before it starts
+        let end = end.max(start);
+        return self
+            .entries
+            .range(start..=end)
+            .filter(move |(id, _)| **id <= end);
+    }
+
+    /// Return the entries after an ID
+    fn after(&self, id: StreamId) -> impl Iterator<Item = (&StreamId, &Vec<(Bytes, Bytes)>)> {
+        return self.entries.range((Bound::Excluded(id), Bound::Unbounded));
+    }
+
+    /// Return whether entries were removed by XDEL between `start` and the
+    /// last entry, so that counting entries since `start` is not possible
+    fn has_tombstones(&self, start: StreamId) -> bool {
+        let Some(first) = self.entries.keys().next() else {
+            return false;
+        };
+        if self.max_deleted_id == StreamId::MIN {
+            return false;
+        }
+        return self.max_deleted_id >= start.max(*first) && self.max_deleted_id <= self.last_id;
+    }
+
+    /// Return how many entries were ever added up to an ID, if that can be
+    /// told from the entries added and removed so far
+    fn entries_up_to(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.entries.is_empty() && id < self.last_id {
+            return Some(self.entries_added);
+        }
+        if id >= self.last_id {
+            return Some(self.entries_added);
+        }
+        let first = *self.entries.keys().next().unwrap();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first {
+            let before_first = self.entries_added - self.len() as u64;
+            if id < first {
+                return Some(before_first);
+            }
+            if id == first {
+                return Some(before_first + 1);
+            }
+        }
+        return None;
+    }
+
+    /// Return how many entries a group has yet to read, if that can be told
+    fn lag(&self, group: &Group) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones(group.last_id) => Some(read),
+            _ => self.entries_up_to(group.last_id),
+        };
+        return entries_read.map(|read| self.entries_added.saturating_sub(read));
+    }
+
+    fn group(&mut self, name: &[u8]) -> Option<&mut Group> {
+        return self.groups.get_mut(name);
+    }
+}
+
+impl Group {
+    /// Make an entry pending for a consumer, taking it from the consumer it
+    /// was pending for
+    fn assign(&mut self, id: StreamId, consumer: &Bytes, delivery_time: i64, delivery_count: u64) {
+        let pending = Pending {
+            consumer: consumer.clone(),
+            delivery_time,
+            delivery_count,
+        };
+        if let Some(old) = self.pending.insert(id, pending) {
+            if let Some(owner) = self.consumers.get_mut(&old.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        self.consumers.get_mut(consumer).unwrap().pending.insert(id);
+    }
+
+    /// Stop tracking a pending entry, returning whether it was pending
+    fn acknowledge(&mut self, id: StreamId) -> bool {
+        let Some(pending) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&pending.consumer) {
+            consumer.pending.remove(&id);
+        }
+        return true;
+    }
+
+    /// Return a consumer, creating it if it does not exist, and record that
+    /// it was seen
+    fn consumer(&mut self, name: &Bytes, now: i64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.clone())
+            .or_insert_with(|| Consumer::new(now));
+        consumer.seen_time = now;
+        return consumer;
+    }
+}
+
+impl Keyspace {
+    /// Return the stream stored under a key, if there is one
+    fn get_stream(&mut self, key: &[u8], now: i64) -> Result<Option<&Stream>, CommandError> {
+        return match self.get(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the stream stored under a key for changing it, if there is one
+    fn get_stream_mut(
+        &mut self,
+        key: &[u8],
+        now: i64,
+    ) -> Result<Option<&mut Stream>, CommandError> {
+        return match self.get_mut(key, now) {
+            None => Ok(None),
+            Some(Entry {
+                val: Value::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(CommandError::WrongType),
+        };
+    }
+
+    /// Return the stream stored under a key for changing one of its consumer
+    /// groups, failing with NOGROUP if the key or the group does not exist
+    fn get_stream_with_group(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        now: i64,
+    ) -> Result<&mut Stream, CommandError> {
+        let no_group = || {
+            return CommandError::Other(format!(
+                "NOGROUP No such key '{}' or consumer group '{}'",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(group)
+            ));
+        };
+        let stream = self.get_stream_mut(key, now)?.ok_or_else(no_group)?;
+        if !stream.groups.contains_key(group) {
+            return Err(no_group());
+        }
+        return Ok(stream);
+    }
+}
+
+/// Convert an entry into a pair of its ID and its fields and values
+fn entry_frame(id: &StreamId, fields: &[(Bytes, Bytes)]) -> Frame {
+    let fields = fields
+        .iter()
+        .flat_map(|(field, val)| [Frame::Bulk(field.clone()), Frame::Bulk(val.clone())]);
+    return Frame::Array(vec![
+        Frame::Bulk(id.to_bytes()),
+        Frame::Array(fields.collect()),
+    ]);
+}
+
+fn entries_frame<'a>(
+    entries: impl Iterator<Item = (&'a StreamId, &'a Vec<(Bytes, Bytes)>)>,
+) -> Frame {
+    return Frame::Array(
+        entries
+            .map(|(id, fields)| entry_frame(id, fields))
+            .collect(),
+    );
+}
+
+fn id_frame(id: StreamId) -> Frame {
+    return Frame::Bulk(id.to_bytes());
+}
+
+fn field(name: &'static str, val: Frame) -> (Frame, Frame) {
+    return (Frame::Bulk(Bytes::from(name)), val);
+}
+
+/// Return the keys XREAD or XREADGROUP waits on and how long it waits, None
+/// meaning forever
+pub(super) fn blocking_keys(cmd: &StreamCommand) -> (Vec<Bytes>, Option<Duration>) {
+    return match cmd {
+        StreamCommand::Read { keys, block, .. } | StreamCommand::ReadGroup { keys, block, .. } => {
+            (keys.clone(), block.filter(|block| !block.is_zero()))
+        }
+        cmd => panic!("{cmd:?} does not block"),
+    };
+}
+
+/// Replace the $ IDs of XREAD with the last IDs of the streams, so that a
+/// blocked XREAD is served the entries added after it blocked
+pub(super) fn pin_last_ids(keyspace: &mut Keyspace, cmd: StreamCommand, now: i64) -> StreamCommand {
+    let StreamCommand::Read {
+        keys,
+        ids,
+        count,
+        block,
+    } = cmd
+    else {
+        return cmd;
+    };
+    let ids = keys
+        .iter()
+        .zip(ids)
+        .map(|(key, id)| match id {
+            ReadId::Last => {
+                let last = keyspace.get_stream(key, now).ok().flatten();
+                ReadId::After(last.map_or(StreamId::MIN, Stream::last_id))
+            }
+            id => id,
+        })
+        .collect();
+    return StreamCommand::Read {
+        keys,
+        ids,
+        count,
+        block,
+    };
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: StreamCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        StreamCommand::Add {
+            key,
+            id,
+            no_mkstream,
+            trim,
+            fields,
+        } => {
+            let id = match keyspace.get_stream(&key, now)? {
+                Some(stream) => stream.next_id(id, now)?,
+                None if no_mkstream => return Ok(Frame::Null),
+                None => Stream::new().next_id(id, now)?,
+            };
+            if keyspace.get_stream(&key, now)?.is_none() {
+                keyspace.insert(key.clone(), Value::Stream(Stream::new()), None, now);
+            }
+            let stream = keyspace.get_stream_mut(&key, now)?.unwrap();
+            stream.add(id, fields);
+            if let Some(trim) = trim {
+                stream.trim(&trim);
+            }
+            keyspace.signal_ready(&key);
+            Ok(id_frame(id))
+        }
+        StreamCommand::Range {
+            key,
+            start,
+            end,
+            count,
+            rev,
+        } => {
+            let Some(stream) = keyspace.get_stream(&key, now)? else {
+                return Ok(Frame::Array(vec![]));
+            };
+            let count = count.unwrap_or(usize::MAX);
+            Ok(match rev {
+                false => entries_frame(stream.range(start, end).take(count)),
+                true => entries_frame(stream.range(start, end).rev().take(count)),
+            })
+        }
+        StreamCommand::Len { key } => {
+            let len = keyspace.get_stream(&key, now)?.map_or(0, Stream::len);
+            Ok(Frame::Integer(len as i64))
+        }
+        StreamCommand::Del { key, ids } => {
+            let Some(stream) = keyspace.get_stream_mut(&key, now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            let mut removed = 0;
+            for id in ids {
+                if stream.entries.remove(&id).is_some() {
+                    stream.max_deleted_id = stream.max_deleted_id.max(id);
+                    removed += 1;
+                }
+            }
+            Ok(Frame::Integer(removed))
+        }
+        StreamCommand::Trim { key, trim } => {
+            let Some(stream) = keyspace.get_stream_mut(&key, now)? else {
+                return Ok(Frame::Integer(0));
+            };
+            Ok(Frame::Integer(stream.trim(&trim) as i64))
+        }
+        StreamCommand::Read {
+            keys, ids, count, ..
+        } => {
+            let count = count.unwrap_or(usize::MAX);
+            let mut replies = vec![];
+            for (key, id) in keys.into_iter().zip(ids) {
+                let Some(stream) = keyspace.get_stream(&key, now)? else {
+                    continue;
+                };
+                let after = match id {
+                    ReadId::After(id) => id,
+                    _ => stream.last_id,
+                };
+                let entries: Vec<Frame> = stream
+                    .after(after)
+                    .take(count)
+                    .map(|(id, fields)| entry_frame(id, fields))
+                    .collect();
+                if !entries.is_empty() {
+                    replies.push(Frame::Array(vec![Frame::Bulk(key), Frame::Array(entries)]));
+                }
+            }
+            Ok(match replies.is_empty() {
+                true => Frame::Null,
+                false => Frame::Array(replies),
+            })
+        }
+        StreamCommand::ReadGroup {
+            group,
+            consumer,
+            keys,
+            ids,
+            count,
+            no_ack,
+            ..
+        } => {
+            // Fail before reading anything if any of the groups is missing
+            for key in &keys {
+                let exists = keyspace
+                    .get_stream(key, now)?
+                    .is_some_and(|stream| stream.groups.contains_key(&group));
+                if !exists {
+                    return Err(CommandError::Other(format!(
+                        "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                        String::from_utf8_lossy(key),
+                        String::from_utf8_lossy(&group)
+                    )));
+                }
+            }
+            let count = count.unwrap_or(usize::MAX);
+            let mut replies = vec![];
+            for (key, id) in keys.into_iter().zip(ids) {
+                let stream = keyspace.get_stream_mut(&key, now)?.unwrap();
+                let entries = match id {
+                    ReadId::After(after) => {
+                        read_history(stream, &group, &consumer, after, count, now)
+                    }
+                    _ => match read_new(stream, &group, &consumer, count, no_ack, now) {
+                        Frame::Array(entries) if entries.is_empty() => continue,
+                        entries => entries,
+                    },
+                };
+                replies.push(Frame::Array(vec![Frame::Bulk(key), entries]));
+            }
+            Ok(match replies.is_empty() {
+                true => Frame::Null,
+                false => Frame::Array(replies),
+            })
+        }
+        StreamCommand::GroupCreate {
+            key,
+            group,
+            id,
+            mkstream,
+            entries_read,
+        } => {
+            if keyspace.get_stream(&key, now)?.is_none() {
+                if !mkstream {
+                    return Err(no_key());
+                }
+                keyspace.insert(key.clone(), Value::Stream(Stream::new()), None, now);
+            }
+            let stream = keyspace.get_stream_mut(&key, now)?.unwrap();
+            if stream.groups.contains_key(&group) {
+                return Err(CommandError::Other(
+                    "BUSYGROUP Consumer Group name already exists".into(),
+                ));
+            }
+            let last_id = match id {
+                ReadId::After(id) => id,
+                _ => stream.last_id,
+            };
+            let entries_read = entries_read.or_else(|| stream.entries_up_to(last_id));
+            stream.groups.insert(
+                group,
+                Group {
+                    last_id,
+                    entries_read,
+                    pending: BTreeMap::new(),
+                    consumers: BTreeMap::new(),
+                },
+            );
+            Ok(Frame::Simple("OK".into()))
+        }
+        StreamCommand::GroupSetId {
+            key,
+            group,
+            id,
+            entries_read,
+        } => {
+            let stream = keyspace.get_stream_mut(&key, now)?.ok_or_else(no_key)?;
+            let last_id = match id {
+                ReadId::After(id) => id,
+                _ => stream.last_id,
+            };
+            let estimate = stream.entries_up_to(last_id);
+            let Some(cg) = stream.group(&group) else {
+                return Err(no_group_for_key(&key, &group));
+            };
+            cg.last_id = last_id;
+            cg.entries_read = entries_read.or(estimate);
+            Ok(Frame::Simple("OK".into()))
+        }
+        StreamCommand::GroupDestroy { key, group } => {
+            let stream = keyspace.get_stream_mut(&key, now)?.ok_or_else(no_key)?;
+            Ok(Frame::Integer(stream.groups.remove(&group).is_some() as i64))
+        }
+        StreamCommand::GroupCreateConsumer {
+            key,
+            group,
+            consumer,
+        } => {
+            let stream = keyspace.get_stream_mut(&key, now)?.ok_or_else(no_key)?;
+            let Some(cg) = stream.group(&group) else {
+                return Err(no_group_for_key(&key, &group));
+            };
+            if cg.consumers.contains_key(&consumer) {
+                return Ok(Frame::Integer(0));
+            }
+            cg.consumers.insert(consumer, Consumer::new(now));
+            Ok(Frame::Integer(1))
+        }
+        StreamCommand::GroupDelConsumer {
+            key,
+            group,
+            consumer,
+        } => {
+            let stream = keyspace.get_stream_mut(&key, now)?.ok_or_else(no_key)?;
+            let Some(cg) = stream.group(&group) else {
+                return Err(no_group_for_key(&key, &group));
+            };
+            let Some(removed) = cg.consumers.remove(&consumer) else {
+                return Ok(Frame::Integer(0));
+            };
+            for id in &removed.pending {
+                cg.pending.remove(id);
+            }
+            Ok(Frame::Integer(removed.pending.len() as i64))
+        }
+        StreamCommand::Ack { key, group, ids } => {
+            let Some(cg) = keyspace
+                .get_stream_mut(&key, now)?
+                .and_then(|stream| stream.group(&group))
+            else {
+                return Ok(Frame::Integer(0));
+            };
+            let acknowledged = ids.into_iter().filter(|id| cg.acknowledge(*id)).count();
+            Ok(Frame::Integer(acknowledged as i64))
+        }
+        StreamCommand::Pending { key, group, range } => {
+            let stream = keyspace.get_stream_with_group(&key, &group, now)?;
+            let cg = &stream.groups[&group];
+            let Some(range) = range else {
+                return Ok(pending_summary(cg));
+            };
+            Ok(pending_range(cg, range, now))
+        }
+        StreamCommand::Claim {
+            key,
+            group,
+            consumer,
+            min_idle,
+            ids,
+            options,
+        } => {
+            let stream = keyspace.get_stream_with_group(&key, &group, now)?;
+            Ok(claim(
+                stream, &group, &consumer, min_idle, ids, &options, now,
+            ))
+        }
+        StreamCommand::AutoClaim {
+            key,
+            group,
+            consumer,
+            min_idle,
+            start,
+            count,
+            just_id,
+        } => {
+            let stream = keyspace.get_stream_with_group(&key, &group, now)?;
+            Ok(autoclaim(
+                stream, &group, &consumer, min_idle, start, count, just_id, now,
+            ))
+        }
+        StreamCommand::InfoStream { key } => {
+            let stream = keyspace.get_stream(&key, now)?.ok_or_else(no_such_key)?;
+            let first = stream.entries.iter().next();
+            let last = stream.entries.iter().next_back();
+            let entry = |entry: Option<(&StreamId, &Vec<(Bytes, Bytes)>)>| {
+                return entry.map_or(Frame::Null, |(id, fields)| entry_frame(id, fields));
+            };
+            Ok(Frame::Map(vec![
+                field("length", Frame::Integer(stream.len() as i64)),
+                field("last-generated-id", id_frame(stream.last_id)),
+                field("max-deleted-entry-id", id_frame(stream.max_deleted_id)),
+                field("entries-added", Frame::Integer(stream.entries_added as i64)),
+                field(
+                    "recorded-first-entry-id",
+                    id_frame(first.map_or(StreamId::MIN, |(id, _)| *id)),
+                ),
+                field("groups", Frame::Integer(stream.groups.len() as i64)),
+                field("first-entry", entry(first)),
+                field("last-entry", entry(last)),
+            ]))
+        }
+        StreamCommand::InfoGroups { key } => {
+            let stream = keyspace.get_stream(&key, now)?.ok_or_else(no_such_key)?;
+            let groups = stream.groups.iter().map(|(name, cg)| {
+                let optional = |n: Option<u64>| n.map_or(Frame::Null, |n| Frame::Integer(n as i64));
+                return Frame::Map(vec![
+                    field("name", Frame::Bulk(name.clone())),
+                    field("consumers", Frame::Integer(cg.consumers.len() as i64)),
+                    field("pending", Frame::Integer(cg.pending.len() as i64)),
+                    field("last-delivered-id", id_frame(cg.last_id)),
+                    field("entries-read", optional(cg.entries_read)),
+                    field("lag", optional(stream.lag(cg))),
+                ]);
+            });
+            Ok(Frame::Array(groups.collect()))
+        }
+        StreamCommand::InfoConsumers { key, group } => {
+            let stream = keyspace.get_stream(&key, now)?.ok_or_else(no_such_key)?;
+            let Some(cg) = stream.groups.get(&group) else {
+                return Err(no_group_for_key(&key, &group));
+            };
+            let consumers = cg.consumers.iter().map(|(name, consumer)| {
+                let inactive = consumer.active_time.map_or(-1, |active| now - active);
+                return Frame::Map(vec![
+                    field("name", Frame::Bulk(name.clone())),
+                    field("pending", Frame::Integer(consumer.pending.len() as i64)),
+                    field("idle", Frame::Integer(now - consumer.seen_time)),
+                    field("inactive", Frame::Integer(inactive)),
+                ]);
+            });
+            Ok(Frame::Array(consumers.collect()))
+        }
+    };
+}
+
+fn no_key() -> CommandError {
+    return CommandError::Other(
+        "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.".into(),
+    );
+}
+
+fn no_such_key() -> CommandError {
+    return CommandError::Other("ERR no such key".into());
+}
+
+fn no_group_for_key(key: &[u8], group: &[u8]) -> CommandError {
+    return CommandError::Other(format!(
+        "NOGROUP No such consumer group '{}' for key name '{}'",
+        String::from_utf8_lossy(group),
+        String::from_utf8_lossy(key)
+    ));
+}
+
+/// Deliver the entries a group has not delivered yet to one of its
+/// consumers, making them pending for it unless `no_ack` is set
+fn read_new(
+    stream: &mut Stream,
+    group: &Bytes,
+    consumer: &Bytes,
+    count: usize,
+    no_ack: bool,
+    now: i64,
+) -> Frame {
+    let last_id = stream.groups[group].last_id;
+    let ids: Vec<StreamId> = stream
+        .after(last_id)
+        .take(count)
+        .map(|(id, _)| *id)
+        .collect();
+    let mut entries_read = stream.groups[group].entries_read;
+    for id in &ids {
+        entries_read = match entries_read {
+            Some(read) if !stream.has_tombstones(*id) => Some(read + 1),
+            _ => stream.entries_up_to(*id),
+        };
+    }
+    let reply = entries_frame(ids.iter().map(|id| (id, &stream.entries[id])));
+
+    let cg = stream.groups.get_mut(group).unwrap();
+    let reader = cg.consumer(consumer, now);
+    if !ids.is_empty() {
+        reader.active_time = Some(now);
+    }
+    if let Some(last) = ids.last() {
+        cg.last_id = *last;
+        cg.entries_read = entries_read;
+    }
+    if !no_ack {
+        for id in ids {
+            cg.assign(id, consumer, now, 1);
+        }
+    }
+    return reply;
+}
+
+/// Return the entries pending for a consumer after an ID. Entries removed
+/// from the stream since are returned with no fields.
+fn read_history(
+    stream: &mut Stream,
+    group: &Bytes,
+    consumer: &Bytes,
+    after: StreamId,
+    count: usize,
+    now: i64,
+) -> Frame {
+    let cg = stream.groups.get_mut(group).unwrap();
+    let reader = cg.consumer(consumer, now);
+    let ids = reader
+        .pending
+        .range((Bound::Excluded(after), Bound::Unbounded))
+        .take(count);
+    let entries = ids.map(|id| match stream.entries.get(id) {
+        Some(fields) => entry_frame(id, fields),
+        None => Frame::Array(vec![id_frame(*id), Frame::Null]),
+    });
+    return Frame::Array(entries.collect());
+}
+
+/// The reply to XPENDING key group: the number of pending entries, the
+/// smallest and greatest of their IDs, and how many each consumer has
+fn pending_summary(cg: &Group) -> Frame {
+    if cg.pending.is_empty() {
+        return Frame::Array(vec![
+            Frame::Integer(0),
+            Frame::Null,
+            Frame::Null,
+            Frame::Null,
+        ]);
+    }
+    let consumers = cg
+        .consumers
+        .iter()
+        .filter(|(_, consumer)| !consumer.pending.is_empty())
+        .map(|(name, consumer)| {
+            return Frame::Array(vec![
+                Frame::Bulk(name.clone()),
+                Frame::Bulk(Bytes::from(consumer.pending.len().to_string())),
+            ]);
+        });
+    return Frame::Array(vec![
+        Frame::Integer(cg.pending.len() as i64),
+        id_frame(*cg.pending.keys().next().unwrap()),
+        id_frame(*cg.pending.keys().next_back().unwrap()),
+        Frame::Array(consumers.collect()),
+    ]);
+}
+
+/// The reply to the extended form of XPENDING: the ID, consumer, idle time
+/// and delivery count of each pending entry in the range
+fn pending_range(cg: &Group, range: PendingRange, now: i64) -> Frame {
+    if range.start > range.end {
+        return Frame::Array(vec![]);
+    }
+    let entries = cg
+        .pending
+        .range(range.start..=range.end)
+        .filter(|(_, pending)| {
+            return range
+                .consumer
+                .as_ref()
+                .is_none_or(|consumer| pending.consumer == *consumer);
+        })
+        .filter(|(_, pending)| {
+            return range
+                .min_idle
+                .is_none_or(|min_idle| now - pending.delivery_time >= min_idle as i64);
+        })
+        .take(range.count)
+        .map(|(id, pending)| {
+            return Frame::Array(vec![
+                id_frame(*id),
+                Frame::Bulk(pending.consumer.clone()),
+                Frame::Integer(now - pending.delivery_time),
+                Frame::Integer(pending.delivery_count as i64),
+            ]);
+        });
+    return Frame::Array(entries.collect());
+}
+
+/// Claim the pending entries idle for at least `min_idle` milliseconds for a
+/// consumer
+fn claim(
+    stream: &mut Stream,
+    group: &Bytes,
+    consumer: &Bytes,
+    min_idle: u64,
+    ids: Vec<StreamId>,
+    options: &ClaimOptions,
+    now: i64,
+) -> Frame {
+    let delivery_time = match (options.idle, options.time) {
+        (Some(idle), _) => now - idle as i64,
+        (None, Some(time)) => time,
+        (None, None) => now,
+    };
+    let cg = stream.groups.get_mut(group).unwrap();
+    if let Some(last_id) = options.last_id {
+        cg.last_id = cg.last_id.max(last_id);
+    }
+    cg.consumer(consumer, now);
+    let mut claimed = vec![];
+    for id in ids {
+        let Some(fields) = stream.entries.get(&id) else {
+            // The entry was removed from the stream, so it cannot be
+            // processed any more
+            cg.acknowledge(id);
+            continue;
+        };
+        let delivery_count = match cg.pending.get(&id) {
+            Some(pending) if now - pending.delivery_time < min_idle as i64 => continue,
+            Some(pending) => pending.delivery_count,
+            None if options.force => 0,
+            None => continue,
+        };
+        let delivery_count = match (options.retry_count, options.just_id) {
+            (Some(retry_count), _) => retry_count,
+            (None, true) => delivery_count,
+            (None, false) => delivery_count + 1,
+        };
+        cg.assign(id, consumer, delivery_time, delivery_count);
+        claimed.push(match options.just_id {
+            true => id_frame(id),
+            false => entry_frame(&id, fields),
+        });
+    }
+    if !claimed.is_empty() {
+        cg.consumers.get_mut(consumer).unwrap().active_time = Some(now);
+    }
+    return Frame::Array(claimed);
+}
+
+/// Claim up to `count` of the pending entries from `start` on that are idle
+/// for at least `min_idle` milliseconds. Like Redis, at most ten times
+/// `count` pending entries are looked at, and the reply starts with the ID
+/// to continue from, 0-0 once all of them were looked at.
+#[allow(clippy::too_many_arguments)]
+fn autoclaim(
+    stream: &mut Stream,
+    group: &Bytes,
+    consumer: &Bytes,
+    min_idle: u64,
+    start: StreamId,
+    count: usize,
+    just_id: bool,
+    now: i64,
+) -> Frame {
+    let cg = stream.groups.get_mut(group).unwrap();
+    cg.consumer(consumer, now);
+    let attempts = count.saturating_mul(10);
+    // Collect the candidates first, since claiming changes the pending entries
+    let candidates: Vec<StreamId> = cg
+        .pending
+        .range(start..)
+        .map(|(id, _)| *id)
+        .take(attempts.saturating_add(1))
+        .collect();
+    let (mut claimed, mut deleted, mut next) = (vec![], vec![], StreamId::MIN);
+    for (i, id) in candidates.into_iter().enumerate() {
+        if claimed.len() == count || i == attempts {
+            next = id;
+            break;
+        }
+        let Some(fields) = stream.entries.get(&id) else {
+            cg.acknowledge(id);
+            deleted.push(id_frame(id));
+            continue;
+        };
+        let pending = &cg.pending[&id];
+        if now - pending.delivery_time < min_idle as i64 {
+            continue;
+        }
+        let delivery_count = pending.delivery_count + u64::from(!just_id);
+        cg.assign(id, consumer, now, delivery_count);
+        claimed.push(match just_id {
+            true => id_frame(id),
+            false => entry_frame(&id, fields),
+        });
+    }
+    if !claimed.is_empty() {
+        cg.consumers.get_mut(consumer).unwrap().active_time = Some(now);
+    }
+    return Frame::Array(vec![
+        id_frame(next),
+        Frame::Array(claimed),
+        Frame::Array(deleted),
+    ]);
+}
@@ -0,0 +1,233 @@
+//! Execution of the commands that operate on string values
+use super::{Keyspace, Value};
+use crate::command::{parse_float, parse_integer, CommandError, SetCondition, StringCommand};
+use crate::Frame;
+use bytes::{Bytes, BytesMut};
+
+/// The longest string a value may grow to, like Redis's default
+/// proto-max-bulk-len
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+fn string_too_long() -> CommandError {
+    return CommandError::Other(
+        "ERR string exceeds maximum allowed size (proto-max-bulk-len)".into(),
+    );
+}
+
+/// Reply with a string if there is one, or Null otherwise
+fn bulk_or_null(s: Option<&Bytes>) -> Frame {
+    return match s {
+        Some(s) => Frame::Bulk(s.clone()),
+        None => Frame::Null,
+    };
+}
+
+pub(super) fn execute(
+    keyspace: &mut Keyspace,
+    cmd: StringCommand,
+    now: i64,
+) -> Result<Frame, CommandError> {
+    return match cmd {
+        StringCommand::Set {
+            key,
+            val,
+            expiry,
+            keep_ttl,
+            condition,
+            get,
+        } => {
+            let expires_at = match expiry {
+                None => None,
+                Some(expiry) => Some(expiry.deadline(now).ok_or_else(|| {
+                    CommandError::Other("ERR invalid expire time in 'set' command".into())
+                })?),
+            };
+            // Without GET, SET overwrites values of any type
+            let (exists, old_expiry, old_val) = match keyspace.peek(&key, now) {
+                None => (false, None, None),
+                Some(entry) => {
+                    let val = match &entry.val {
+                        Value::String(s) => Some(s.clone()),
+                        _ if get => return Err(CommandError::WrongType),
+                        _ => None,
+                    };
+                    (true, entry.expires_at, val)
+                }
+            };
+            let skip = match condition {
+                Some(SetCondition::Nx) => exists,
+                Some(SetCondition::Xx) => !exists,
+                None => false,
+            };
+            if skip {
+                return Ok(match get {
+                    true => bulk_or_null(old_val.as_ref()),
+                    false => Frame::Null,
+                });
+            }
+
+            let expires_at = match keep_ttl {
+                true => old_expiry,
+                false => expires_at,
+            };
+            if expires_at.is_some_and(|expires_at| expires_at <= now) {
+                keyspace.remove(&key, now);
+            } else {
+                keyspace.insert(key, Value::String(val), expires_at, now);
+            }
+            match get {
+                true => Ok(bulk_or_null(old_val.as_ref())),
+                false => Ok(Frame::Simple("OK".into())),
+            }
+        }
+        StringCommand::Get { key } => Ok(bulk_or_null(keyspace.get_string(&key, now)?)),
+        StringCommand::SetNx { key, val } => match keyspace.peek(&key, now) {
+            Some(_) => Ok(Frame::Integer(0)),
+            None => {
+                keyspace.insert(key, Value::String(val), None, now);
+                Ok(Frame::Integer(1))
+            }
+        },
+        StringCommand::GetDel { key } => {
+            let reply = bulk_or_null(keyspace.get_string(&key, now)?);
+            keyspace.remove(&key, now);
+            Ok(reply)
+        }
+        StringCommand::GetEx {
+            key,
+            expiry,
+            persist,
+        } => {
+            let expires_at = match expiry {
+                None => None,
+                Some(expiry) => Some(expiry.deadline(now).ok_or_else(|| {
+                    CommandError::Other("ERR invalid expire time in 'getex' command".into())
+                })?),
+            };
+            let Some(val) = keyspace.get_string(&key, now)?.cloned() else {
+                return Ok(Frame::Null);
+            };
+            match expires_at {
+                Some(expires_at) if expires_at <= now => {
+                    keyspace.remove(&key, now);
+                }
+                Some(expires_at) => keyspace.set_expiry(&key, Some(expires_at)),
+                None if persist => keyspace.set_expiry(&key, None),
+                None => {}
+            }
+            Ok(Frame::Bulk(val))
+        }
+        StringCommand::Append { key, val } => {
+            let old = keyspace.get_string(&key, now)?.cloned().unwrap_or_default();
+            if old.len() + val.len() > MAX_STRING_LEN {
+                return Err(string_too_long());
+            }
+            let mut new = BytesMut::with_capacity(old.len() + val.len());
+            new.extend_from_slice(&old);
+            new.extend_from_slice(&val);
+            let len = new.len();
+            keyspace.set_value(key, Value::String(new.freeze()), now);
+            Ok(Frame::Integer(len as i64))
+        }
+        StringCommand::Strlen { key } => {
+            let len = keyspace.get_string(&key, now)?.map_or(0, |s| s.len());
+            Ok(Frame::Integer(len as i64))
+        }
+        StringCommand::GetRange { key, start, end } => {
+            let Some(s) = keyspace.get_string(&key, now)? else {
+                return Ok(Frame::Bulk(Bytes::new()));
+            };
+            let len = s.len() as i64;
+            if start < 0 && end < 0 && start > end {
+                return Ok(Frame::Bulk(Bytes::new()));
+            }
+            let start = if start < 0 {
+                (len + start).max(0)
+            } else {
+                start
+            };
+            let end = if end < 0 { (len + end).max(0) } else { end };
+            let end = end.min(len - 1);
+            if start > end || len == 0 {
+                return Ok(Frame::Bulk(Bytes::new()));
+            }
+            Ok(Frame::Bulk(s.slice(start as usize..=end as usize)))
+        }
+        StringCommand::SetRange { key, offset, val } => {
+            if offset < 0 {
+                return Err(CommandError::Other("ERR offset is out of range".into()));
+            }
+            let old = keyspace.get_string(&key, now)?.cloned();
+            if val.is_empty() {
+                // Nothing to write, and a missing key is not created
+                return Ok(Frame::Integer(old.map_or(0, |old| old.len() as i64)));
+            }
+            let offset = offset as usize;
+            if offset.saturating_add(val.len()) > MAX_STRING_LEN {
+                return Err(string_too_long());
+            }
+            let old = old.unwrap_or_default();
+            let mut new = BytesMut::from(&old[..]);
+            if new.len() < offset + val.len() {
+                new.resize(offset + val.len(), 0);
+            }
+            new[offset..offset + val.len()].copy_from_slice(&val);
+            let len = new.len();
+            keyspace.set_value(key, Value::String(new.freeze()), now);
+            Ok(Frame::Integer(len as i64))
+        }
+        StringCommand::IncrBy { key, delta } => {
+            let old = match keyspace.get_string(&key, now)? {
+                Some(s) => parse_integer(s).ok_or(CommandError::NotInteger)?,
+                None => 0,
+            };
+            let new = old.checked_add(delta).ok_or_else(|| {
+                CommandError::Other("ERR increment or decrement would overflow".into())
+            })?;
+            keyspace.set_value(key, Value::String(Bytes::from(new.to_string())), now);
+            Ok(Frame::Integer(new))
+        }
+        StringCommand::IncrByFloat { key, delta } => {
+            let old = match keyspace.get_string(&key, now)? {
+                Some(s) => parse_float(s).ok_or(CommandError::NotFloat)?,
+                None => 0.0,
+            };
+            let new = old + delta;
+            if !new.is_finite() {
+                return Err(CommandError::Other(
+                    "ERR increment would produce NaN or Infinity".into(),
+                ));
+            }
+            let new = Bytes::from(new.to_string());
+            keyspace.set_value(key, Value::String(new.clone()), now);
+            Ok(Frame::Bulk(new))
+        }
+        StringCommand::MGet { keys } => {
+            // Values of other types are reported as missing rather than
+            // failing the whole command
+            let vals = keys
+                .iter()
+                .map(|key| bulk_or_null(keyspace.get_string(key, now).ok().flatten()))
+                .collect();
+            Ok(Frame::Array(vals))
+        }
+        StringCommand::MSet { pairs } => {
+            for (key, val) in pairs {
+                keyspace.insert(key, Value::String(val), None, now);
+            }
+            Ok(Frame::Simple("OK".into()))
+        }
+        StringCommand::MSetNx { pairs } => {
+            if pairs
+                .iter()
+                .any(|(key, _)| keyspace.peek(key, now).is_some())
+            {
+                return Ok(Frame::Integer(0));
+            }
+            for (key, val) in pairs {
+                keyspace.insert(key, Value::String(val), None, now);
+            }
+            Ok(Frame::Integer(1))
+        }
+    };
+}
@@ -1,13 +1,80 @@
-//! Shared layers of abstraction: Bytes, Frame, Command, Connection, Client
-use bytes::{Buf, Bytes, BytesMut};
+//! Shared layers of abstraction: Bytes, Frame, Command, Connection, Client, DB
+pub mod command;
+pub mod db;
+
+pub use command::{Command, CommandError};
+
+use command::{
+    Aggregate, BitFieldOp, BitOp, BitRange, BitmapCommand, ClaimOptions, Coordinates, DistanceUnit,
+    End, Expiry, GeoCommand, GeoSearch, HashCommand, HyperLogLogCommand, KeyCommand, Limit,
+    ListCommand, NewId, PendingRange, Position, PubSubCommand, Range, ReadId, ScoreBound,
+    SetCommand, SetOp, Side, SortedSetCommand, StoreOp, StreamCommand, StreamId, StringCommand,
+    Trim,
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_stream::Stream;
+
+/// Errors are Send + Sync so that they can be held across await points in
+/// tasks spawned onto the multi-threaded runtime
+pub type MyResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
 
-pub type MyResult<T> = Result<T, Box<dyn Error>>;
+/// A stream entry: its ID and its field value pairs
+pub type StreamEntry = (StreamId, Vec<(Bytes, Bytes)>);
+
+/// A member found by GEOSEARCH, along with what the search asked for
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoMatch {
+    pub member: Bytes,
+    /// The distance from the center of the search, in the unit of the search
+    pub distance: Option<f64>,
+    pub hash: Option<i64>,
+    pub coordinates: Option<Coordinates>,
+}
+
+/// A message published to a channel that a Subscriber subscribed to, or to a
+/// channel matching one of its patterns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: Bytes,
+    /// The pattern the channel matched, if the message was received through a
+    /// pattern subscription
+    pub pattern: Option<Bytes>,
+    pub content: Bytes,
+}
 
 const CRLF: &str = "\r\n";
 
+/// Buffered outgoing frames are written into the socket as soon as the write
+/// buffer grows past this many bytes, even without an explicit flush
+const WRITE_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// While waiting for the peer to close the connection, bytes it sends are kept
+/// for later until the read buffer grows past this many bytes
+const PENDING_READ_LIMIT: usize = 1024 * 1024;
+
+/// Format a double the way it is sent over the wire: the shortest
+/// representation that parses back into the same number, switching to
+/// scientific notation for very large and very small magnitudes
+pub fn format_double(num: f64) -> String {
+    if num.is_nan() {
+        return "nan".into();
+    }
+    if num.is_infinite() {
+        return if num > 0.0 { "inf" } else { "-inf" }.into();
+    }
+    let magnitude = num.abs();
+    if magnitude != 0.0 && !(1e-5..1e17).contains(&magnitude) {
+        return format!("{num:e}");
+    }
+    return format!("{num}");
+}
+
 /// A client provides high-level methods for sending commands to and receiving
 /// commands from the server.
 pub struct Client {
@@ -70,500 +137,3353 @@ impl Client {
         }
         return Ok(None);
     }
-}
 
-/// The Command enum provides abstraction over Frames
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum Command {
-    Set { key: Bytes, val: Bytes },
-    Get { key: Bytes },
-    Del { key: Bytes },
-}
+    /// Send a "DEL key [key ...]" command to the server. Return the number of
+    /// keys that were deleted.
+    pub async fn del_many(&mut self, keys: &[&str]) -> MyResult<i64> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        return self
+            .request_integer(Command::Key(KeyCommand::Del { keys }))
+            .await;
+    }
 
-impl Command {
-    /// Create a new Set command
-    pub fn set(key: Bytes, val: Bytes) -> Self {
-        return Self::Set { key, val };
+    /// Send an "UNLINK key [key ...]" command to the server. Return the number
+    /// of keys that were deleted.
+    pub async fn unlink(&mut self, keys: &[&str]) -> MyResult<i64> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        return self
+            .request_integer(Command::Key(KeyCommand::Unlink { keys }))
+            .await;
     }
 
-    /// Create a new Get command
-    pub fn get(key: Bytes) -> Self {
-        return Self::Get { key };
+    /// Send an "EXISTS key [key ...]" command to the server. Return the number
+    /// of keys that exist, counting a key as many times as it is repeated.
+    pub async fn exists(&mut self, keys: &[&str]) -> MyResult<i64> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        return self
+            .request_integer(Command::Key(KeyCommand::Exists { keys }))
+            .await;
     }
 
-    /// Create a new Pop command
-    pub fn del(key: Bytes) -> Self {
-        return Self::Del { key };
+    /// Send a "TOUCH key [key ...]" command to the server. Return the number
+    /// of keys that exist.
+    pub async fn touch(&mut self, keys: &[&str]) -> MyResult<i64> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        return self
+            .request_integer(Command::Key(KeyCommand::Touch { keys }))
+            .await;
     }
 
-    /// Convert a command into the appropriate Frame
-    pub fn to_frame(&self) -> Frame {
-        return match self {
-            Self::Set { key, val } => Frame::Array(vec![
-                Frame::Bulk(Bytes::from("SET")),
-                Frame::Bulk(Bytes::copy_from_slice(key)),
-                Frame::Bulk(Bytes::copy_from_slice(val)),
-            ]),
-            Self::Get { key } => Frame::Array(vec![
-                Frame::Bulk(Bytes::from("GET")),
-                Frame::Bulk(Bytes::copy_from_slice(key)),
-            ]),
-            Self::Del { key } => Frame::Array(vec![
-                Frame::Bulk(Bytes::from("DEL")),
-                Frame::Bulk(Bytes::copy_from_slice(key)),
-            ]),
+    /// Send an "MGET key [key ...]" command to the server. Return the values
+    /// in the same order as the keys, with None for the keys that do not
+    /// exist.
+    pub async fn mget(&mut self, keys: &[&str]) -> MyResult<Vec<Option<Bytes>>> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        let resp = self
+            .request(Command::String(StringCommand::MGet { keys }))
+            .await?;
+        let Frame::Array(vals) = resp else {
+            return Err(format!("unexpected response {resp:?}").into());
         };
+        return vals.into_iter().map(optional_bulk).collect();
     }
 
-    /// Parse a frame back into a command. If the frame does not correspond to
-    /// any of the supported commands, return None
-    ///
-    /// If the frame does not strictly conform to the expected format of the
-    /// command, this method will return None. For example, if the input frame
-    /// has more than three elements, then it will never be parsed into a SET
-    /// command even if the first three elements form a valid SET command.
-    pub fn parse_command(frame: &Frame) -> Option<Self> {
-        if let Frame::Array(frames) = frame {
-            match frames.get(0) {
-                Some(Frame::Bulk(bytes)) if bytes == &Bytes::from("SET") => {
-                    if frames.len() != 3 {
-                        return None;
-                    }
-                    // unwrapping is ok because the length is already guaranteed
-                    let key = frames.get(1).unwrap();
-                    let val = frames.get(2).unwrap();
-                    if let Frame::Bulk(key) = key {
-                        if let Frame::Bulk(val) = val {
-                            return Some(Self::set(
-                                Bytes::copy_from_slice(key),
-                                Bytes::copy_from_slice(val),
-                            ));
-                        }
-                    }
-                    return None;
-                }
-                Some(Frame::Bulk(bytes)) if bytes == &Bytes::from("GET") => {
-                    if frames.len() != 2 {
-                        return None;
-                    }
-                    let key = frames.get(1).unwrap();
-                    if let Frame::Bulk(key) = key {
-                        return Some(Self::get(Bytes::copy_from_slice(&key)));
-                    }
-                    return None;
-                }
-                Some(Frame::Bulk(bytes)) if bytes == &Bytes::from("DEL") => {
-                    if frames.len() != 2 {
-                        return None;
-                    }
-                    let key = frames.get(1).unwrap();
-                    if let Frame::Bulk(key_bytes) = key {
-                        return Some(Self::del(Bytes::copy_from_slice(&key_bytes)));
-                    }
-                    return None;
-                }
-                _ => {
-                    return None;
-                }
-            }
-        }
-        // For now, all commands must be arrays
-        return None;
+    /// Send an "MSET key val [key val ...]" command to the server
+    pub async fn mset(&mut self, pairs: &[(&str, &str)]) -> MyResult<()> {
+        let pairs = pairs
+            .iter()
+            .map(|(key, val)| (str_to_bytes(key), str_to_bytes(val)))
+            .collect();
+        self.request(Command::String(StringCommand::MSet { pairs }))
+            .await?;
+        return Ok(());
     }
-}
 
-/// The various RESP data types. The data types are explained here:
-/// https://redis.io/docs/reference/protocol-spec/
-#[derive(Debug, PartialEq, Eq)]
-pub enum Frame {
-    Simple(String),
-    Error(String),
-    Integer(i64),
-    Bulk(Bytes),
-    Null,
-    Array(Vec<Frame>),
-}
+    /// Send an "MSETNX key val [key val ...]" command to the server. Return
+    /// whether the keys were set, which only happens if none of them existed.
+    pub async fn msetnx(&mut self, pairs: &[(&str, &str)]) -> MyResult<bool> {
+        let pairs = pairs
+            .iter()
+            .map(|(key, val)| (str_to_bytes(key), str_to_bytes(val)))
+            .collect();
+        let cmd = Command::String(StringCommand::MSetNx { pairs });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
 
-impl Frame {
-    /// Serialize a frame into a byte array for transmission over the network
-    pub fn serialize(&self) -> Bytes {
-        return match self {
-            Self::Simple(_) => self._serialize_simple_string(),
-            Self::Error(_) => self._serialize_error(),
-            Self::Integer(_) => self._serialize_integer(),
-            Self::Bulk(_) => self._serialize_bulk_string(),
-            Self::Null => self._serialize_null(),
-            Self::Array(_) => self._serialize_array(),
-        };
+    /// Send an "LPUSH key element [element ...]" command to the server. Return
+    /// the length of the list after the push.
+    pub async fn lpush(&mut self, key: &str, elems: &[&str]) -> MyResult<i64> {
+        return self.push(key, elems, Side::Left, false).await;
     }
 
-    /// Serialize a simple string to a byte array.
-    fn _serialize_simple_string(&self) -> Bytes {
-        // "+<content>\r\n"
-        if let Self::Simple(s) = self {
-            let data = format!("+{}{}", s, CRLF);
-            return Bytes::copy_from_slice(&data.as_bytes());
-        }
-        unreachable!("Self is not Frame::Simple");
+    /// Send an "RPUSH key element [element ...]" command to the server. Return
+    /// the length of the list after the push.
+    pub async fn rpush(&mut self, key: &str, elems: &[&str]) -> MyResult<i64> {
+        return self.push(key, elems, Side::Right, false).await;
     }
 
-    /// Serialize an error to a byte array
-    fn _serialize_error(&self) -> Bytes {
-        // "-<err>\r\n"
-        if let Self::Error(s) = self {
-            let data = format!("-{}{}", s, CRLF);
-            return Bytes::copy_from_slice(&data.as_bytes());
-        }
-        unreachable!("Self is not Frame::Error");
+    /// Send an "LPUSHX key element [element ...]" command to the server, which
+    /// only pushes onto a list that already exists
+    pub async fn lpushx(&mut self, key: &str, elems: &[&str]) -> MyResult<i64> {
+        return self.push(key, elems, Side::Left, true).await;
     }
 
-    /// Serialize a u64 integer to a byte array
-    fn _serialize_integer(&self) -> Bytes {
-        // ":<integer>\r\n"
-        if let Self::Integer(n) = self {
-            let data = format!(":{n}{CRLF}");
-            return Bytes::copy_from_slice(&data.as_bytes());
-        }
-        unreachable!("Self is not Frame::Integer!");
+    /// Send an "RPUSHX key element [element ...]" command to the server, which
+    /// only pushes onto a list that already exists
+    pub async fn rpushx(&mut self, key: &str, elems: &[&str]) -> MyResult<i64> {
+        return self.push(key, elems, Side::Right, true).await;
     }
 
-    /// Serialize a bulk string, including the empty string, but not including
-    /// Frame::Null
-    fn _serialize_bulk_string(&self) -> Bytes {
-        // "$<len><CRLF><data><CRLF>"
-        if let Self::Bulk(arr) = self {
-            let len = arr.len();
-            let buf = vec![
-                b"$".to_vec(),
-                format!("{len}").as_bytes().to_vec(),
-                CRLF.as_bytes().to_vec(),
-                arr.to_vec(),
-                CRLF.as_bytes().to_vec(),
-            ]
-            .concat();
+    async fn push(
+        &mut self,
+        key: &str,
+        elems: &[&str],
+        side: Side,
+        only_if_exists: bool,
+    ) -> MyResult<i64> {
+        let cmd = Command::List(ListCommand::Push {
+            key: str_to_bytes(key),
+            side,
+            elems: elems.iter().map(|elem| str_to_bytes(elem)).collect(),
+            only_if_exists,
+        });
+        return self.request_integer(cmd).await;
+    }
 
-            return Bytes::copy_from_slice(&buf);
-        }
-        unreachable!("Self is not Frame::Bulk");
+    /// Send an "LPOP key" command to the server. Return the first element, or
+    /// None if the list does not exist.
+    pub async fn lpop(&mut self, key: &str) -> MyResult<Option<Bytes>> {
+        return self.pop(key, Side::Left).await;
     }
 
-    /// Serialize a Null frame, which is a Bulk frame with a length of -1
-    fn _serialize_null(&self) -> Bytes {
-        // "$-1<CRLF>"
-        if let Self::Null = self {
-            let buf = format!("$-1{CRLF}");
-            return Bytes::copy_from_slice(&buf.as_bytes());
-        }
-        unreachable!("Self is not Frame::Null");
+    /// Send an "RPOP key" command to the server. Return the last element, or
+    /// None if the list does not exist.
+    pub async fn rpop(&mut self, key: &str) -> MyResult<Option<Bytes>> {
+        return self.pop(key, Side::Right).await;
     }
 
-    /// Serialize an Array frame by recursively calling "serialize" on its
-    /// elements
-    fn _serialize_array(&self) -> Bytes {
-        if let Self::Array(v) = self {
-            let nelems = v.len();
-            let prefix = Bytes::from(format!("*{nelems}{CRLF}").as_bytes().to_vec());
-            let elems: Bytes = v
-                .iter()
-                .map(|frame| frame.serialize())
-                .collect::<Vec<Bytes>>()
-                .concat()
-                .into();
-            return vec![prefix, elems].concat().into();
-        }
-        unreachable!("Self is not Frame::Array");
+    async fn pop(&mut self, key: &str, side: Side) -> MyResult<Option<Bytes>> {
+        let key = str_to_bytes(key);
+        let cmd = Command::List(ListCommand::Pop {
+            key,
+            side,
+            count: None,
+        });
+        return optional_bulk(self.request(cmd).await?);
     }
 
-    /// Read the input byte array and check if there is a valid frame inside.
-    /// If yes, return the parsed frame in the "Some" variant, else return
-    /// None
-    fn parse(bytes: &mut Bytes) -> Option<Frame> {
-        if !bytes.has_remaining() {
-            return None;
-        }
-        match bytes.get_u8() {
-            b'+' => {
-                if let Some(msg) = Self::_parse_binary_safe_string(bytes) {
-                    return Some(Frame::Simple(msg));
-                }
-            }
-            b'-' => {
-                if let Some(msg) = Self::_parse_binary_safe_string(bytes) {
-                    return Some(Frame::Error(msg));
-                }
-            }
-            b':' => {
-                if let Some(num) = Self::_parse_binary_safe_string(bytes) {
-                    if let Ok(num) = num.parse::<i64>() {
-                        return Some(Frame::Integer(num));
-                    }
-                }
-            }
-            b'$' => {
-                // Check against Null frame
-                if bytes.starts_with(b"-1\r\n") {
-                    return Some(Frame::Null);
-                }
+    /// Send an "LPOP key count" command to the server. Return up to `count`
+    /// elements from the head of the list.
+    pub async fn lpop_count(&mut self, key: &str, count: usize) -> MyResult<Vec<Bytes>> {
+        return self.pop_count(key, Side::Left, count).await;
+    }
 
-                // Read until the first CRLF to parse the number of bytes
-                if let Some(nbytes) = Self::_parse_binary_safe_string(bytes) {
-                    if let Ok(nbytes) = nbytes.parse::<usize>() {
-                        // bytes[0..nbytes] should be the content
-                        // bytes[nbytes..nbytes+2] should be another CRLF
-                        if bytes.remaining() >= nbytes + 2
-                            && bytes.slice(nbytes..nbytes + 2).starts_with(CRLF.as_bytes())
-                        {
-                            let frame = Frame::Bulk(Bytes::from(bytes.slice(0..nbytes)));
-                            bytes.advance(nbytes + 2);
-                            return Some(frame);
-                        }
-                    }
-                }
-            }
-            b'*' => {
-                // Parsing an array: first obtain the number of elements, then
-                // fill a Vector with that number of elements
-                if let Some(nelems) = Self::_parse_binary_safe_string(bytes) {
-                    if let Ok(nelems) = nelems.parse::<usize>() {
-                        let mut elems = vec![];
-
-                        for _ in 0..nelems {
-                            if let Some(frame) = Self::parse(bytes) {
-                                elems.push(frame);
-                            } else {
-                                return None;
-                            }
-                        }
+    /// Send an "RPOP key count" command to the server. Return up to `count`
+    /// elements from the tail of the list.
+    pub async fn rpop_count(&mut self, key: &str, count: usize) -> MyResult<Vec<Bytes>> {
+        return self.pop_count(key, Side::Right, count).await;
+    }
 
-                        return Some(Frame::Array(elems));
-                    }
-                }
-            }
-            _ => (),
-        }
+    async fn pop_count(&mut self, key: &str, side: Side, count: usize) -> MyResult<Vec<Bytes>> {
+        let key = str_to_bytes(key);
+        let count = Some(count);
+        let cmd = Command::List(ListCommand::Pop { key, side, count });
+        return bulks(self.request(cmd).await?);
+    }
 
-        return None;
+    /// Send an "LLEN key" command to the server. Return the length of the
+    /// list, 0 if it does not exist.
+    pub async fn llen(&mut self, key: &str) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        return self
+            .request_integer(Command::List(ListCommand::Len { key }))
+            .await;
     }
 
-    /// Given some bytes that are assumed to be binary safe, extract the
-    /// string between the start of the bytes and the first CRLF. If the bytes
-    /// do not contain CRLF, return None
-    fn _parse_binary_safe_string(bytes: &mut Bytes) -> Option<String> {
-        let mut msg = vec![];
+    /// Send an "LRANGE key start stop" command to the server. Negative
+    /// indices count from the end of the list.
+    pub async fn lrange(&mut self, key: &str, start: i64, stop: i64) -> MyResult<Vec<Bytes>> {
+        let key = str_to_bytes(key);
+        let cmd = Command::List(ListCommand::Range { key, start, stop });
+        return bulks(self.request(cmd).await?);
+    }
 
-        while bytes.has_remaining() && !bytes.starts_with(CRLF.as_bytes()) {
-            msg.push(bytes.get_u8());
-        }
+    /// Send an "LINDEX key index" command to the server. Return None if the
+    /// index is out of range.
+    pub async fn lindex(&mut self, key: &str, index: i64) -> MyResult<Option<Bytes>> {
+        let key = str_to_bytes(key);
+        let cmd = Command::List(ListCommand::Index { key, index });
+        return optional_bulk(self.request(cmd).await?);
+    }
 
-        if bytes.has_remaining() {
-            // CRLF should be consumed, as well
-            bytes.advance(CRLF.as_bytes().len());
-            if let Ok(msg) = String::from_utf8(msg) {
-                return Some(msg);
-            }
-        }
-        return None;
+    /// Send an "LSET key index element" command to the server
+    pub async fn lset(&mut self, key: &str, index: i64, elem: &str) -> MyResult<()> {
+        let (key, elem) = (str_to_bytes(key), str_to_bytes(elem));
+        self.request(Command::List(ListCommand::Set { key, index, elem }))
+            .await?;
+        return Ok(());
     }
-}
 
-/// A wrapper around a TCP socket (TcpStream) for writing byte stream into
-/// Bytes and for parsing Bytes into frames
-pub struct Connection {
-    pub socket: TcpStream,
-}
+    /// Send an "LREM key count element" command to the server. Return the
+    /// number of elements removed.
+    pub async fn lrem(&mut self, key: &str, count: i64, elem: &str) -> MyResult<i64> {
+        let (key, elem) = (str_to_bytes(key), str_to_bytes(elem));
+        let cmd = Command::List(ListCommand::Rem { key, count, elem });
+        return self.request_integer(cmd).await;
+    }
 
-impl Connection {
-    /// Instantiate a new connection
-    pub fn new(socket: TcpStream) -> Self {
-        return Self { socket };
+    /// Send an "LTRIM key start stop" command to the server
+    pub async fn ltrim(&mut self, key: &str, start: i64, stop: i64) -> MyResult<()> {
+        let key = str_to_bytes(key);
+        self.request(Command::List(ListCommand::Trim { key, start, stop }))
+            .await?;
+        return Ok(());
     }
 
-    /// Read bytes from the TcpStream, then parse it. If there is a valid
-    /// Frame in the bytes read, then return it. Else return None.
-    pub async fn read_frame(&mut self) -> Result<Option<Frame>, Box<dyn Error>> {
-        let mut buf = BytesMut::with_capacity(4096);
+    /// Send an "LINSERT key BEFORE|AFTER pivot element" command to the server.
+    /// Return the length of the list, or -1 if the pivot was not found.
+    pub async fn linsert(
+        &mut self,
+        key: &str,
+        position: Position,
+        pivot: &str,
+        elem: &str,
+    ) -> MyResult<i64> {
+        let cmd = Command::List(ListCommand::Insert {
+            key: str_to_bytes(key),
+            position,
+            pivot: str_to_bytes(pivot),
+            elem: str_to_bytes(elem),
+        });
+        return self.request_integer(cmd).await;
+    }
 
-        loop {
-            // TODO: unnecessary copy but oh well
-            if let Some(frame) = Frame::parse(&mut Bytes::from(buf.to_vec())) {
-                return Ok(Some(frame));
-            }
+    /// Send an "LMOVE source destination LEFT|RIGHT LEFT|RIGHT" command to the
+    /// server. Return the element that was moved, or None if the source does
+    /// not exist.
+    pub async fn lmove(
+        &mut self,
+        source: &str,
+        destination: &str,
+        from: Side,
+        to: Side,
+    ) -> MyResult<Option<Bytes>> {
+        let cmd = Command::List(ListCommand::Move {
+            source: str_to_bytes(source),
+            destination: str_to_bytes(destination),
+            from,
+            to,
+        });
+        return optional_bulk(self.request(cmd).await?);
+    }
 
-            self.socket.readable().await?;
-            let nbytes = self.socket.read_buf(&mut buf).await?;
-            if nbytes == 0 {
-                return Ok(None);
-            }
-        }
+    /// Send an "RPOPLPUSH source destination" command to the server, which is
+    /// an LMOVE from the right to the left
+    pub async fn rpoplpush(&mut self, source: &str, destination: &str) -> MyResult<Option<Bytes>> {
+        return self
+            .lmove(source, destination, Side::Right, Side::Left)
+            .await;
     }
 
-    /// Convert the input frame into bytes, then write into the socket
-    pub async fn write_frame(&mut self, frame: &Frame) -> Result<usize, Box<dyn Error>> {
-        self.socket.writable().await?;
-        let nbytes = self.socket.write(&frame.serialize()).await?;
-        return Ok(nbytes);
+    /// Send an "LPOS key element" command to the server. Return the index of
+    /// the first match, or None if there is none.
+    pub async fn lpos(&mut self, key: &str, elem: &str) -> MyResult<Option<i64>> {
+        let cmd = Command::List(ListCommand::Pos {
+            key: str_to_bytes(key),
+            elem: str_to_bytes(elem),
+            rank: 1,
+            count: None,
+            maxlen: 0,
+        });
+        return match self.request(cmd).await? {
+            Frame::Integer(index) => Ok(Some(index)),
+            Frame::Null => Ok(None),
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Send an "LMPOP numkeys key [key ...] LEFT|RIGHT COUNT count" command to
+    /// the server. Return the first key that holds a list along with up to
+    /// `count` elements popped from it, or None if none of the keys do.
+    pub async fn lmpop(
+        &mut self,
+        keys: &[&str],
+        side: Side,
+        count: usize,
+    ) -> MyResult<Option<(Bytes, Vec<Bytes>)>> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        let cmd = Command::List(ListCommand::MPop { keys, side, count });
+        return keyed_elems(self.request(cmd).await?);
+    }
 
-    #[test]
-    fn test_simple_string_serialization() {
-        let simple = Frame::Simple("OK".into());
-        let expected: Vec<u8> = b"+OK\r\n".to_vec();
-        assert_eq!(simple.serialize(), Bytes::copy_from_slice(&expected));
+    /// Send a "BLPOP key [key ...] timeout" command to the server. Return the
+    /// key the element was popped from along with the element, or None if the
+    /// timeout ran out first. A timeout of None waits forever.
+    pub async fn blpop(
+        &mut self,
+        keys: &[&str],
+        timeout: Option<Duration>,
+    ) -> MyResult<Option<(Bytes, Bytes)>> {
+        return self.bpop(keys, Side::Left, timeout).await;
     }
 
-    #[test]
-    fn test_integer_serialization() {
-        let num = Frame::Integer(0);
-        let expected = b":0\r\n";
-        assert_eq!(num.serialize(), Bytes::copy_from_slice(expected));
+    /// Send a "BRPOP key [key ...] timeout" command to the server. Return the
+    /// key the element was popped from along with the element, or None if the
+    /// timeout ran out first. A timeout of None waits forever.
+    pub async fn brpop(
+        &mut self,
+        keys: &[&str],
+        timeout: Option<Duration>,
+    ) -> MyResult<Option<(Bytes, Bytes)>> {
+        return self.bpop(keys, Side::Right, timeout).await;
     }
 
-    #[test]
-    fn test_bulk_string_serialization() {
-        let bulk = Frame::Bulk(Bytes::from(vec![b'6', b'9', b'4', b'2', b'0']));
-        let expected = b"$5\r\n69420\r\n";
-        assert_eq!(bulk.serialize(), Bytes::copy_from_slice(expected));
+    async fn bpop(
+        &mut self,
+        keys: &[&str],
+        side: Side,
+        timeout: Option<Duration>,
+    ) -> MyResult<Option<(Bytes, Bytes)>> {
+        let cmd = Command::List(ListCommand::BPop {
+            keys: keys.iter().map(|key| str_to_bytes(key)).collect(),
+            side,
+            timeout,
+        });
+        return match self.request(cmd).await? {
+            Frame::Array(pair) => match &pair[..] {
+                [Frame::Bulk(key), Frame::Bulk(elem)] => Ok(Some((key.clone(), elem.clone()))),
+                _ => Err(format!("unexpected response {pair:?}").into()),
+            },
+            Frame::Null => Ok(None),
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
     }
 
-    #[test]
-    fn test_null_serialization() {
-        let null = Frame::Null;
-        assert_eq!(null.serialize(), Bytes::from(b"$-1\r\n".to_vec()));
+    /// Send a "BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout"
+    /// command to the server. Return the element that was moved, or None if
+    /// the timeout ran out first. A timeout of None waits forever.
+    pub async fn blmove(
+        &mut self,
+        source: &str,
+        destination: &str,
+        from: Side,
+        to: Side,
+        timeout: Option<Duration>,
+    ) -> MyResult<Option<Bytes>> {
+        let cmd = Command::List(ListCommand::BMove {
+            source: str_to_bytes(source),
+            destination: str_to_bytes(destination),
+            from,
+            to,
+            timeout,
+        });
+        return optional_bulk(self.request(cmd).await?);
     }
 
-    #[test]
-    fn test_array_serialization() {
-        let set = Frame::Simple("SET".into());
-        let key = Frame::Bulk(Bytes::from("foo"));
-        let val = Frame::Bulk(Bytes::from("bar"));
-        let cmd = Frame::Array(vec![set, key, val]);
-        assert_eq!(
-            cmd.serialize(),
-            Bytes::from(b"*3\r\n+SET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".to_vec())
-        );
+    /// Send a "BLMPOP timeout numkeys key [key ...] LEFT|RIGHT COUNT count"
+    /// command to the server. Like lmpop, but waits for one of the keys to
+    /// hold a list. A timeout of None waits forever.
+    pub async fn blmpop(
+        &mut self,
+        keys: &[&str],
+        side: Side,
+        count: usize,
+        timeout: Option<Duration>,
+    ) -> MyResult<Option<(Bytes, Vec<Bytes>)>> {
+        let cmd = Command::List(ListCommand::BMPop {
+            keys: keys.iter().map(|key| str_to_bytes(key)).collect(),
+            side,
+            count,
+            timeout,
+        });
+        return keyed_elems(self.request(cmd).await?);
     }
 
-    #[test]
-    fn test_simple_string_deserialization() {
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("+SET\r\n")),
-            Some(Frame::Simple("SET".into())),
-        );
+    /// Send an "HSET key field value [field value ...]" command to the server.
+    /// Return the number of fields that were added rather than updated.
+    pub async fn hset(&mut self, key: &str, pairs: &[(&str, &str)]) -> MyResult<i64> {
+        let cmd = Command::Hash(HashCommand::Set {
+            key: str_to_bytes(key),
+            pairs: pairs
+                .iter()
+                .map(|(field, val)| (str_to_bytes(field), str_to_bytes(val)))
+                .collect(),
+        });
+        return self.request_integer(cmd).await;
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("+SET\r\n+++++")),
-            Some(Frame::Simple("SET".into())),
-        );
+    /// Send an "HSETNX key field value" command to the server. Return whether
+    /// the field was set, which it is not if it already exists.
+    pub async fn hsetnx(&mut self, key: &str, field: &str, val: &str) -> MyResult<bool> {
+        let cmd = Command::Hash(HashCommand::SetNx {
+            key: str_to_bytes(key),
+            field: str_to_bytes(field),
+            val: str_to_bytes(val),
+        });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
 
-        assert_eq!(Frame::parse(&mut Bytes::from("+SET\r")), None,);
+    /// Send an "HGET key field" command to the server
+    pub async fn hget(&mut self, key: &str, field: &str) -> MyResult<Option<Bytes>> {
+        let (key, field) = (str_to_bytes(key), str_to_bytes(field));
+        let cmd = Command::Hash(HashCommand::Get { key, field });
+        return optional_bulk(self.request(cmd).await?);
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("+\r\n")),
-            Some(Frame::Simple("".into())),
-        );
+    /// Send an "HMGET key field [field ...]" command to the server. Return the
+    /// value of each field, None for the fields that do not exist.
+    pub async fn hmget(&mut self, key: &str, fields: &[&str]) -> MyResult<Vec<Option<Bytes>>> {
+        let key = str_to_bytes(key);
+        let fields = fields.iter().map(|field| str_to_bytes(field)).collect();
+        let Frame::Array(vals) = self
+            .request(Command::Hash(HashCommand::MGet { key, fields }))
+            .await?
+        else {
+            return Err("unexpected response to HMGET".into());
+        };
+        return vals.into_iter().map(optional_bulk).collect();
     }
 
-    #[test]
-    fn test_error_deserialization() {
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("-Key not found\r\n")),
-            Some(Frame::Error("Key not found".into())),
-        );
+    /// Send an "HDEL key field [field ...]" command to the server. Return the
+    /// number of fields that were removed.
+    pub async fn hdel(&mut self, key: &str, fields: &[&str]) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        let fields = fields.iter().map(|field| str_to_bytes(field)).collect();
+        return self
+            .request_integer(Command::Hash(HashCommand::Del { key, fields }))
+            .await;
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("-Key not found\r\n-----")),
-            Some(Frame::Error("Key not found".into())),
-        );
+    /// Send an "HEXISTS key field" command to the server
+    pub async fn hexists(&mut self, key: &str, field: &str) -> MyResult<bool> {
+        let (key, field) = (str_to_bytes(key), str_to_bytes(field));
+        let cmd = Command::Hash(HashCommand::Exists { key, field });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
 
-        assert_eq!(Frame::parse(&mut Bytes::from("-Key not found\r")), None,);
+    /// Send an "HLEN key" command to the server
+    pub async fn hlen(&mut self, key: &str) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        return self
+            .request_integer(Command::Hash(HashCommand::Len { key }))
+            .await;
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("-\r\n")),
-            Some(Frame::Error("".into())),
-        );
+    /// Send an "HSTRLEN key field" command to the server. Return the length of
+    /// the value, 0 if the field does not exist.
+    pub async fn hstrlen(&mut self, key: &str, field: &str) -> MyResult<i64> {
+        let (key, field) = (str_to_bytes(key), str_to_bytes(field));
+        let cmd = Command::Hash(HashCommand::StrLen { key, field });
+        return self.request_integer(cmd).await;
     }
 
-    #[test]
-    fn test_integer_deserialization() {
-        assert_eq!(
-            Frame::parse(&mut Bytes::from(":0\r\n")),
-            Some(Frame::Integer(0)),
+    /// Send an "HKEYS key" command to the server
+    pub async fn hkeys(&mut self, key: &str) -> MyResult<Vec<Bytes>> {
+        let key = str_to_bytes(key);
+        return bulks(
+            self.request(Command::Hash(HashCommand::Keys { key }))
+                .await?,
         );
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from(":1\r\n")),
-            Some(Frame::Integer(1)),
+    /// Send an "HVALS key" command to the server
+    pub async fn hvals(&mut self, key: &str) -> MyResult<Vec<Bytes>> {
+        let key = str_to_bytes(key);
+        return bulks(
+            self.request(Command::Hash(HashCommand::Vals { key }))
+                .await?,
         );
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from(":-1\r\n")),
-            Some(Frame::Integer(-1)),
-        );
+    /// Send an "HGETALL key" command to the server. Return an empty map if the
+    /// hash does not exist.
+    pub async fn hgetall(&mut self, key: &str) -> MyResult<HashMap<Bytes, Bytes>> {
+        let key = str_to_bytes(key);
+        let reply = self
+            .request(Command::Hash(HashCommand::GetAll { key }))
+            .await?;
+        return Ok(bulk_pairs(reply)?.into_iter().collect());
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from(":9223372036854775807\r\n")),
-            Some(Frame::Integer(9223372036854775807)),
-        );
+    /// Send an "HINCRBY key field increment" command to the server. Return the
+    /// value after the increment.
+    pub async fn hincrby(&mut self, key: &str, field: &str, delta: i64) -> MyResult<i64> {
+        let (key, field) = (str_to_bytes(key), str_to_bytes(field));
+        let cmd = Command::Hash(HashCommand::IncrBy { key, field, delta });
+        return self.request_integer(cmd).await;
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from(":-9223372036854775808\r\n")),
-            Some(Frame::Integer(-9223372036854775808)),
-        );
+    /// Send an "HINCRBYFLOAT key field increment" command to the server.
+    /// Return the value after the increment.
+    pub async fn hincrbyfloat(&mut self, key: &str, field: &str, delta: f64) -> MyResult<f64> {
+        let (key, field) = (str_to_bytes(key), str_to_bytes(field));
+        let cmd = Command::Hash(HashCommand::IncrByFloat { key, field, delta });
+        return match self.request(cmd).await? {
+            Frame::Bulk(val) => {
+                command::parse_float(&val).ok_or_else(|| format!("unexpected value {val:?}").into())
+            }
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
+    }
 
-        assert_eq!(
-            Frame::parse(&mut Bytes::from(":9223372036854775808\r\n")),
-            None,
-        );
+    /// Send an "HRANDFIELD key count" command to the server. A positive count
+    /// returns distinct fields, a negative one may repeat fields.
+    pub async fn hrandfield(&mut self, key: &str, count: i64) -> MyResult<Vec<Bytes>> {
+        let cmd = Command::Hash(HashCommand::RandField {
+            key: str_to_bytes(key),
+            count: Some(count),
+            with_values: false,
+        });
+        return bulks(self.request(cmd).await?);
     }
 
-    #[test]
-    fn test_bulk_string_deserialization() {
-        // Empty bulk string
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("$0\r\n\r\n")),
-            Some(Frame::Bulk(Bytes::new())),
-        );
+    /// Send an "HRANDFIELD key count WITHVALUES" command to the server
+    pub async fn hrandfield_with_values(
+        &mut self,
+        key: &str,
+        count: i64,
+    ) -> MyResult<Vec<(Bytes, Bytes)>> {
+        let cmd = Command::Hash(HashCommand::RandField {
+            key: str_to_bytes(key),
+            count: Some(count),
+            with_values: true,
+        });
+        return bulk_pairs(self.request(cmd).await?);
+    }
 
-        // Non-empty bulk string
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("$36\r\n那么古尔丹，代价是什么呢\r\n")),
-            Some(Frame::Bulk(Bytes::from("那么古尔丹，代价是什么呢")))
-        );
+    /// Send an "HSCAN key cursor [MATCH pattern] [COUNT count]" command to the
+    /// server. Return the cursor to continue from, 0 once the scan is over,
+    /// and the fields found along with their values.
+    pub async fn hscan(
+        &mut self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> MyResult<(u64, HashMap<Bytes, Bytes>)> {
+        let cmd = Command::Hash(HashCommand::Scan {
+            key: str_to_bytes(key),
+            cursor,
+            pattern: pattern.map(str_to_bytes),
+            count: count.unwrap_or(command::DEFAULT_SCAN_COUNT),
+            no_values: false,
+        });
+        let Frame::Array(reply) = self.request(cmd).await? else {
+            return Err("unexpected response to HSCAN".into());
+        };
+        return match <[Frame; 2]>::try_from(reply) {
+            Ok([Frame::Bulk(cursor), pairs]) => {
+                let cursor = std::str::from_utf8(&cursor)?.parse()?;
+                Ok((cursor, bulk_pairs(pairs)?.into_iter().collect()))
+            }
+            _ => Err("unexpected response to HSCAN".into()),
+        };
+    }
 
-        // Binary unsafe string
-        assert_eq!(
-            Frame::parse(&mut Bytes::from(b"$2\r\n\r\n\r\n".to_vec())),
-            Some(Frame::Bulk(Bytes::from(b"\r\n".to_vec())))
-        );
+    /// Send an "HPEXPIRE key milliseconds FIELDS numfields field [field ...]"
+    /// command to the server, so that the fields expire after `ttl`. Return
+    /// for each field 1 if its expiry was set, 2 if it was deleted right away,
+    /// and -2 if it does not exist.
+    pub async fn hexpire(
+        &mut self,
+        key: &str,
+        ttl: Duration,
+        fields: &[&str],
+    ) -> MyResult<Vec<i64>> {
+        let cmd = Command::Hash(HashCommand::Expire {
+            key: str_to_bytes(key),
+            expiry: Expiry::Px(ttl.as_millis().try_into()?),
+            condition: None,
+            fields: fields.iter().map(|field| str_to_bytes(field)).collect(),
+        });
+        return integers(self.request(cmd).await?);
+    }
 
-        // Incomplete
-        assert_eq!(Frame::parse(&mut Bytes::from("$10\r\n0123456789")), None);
+    /// Send an "HPTTL key FIELDS numfields field [field ...]" command to the
+    /// server. Return the remaining time to live of each field, None for the
+    /// fields that do not exist or never expire.
+    pub async fn httl(&mut self, key: &str, fields: &[&str]) -> MyResult<Vec<Option<Duration>>> {
+        let cmd = Command::Hash(HashCommand::Pttl {
+            key: str_to_bytes(key),
+            fields: fields.iter().map(|field| str_to_bytes(field)).collect(),
+        });
+        let ttls = integers(self.request(cmd).await?)?;
+        return Ok(ttls
+            .into_iter()
+            .map(|ms| u64::try_from(ms).ok().map(Duration::from_millis))
+            .collect());
+    }
 
-        // Inconsistent number
-        assert_eq!(Frame::parse(&mut Bytes::from("$10\r\n0123456\r\n")), None,);
+    /// Send an "HPERSIST key FIELDS numfields field [field ...]" command to
+    /// the server. Return for each field 1 if its expiry was removed, -1 if it
+    /// had none, and -2 if it does not exist.
+    pub async fn hpersist(&mut self, key: &str, fields: &[&str]) -> MyResult<Vec<i64>> {
+        let cmd = Command::Hash(HashCommand::Persist {
+            key: str_to_bytes(key),
+            fields: fields.iter().map(|field| str_to_bytes(field)).collect(),
+        });
+        return integers(self.request(cmd).await?);
+    }
 
-        // Noise at the end
-        assert_eq!(
-            Frame::parse(&mut Bytes::from("$10\r\n0123456789\r\nxxxxxx")),
-            Some(Frame::Bulk(Bytes::from("0123456789")))
-        );
+    /// Send an "HGETEX key [PX milliseconds] FIELDS numfields field
+    /// [field ...]" command to the server. Return the value of each field,
+    /// and make the fields that exist expire after `ttl` if given.
+    pub async fn hgetex(
+        &mut self,
+        key: &str,
+        ttl: Option<Duration>,
+        fields: &[&str],
+    ) -> MyResult<Vec<Option<Bytes>>> {
+        let expiry = match ttl {
+            Some(ttl) => Some(Expiry::Px(ttl.as_millis().try_into()?)),
+            None => None,
+        };
+        let cmd = Command::Hash(HashCommand::GetEx {
+            key: str_to_bytes(key),
+            expiry,
+            persist: false,
+            fields: fields.iter().map(|field| str_to_bytes(field)).collect(),
+        });
+        let Frame::Array(vals) = self.request(cmd).await? else {
+            return Err("unexpected response to HGETEX".into());
+        };
+        return vals.into_iter().map(optional_bulk).collect();
     }
 
-    #[test]
-    fn test_null_frame_deserialization() {
-        assert_eq!(Frame::parse(&mut Bytes::from("$-1\r\n")), Some(Frame::Null));
+    /// Send an "SADD key member [member ...]" command to the server. Return
+    /// the number of members that were added rather than already there.
+    pub async fn sadd(&mut self, key: &str, members: &[&str]) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        let members = members.iter().map(|member| str_to_bytes(member)).collect();
+        return self
+            .request_integer(Command::Set(SetCommand::Add { key, members }))
+            .await;
     }
 
-    #[test]
+    /// Send an "SREM key member [member ...]" command to the server. Return
+    /// the number of members that were removed.
+    pub async fn srem(&mut self, key: &str, members: &[&str]) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        let members = members.iter().map(|member| str_to_bytes(member)).collect();
+        return self
+            .request_integer(Command::Set(SetCommand::Rem { key, members }))
+            .await;
+    }
+
+    /// Send an "SISMEMBER key member" command to the server
+    pub async fn sismember(&mut self, key: &str, member: &str) -> MyResult<bool> {
+        let (key, member) = (str_to_bytes(key), str_to_bytes(member));
+        let cmd = Command::Set(SetCommand::IsMember { key, member });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
+
+    /// Send an "SMISMEMBER key member [member ...]" command to the server.
+    /// Return whether each member is in the set.
+    pub async fn smismember(&mut self, key: &str, members: &[&str]) -> MyResult<Vec<bool>> {
+        let key = str_to_bytes(key);
+        let members = members.iter().map(|member| str_to_bytes(member)).collect();
+        let reply = self
+            .request(Command::Set(SetCommand::MIsMember { key, members }))
+            .await?;
+        return Ok(integers(reply)?.into_iter().map(|n| n == 1).collect());
+    }
+
+    /// Send an "SCARD key" command to the server
+    pub async fn scard(&mut self, key: &str) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        return self
+            .request_integer(Command::Set(SetCommand::Card { key }))
+            .await;
+    }
+
+    /// Send an "SMEMBERS key" command to the server
+    pub async fn smembers(&mut self, key: &str) -> MyResult<HashSet<Bytes>> {
+        let key = str_to_bytes(key);
+        let reply = self
+            .request(Command::Set(SetCommand::Members { key }))
+            .await?;
+        return Ok(bulks(reply)?.into_iter().collect());
+    }
+
+    /// Send an "SPOP key" command to the server. Return the member that was
+    /// removed, None if the set does not exist.
+    pub async fn spop(&mut self, key: &str) -> MyResult<Option<Bytes>> {
+        let key = str_to_bytes(key);
+        let cmd = Command::Set(SetCommand::Pop { key, count: None });
+        return optional_bulk(self.request(cmd).await?);
+    }
+
+    /// Send an "SPOP key count" command to the server. Return the members
+    /// that were removed.
+    pub async fn spop_count(&mut self, key: &str, count: usize) -> MyResult<Vec<Bytes>> {
+        let key = str_to_bytes(key);
+        let cmd = Command::Set(SetCommand::Pop {
+            key,
+            count: Some(count),
+        });
+        return bulks(self.request(cmd).await?);
+    }
+
+    /// Send an "SRANDMEMBER key count" command to the server. A positive
+    /// count returns distinct members, a negative one may repeat members.
+    pub async fn srandmember(&mut self, key: &str, count: i64) -> MyResult<Vec<Bytes>> {
+        let key = str_to_bytes(key);
+        let cmd = Command::Set(SetCommand::RandMember {
+            key,
+            count: Some(count),
+        });
+        return bulks(self.request(cmd).await?);
+    }
+
+    /// Send an "SMOVE source destination member" command to the server.
+    /// Return whether the member was moved, which it is not if it is not in
+    /// the source.
+    pub async fn smove(&mut self, source: &str, destination: &str, member: &str) -> MyResult<bool> {
+        let cmd = Command::Set(SetCommand::Move {
+            source: str_to_bytes(source),
+            destination: str_to_bytes(destination),
+            member: str_to_bytes(member),
+        });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
+
+    /// Send an "SINTER key [key ...]" command to the server
+    pub async fn sinter(&mut self, keys: &[&str]) -> MyResult<HashSet<Bytes>> {
+        return self.combine(SetOp::Inter, keys).await;
+    }
+
+    /// Send an "SUNION key [key ...]" command to the server
+    pub async fn sunion(&mut self, keys: &[&str]) -> MyResult<HashSet<Bytes>> {
+        return self.combine(SetOp::Union, keys).await;
+    }
+
+    /// Send an "SDIFF key [key ...]" command to the server. Return the members
+    /// of the first set that are in none of the others.
+    pub async fn sdiff(&mut self, keys: &[&str]) -> MyResult<HashSet<Bytes>> {
+        return self.combine(SetOp::Diff, keys).await;
+    }
+
+    async fn combine(&mut self, op: SetOp, keys: &[&str]) -> MyResult<HashSet<Bytes>> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        let reply = self
+            .request(Command::Set(SetCommand::Combine { op, keys }))
+            .await?;
+        return Ok(bulks(reply)?.into_iter().collect());
+    }
+
+    /// Send an "SINTERSTORE destination key [key ...]" command to the server.
+    /// Return the size of the stored set.
+    pub async fn sinterstore(&mut self, destination: &str, keys: &[&str]) -> MyResult<i64> {
+        return self.store(SetOp::Inter, destination, keys).await;
+    }
+
+    /// Send an "SUNIONSTORE destination key [key ...]" command to the server.
+    /// Return the size of the stored set.
+    pub async fn sunionstore(&mut self, destination: &str, keys: &[&str]) -> MyResult<i64> {
+        return self.store(SetOp::Union, destination, keys).await;
+    }
+
+    /// Send an "SDIFFSTORE destination key [key ...]" command to the server.
+    /// Return the size of the stored set.
+    pub async fn sdiffstore(&mut self, destination: &str, keys: &[&str]) -> MyResult<i64> {
+        return self.store(SetOp::Diff, destination, keys).await;
+    }
+
+    async fn store(&mut self, op: SetOp, destination: &str, keys: &[&str]) -> MyResult<i64> {
+        let cmd = Command::Set(SetCommand::Store {
+            op,
+            destination: str_to_bytes(destination),
+            keys: keys.iter().map(|key| str_to_bytes(key)).collect(),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send an "SINTERCARD numkeys key [key ...] LIMIT limit" command to the
+    /// server. Return the size of the intersection, counting no further than
+    /// `limit` unless it is 0.
+    pub async fn sintercard(&mut self, keys: &[&str], limit: usize) -> MyResult<i64> {
+        let keys = keys.iter().map(|key| str_to_bytes(key)).collect();
+        return self
+            .request_integer(Command::Set(SetCommand::InterCard { keys, limit }))
+            .await;
+    }
+
+    /// Send an "SSCAN key cursor [MATCH pattern] [COUNT count]" command to the
+    /// server. Return the cursor to continue from, 0 once the scan is over,
+    /// and the members found.
+    pub async fn sscan(
+        &mut self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> MyResult<(u64, Vec<Bytes>)> {
+        let cmd = Command::Set(SetCommand::Scan {
+            key: str_to_bytes(key),
+            cursor,
+            pattern: pattern.map(str_to_bytes),
+            count: count.unwrap_or(command::DEFAULT_SCAN_COUNT),
+        });
+        let Frame::Array(reply) = self.request(cmd).await? else {
+            return Err("unexpected response to SSCAN".into());
+        };
+        return match <[Frame; 2]>::try_from(reply) {
+            Ok([Frame::Bulk(cursor), members]) => {
+                let cursor = std::str::from_utf8(&cursor)?.parse()?;
+                Ok((cursor, bulks(members)?))
+            }
+            _ => Err("unexpected response to SSCAN".into()),
+        };
+    }
+
+    /// Send a "ZADD key score member [score member ...]" command to the
+    /// server. Return the number of members that were added rather than
+    /// updated.
+    pub async fn zadd(&mut self, key: &str, pairs: &[(f64, &str)]) -> MyResult<i64> {
+        let cmd = Command::SortedSet(SortedSetCommand::Add {
+            key: str_to_bytes(key),
+            condition: None,
+            comparison: None,
+            changed: false,
+            incr: false,
+            pairs: pairs
+                .iter()
+                .map(|(score, member)| (*score, str_to_bytes(member)))
+                .collect(),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "ZINCRBY key increment member" command to the server. Return
+    /// the new score.
+    pub async fn zincrby(&mut self, key: &str, delta: f64, member: &str) -> MyResult<f64> {
+        let (key, member) = (str_to_bytes(key), str_to_bytes(member));
+        let cmd = Command::SortedSet(SortedSetCommand::IncrBy { key, delta, member });
+        return optional_double(self.request(cmd).await?)?
+            .ok_or_else(|| "unexpected response to ZINCRBY".into());
+    }
+
+    /// Send a "ZREM key member [member ...]" command to the server. Return the
+    /// number of members that were removed.
+    pub async fn zrem(&mut self, key: &str, members: &[&str]) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        let members = members.iter().map(|member| str_to_bytes(member)).collect();
+        return self
+            .request_integer(Command::SortedSet(SortedSetCommand::Rem { key, members }))
+            .await;
+    }
+
+    /// Send a "ZSCORE key member" command to the server
+    pub async fn zscore(&mut self, key: &str, member: &str) -> MyResult<Option<f64>> {
+        let (key, member) = (str_to_bytes(key), str_to_bytes(member));
+        let cmd = Command::SortedSet(SortedSetCommand::Score { key, member });
+        return optional_double(self.request(cmd).await?);
+    }
+
+    /// Send a "ZCARD key" command to the server
+    pub async fn zcard(&mut self, key: &str) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        return self
+            .request_integer(Command::SortedSet(SortedSetCommand::Card { key }))
+            .await;
+    }
+
+    /// Send a "ZCOUNT key min max" command to the server
+    pub async fn zcount(&mut self, key: &str, min: ScoreBound, max: ScoreBound) -> MyResult<i64> {
+        let key = str_to_bytes(key);
+        return self
+            .request_integer(Command::SortedSet(SortedSetCommand::Count {
+                key,
+                min,
+                max,
+            }))
+            .await;
+    }
+
+    /// Send a "ZRANK key member" command to the server. Return the position
+    /// of the member from the lowest score, None if it is not in the sorted
+    /// set.
+    pub async fn zrank(&mut self, key: &str, member: &str) -> MyResult<Option<i64>> {
+        return self.rank(key, member, false).await;
+    }
+
+    /// Send a "ZREVRANK key member" command to the server. Return the position
+    /// of the member from the highest score, None if it is not in the sorted
+    /// set.
+    pub async fn zrevrank(&mut self, key: &str, member: &str) -> MyResult<Option<i64>> {
+        return self.rank(key, member, true).await;
+    }
+
+    async fn rank(&mut self, key: &str, member: &str, rev: bool) -> MyResult<Option<i64>> {
+        let cmd = Command::SortedSet(SortedSetCommand::Rank {
+            key: str_to_bytes(key),
+            member: str_to_bytes(member),
+            rev,
+            with_score: false,
+        });
+        return match self.request(cmd).await? {
+            Frame::Integer(rank) => Ok(Some(rank)),
+            Frame::Null => Ok(None),
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
+    }
+
+    /// Send a "ZRANGE key start stop" command to the server. Return the
+    /// members between two positions, either of which may count from the end.
+    pub async fn zrange(&mut self, key: &str, start: i64, stop: i64) -> MyResult<Vec<Bytes>> {
+        let range = Range::Rank { start, stop };
+        return bulks(self.zrange_request(key, range, None, false).await?);
+    }
+
+    /// Send a "ZRANGE key start stop WITHSCORES" command to the server
+    pub async fn zrange_with_scores(
+        &mut self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> MyResult<Vec<(Bytes, f64)>> {
+        let range = Range::Rank { start, stop };
+        return scored_members(self.zrange_request(key, range, None, true).await?);
+    }
+
+    /// Send a "ZRANGE key min max BYSCORE [LIMIT offset count]" command to the
+    /// server
+    pub async fn zrangebyscore(
+        &mut self,
+        key: &str,
+        min: ScoreBound,
+        max: ScoreBound,
+        limit: Option<Limit>,
+    ) -> MyResult<Vec<Bytes>> {
+        let range = Range::Score { min, max };
+        return bulks(self.zrange_request(key, range, limit, false).await?);
+    }
+
+    async fn zrange_request(
+        &mut self,
+        key: &str,
+        range: Range,
+        limit: Option<Limit>,
+        with_scores: bool,
+    ) -> MyResult<Frame> {
+        let cmd = Command::SortedSet(SortedSetCommand::Range {
+            key: str_to_bytes(key),
+            range,
+            rev: false,
+            limit,
+            with_scores,
+        });
+        return self.request(cmd).await;
+    }
+
+    /// Send a "ZPOPMIN key count" command to the server. Return the members
+    /// that were removed along with their scores, lowest first.
+    pub async fn zpopmin(&mut self, key: &str, count: usize) -> MyResult<Vec<(Bytes, f64)>> {
+        return self.zpop(key, End::Min, count).await;
+    }
+
+    /// Send a "ZPOPMAX key count" command to the server. Return the members
+    /// that were removed along with their scores, highest first.
+    pub async fn zpopmax(&mut self, key: &str, count: usize) -> MyResult<Vec<(Bytes, f64)>> {
+        return self.zpop(key, End::Max, count).await;
+    }
+
+    async fn zpop(&mut self, key: &str, end: End, count: usize) -> MyResult<Vec<(Bytes, f64)>> {
+        let cmd = Command::SortedSet(SortedSetCommand::Pop {
+            key: str_to_bytes(key),
+            end,
+            count: Some(count),
+        });
+        return scored_members(self.request(cmd).await?);
+    }
+
+    /// Send a "BZPOPMIN key [key ...] timeout" command to the server. Return
+    /// the key the member was popped from along with the member and its
+    /// score, or None if the timeout ran out first. A timeout of None waits
+    /// forever.
+    pub async fn bzpopmin(
+        &mut self,
+        keys: &[&str],
+        timeout: Option<Duration>,
+    ) -> MyResult<Option<(Bytes, Bytes, f64)>> {
+        let cmd = Command::SortedSet(SortedSetCommand::BPop {
+            keys: keys.iter().map(|key| str_to_bytes(key)).collect(),
+            end: End::Min,
+            timeout,
+        });
+        return match self.request(cmd).await? {
+            Frame::Array(reply) => match <[Frame; 3]>::try_from(reply) {
+                Ok([Frame::Bulk(key), Frame::Bulk(member), score]) => {
+                    let score = optional_double(score)?.ok_or("unexpected score")?;
+                    Ok(Some((key, member, score)))
+                }
+                _ => Err("unexpected response to BZPOPMIN".into()),
+            },
+            Frame::Null => Ok(None),
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
+    }
+
+    /// Send a "ZUNIONSTORE destination numkeys key [key ...] WEIGHTS weight
+    /// [weight ...] AGGREGATE SUM|MIN|MAX" command to the server. Without
+    /// weights, every key has a weight of 1. Return the size of the stored
+    /// sorted set.
+    pub async fn zunionstore(
+        &mut self,
+        destination: &str,
+        keys: &[&str],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> MyResult<i64> {
+        return self
+            .zstore(StoreOp::Union, destination, keys, weights, aggregate)
+            .await;
+    }
+
+    /// Send a "ZINTERSTORE destination numkeys key [key ...] WEIGHTS weight
+    /// [weight ...] AGGREGATE SUM|MIN|MAX" command to the server. Without
+    /// weights, every key has a weight of 1. Return the size of the stored
+    /// sorted set.
+    pub async fn zinterstore(
+        &mut self,
+        destination: &str,
+        keys: &[&str],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> MyResult<i64> {
+        return self
+            .zstore(StoreOp::Inter, destination, keys, weights, aggregate)
+            .await;
+    }
+
+    async fn zstore(
+        &mut self,
+        op: StoreOp,
+        destination: &str,
+        keys: &[&str],
+        weights: Option<&[f64]>,
+        aggregate: Aggregate,
+    ) -> MyResult<i64> {
+        let cmd = Command::SortedSet(SortedSetCommand::Store {
+            op,
+            destination: str_to_bytes(destination),
+            keys: keys.iter().map(|key| str_to_bytes(key)).collect(),
+            weights: weights.map_or(vec![1.0; keys.len()], <[f64]>::to_vec),
+            aggregate,
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "ZSCAN key cursor [MATCH pattern] [COUNT count]" command to the
+    /// server. Return the cursor to continue from, 0 once the scan is over,
+    /// and the members found along with their scores.
+    pub async fn zscan(
+        &mut self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> MyResult<(u64, Vec<(Bytes, f64)>)> {
+        let cmd = Command::SortedSet(SortedSetCommand::Scan {
+            key: str_to_bytes(key),
+            cursor,
+            pattern: pattern.map(str_to_bytes),
+            count: count.unwrap_or(command::DEFAULT_SCAN_COUNT),
+        });
+        let Frame::Array(reply) = self.request(cmd).await? else {
+            return Err("unexpected response to ZSCAN".into());
+        };
+        return match <[Frame; 2]>::try_from(reply) {
+            Ok([Frame::Bulk(cursor), members]) => {
+                let cursor = std::str::from_utf8(&cursor)?.parse()?;
+                Ok((cursor, scored_members(members)?))
+            }
+            _ => Err("unexpected response to ZSCAN".into()),
+        };
+    }
+
+    /// Send a "SETBIT key offset value" command to the server. Return the
+    /// previous value of the bit.
+    pub async fn setbit(&mut self, key: &str, offset: u64, value: bool) -> MyResult<bool> {
+        let cmd = Command::Bitmap(BitmapCommand::SetBit {
+            key: str_to_bytes(key),
+            offset,
+            value,
+        });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
+
+    /// Send a "GETBIT key offset" command to the server
+    pub async fn getbit(&mut self, key: &str, offset: u64) -> MyResult<bool> {
+        let cmd = Command::Bitmap(BitmapCommand::GetBit {
+            key: str_to_bytes(key),
+            offset,
+        });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
+
+    /// Send a "BITCOUNT key [start end [BYTE|BIT]]" command to the server.
+    /// The range must have an end.
+    pub async fn bitcount(&mut self, key: &str, range: Option<BitRange>) -> MyResult<i64> {
+        let cmd = Command::Bitmap(BitmapCommand::Count {
+            key: str_to_bytes(key),
+            range,
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "BITPOS key bit [start [end [BYTE|BIT]]]" command to the server.
+    /// Return the position of the first bit set to `bit`, or -1 if there is
+    /// none.
+    pub async fn bitpos(&mut self, key: &str, bit: bool, range: Option<BitRange>) -> MyResult<i64> {
+        let cmd = Command::Bitmap(BitmapCommand::Pos {
+            key: str_to_bytes(key),
+            bit,
+            range,
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "BITOP operation destination key [key ...]" command to the
+    /// server. Return the length of the string stored under the destination.
+    pub async fn bitop(&mut self, op: BitOp, destination: &str, keys: &[&str]) -> MyResult<i64> {
+        let cmd = Command::Bitmap(BitmapCommand::Op {
+            op,
+            destination: str_to_bytes(destination),
+            keys: keys.iter().map(|key| str_to_bytes(key)).collect(),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "BITFIELD key [GET | SET | INCRBY | OVERFLOW ...]" command to
+    /// the server. Return a reply for every operation but OVERFLOW, which is
+    /// None when a SET or INCRBY fails on an overflow.
+    pub async fn bitfield(&mut self, key: &str, ops: &[BitFieldOp]) -> MyResult<Vec<Option<i64>>> {
+        let cmd = Command::Bitmap(BitmapCommand::Field {
+            key: str_to_bytes(key),
+            ops: ops.to_vec(),
+            readonly: false,
+        });
+        let Frame::Array(replies) = self.request(cmd).await? else {
+            return Err("unexpected response to BITFIELD".into());
+        };
+        return replies
+            .into_iter()
+            .map(|reply| match reply {
+                Frame::Integer(n) => Ok(Some(n)),
+                Frame::Null => Ok(None),
+                reply => Err(format!("unexpected value {reply:?}").into()),
+            })
+            .collect();
+    }
+
+    /// Send a "GEOADD key longitude latitude member [...]" command to the
+    /// server. Return the number of members added.
+    pub async fn geoadd(&mut self, key: &str, items: &[(f64, f64, &str)]) -> MyResult<i64> {
+        let cmd = Command::Geo(GeoCommand::Add {
+            key: str_to_bytes(key),
+            condition: None,
+            changed: false,
+            items: items
+                .iter()
+                .map(|(longitude, latitude, member)| {
+                    let coordinates = Coordinates {
+                        longitude: *longitude,
+                        latitude: *latitude,
+                    };
+                    return (coordinates, str_to_bytes(member));
+                })
+                .collect(),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "GEOPOS key [member ...]" command to the server. Return the
+    /// coordinates of each member, or None for missing ones.
+    pub async fn geopos(
+        &mut self,
+        key: &str,
+        members: &[&str],
+    ) -> MyResult<Vec<Option<Coordinates>>> {
+        let cmd = Command::Geo(GeoCommand::Pos {
+            key: str_to_bytes(key),
+            members: members.iter().map(|member| str_to_bytes(member)).collect(),
+        });
+        let Frame::Array(positions) = self.request(cmd).await? else {
+            return Err("unexpected response to GEOPOS".into());
+        };
+        return positions
+            .into_iter()
+            .map(|position| match position {
+                Frame::Null => Ok(None),
+                position => Ok(Some(coordinates(position)?)),
+            })
+            .collect();
+    }
+
+    /// Send a "GEODIST key member1 member2 unit" command to the server.
+    /// Return None if either member is missing.
+    pub async fn geodist(
+        &mut self,
+        key: &str,
+        member1: &str,
+        member2: &str,
+        unit: DistanceUnit,
+    ) -> MyResult<Option<f64>> {
+        let cmd = Command::Geo(GeoCommand::Dist {
+            key: str_to_bytes(key),
+            member1: str_to_bytes(member1),
+            member2: str_to_bytes(member2),
+            unit,
+        });
+        return optional_double(self.request(cmd).await?);
+    }
+
+    /// Send a "GEOHASH key [member ...]" command to the server. Return the
+    /// geohash of each member, or None for missing ones.
+    pub async fn geohash(&mut self, key: &str, members: &[&str]) -> MyResult<Vec<Option<Bytes>>> {
+        let cmd = Command::Geo(GeoCommand::Hash {
+            key: str_to_bytes(key),
+            members: members.iter().map(|member| str_to_bytes(member)).collect(),
+        });
+        let Frame::Array(hashes) = self.request(cmd).await? else {
+            return Err("unexpected response to GEOHASH".into());
+        };
+        return hashes.into_iter().map(optional_bulk).collect();
+    }
+
+    /// Send a "GEOSEARCH key ..." command to the server. Return the members
+    /// found, with what the search asked for.
+    pub async fn geosearch(&mut self, key: &str, search: GeoSearch) -> MyResult<Vec<GeoMatch>> {
+        let (with_dist, with_hash, with_coord) =
+            (search.with_dist, search.with_hash, search.with_coord);
+        let cmd = Command::Geo(GeoCommand::Search {
+            key: str_to_bytes(key),
+            search,
+        });
+        let Frame::Array(found) = self.request(cmd).await? else {
+            return Err("unexpected response to GEOSEARCH".into());
+        };
+        return found
+            .into_iter()
+            .map(|found| {
+                let mut fields = match found {
+                    Frame::Array(fields) => fields.into_iter(),
+                    member => vec![member].into_iter(),
+                };
+                let Some(Frame::Bulk(member)) = fields.next() else {
+                    return Err("unexpected member in response".into());
+                };
+                let mut geo_match = GeoMatch {
+                    member,
+                    distance: None,
+                    hash: None,
+                    coordinates: None,
+                };
+                if with_dist {
+                    geo_match.distance = optional_double(fields.next().unwrap_or(Frame::Null))?;
+                }
+                if with_hash {
+                    geo_match.hash = match fields.next() {
+                        Some(Frame::Integer(hash)) => Some(hash),
+                        _ => return Err("unexpected hash in response".into()),
+                    };
+                }
+                if with_coord {
+                    geo_match.coordinates =
+                        Some(coordinates(fields.next().unwrap_or(Frame::Null))?);
+                }
+                return Ok(geo_match);
+            })
+            .collect();
+    }
+
+    /// Send a "PFADD key element [element ...]" command to the server. Return
+    /// whether the estimated cardinality may have changed.
+    pub async fn pfadd(&mut self, key: &str, elements: &[&str]) -> MyResult<bool> {
+        let cmd = Command::HyperLogLog(HyperLogLogCommand::Add {
+            key: str_to_bytes(key),
+            elements: elements.iter().map(|elem| str_to_bytes(elem)).collect(),
+        });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
+
+    /// Send a "PFCOUNT key [key ...]" command to the server. Return the
+    /// estimated number of distinct elements added to any of the keys.
+    pub async fn pfcount(&mut self, keys: &[&str]) -> MyResult<i64> {
+        let cmd = Command::HyperLogLog(HyperLogLogCommand::Count {
+            keys: keys.iter().map(|key| str_to_bytes(key)).collect(),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "PFMERGE destination [source ...]" command to the server
+    pub async fn pfmerge(&mut self, destination: &str, sources: &[&str]) -> MyResult<()> {
+        let cmd = Command::HyperLogLog(HyperLogLogCommand::Merge {
+            destination: str_to_bytes(destination),
+            sources: sources.iter().map(|key| str_to_bytes(key)).collect(),
+        });
+        return match self.request(cmd).await? {
+            Frame::Simple(s) if s == "OK" => Ok(()),
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
+    }
+
+    /// Send an "XADD key id field value [field value ...]" command to the
+    /// server, where an ID of None lets the server generate one. Return the ID
+    /// of the new entry.
+    pub async fn xadd(
+        &mut self,
+        key: &str,
+        id: Option<StreamId>,
+        fields: &[(&str, &str)],
+    ) -> MyResult<StreamId> {
+        let cmd = Command::Stream(StreamCommand::Add {
+            key: str_to_bytes(key),
+            id: id.map_or(NewId::Auto, NewId::Explicit),
+            no_mkstream: false,
+            trim: None,
+            fields: fields
+                .iter()
+                .map(|(field, val)| (str_to_bytes(field), str_to_bytes(val)))
+                .collect(),
+        });
+        return stream_id(self.request(cmd).await?);
+    }
+
+    /// Send an "XLEN key" command to the server. Return the number of entries
+    /// in the stream.
+    pub async fn xlen(&mut self, key: &str) -> MyResult<i64> {
+        let cmd = Command::Stream(StreamCommand::Len {
+            key: str_to_bytes(key),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send an "XRANGE key start end [COUNT count]" command to the server.
+    /// Return the entries with IDs between start and end, inclusive.
+    pub async fn xrange(
+        &mut self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> MyResult<Vec<StreamEntry>> {
+        return self.xrange_request(key, start, end, count, false).await;
+    }
+
+    /// Send an "XREVRANGE key end start [COUNT count]" command to the server.
+    /// Return the entries with IDs between start and end, inclusive, last
+    /// first.
+    pub async fn xrevrange(
+        &mut self,
+        key: &str,
+        end: StreamId,
+        start: StreamId,
+        count: Option<usize>,
+    ) -> MyResult<Vec<StreamEntry>> {
+        return self.xrange_request(key, start, end, count, true).await;
+    }
+
+    async fn xrange_request(
+        &mut self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> MyResult<Vec<StreamEntry>> {
+        let cmd = Command::Stream(StreamCommand::Range {
+            key: str_to_bytes(key),
+            start,
+            end,
+            count,
+            rev,
+        });
+        return stream_entries(self.request(cmd).await?);
+    }
+
+    /// Send an "XDEL key id [id ...]" command to the server. Return the number
+    /// of entries removed.
+    pub async fn xdel(&mut self, key: &str, ids: &[StreamId]) -> MyResult<i64> {
+        let cmd = Command::Stream(StreamCommand::Del {
+            key: str_to_bytes(key),
+            ids: ids.to_vec(),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send an "XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]" command
+    /// to the server. Return the number of entries removed.
+    pub async fn xtrim(&mut self, key: &str, trim: Trim) -> MyResult<i64> {
+        let cmd = Command::Stream(StreamCommand::Trim {
+            key: str_to_bytes(key),
+            trim,
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send an "XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...]
+    /// id [id ...]" command to the server, reading each stream after its ID.
+    /// Return the entries read from each stream that had any. With a block
+    /// time, wait that long for entries if there are none yet, where zero
+    /// waits forever.
+    pub async fn xread(
+        &mut self,
+        streams: &[(&str, ReadId)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> MyResult<Vec<(Bytes, Vec<StreamEntry>)>> {
+        let cmd = Command::Stream(StreamCommand::Read {
+            keys: streams.iter().map(|(key, _)| str_to_bytes(key)).collect(),
+            ids: streams.iter().map(|(_, id)| *id).collect(),
+            count,
+            block,
+        });
+        return keyed_stream_entries(self.request(cmd).await?);
+    }
+
+    /// Send an "XGROUP CREATE key group id [MKSTREAM]" command to the server
+    pub async fn xgroup_create(
+        &mut self,
+        key: &str,
+        group: &str,
+        id: ReadId,
+        mkstream: bool,
+    ) -> MyResult<()> {
+        let cmd = Command::Stream(StreamCommand::GroupCreate {
+            key: str_to_bytes(key),
+            group: str_to_bytes(group),
+            id,
+            mkstream,
+            entries_read: None,
+        });
+        return match self.request(cmd).await? {
+            Frame::Simple(s) if s == "OK" => Ok(()),
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
+    }
+
+    /// Send an "XGROUP DESTROY key group" command to the server. Return
+    /// whether the group existed.
+    pub async fn xgroup_destroy(&mut self, key: &str, group: &str) -> MyResult<bool> {
+        let cmd = Command::Stream(StreamCommand::GroupDestroy {
+            key: str_to_bytes(key),
+            group: str_to_bytes(group),
+        });
+        return Ok(self.request_integer(cmd).await? == 1);
+    }
+
+    /// Send an "XREADGROUP GROUP group consumer [COUNT count] [BLOCK
+    /// milliseconds] [NOACK] STREAMS key [key ...] id [id ...]" command to the
+    /// server. Return the entries read from each stream. Reading after an ID
+    /// rather than ReadId::New returns the entries pending for the consumer,
+    /// where entries removed from the stream since have no fields.
+    pub async fn xreadgroup(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        streams: &[(&str, ReadId)],
+        count: Option<usize>,
+        block: Option<Duration>,
+        no_ack: bool,
+    ) -> MyResult<Vec<(Bytes, Vec<StreamEntry>)>> {
+        let cmd = Command::Stream(StreamCommand::ReadGroup {
+            group: str_to_bytes(group),
+            consumer: str_to_bytes(consumer),
+            keys: streams.iter().map(|(key, _)| str_to_bytes(key)).collect(),
+            ids: streams.iter().map(|(_, id)| *id).collect(),
+            count,
+            block,
+            no_ack,
+        });
+        return keyed_stream_entries(self.request(cmd).await?);
+    }
+
+    /// Send an "XACK key group id [id ...]" command to the server. Return the
+    /// number of entries that were pending.
+    pub async fn xack(&mut self, key: &str, group: &str, ids: &[StreamId]) -> MyResult<i64> {
+        let cmd = Command::Stream(StreamCommand::Ack {
+            key: str_to_bytes(key),
+            group: str_to_bytes(group),
+            ids: ids.to_vec(),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send an "XPENDING key group start end count" command to the server.
+    /// Return the ID, consumer, idle time in milliseconds and delivery count
+    /// of the pending entries with IDs between start and end.
+    pub async fn xpending(
+        &mut self,
+        key: &str,
+        group: &str,
+        start: StreamId,
+        end: StreamId,
+        count: usize,
+    ) -> MyResult<Vec<(StreamId, Bytes, i64, i64)>> {
+        let cmd = Command::Stream(StreamCommand::Pending {
+            key: str_to_bytes(key),
+            group: str_to_bytes(group),
+            range: Some(PendingRange {
+                min_idle: None,
+                start,
+                end,
+                count,
+                consumer: None,
+            }),
+        });
+        let Frame::Array(entries) = self.request(cmd).await? else {
+            return Err("unexpected response to XPENDING".into());
+        };
+        return entries
+            .into_iter()
+            .map(|entry| match entry {
+                Frame::Array(entry) => match <[Frame; 4]>::try_from(entry) {
+                    Ok([id, Frame::Bulk(consumer), Frame::Integer(idle), Frame::Integer(n)]) => {
+                        Ok((stream_id(id)?, consumer, idle, n))
+                    }
+                    _ => Err("unexpected pending entry".into()),
+                },
+                entry => Err(format!("unexpected value {entry:?}").into()),
+            })
+            .collect();
+    }
+
+    /// Send an "XCLAIM key group consumer min-idle-time id [id ...]" command
+    /// to the server. Return the entries claimed for the consumer.
+    pub async fn xclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: Duration,
+        ids: &[StreamId],
+    ) -> MyResult<Vec<StreamEntry>> {
+        let cmd = Command::Stream(StreamCommand::Claim {
+            key: str_to_bytes(key),
+            group: str_to_bytes(group),
+            consumer: str_to_bytes(consumer),
+            min_idle: min_idle.as_millis() as u64,
+            ids: ids.to_vec(),
+            options: ClaimOptions::default(),
+        });
+        return stream_entries(self.request(cmd).await?);
+    }
+
+    /// Send an "XAUTOCLAIM key group consumer min-idle-time start COUNT
+    /// count" command to the server. Return the ID to continue from, 0-0 once
+    /// every pending entry was looked at, the entries claimed for the
+    /// consumer, and the IDs of pending entries removed from the stream.
+    pub async fn xautoclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: Duration,
+        start: StreamId,
+        count: usize,
+    ) -> MyResult<(StreamId, Vec<StreamEntry>, Vec<StreamId>)> {
+        let cmd = Command::Stream(StreamCommand::AutoClaim {
+            key: str_to_bytes(key),
+            group: str_to_bytes(group),
+            consumer: str_to_bytes(consumer),
+            min_idle: min_idle.as_millis() as u64,
+            start,
+            count,
+            just_id: false,
+        });
+        let Frame::Array(reply) = self.request(cmd).await? else {
+            return Err("unexpected response to XAUTOCLAIM".into());
+        };
+        return match <[Frame; 3]>::try_from(reply) {
+            Ok([next, entries, Frame::Array(deleted)]) => Ok((
+                stream_id(next)?,
+                stream_entries(entries)?,
+                deleted
+                    .into_iter()
+                    .map(stream_id)
+                    .collect::<MyResult<_>>()?,
+            )),
+            _ => Err("unexpected response to XAUTOCLAIM".into()),
+        };
+    }
+
+    /// Send a "PUBLISH channel message" command to the server. Return how many
+    /// subscribers received the message.
+    pub async fn publish(&mut self, channel: &str, message: &str) -> MyResult<i64> {
+        let cmd = Command::PubSub(PubSubCommand::Publish {
+            channel: str_to_bytes(channel),
+            message: str_to_bytes(message),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send a "SUBSCRIBE channel [channel ...]" command to the server and turn
+    /// the client into a subscriber, which can only receive messages and
+    /// change its subscriptions
+    pub async fn subscribe(self, channels: &[&str]) -> MyResult<Subscriber> {
+        let mut subscriber = Subscriber::new(self);
+        subscriber.subscribe(channels).await?;
+        return Ok(subscriber);
+    }
+
+    /// Send a "PSUBSCRIBE pattern [pattern ...]" command to the server and
+    /// turn the client into a subscriber, which can only receive messages and
+    /// change its subscriptions
+    pub async fn psubscribe(self, patterns: &[&str]) -> MyResult<Subscriber> {
+        let mut subscriber = Subscriber::new(self);
+        subscriber.psubscribe(patterns).await?;
+        return Ok(subscriber);
+    }
+
+    /// Send a "PUBSUB CHANNELS [pattern]" command to the server. Return the
+    /// channels with subscribers, limited to those matching the pattern.
+    pub async fn pubsub_channels(&mut self, pattern: Option<&str>) -> MyResult<Vec<Bytes>> {
+        let cmd = Command::PubSub(PubSubCommand::Channels {
+            pattern: pattern.map(str_to_bytes),
+        });
+        return bulks(self.request(cmd).await?);
+    }
+
+    /// Send a "PUBSUB NUMSUB [channel [channel ...]]" command to the server.
+    /// Return each channel along with its number of subscribers, leaving out
+    /// pattern subscribers.
+    pub async fn pubsub_numsub(&mut self, channels: &[&str]) -> MyResult<Vec<(Bytes, i64)>> {
+        let cmd = Command::PubSub(PubSubCommand::NumSub {
+            channels: channels
+                .iter()
+                .map(|channel| str_to_bytes(channel))
+                .collect(),
+        });
+        return channel_counts(self.request(cmd).await?);
+    }
+
+    /// Send a "PUBSUB NUMPAT" command to the server. Return the number of
+    /// patterns subscribed to.
+    pub async fn pubsub_numpat(&mut self) -> MyResult<i64> {
+        return self
+            .request_integer(Command::PubSub(PubSubCommand::NumPat))
+            .await;
+    }
+
+    /// Send an "SPUBLISH shardchannel message" command to the server. Return
+    /// how many subscribers received the message.
+    pub async fn spublish(&mut self, channel: &str, message: &str) -> MyResult<i64> {
+        let cmd = Command::PubSub(PubSubCommand::SPublish {
+            channel: str_to_bytes(channel),
+            message: str_to_bytes(message),
+        });
+        return self.request_integer(cmd).await;
+    }
+
+    /// Send an "SSUBSCRIBE shardchannel [shardchannel ...]" command to the
+    /// server and turn the client into a subscriber. The shard channels must
+    /// all hash to the same slot.
+    pub async fn ssubscribe(self, channels: &[&str]) -> MyResult<Subscriber> {
+        let mut subscriber = Subscriber::new(self);
+        subscriber.ssubscribe(channels).await?;
+        return Ok(subscriber);
+    }
+
+    /// Send a "PUBSUB SHARDCHANNELS [pattern]" command to the server. Return
+    /// the shard channels with subscribers, limited to those matching the
+    /// pattern.
+    pub async fn pubsub_shardchannels(&mut self, pattern: Option<&str>) -> MyResult<Vec<Bytes>> {
+        let cmd = Command::PubSub(PubSubCommand::ShardChannels {
+            pattern: pattern.map(str_to_bytes),
+        });
+        return bulks(self.request(cmd).await?);
+    }
+
+    /// Send a "PUBSUB SHARDNUMSUB [shardchannel [shardchannel ...]]" command
+    /// to the server. Return each shard channel along with its number of
+    /// subscribers.
+    pub async fn pubsub_shardnumsub(&mut self, channels: &[&str]) -> MyResult<Vec<(Bytes, i64)>> {
+        let cmd = Command::PubSub(PubSubCommand::ShardNumSub {
+            channels: channels
+                .iter()
+                .map(|channel| str_to_bytes(channel))
+                .collect(),
+        });
+        return channel_counts(self.request(cmd).await?);
+    }
+
+    /// Send a "HELLO protover" command to the server and switch the connection
+    /// over to the negotiated protocol. Return the server's description of
+    /// itself as a list of (field, value) pairs.
+    pub async fn hello(&mut self, protocol: Protocol) -> MyResult<Vec<(Frame, Frame)>> {
+        let cmd = Command::hello(Some(protocol.version()));
+        self.connection.write_frame(&cmd.to_frame()).await?;
+        let resp = self.connection.read_frame().await?;
+        let fields = match resp {
+            Some(Frame::Map(pairs)) => pairs,
+            // RESP2 servers send the map as a flat array of keys and values
+            Some(Frame::Array(elems)) => {
+                let mut elems = elems.into_iter();
+                let mut pairs = vec![];
+                while let (Some(key), Some(val)) = (elems.next(), elems.next()) {
+                    pairs.push((key, val));
+                }
+                pairs
+            }
+            Some(Frame::Error(msg)) => return Err(msg.into()),
+            resp => return Err(format!("unexpected response {resp:?}").into()),
+        };
+        self.connection.set_protocol(protocol);
+        return Ok(fields);
+    }
+
+    /// Send a command and read the reply, turning an Error reply into an error
+    async fn request(&mut self, cmd: Command) -> MyResult<Frame> {
+        self.connection.write_frame(&cmd.to_frame()).await?;
+        return match self.connection.read_frame().await? {
+            Some(Frame::Error(msg)) => Err(msg.into()),
+            Some(frame) => Ok(frame),
+            None => Err("connection closed by server".into()),
+        };
+    }
+
+    /// Send a command whose reply is an Integer
+    async fn request_integer(&mut self, cmd: Command) -> MyResult<i64> {
+        return match self.request(cmd).await? {
+            Frame::Integer(num) => Ok(num),
+            resp => Err(format!("unexpected response {resp:?}").into()),
+        };
+    }
+}
+
+/// A client in subscriber mode, created by Client::subscribe or
+/// Client::psubscribe. It receives the messages published to the channels and
+/// patterns it subscribed to, and can only change its subscriptions.
+pub struct Subscriber {
+    client: Client,
+    channels: Vec<Bytes>,
+    patterns: Vec<Bytes>,
+    shard_channels: Vec<Bytes>,
+    /// Messages received while waiting for the server to confirm a change to
+    /// the subscriptions
+    pending: VecDeque<Message>,
+}
+
+/// A frame pushed to a subscriber
+enum Pushed {
+    Message(Message),
+    /// The confirmation of a change to the subscriptions, such as
+    /// "subscribe", for a channel or pattern. The name is missing when
+    /// unsubscribing from everything while subscribed to nothing.
+    Confirmation {
+        kind: Bytes,
+        name: Option<Bytes>,
+    },
+}
+
+impl Subscriber {
+    fn new(client: Client) -> Self {
+        return Self {
+            client,
+            channels: vec![],
+            patterns: vec![],
+            shard_channels: vec![],
+            pending: VecDeque::new(),
+        };
+    }
+
+    /// The channels subscribed to
+    pub fn channels(&self) -> &[Bytes] {
+        return &self.channels;
+    }
+
+    /// The patterns subscribed to
+    pub fn patterns(&self) -> &[Bytes] {
+        return &self.patterns;
+    }
+
+    /// The shard channels subscribed to
+    pub fn shard_channels(&self) -> &[Bytes] {
+        return &self.shard_channels;
+    }
+
+    /// Wait for the next message published to the subscriptions. Return None
+    /// once the server closed the connection.
+    pub async fn next_message(&mut self) -> MyResult<Option<Message>> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        loop {
+            let frame = match self.client.connection.read_frame().await? {
+                Some(Frame::Error(msg)) => return Err(msg.into()),
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            match pushed(frame)? {
+                Pushed::Message(message) => return Ok(Some(message)),
+                Pushed::Confirmation { kind, name } => self.confirm(&kind, name),
+            }
+        }
+    }
+
+    /// Turn the subscriber into a stream of the messages published to its
+    /// subscriptions, which ends once the server closed the connection
+    pub fn into_stream(mut self) -> impl Stream<Item = MyResult<Message>> {
+        return async_stream::try_stream! {
+            while let Some(message) = self.next_message().await? {
+                yield message;
+            }
+        };
+    }
+
+    /// Send a "SUBSCRIBE channel [channel ...]" command to the server
+    pub async fn subscribe(&mut self, channels: &[&str]) -> MyResult<()> {
+        let channels: Vec<Bytes> = channels
+            .iter()
+            .map(|channel| str_to_bytes(channel))
+            .collect();
+        let confirmations = channels.len();
+        let cmd = PubSubCommand::Subscribe { channels };
+        return self.change(cmd, confirmations).await;
+    }
+
+    /// Send a "PSUBSCRIBE pattern [pattern ...]" command to the server
+    pub async fn psubscribe(&mut self, patterns: &[&str]) -> MyResult<()> {
+        let patterns: Vec<Bytes> = patterns
+            .iter()
+            .map(|pattern| str_to_bytes(pattern))
+            .collect();
+        let confirmations = patterns.len();
+        let cmd = PubSubCommand::PSubscribe { patterns };
+        return self.change(cmd, confirmations).await;
+    }
+
+    /// Send an "UNSUBSCRIBE [channel [channel ...]]" command to the server,
+    /// which unsubscribes from every channel if none are given
+    pub async fn unsubscribe(&mut self, channels: &[&str]) -> MyResult<()> {
+        let channels: Vec<Bytes> = channels
+            .iter()
+            .map(|channel| str_to_bytes(channel))
+            .collect();
+        let confirmations = match channels.is_empty() {
+            true => self.channels.len().max(1),
+            false => channels.len(),
+        };
+        let cmd = PubSubCommand::Unsubscribe { channels };
+        return self.change(cmd, confirmations).await;
+    }
+
+    /// Send a "PUNSUBSCRIBE [pattern [pattern ...]]" command to the server,
+    /// which unsubscribes from every pattern if none are given
+    pub async fn punsubscribe(&mut self, patterns: &[&str]) -> MyResult<()> {
+        let patterns: Vec<Bytes> = patterns
+            .iter()
+            .map(|pattern| str_to_bytes(pattern))
+            .collect();
+        let confirmations = match patterns.is_empty() {
+            true => self.patterns.len().max(1),
+            false => patterns.len(),
+        };
+        let cmd = PubSubCommand::PUnsubscribe { patterns };
+        return self.change(cmd, confirmations).await;
+    }
+
+    /// Send an "SSUBSCRIBE shardchannel [shardchannel ...]" command to the
+    /// server. The shard channels must all hash to the same slot.
+    pub async fn ssubscribe(&mut self, channels: &[&str]) -> MyResult<()> {
+        let channels: Vec<Bytes> = channels
+            .iter()
+            .map(|channel| str_to_bytes(channel))
+            .collect();
+        let confirmations = channels.len();
+        let cmd = PubSubCommand::SSubscribe { channels };
+        return self.change(cmd, confirmations).await;
+    }
+
+    /// Send an "SUNSUBSCRIBE [shardchannel [shardchannel ...]]" command to
+    /// the server, which unsubscribes from every shard channel if none are
+    /// given
+    pub async fn sunsubscribe(&mut self, channels: &[&str]) -> MyResult<()> {
+        let channels: Vec<Bytes> = channels
+            .iter()
+            .map(|channel| str_to_bytes(channel))
+            .collect();
+        let confirmations = match channels.is_empty() {
+            true => self.shard_channels.len().max(1),
+            false => channels.len(),
+        };
+        let cmd = PubSubCommand::SUnsubscribe { channels };
+        return self.change(cmd, confirmations).await;
+    }
+
+    /// Send a command that changes the subscriptions and wait for the given
+    /// number of confirmations, keeping the messages that arrive in the
+    /// meantime for later
+    async fn change(&mut self, cmd: PubSubCommand, confirmations: usize) -> MyResult<()> {
+        let frame = Command::PubSub(cmd).to_frame();
+        self.client.connection.write_frame(&frame).await?;
+        let mut confirmed = 0;
+        while confirmed < confirmations {
+            let frame = match self.client.connection.read_frame().await? {
+                Some(Frame::Error(msg)) => return Err(msg.into()),
+                Some(frame) => frame,
+                None => return Err("connection closed by server".into()),
+            };
+            match pushed(frame)? {
+                Pushed::Message(message) => self.pending.push_back(message),
+                Pushed::Confirmation { kind, name } => {
+                    self.confirm(&kind, name);
+                    confirmed += 1;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Keep track of a confirmed change to the subscriptions
+    fn confirm(&mut self, kind: &[u8], name: Option<Bytes>) {
+        let Some(name) = name else {
+            return;
+        };
+        match kind {
+            b"subscribe" if !self.channels.contains(&name) => self.channels.push(name),
+            b"unsubscribe" => self.channels.retain(|channel| *channel != name),
+            b"psubscribe" if !self.patterns.contains(&name) => self.patterns.push(name),
+            b"punsubscribe" => self.patterns.retain(|pattern| *pattern != name),
+            b"ssubscribe" if !self.shard_channels.contains(&name) => self.shard_channels.push(name),
+            b"sunsubscribe" => self.shard_channels.retain(|channel| *channel != name),
+            _ => {}
+        }
+    }
+}
+
+/// Convert a frame pushed to a subscriber, which is a Push for RESP3 peers
+/// and an Array for RESP2 peers
+fn pushed(frame: Frame) -> MyResult<Pushed> {
+    let (Frame::Push(elems) | Frame::Array(elems)) = frame else {
+        return Err(format!("unexpected message {frame:?}").into());
+    };
+    let mut elems = elems.into_iter();
+    let elems = (
+        elems.next(),
+        elems.next(),
+        elems.next(),
+        elems.next(),
+        elems.next(),
+    );
+    return match elems {
+        (
+            Some(Frame::Bulk(kind)),
+            Some(Frame::Bulk(channel)),
+            Some(Frame::Bulk(content)),
+            None,
+            None,
+        ) if kind == "message" || kind == "smessage" => Ok(Pushed::Message(Message {
+            channel,
+            pattern: None,
+            content,
+        })),
+        (
+            Some(Frame::Bulk(kind)),
+            Some(Frame::Bulk(pattern)),
+            Some(Frame::Bulk(channel)),
+            Some(Frame::Bulk(content)),
+            None,
+        ) if kind == "pmessage" => Ok(Pushed::Message(Message {
+            channel,
+            pattern: Some(pattern),
+            content,
+        })),
+        (Some(Frame::Bulk(kind)), Some(name), Some(Frame::Integer(_)), None, None) => {
+            Ok(Pushed::Confirmation {
+                kind,
+                name: optional_bulk(name)?,
+            })
+        }
+        elems => Err(format!("unexpected message {elems:?}").into()),
+    };
+}
+
+/// Convert a flat Array of channels each followed by its number of
+/// subscribers
+fn channel_counts(frame: Frame) -> MyResult<Vec<(Bytes, i64)>> {
+    let Frame::Array(elems) = frame else {
+        return Err(format!("unexpected response {frame:?}").into());
+    };
+    let mut counts = vec![];
+    let mut elems = elems.into_iter();
+    while let (Some(channel), Some(count)) = (elems.next(), elems.next()) {
+        match (channel, count) {
+            (Frame::Bulk(channel), Frame::Integer(count)) => counts.push((channel, count)),
+            pair => return Err(format!("unexpected subscriber count {pair:?}").into()),
+        }
+    }
+    return Ok(counts);
+}
+
+/// Copy a string into Bytes
+fn str_to_bytes(s: &str) -> Bytes {
+    return Bytes::copy_from_slice(s.as_bytes());
+}
+
+/// Convert a reply that is either a Bulk or Null
+fn optional_bulk(frame: Frame) -> MyResult<Option<Bytes>> {
+    return match frame {
+        Frame::Bulk(bytes) => Ok(Some(bytes)),
+        Frame::Null => Ok(None),
+        frame => Err(format!("unexpected value {frame:?}").into()),
+    };
+}
+
+/// Convert the reply of LMPOP and BLMPOP: a key and an Array of the elements
+/// popped from it, or Null
+fn keyed_elems(frame: Frame) -> MyResult<Option<(Bytes, Vec<Bytes>)>> {
+    return match frame {
+        Frame::Array(pair) => match &pair[..] {
+            [Frame::Bulk(key), elems] => Ok(Some((key.clone(), bulks(elems.clone())?))),
+            _ => Err(format!("unexpected response {pair:?}").into()),
+        },
+        Frame::Null => Ok(None),
+        frame => Err(format!("unexpected response {frame:?}").into()),
+    };
+}
+
+/// Convert a reply of field value pairs, which is a Map for RESP3 peers and a
+/// flat Array for RESP2 peers
+fn bulk_pairs(frame: Frame) -> MyResult<Vec<(Bytes, Bytes)>> {
+    let pairs = match frame {
+        Frame::Map(pairs) => pairs,
+        Frame::Array(elems) if elems.len().is_multiple_of(2) => {
+            let mut elems = elems.into_iter();
+            let mut pairs = vec![];
+            while let (Some(field), Some(val)) = (elems.next(), elems.next()) {
+                pairs.push((field, val));
+            }
+            pairs
+        }
+        frame => return Err(format!("unexpected response {frame:?}").into()),
+    };
+    return pairs
+        .into_iter()
+        .map(|pair| match pair {
+            (Frame::Bulk(field), Frame::Bulk(val)) => Ok((field, val)),
+            pair => Err(format!("unexpected value {pair:?}").into()),
+        })
+        .collect();
+}
+
+/// Convert a reply that is an Array or a Set of Bulk frames. Null counts as
+/// empty.
+fn bulks(frame: Frame) -> MyResult<Vec<Bytes>> {
+    return match frame {
+        Frame::Array(elems) | Frame::Set(elems) => elems
+            .into_iter()
+            .map(|elem| match elem {
+                Frame::Bulk(bytes) => Ok(bytes),
+                elem => Err(format!("unexpected value {elem:?}").into()),
+            })
+            .collect(),
+        Frame::Null => Ok(vec![]),
+        frame => Err(format!("unexpected response {frame:?}").into()),
+    };
+}
+
+/// Convert a score, which is a Double for RESP3 peers and a Bulk for RESP2
+/// peers, or Null
+fn optional_double(frame: Frame) -> MyResult<Option<f64>> {
+    return match frame {
+        Frame::Double(score) => Ok(Some(score)),
+        Frame::Bulk(score) => match command::parse_float(&score) {
+            Some(score) => Ok(Some(score)),
+            None => Err(format!("unexpected value {score:?}").into()),
+        },
+        Frame::Null => Ok(None),
+        frame => Err(format!("unexpected value {frame:?}").into()),
+    };
+}
+
+/// Convert a longitude and latitude pair
+fn coordinates(frame: Frame) -> MyResult<Coordinates> {
+    let Frame::Array(pair) = frame else {
+        return Err(format!("unexpected coordinates {frame:?}").into());
+    };
+    return match <[Frame; 2]>::try_from(pair) {
+        Ok([longitude, latitude]) => {
+            match (optional_double(longitude)?, optional_double(latitude)?) {
+                (Some(longitude), Some(latitude)) => Ok(Coordinates {
+                    longitude,
+                    latitude,
+                }),
+                _ => Err("unexpected coordinates".into()),
+            }
+        }
+        Err(pair) => Err(format!("unexpected coordinates {pair:?}").into()),
+    };
+}
+
+/// Convert a flat Array of members each followed by its score
+fn scored_members(frame: Frame) -> MyResult<Vec<(Bytes, f64)>> {
+    let Frame::Array(elems) = frame else {
+        return Err(format!("unexpected response {frame:?}").into());
+    };
+    let mut elems = elems.into_iter();
+    let mut pairs = vec![];
+    while let Some(member) = elems.next() {
+        let (Frame::Bulk(member), Some(Some(score))) =
+            (member, elems.next().map(optional_double).transpose()?)
+        else {
+            return Err("unexpected member and score".into());
+        };
+        pairs.push((member, score));
+    }
+    return Ok(pairs);
+}
+
+/// Convert a stream ID sent as a Bulk
+fn stream_id(frame: Frame) -> MyResult<StreamId> {
+    return match frame {
+        Frame::Bulk(id) => StreamId::parse(&id, 0).ok_or_else(|| "invalid stream ID".into()),
+        frame => Err(format!("unexpected value {frame:?}").into()),
+    };
+}
+
+/// Convert an Array of stream entries, each an ID and a flat Array of fields
+/// and values. An entry with Null fields, which was removed from the stream,
+/// has no fields.
+fn stream_entries(frame: Frame) -> MyResult<Vec<StreamEntry>> {
+    let Frame::Array(entries) = frame else {
+        return Err(format!("unexpected response {frame:?}").into());
+    };
+    return entries
+        .into_iter()
+        .map(|entry| match entry {
+            Frame::Array(entry) => match <[Frame; 2]>::try_from(entry) {
+                Ok([id, Frame::Null]) => Ok((stream_id(id)?, vec![])),
+                Ok([id, fields]) => Ok((stream_id(id)?, bulk_pairs(fields)?)),
+                Err(entry) => Err(format!("unexpected entry {entry:?}").into()),
+            },
+            entry => Err(format!("unexpected value {entry:?}").into()),
+        })
+        .collect();
+}
+
+/// Convert the reply of XREAD and XREADGROUP: for each stream, its key and
+/// the entries read from it, or Null if there were none
+fn keyed_stream_entries(frame: Frame) -> MyResult<Vec<(Bytes, Vec<StreamEntry>)>> {
+    let streams = match frame {
+        Frame::Array(streams) => streams,
+        Frame::Null => return Ok(vec![]),
+        frame => return Err(format!("unexpected response {frame:?}").into()),
+    };
+    return streams
+        .into_iter()
+        .map(|stream| match stream {
+            Frame::Array(stream) => match <[Frame; 2]>::try_from(stream) {
+                Ok([Frame::Bulk(key), entries]) => Ok((key, stream_entries(entries)?)),
+                _ => Err("unexpected stream in response".into()),
+            },
+            stream => Err(format!("unexpected value {stream:?}").into()),
+        })
+        .collect();
+}
+
+/// Convert a reply that is an Array of Integer frames
+fn integers(frame: Frame) -> MyResult<Vec<i64>> {
+    let Frame::Array(elems) = frame else {
+        return Err(format!("unexpected response {frame:?}").into());
+    };
+    return elems
+        .into_iter()
+        .map(|elem| match elem {
+            Frame::Integer(n) => Ok(n),
+            elem => Err(format!("unexpected value {elem:?}").into()),
+        })
+        .collect();
+}
+
+/// The protocol versions a connection can speak. Every connection starts out
+/// with RESP2 and can switch to RESP3 through the HELLO command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    /// Return the version number HELLO uses for this protocol
+    pub fn version(&self) -> i64 {
+        return match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
+        };
+    }
+}
+
+/// The various RESP data types. The data types are explained here:
+/// https://redis.io/docs/reference/protocol-spec/
+///
+/// The RESP3-only types (everything after Array) can always be parsed, but
+/// when encoded for a RESP2 peer they are downgraded to the closest RESP2
+/// type, the same way Redis shapes its replies for RESP2 clients.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<Frame>),
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    Verbatim {
+        format: String,
+        text: Bytes,
+    },
+    /// Auxiliary data about the frame that follows it: the next element of
+    /// an Array, Set or Push, which does not count it as an element, or the
+    /// next reply at the top level. Map keys and values cannot have any.
+    Attribute(Vec<(Frame, Frame)>),
+    Push(Vec<Frame>),
+}
+
+impl Frame {
+    /// Serialize a frame into a byte array for transmission to a RESP2 peer.
+    /// This is a convenience over Frame::encode that allocates a single buffer
+    /// of exactly the right size.
+    pub fn serialize(&self) -> Bytes {
+        return self.serialize_as(Protocol::Resp2);
+    }
+
+    /// Serialize a frame into a byte array for transmission to a peer that
+    /// speaks the given protocol
+    pub fn serialize_as(&self, protocol: Protocol) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.encoded_len_as(protocol));
+        self.encode_as(protocol, &mut buf);
+        return buf.freeze();
+    }
+
+    /// Serialize a frame for a RESP2 peer directly into the destination
+    /// buffer, without allocating any intermediate buffers
+    pub fn encode(&self, dst: &mut impl BufMut) {
+        self.encode_as(Protocol::Resp2, dst);
+    }
+
+    /// Serialize a frame for a peer that speaks the given protocol directly
+    /// into the destination buffer
+    pub fn encode_as(&self, protocol: Protocol, dst: &mut impl BufMut) {
+        let resp3 = protocol == Protocol::Resp3;
+        match self {
+            // "+<content>\r\n"
+            Self::Simple(s) => Self::_encode_line(dst, b'+', s.as_bytes()),
+            // "-<err>\r\n"
+            Self::Error(s) => Self::_encode_line(dst, b'-', s.as_bytes()),
+            // ":<integer>\r\n"
+            Self::Integer(n) => Self::_encode_decimal(dst, b':', *n),
+            // "$<len><CRLF><data><CRLF>", including the empty string
+            Self::Bulk(data) => Self::_encode_bulk(dst, data),
+            // RESP3 has a dedicated null type: "_<CRLF>". RESP2 uses a Bulk
+            // frame with a length of -1: "$-1<CRLF>"
+            Self::Null if resp3 => Self::_encode_line(dst, b'_', b""),
+            Self::Null => Self::_encode_decimal(dst, b'$', -1),
+            // "*<nelems><CRLF>" followed by each of the elements
+            Self::Array(elems) => Self::_encode_aggregate(dst, protocol, b'*', elems),
+            // "%<npairs><CRLF>" followed by each key and value; RESP2 gets a
+            // flat array of keys and values
+            Self::Map(pairs) if resp3 => Self::_encode_pairs(dst, protocol, b'%', pairs),
+            Self::Map(pairs) => {
+                let flat = pairs.iter().flat_map(|(key, val)| [key, val]);
+                Self::_encode_decimal(dst, b'*', Self::_count_elems(flat) as i64);
+                for (key, val) in pairs {
+                    key.encode_as(protocol, dst);
+                    val.encode_as(protocol, dst);
+                }
+            }
+            // "~<nelems><CRLF>" followed by each of the elements
+            Self::Set(elems) if resp3 => Self::_encode_aggregate(dst, protocol, b'~', elems),
+            Self::Set(elems) => Self::_encode_aggregate(dst, protocol, b'*', elems),
+            // ",<double><CRLF>"; RESP2 gets the same digits as a Bulk frame
+            Self::Double(num) if resp3 => {
+                Self::_encode_line(dst, b',', format_double(*num).as_bytes())
+            }
+            Self::Double(num) => Self::_encode_bulk(dst, format_double(*num).as_bytes()),
+            // "#t<CRLF>" or "#f<CRLF>"; RESP2 gets 1 or 0
+            Self::Boolean(b) if resp3 => {
+                Self::_encode_line(dst, b'#', if *b { b"t" } else { b"f" })
+            }
+            Self::Boolean(b) => Self::_encode_decimal(dst, b':', *b as i64),
+            // "(<digits><CRLF>"; RESP2 gets the digits as a Bulk frame
+            Self::BigNumber(num) if resp3 => Self::_encode_line(dst, b'(', num.as_bytes()),
+            Self::BigNumber(num) => Self::_encode_bulk(dst, num.as_bytes()),
+            // "=<len><CRLF><format>:<text><CRLF>"; RESP2 gets the text as a
+            // Bulk frame
+            Self::Verbatim { format, text } if resp3 => {
+                Self::_encode_decimal(dst, b'=', (format.len() + 1 + text.len()) as i64);
+                dst.put_slice(format.as_bytes());
+                dst.put_u8(b':');
+                dst.put_slice(text);
+                dst.put_slice(CRLF.as_bytes());
+            }
+            Self::Verbatim { text, .. } => Self::_encode_bulk(dst, text),
+            // "|<npairs><CRLF>" followed by each key and value. RESP2 has no
+            // out-of-band data, so attributes are dropped entirely
+            Self::Attribute(pairs) if resp3 => Self::_encode_pairs(dst, protocol, b'|', pairs),
+            Self::Attribute(_) => (),
+            // "><nelems><CRLF>" followed by each of the elements; RESP2 gets
+            // a plain array
+            Self::Push(elems) if resp3 => Self::_encode_aggregate(dst, protocol, b'>', elems),
+            Self::Push(elems) => Self::_encode_aggregate(dst, protocol, b'*', elems),
+        }
+    }
+
+    /// Return the number of bytes Frame::encode writes for this frame
+    pub fn encoded_len(&self) -> usize {
+        return self.encoded_len_as(Protocol::Resp2);
+    }
+
+    /// Return the number of bytes Frame::encode_as writes for this frame and
+    /// protocol
+    pub fn encoded_len_as(&self, protocol: Protocol) -> usize {
+        let resp3 = protocol == Protocol::Resp3;
+        let elems_len = |elems: &[Frame]| {
+            let prefix = Self::_decimal_line_len(Self::_count_elems(elems) as i64);
+            prefix
+                + elems
+                    .iter()
+                    .map(|elem| elem.encoded_len_as(protocol))
+                    .sum::<usize>()
+        };
+        let pairs_len = |pairs: &[(Frame, Frame)]| {
+            let count = match protocol {
+                Protocol::Resp3 => pairs.len(),
+                Protocol::Resp2 => Self::_count_elems(pairs.iter().flat_map(|(k, v)| [k, v])),
+            };
+            let prefix = Self::_decimal_line_len(count as i64);
+            let kvs = pairs
+                .iter()
+                .map(|(key, val)| key.encoded_len_as(protocol) + val.encoded_len_as(protocol));
+            prefix + kvs.sum::<usize>()
+        };
+        let bulk_len = |len: usize| Self::_decimal_line_len(len as i64) + len + CRLF.len();
+
+        return match self {
+            Self::Simple(s) | Self::Error(s) => 1 + s.len() + CRLF.len(),
+            Self::Integer(n) => Self::_decimal_line_len(*n),
+            Self::Bulk(data) => bulk_len(data.len()),
+            Self::Null if resp3 => 1 + CRLF.len(),
+            Self::Null => Self::_decimal_line_len(-1),
+            Self::Array(elems) | Self::Set(elems) | Self::Push(elems) => elems_len(elems),
+            Self::Map(pairs) => pairs_len(pairs),
+            Self::Double(num) if resp3 => 1 + format_double(*num).len() + CRLF.len(),
+            Self::Double(num) => bulk_len(format_double(*num).len()),
+            // Both "#t<CRLF>" and ":1<CRLF>"
+            Self::Boolean(_) => 2 + CRLF.len(),
+            Self::BigNumber(num) if resp3 => 1 + num.len() + CRLF.len(),
+            Self::BigNumber(num) => bulk_len(num.len()),
+            Self::Verbatim { format, text } if resp3 => bulk_len(format.len() + 1 + text.len()),
+            Self::Verbatim { text, .. } => bulk_len(text.len()),
+            Self::Attribute(pairs) if resp3 => pairs_len(pairs),
+            Self::Attribute(_) => 0,
+        };
+    }
+
+    /// Write a Bulk frame holding the data
+    fn _encode_bulk(dst: &mut impl BufMut, data: &[u8]) {
+        Self::_encode_decimal(dst, b'$', data.len() as i64);
+        dst.put_slice(data);
+        dst.put_slice(CRLF.as_bytes());
+    }
+
+    /// Write the type byte and the number of elements, then each element
+    fn _encode_aggregate(dst: &mut impl BufMut, protocol: Protocol, prefix: u8, elems: &[Frame]) {
+        Self::_encode_decimal(dst, prefix, Self::_count_elems(elems) as i64);
+        for elem in elems {
+            elem.encode_as(protocol, dst);
+        }
+    }
+
+    /// Write the type byte and the number of pairs, then each key and value
+    fn _encode_pairs(
+        dst: &mut impl BufMut,
+        protocol: Protocol,
+        prefix: u8,
+        pairs: &[(Frame, Frame)],
+    ) {
+        Self::_encode_decimal(dst, prefix, pairs.len() as i64);
+        for (key, val) in pairs {
+            key.encode_as(protocol, dst);
+            val.encode_as(protocol, dst);
+        }
+    }
+
+    /// Count the elements of an aggregate. Attributes are not elements of
+    /// their own but annotate the element after them, and RESP2 drops them.
+    fn _count_elems<'a>(frames: impl IntoIterator<Item = &'a Frame>) -> usize {
+        return frames
+            .into_iter()
+            .filter(|frame| !matches!(frame, Self::Attribute(_)))
+            .count();
+    }
+
+    /// Write the type byte, then the line, then CRLF
+    fn _encode_line(dst: &mut impl BufMut, prefix: u8, line: &[u8]) {
+        dst.put_u8(prefix);
+        dst.put_slice(line);
+        dst.put_slice(CRLF.as_bytes());
+    }
+
+    /// Write the type byte, then the decimal representation of the number,
+    /// then CRLF. The digits are formatted on the stack instead of through
+    /// format!, which would allocate a String.
+    fn _encode_decimal(dst: &mut impl BufMut, prefix: u8, n: i64) {
+        // u64::MAX has 20 digits
+        let mut digits = [0u8; 20];
+        let mut start = digits.len();
+        let mut rest = n.unsigned_abs();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+
+        dst.put_u8(prefix);
+        if n < 0 {
+            dst.put_u8(b'-');
+        }
+        dst.put_slice(&digits[start..]);
+        dst.put_slice(CRLF.as_bytes());
+    }
+
+    /// Return the number of bytes Frame::_encode_decimal writes for the number
+    fn _decimal_line_len(n: i64) -> usize {
+        let sign = if n < 0 { 1 } else { 0 };
+        let ndigits = n.unsigned_abs().checked_ilog10().unwrap_or(0) as usize + 1;
+        return 1 + sign + ndigits + CRLF.len();
+    }
+
+    /// Read the input buffer and check if there is a valid frame at its
+    /// front. If yes, the bytes of that frame are consumed from the buffer and
+    /// the parsed frame is returned in the "Ok" variant.
+    ///
+    /// If the buffer ends before the frame does, ParseError::Incomplete is
+    /// returned; if the buffer holds bytes that can never become a valid frame,
+    /// ParseError::Protocol is returned. In both cases the buffer is left in an
+    /// unspecified position and should be discarded by the caller.
+    ///
+    /// Attributes sent ahead of a reply are returned as a frame of their own.
+    ///
+    /// The buffer is expected to be contiguous (e.g. Bytes or &[u8]): only its
+    /// first chunk is ever inspected.
+    pub fn parse<B: Buf>(bytes: &mut B) -> Result<Frame, ParseError> {
+        return Self::parse_with_limits(bytes, &Limits::default());
+    }
+
+    /// Same as Frame::parse, but frames that exceed the given limits are
+    /// rejected with ParseError::Protocol as soon as their headers are read,
+    /// before their contents are buffered.
+    pub fn parse_with_limits<B: Buf>(bytes: &mut B, limits: &Limits) -> Result<Frame, ParseError> {
+        return Self::_parse_partial(bytes, limits, &mut PartialFrame::default());
+    }
+
+    /// Parse a frame off the front of the buffer, picking up where an earlier
+    /// parse of the same frame ran out of bytes. The buffer must start after
+    /// the bytes the partial frame already holds.
+    ///
+    /// Aggregates are parsed with an explicit stack rather than recursively,
+    /// so that if the buffer ends in the middle of the frame, the elements
+    /// parsed so far are kept in the partial frame and never parsed again.
+    fn _parse_partial<B: Buf>(
+        bytes: &mut B,
+        limits: &Limits,
+        partial: &mut PartialFrame,
+    ) -> Result<Frame, ParseError> {
+        let ctx = ParseContext {
+            origin: partial.consumed + bytes.remaining(),
+            limits,
+        };
+        loop {
+            let pos = ctx.pos(bytes);
+            let token = match Self::_parse_token(bytes, &ctx, partial.open.len()) {
+                Err(ParseError::Incomplete) => {
+                    partial.consumed = pos;
+                    return Err(ParseError::Incomplete);
+                }
+                token => token?,
+            };
+            let (mut frame, mut frame_pos) = match token {
+                Token::Frame(frame) => (frame, pos),
+                Token::Open(aggregate) if aggregate.remaining == 0 => (aggregate.finish(), pos),
+                Token::Open(aggregate) => {
+                    partial.open.push(aggregate);
+                    continue;
+                }
+            };
+            // Hand the frame to the aggregate it belongs to, which may be
+            // complete in turn
+            loop {
+                let Some(parent) = partial.open.last_mut() else {
+                    *partial = PartialFrame::default();
+                    return Ok(frame);
+                };
+                parent.push(frame, frame_pos, &ctx)?;
+                if parent.remaining > 0 {
+                    break;
+                }
+                let parent = partial.open.pop().unwrap();
+                frame_pos = parent.pos;
+                frame = parent.finish();
+            }
+        }
+    }
+
+    /// Parse a whole frame that holds no other frames, or the header of an
+    /// aggregate frame off the front of the buffer. "depth" is the number of
+    /// aggregate frames that enclose it.
+    fn _parse_token<B: Buf>(
+        bytes: &mut B,
+        ctx: &ParseContext,
+        depth: usize,
+    ) -> Result<Token, ParseError> {
+        if !bytes.has_remaining() {
+            return Err(ParseError::Incomplete);
+        }
+        let pos = ctx.pos(bytes);
+        let frame = match bytes.get_u8() {
+            b'+' => Frame::Simple(Self::_parse_binary_safe_string(bytes, ctx)?),
+            b'-' => Frame::Error(Self::_parse_binary_safe_string(bytes, ctx)?),
+            b':' => Frame::Integer(Self::_parse_decimal(bytes, ctx, "invalid integer")?),
+            b'$' => {
+                // Read until the first CRLF to parse the number of bytes. A
+                // length of -1 is the Null frame
+                match Self::_parse_length(bytes, ctx, "invalid bulk length")? {
+                    None => Frame::Null,
+                    Some(nbytes) => {
+                        Self::_check_bulk_len(ctx, pos, nbytes)?;
+                        Frame::Bulk(Self::_parse_blob(bytes, ctx, nbytes)?)
+                    }
+                }
+            }
+            b'*' => {
+                // Parsing an array: first obtain the number of elements, which
+                // are parsed next. A count of -1 is the RESP2 null array
+                match Self::_parse_length(bytes, ctx, "invalid multibulk length")? {
+                    None => Frame::Null,
+                    Some(nelems) => {
+                        Self::_check_aggregate(ctx, pos, depth, nelems)?;
+                        return Ok(Token::Open(OpenAggregate::new(b'*', pos, nelems)));
+                    }
+                }
+            }
+            b'_' => {
+                let line = Self::_parse_binary_safe_string(bytes, ctx)?;
+                if !line.is_empty() {
+                    return Err(ParseError::protocol(pos + 1, "invalid null"));
+                }
+                Frame::Null
+            }
+            b'#' => match Self::_parse_binary_safe_string(bytes, ctx)?.as_str() {
+                "t" => Frame::Boolean(true),
+                "f" => Frame::Boolean(false),
+                _ => return Err(ParseError::protocol(pos + 1, "invalid boolean")),
+            },
+            b',' => {
+                let num = Self::_parse_binary_safe_string(bytes, ctx)?
+                    .parse::<f64>()
+                    .map_err(|_| ParseError::protocol(pos + 1, "invalid double"))?;
+                Frame::Double(num)
+            }
+            b'(' => {
+                let num = Self::_parse_binary_safe_string(bytes, ctx)?;
+                let digits = num.strip_prefix('-').unwrap_or(&num);
+                if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(ParseError::protocol(pos + 1, "invalid big number"));
+                }
+                Frame::BigNumber(num)
+            }
+            b'=' => {
+                // The content is "<format>:<text>" where the format is exactly
+                // three bytes long
+                let nbytes = Self::_parse_length(bytes, ctx, "invalid verbatim length")?
+                    .ok_or_else(|| ParseError::protocol(pos + 1, "invalid verbatim length"))?;
+                Self::_check_bulk_len(ctx, pos, nbytes)?;
+                let content = Self::_parse_blob(bytes, ctx, nbytes)?;
+                let format = content
+                    .get(..3)
+                    .filter(|_| content.get(3) == Some(&b':'))
+                    .and_then(|format| String::from_utf8(format.to_vec()).ok())
+                    .ok_or_else(|| ParseError::protocol(pos, "invalid verbatim format"))?;
+                Frame::Verbatim {
+                    format,
+                    text: content.slice(4..),
+                }
+            }
+            prefix @ (b'%' | b'|') => {
+                // The keys and values of the pairs are parsed next, in turn
+                let npairs = Self::_parse_length(bytes, ctx, "invalid map length")?
+                    .ok_or_else(|| ParseError::protocol(pos + 1, "invalid map length"))?;
+                let nelems = npairs.saturating_mul(2);
+                Self::_check_aggregate(ctx, pos, depth, nelems)?;
+                return Ok(Token::Open(OpenAggregate::new(prefix, pos, nelems)));
+            }
+            prefix @ (b'~' | b'>') => {
+                let nelems = Self::_parse_length(bytes, ctx, "invalid multibulk length")?
+                    .ok_or_else(|| ParseError::protocol(pos + 1, "invalid multibulk length"))?;
+                Self::_check_aggregate(ctx, pos, depth, nelems)?;
+                return Ok(Token::Open(OpenAggregate::new(prefix, pos, nelems)));
+            }
+            byte => {
+                return Err(ParseError::protocol(
+                    pos,
+                    format!("unexpected frame type byte '{}'", byte.escape_ascii()),
+                ));
+            }
+        };
+        return Ok(Token::Frame(frame));
+    }
+
+    /// Reject a Bulk or Verbatim frame whose declared length is over the limit.
+    /// "pos" is where the frame starts.
+    fn _check_bulk_len(ctx: &ParseContext, pos: usize, nbytes: usize) -> Result<(), ParseError> {
+        if nbytes > ctx.limits.max_bulk_len {
+            return Err(ParseError::protocol(pos + 1, "bulk length exceeds limit"));
+        }
+        return Ok(());
+    }
+
+    /// Reject an aggregate frame that declares too many elements or that is
+    /// nested too deep. "pos" is where the frame starts.
+    fn _check_aggregate(
+        ctx: &ParseContext,
+        pos: usize,
+        depth: usize,
+        nelems: usize,
+    ) -> Result<(), ParseError> {
+        if nelems > ctx.limits.max_multibulk_len {
+            return Err(ParseError::protocol(
+                pos + 1,
+                "multibulk length exceeds limit",
+            ));
+        }
+        if depth >= ctx.limits.max_depth {
+            return Err(ParseError::protocol(pos, "nesting depth exceeds limit"));
+        }
+        return Ok(());
+    }
+
+    /// Extract the length prefix of a Bulk or aggregate frame. Return None
+    /// for a length of -1, which marks a RESP2 null
+    fn _parse_length<B: Buf>(
+        bytes: &mut B,
+        ctx: &ParseContext,
+        reason: &'static str,
+    ) -> Result<Option<usize>, ParseError> {
+        let pos = ctx.pos(bytes);
+        return match Self::_parse_decimal(bytes, ctx, reason)? {
+            -1 => Ok(None),
+            len if len < 0 => Err(ParseError::protocol(pos, reason)),
+            len => Ok(Some(len as usize)),
+        };
+    }
+
+    /// Extract the payload of a length-prefixed frame, which is exactly
+    /// "nbytes" bytes followed by CRLF
+    fn _parse_blob<B: Buf>(
+        bytes: &mut B,
+        ctx: &ParseContext,
+        nbytes: usize,
+    ) -> Result<Bytes, ParseError> {
+        // bytes[0..nbytes] should be the content
+        // bytes[nbytes..nbytes+2] should be another CRLF
+        let chunk = bytes.chunk();
+        if chunk.len() < nbytes + CRLF.len() {
+            return Err(ParseError::Incomplete);
+        }
+        if !chunk[nbytes..].starts_with(CRLF.as_bytes()) {
+            let pos = ctx.pos(bytes) + nbytes;
+            return Err(ParseError::protocol(
+                pos,
+                "bulk string is not terminated by CRLF",
+            ));
+        }
+        let blob = bytes.copy_to_bytes(nbytes);
+        bytes.advance(CRLF.len());
+        return Ok(blob);
+    }
+
+    /// Given some bytes that are assumed to be binary safe, extract the
+    /// string between the start of the bytes and the first CRLF. If the bytes
+    /// do not contain CRLF, the frame is incomplete; if the string is not valid
+    /// UTF-8, the frame is malformed.
+    fn _parse_binary_safe_string<B: Buf>(
+        bytes: &mut B,
+        ctx: &ParseContext,
+    ) -> Result<String, ParseError> {
+        let pos = ctx.pos(bytes);
+        let chunk = bytes.chunk();
+        let len = chunk
+            .windows(CRLF.len())
+            .position(|window| window == CRLF.as_bytes());
+        let len = match len {
+            Some(len) if len <= ctx.limits.max_inline_len => len,
+            None if chunk.len() <= ctx.limits.max_inline_len => {
+                return Err(ParseError::Incomplete);
+            }
+            // Without a limit, a peer could keep us buffering a line forever
+            _ => return Err(ParseError::protocol(pos, "line length exceeds limit")),
+        };
+        let msg = String::from_utf8(chunk[..len].to_vec())
+            .map_err(|_| ParseError::protocol(pos, "invalid UTF-8 in line"))?;
+
+        // CRLF should be consumed, as well
+        bytes.advance(len + CRLF.len());
+        return Ok(msg);
+    }
+
+    /// Extract a signed decimal number terminated by CRLF, such as the value
+    /// of an Integer frame or the length prefix of a Bulk or Array frame. If
+    /// the line is not a number, a protocol error with the given reason is
+    /// returned.
+    fn _parse_decimal<B: Buf>(
+        bytes: &mut B,
+        ctx: &ParseContext,
+        reason: &'static str,
+    ) -> Result<i64, ParseError> {
+        let pos = ctx.pos(bytes);
+        return Self::_parse_binary_safe_string(bytes, ctx)?
+            .parse::<i64>()
+            .map_err(|_| ParseError::protocol(pos, reason));
+    }
+
+    /// Return whether the byte is one of the type bytes that a RESP frame
+    /// starts with
+    fn is_type_byte(byte: u8) -> bool {
+        return b"+-:$*_#,(=%|~>".contains(&byte);
+    }
+
+    /// Parse an inline command off the front of the buffer: a line of
+    /// whitespace-separated arguments terminated by LF or CRLF, as typed by a
+    /// human over telnet. The arguments are returned as an Array of Bulk
+    /// frames, the same shape as a command sent by a regular client.
+    ///
+    /// Lines longer than the inline length limit are rejected.
+    ///
+    /// Like redis-cli, arguments can be quoted to include whitespace. Double
+    /// quotes support escape sequences such as "\n" and "\x41", while single
+    /// quotes only support escaping the single quote itself.
+    fn parse_inline<B: Buf>(bytes: &mut B, limits: &Limits) -> Result<Frame, ParseError> {
+        let chunk = bytes.chunk();
+        let len = match chunk.iter().position(|byte| *byte == b'\n') {
+            Some(len) if len <= limits.max_inline_len => len,
+            None if chunk.len() <= limits.max_inline_len => return Err(ParseError::Incomplete),
+            _ => return Err(ParseError::protocol(0, "too big inline request")),
+        };
+        let line = chunk[..len].strip_suffix(b"\r").unwrap_or(&chunk[..len]);
+        let args = Self::_split_inline_args(line)
+            .ok_or_else(|| ParseError::protocol(0, "unbalanced quotes in request"))?;
+
+        bytes.advance(len + 1);
+        return Ok(Frame::Array(args.into_iter().map(Frame::Bulk).collect()));
+    }
+
+    /// Split a line into arguments the way redis-cli does. Return None if the
+    /// line has unbalanced quotes, or if a closing quote is not followed by
+    /// whitespace.
+    fn _split_inline_args(line: &[u8]) -> Option<Vec<Bytes>> {
+        let mut args = vec![];
+        let mut pos = 0;
+        loop {
+            while pos < line.len() && line[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos == line.len() {
+                return Some(args);
+            }
+
+            let mut arg = vec![];
+            let quote = match line[pos] {
+                quote @ (b'"' | b'\'') => {
+                    pos += 1;
+                    Some(quote)
+                }
+                _ => None,
+            };
+            loop {
+                match (quote, line.get(pos)) {
+                    // Unquoted arguments end at whitespace or at the end of
+                    // the line
+                    (None, None) => break,
+                    (None, Some(byte)) if byte.is_ascii_whitespace() => break,
+                    (None, Some(byte)) => arg.push(*byte),
+                    // Quoted arguments must be closed before the line ends,
+                    // and the closing quote must be followed by whitespace
+                    (Some(_), None) => return None,
+                    (Some(quote), Some(byte)) if *byte == quote => {
+                        pos += 1;
+                        match line.get(pos) {
+                            Some(next) if !next.is_ascii_whitespace() => return None,
+                            _ => break,
+                        }
+                    }
+                    (Some(b'"'), Some(b'\\')) => {
+                        let hex = line
+                            .get(pos + 1..pos + 4)
+                            .filter(|hex| hex[0] == b'x')
+                            .and_then(|hex| std::str::from_utf8(&hex[1..]).ok())
+                            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                        if let Some(byte) = hex {
+                            arg.push(byte);
+                            pos += 3;
+                        } else if let Some(escaped) = line.get(pos + 1) {
+                            arg.push(match escaped {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 0x08,
+                                b'a' => 0x07,
+                                other => *other,
+                            });
+                            pos += 1;
+                        } else {
+                            return None;
+                        }
+                    }
+                    (Some(b'\''), Some(b'\\')) if line.get(pos + 1) == Some(&b'\'') => {
+                        arg.push(b'\'');
+                        pos += 1;
+                    }
+                    (Some(_), Some(byte)) => arg.push(*byte),
+                }
+                pos += 1;
+            }
+            args.push(Bytes::from(arg));
+        }
+    }
+}
+
+/// Bounds on what a peer may send, so that a single hostile client cannot make
+/// the parser allocate unbounded memory or nest frames arbitrarily deep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// The longest Bulk (or Verbatim) payload, in bytes
+    pub max_bulk_len: usize,
+    /// The most elements an aggregate frame may declare; a map of n pairs
+    /// counts as 2n elements
+    pub max_multibulk_len: usize,
+    /// How many aggregate frames may enclose one another
+    pub max_depth: usize,
+    /// The longest line that is not a Bulk payload, in bytes: an inline
+    /// command, a simple string, or a length prefix
+    pub max_inline_len: usize,
+}
+
+impl Default for Limits {
+    /// The same defaults as Redis where Redis has one
+    fn default() -> Self {
+        return Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+            max_depth: 128,
+            max_inline_len: 64 * 1024,
+        };
+    }
+}
+
+/// Bookkeeping shared by every level of a parse
+struct ParseContext<'a> {
+    /// The number of bytes that would be remaining if the buffer started with
+    /// the outermost frame, so that errors can point at an offset from its
+    /// start
+    origin: usize,
+    limits: &'a Limits,
+}
+
+impl ParseContext<'_> {
+    /// Return the offset of the buffer's front from the start of the
+    /// outermost frame
+    fn pos<B: Buf>(&self, bytes: &B) -> usize {
+        return self.origin - bytes.remaining();
+    }
+}
+
+/// What a single step of a parse reads: a whole frame that holds no other
+/// frames, or the header of an aggregate frame whose elements come next
+enum Token {
+    Frame(Frame),
+    Open(OpenAggregate),
+}
+
+/// An aggregate frame whose elements have not all been parsed yet
+#[derive(Debug)]
+struct OpenAggregate {
+    /// The type byte of the frame
+    prefix: u8,
+    /// Where the frame starts
+    pos: usize,
+    /// The number of elements still to come, counting each key and each
+    /// value of a map as one
+    remaining: usize,
+    elems: Vec<Frame>,
+    /// The number of attributes among the elements, which do not count
+    attributes: usize,
+}
+
+impl OpenAggregate {
+    fn new(prefix: u8, pos: usize, nelems: usize) -> Self {
+        return Self {
+            prefix,
+            pos,
+            remaining: nelems,
+            elems: vec![],
+            attributes: 0,
+        };
+    }
+
+    /// Add the next element, which starts at "pos". Attributes are kept in
+    /// front of the element they annotate without taking the place of one,
+    /// except in maps, which have nowhere to keep them.
+    fn push(&mut self, elem: Frame, pos: usize, ctx: &ParseContext) -> Result<(), ParseError> {
+        if let Frame::Attribute(_) = elem {
+            if self.attributes >= ctx.limits.max_multibulk_len {
+                return Err(ParseError::protocol(pos, "too many attributes"));
+            }
+            self.attributes += 1;
+            if matches!(self.prefix, b'%' | b'|') {
+                return Ok(());
+            }
+        } else {
+            self.remaining -= 1;
+        }
+        self.elems.push(elem);
+        return Ok(());
+    }
+
+    /// Turn the aggregate into a frame once it has all its elements
+    fn finish(self) -> Frame {
+        let pairs = |elems: Vec<Frame>| {
+            let mut elems = elems.into_iter();
+            let mut pairs = vec![];
+            while let (Some(key), Some(val)) = (elems.next(), elems.next()) {
+                pairs.push((key, val));
+            }
+            return pairs;
+        };
+        return match self.prefix {
+            b'~' => Frame::Set(self.elems),
+            b'>' => Frame::Push(self.elems),
+            b'%' => Frame::Map(pairs(self.elems)),
+            b'|' => Frame::Attribute(pairs(self.elems)),
+            _ => Frame::Array(self.elems),
+        };
+    }
+}
+
+/// The part of a frame parsed before the buffer ran out of bytes, so that a
+/// frame that arrives in many reads is parsed once rather than once per read
+#[derive(Debug, Default)]
+struct PartialFrame {
+    /// The number of bytes of the frame that were parsed into the aggregates
+    consumed: usize,
+    /// The aggregates that wait for more elements, the innermost last
+    open: Vec<OpenAggregate>,
+}
+
+/// The reasons why a frame cannot be parsed from a buffer
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The buffer ends before the frame does; more bytes need to be read
+    Incomplete,
+
+    /// The buffer holds bytes that violate the protocol. "pos" is the offset
+    /// of the offending bytes from the start of the frame
+    Protocol { pos: usize, reason: String },
+}
+
+impl ParseError {
+    /// Create a new protocol error
+    fn protocol(pos: usize, reason: impl Into<String>) -> Self {
+        return Self::Protocol {
+            pos,
+            reason: reason.into(),
+        };
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Self::Incomplete => write!(f, "incomplete frame"),
+            Self::Protocol { reason, .. } => write!(f, "Protocol error: {reason}"),
+        };
+    }
+}
+
+impl Error for ParseError {}
+
+/// A wrapper around a TCP socket (TcpStream) for writing byte stream into
+/// Bytes and for parsing Bytes into frames
+///
+/// Outgoing frames go through a write buffer so that the replies to a batch of
+/// pipelined commands can be sent to the peer with a single syscall.
+pub struct Connection {
+    pub socket: TcpStream,
+    /// Bytes read from the socket that have not been parsed into a frame yet,
+    /// such as the rest of a pipelined batch of commands
+    buffer: BytesMut,
+    /// The elements of the frame at the front of the read buffer that were
+    /// already parsed, while the rest of it has yet to arrive
+    partial: PartialFrame,
+    /// Encoded frames that have not been written into the socket yet
+    write_buffer: BytesMut,
+    /// The protocol outgoing frames are encoded for
+    protocol: Protocol,
+    /// Bounds on the frames the peer may send
+    limits: Limits,
+}
+
+impl Connection {
+    /// Instantiate a new connection
+    pub fn new(socket: TcpStream) -> Self {
+        return Self {
+            socket,
+            buffer: BytesMut::with_capacity(4096),
+            partial: PartialFrame::default(),
+            write_buffer: BytesMut::with_capacity(4096),
+            protocol: Protocol::default(),
+            limits: Limits::default(),
+        };
+    }
+
+    /// Change the bounds on the frames the peer may send. Frames that break
+    /// them make read_frame fail with ParseError::Protocol.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Return the protocol outgoing frames are encoded for
+    pub fn protocol(&self) -> Protocol {
+        return self.protocol;
+    }
+
+    /// Switch the protocol outgoing frames are encoded for. Frames that are
+    /// already buffered are not affected.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Return the next frame sent by the peer. Frames that are already in the
+    /// read buffer are returned without touching the socket; otherwise any
+    /// buffered outgoing frames are flushed, then bytes are read from the
+    /// socket until a complete frame has been buffered.
+    ///
+    /// Return None if the peer closed the connection between two frames, and
+    /// an error if the peer closed the connection in the middle of a frame. If
+    /// the peer sent bytes that violate the protocol, the ParseError::Protocol
+    /// is returned as the error; the connection cannot be used for reading
+    /// afterwards.
+    pub async fn read_frame(&mut self) -> MyResult<Option<Frame>> {
+        loop {
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(Some(frame));
+            }
+
+            // The peer may be waiting on our replies before sending more
+            self.flush().await?;
+            let nbytes = self.socket.read_buf(&mut self.buffer).await?;
+            if nbytes == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err("connection reset by peer".into());
+            }
+        }
+    }
+
+    /// Parse a frame off the front of the read buffer, consuming exactly the
+    /// bytes that belong to it and leaving any following frames in place.
+    /// Return None if the buffer does not hold a complete frame yet; the
+    /// elements that did arrive are not parsed again on the next call.
+    ///
+    /// Lines that do not start with a RESP type byte are inline commands and
+    /// are parsed into an Array of Bulk frames. Empty inline commands are
+    /// skipped, like Redis does. Attributes sent ahead of a frame are skipped
+    /// too, so that a reply is never mistaken for the attributes in front
+    /// of it.
+    fn parse_frame(&mut self) -> Result<Option<Frame>, ParseError> {
+        loop {
+            let mut unparsed: &[u8] = &self.buffer[self.partial.consumed..];
+            let inline = self.partial.consumed == 0
+                && matches!(unparsed.first(), Some(byte) if !Frame::is_type_byte(*byte));
+            let parsed = if inline {
+                Frame::parse_inline(&mut unparsed, &self.limits)
+            } else {
+                Frame::_parse_partial(&mut unparsed, &self.limits, &mut self.partial)
+            };
+            let frame = match parsed {
+                Ok(frame) => frame,
+                Err(ParseError::Incomplete) => return Ok(None),
+                Err(err) => return Err(err),
+            };
+
+            let nbytes = self.buffer.len() - unparsed.len();
+            self.buffer.advance(nbytes);
+            if inline && frame == Frame::Array(vec![]) || matches!(frame, Frame::Attribute(_)) {
+                continue;
+            }
+            return Ok(Some(frame));
+        }
+    }
+
+    /// Convert the input frame into bytes, then write all of them into the
+    /// socket, together with any frames buffered before it
+    pub async fn write_frame(&mut self, frame: &Frame) -> MyResult<()> {
+        self.buffer_frame(frame).await?;
+        return self.flush().await;
+    }
+
+    /// Encode the input frame into the write buffer. Nothing is guaranteed to
+    /// reach the peer until the connection is flushed, either explicitly or by
+    /// the next read_frame.
+    pub async fn buffer_frame(&mut self, frame: &Frame) -> MyResult<()> {
+        frame.encode_as(self.protocol, &mut self.write_buffer);
+        if self.write_buffer.len() >= WRITE_BUFFER_LIMIT {
+            self.flush().await?;
+        }
+        return Ok(());
+    }
+
+    /// Write every buffered frame into the socket
+    pub async fn flush(&mut self) -> MyResult<()> {
+        self.socket.write_all_buf(&mut self.write_buffer).await?;
+        return Ok(());
+    }
+
+    /// Flush any buffered frames, then wait until the peer closes the
+    /// connection. Bytes the peer sends meanwhile stay in the read buffer for
+    /// read_frame, so the future can be dropped at any point without losing
+    /// them. Servers race it against a blocked command, to stop waiting on
+    /// behalf of a peer that is gone.
+    pub async fn closed(&mut self) -> MyResult<()> {
+        self.flush().await?;
+        while self.buffer.len() < PENDING_READ_LIMIT {
+            if self.socket.read_buf(&mut self.buffer).await? == 0 {
+                return Ok(());
+            }
+        }
+        // Stop reading until the peer is served, like Redis does with clients
+        // that send too much at once
+        return std::future::pending().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple_string_serialization() {
+        let simple = Frame::Simple("OK".into());
+        let expected: Vec<u8> = b"+OK\r\n".to_vec();
+        assert_eq!(simple.serialize(), Bytes::copy_from_slice(&expected));
+    }
+
+    #[test]
+    fn test_integer_serialization() {
+        let num = Frame::Integer(0);
+        let expected = b":0\r\n";
+        assert_eq!(num.serialize(), Bytes::copy_from_slice(expected));
+    }
+
+    #[test]
+    fn test_bulk_string_serialization() {
+        let bulk = Frame::Bulk(Bytes::from(vec![b'6', b'9', b'4', b'2', b'0']));
+        let expected = b"$5\r\n69420\r\n";
+        assert_eq!(bulk.serialize(), Bytes::copy_from_slice(expected));
+    }
+
+    #[test]
+    fn test_null_serialization() {
+        let null = Frame::Null;
+        assert_eq!(null.serialize(), Bytes::from(b"$-1\r\n".to_vec()));
+    }
+
+    #[test]
+    fn test_array_serialization() {
+        let set = Frame::Simple("SET".into());
+        let key = Frame::Bulk(Bytes::from("foo"));
+        let val = Frame::Bulk(Bytes::from("bar"));
+        let cmd = Frame::Array(vec![set, key, val]);
+        assert_eq!(
+            cmd.serialize(),
+            Bytes::from(b"*3\r\n+SET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let frames = vec![
+            Frame::Simple("OK".into()),
+            Frame::Error("ERR unknown command".into()),
+            Frame::Integer(0),
+            Frame::Integer(-42),
+            Frame::Integer(i64::MAX),
+            Frame::Integer(i64::MIN),
+            Frame::Bulk(Bytes::new()),
+            Frame::Bulk(Bytes::from(vec![b'x'; 1000])),
+            Frame::Null,
+            Frame::Array(vec![]),
+            Frame::Array(vec![
+                Frame::Integer(10),
+                Frame::Array(vec![Frame::Null, Frame::Bulk(Bytes::from("foo"))]),
+            ]),
+        ];
+        for frame in frames.iter() {
+            let mut buf = BytesMut::new();
+            frame.encode(&mut buf);
+            assert_eq!(buf.len(), frame.encoded_len());
+            assert_eq!(Frame::parse(&mut buf.freeze()).as_ref(), Ok(frame));
+        }
+
+        // Frames are appended to whatever is already in the buffer
+        let mut buf = BytesMut::from("+OK\r\n");
+        Frame::Integer(-1).encode(&mut buf);
+        assert_eq!(buf, BytesMut::from("+OK\r\n:-1\r\n"));
+        assert_eq!(
+            Frame::Integer(i64::MIN).serialize(),
+            Bytes::from(":-9223372036854775808\r\n")
+        );
+    }
+
+    #[test]
+    fn test_simple_string_deserialization() {
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("+SET\r\n")),
+            Ok(Frame::Simple("SET".into())),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("+SET\r\n+++++")),
+            Ok(Frame::Simple("SET".into())),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("+SET\r")),
+            Err(ParseError::Incomplete)
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("+\r\n")),
+            Ok(Frame::Simple("".into())),
+        );
+    }
+
+    #[test]
+    fn test_error_deserialization() {
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("-Key not found\r\n")),
+            Ok(Frame::Error("Key not found".into())),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("-Key not found\r\n-----")),
+            Ok(Frame::Error("Key not found".into())),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("-Key not found\r")),
+            Err(ParseError::Incomplete)
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("-\r\n")),
+            Ok(Frame::Error("".into())),
+        );
+    }
+
+    #[test]
+    fn test_integer_deserialization() {
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(":0\r\n")),
+            Ok(Frame::Integer(0)),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(":1\r\n")),
+            Ok(Frame::Integer(1)),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(":-1\r\n")),
+            Ok(Frame::Integer(-1)),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(":9223372036854775807\r\n")),
+            Ok(Frame::Integer(9223372036854775807)),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(":-9223372036854775808\r\n")),
+            Ok(Frame::Integer(-9223372036854775808)),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(":9223372036854775808\r\n")),
+            Err(ParseError::protocol(1, "invalid integer")),
+        );
+    }
+
+    #[test]
+    fn test_bulk_string_deserialization() {
+        // Empty bulk string
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("$0\r\n\r\n")),
+            Ok(Frame::Bulk(Bytes::new())),
+        );
+
+        // Non-empty bulk string
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("$36\r\n那么古尔丹，代价是什么呢\r\n")),
+            Ok(Frame::Bulk(Bytes::from("那么古尔丹，代价是什么呢")))
+        );
+
+        // Binary unsafe string
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(b"$2\r\n\r\n\r\n".to_vec())),
+            Ok(Frame::Bulk(Bytes::from(b"\r\n".to_vec())))
+        );
+
+        // Incomplete
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("$10\r\n0123456789")),
+            Err(ParseError::Incomplete)
+        );
+
+        // Inconsistent number
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("$10\r\n0123456\r\n")),
+            Err(ParseError::Incomplete)
+        );
+
+        // Noise at the end
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("$10\r\n0123456789\r\nxxxxxx")),
+            Ok(Frame::Bulk(Bytes::from("0123456789")))
+        );
+    }
+
+    #[test]
+    fn test_null_frame_deserialization() {
+        assert_eq!(Frame::parse(&mut Bytes::from("$-1\r\n")), Ok(Frame::Null));
+    }
+
+    #[test]
     fn test_array_deserialization() {
         assert_eq!(
             Frame::parse(&mut Bytes::from("*0\r\n")),
-            Some(Frame::Array(vec![]))
+            Ok(Frame::Array(vec![]))
         );
 
         let some_cmd = Frame::Array(vec![
@@ -571,7 +3491,7 @@ mod tests {
             Frame::Bulk(Bytes::from("foo")),
             Frame::Bulk(Bytes::from("bar")),
         ]);
-        assert_eq!(Frame::parse(&mut some_cmd.serialize()), Some(some_cmd),);
+        assert_eq!(Frame::parse(&mut some_cmd.serialize()), Ok(some_cmd),);
 
         let some_cmd = Frame::Array(vec![
             Frame::Simple("DEL".into()),
@@ -582,45 +3502,812 @@ mod tests {
                 Frame::Bulk(Bytes::from("key4")),
             ]),
         ]);
-        assert_eq!(Frame::parse(&mut some_cmd.serialize()), Some(some_cmd),);
+        assert_eq!(Frame::parse(&mut some_cmd.serialize()), Ok(some_cmd),);
 
-        assert_eq!(Frame::parse(&mut Bytes::from("*3\r\n:0\r\n:1\r\n")), None);
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("*3\r\n:0\r\n:1\r\n")),
+            Err(ParseError::Incomplete)
+        );
 
         assert_eq!(
             Frame::parse(&mut Bytes::from("*2\r\n:0\r\n:1\r\n+++++++")),
-            Some(Frame::Array(vec![Frame::Integer(0), Frame::Integer(1)])),
+            Ok(Frame::Array(vec![Frame::Integer(0), Frame::Integer(1)])),
         );
     }
 
     #[test]
-    fn test_parse_command() {
-        assert_eq!(Command::parse_command(&Frame::Simple("OK".into())), None,);
+    fn test_malformed_frame_deserialization() {
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("!foo\r\n")),
+            Err(ParseError::protocol(0, "unexpected frame type byte '!'")),
+        );
 
         assert_eq!(
-            Command::parse_command(&Frame::Array(vec![
-                Frame::Bulk(Bytes::from("SET")),
-                Frame::Bulk(Bytes::from("foo")),
-                Frame::Bulk(Bytes::from("bar")),
-            ])),
-            Some(Command::set(Bytes::from("foo"), Bytes::from("bar"))),
+            Frame::parse(&mut Bytes::from(b"+\xff\r\n".to_vec())),
+            Err(ParseError::protocol(1, "invalid UTF-8 in line")),
         );
 
         assert_eq!(
-            Command::parse_command(&Frame::Array(vec![
-                Frame::Bulk(Bytes::from("SET")),
-                Frame::Bulk(Bytes::from("foo")),
-            ])),
-            None,
+            Frame::parse(&mut Bytes::from("$abc\r\n")),
+            Err(ParseError::protocol(1, "invalid bulk length")),
         );
 
         assert_eq!(
-            Command::parse_command(&Frame::Array(vec![
-                Frame::Bulk(Bytes::from("SET")),
-                Frame::Bulk(Bytes::from("foo")),
-                Frame::Bulk(Bytes::from("bar")),
-                Frame::Bulk(Bytes::from("baz")),
-            ])),
-            None,
-        )
+            Frame::parse(&mut Bytes::from("$-2\r\n")),
+            Err(ParseError::protocol(1, "invalid bulk length")),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("$3\r\nfoobar\r\n")),
+            Err(ParseError::protocol(
+                7,
+                "bulk string is not terminated by CRLF"
+            )),
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("*x\r\n")),
+            Err(ParseError::protocol(1, "invalid multibulk length")),
+        );
+
+        // Errors nested in arrays point at the offending element
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("*2\r\n$3\r\nfoo\r\n$x\r\n")),
+            Err(ParseError::protocol(14, "invalid bulk length")),
+        );
+
+        // Lines are only inspected once their CRLF has arrived
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("*2\r\n$3\r\nfoo\r\n$x")),
+            Err(ParseError::Incomplete),
+        );
+    }
+
+    #[test]
+    fn test_resp3_deserialization() {
+        assert_eq!(Frame::parse(&mut Bytes::from("_\r\n")), Ok(Frame::Null));
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("#t\r\n")),
+            Ok(Frame::Boolean(true))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("#f\r\n")),
+            Ok(Frame::Boolean(false))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(",3.25\r\n")),
+            Ok(Frame::Double(3.25))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(",-inf\r\n")),
+            Ok(Frame::Double(f64::NEG_INFINITY))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(
+                "(-3492890328409238509324850943850943825024385\r\n"
+            )),
+            Ok(Frame::BigNumber(
+                "-3492890328409238509324850943850943825024385".into()
+            ))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("=15\r\ntxt:Some string\r\n")),
+            Ok(Frame::Verbatim {
+                format: "txt".into(),
+                text: Bytes::from("Some string"),
+            })
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("%2\r\n+first\r\n:1\r\n+second\r\n#f\r\n")),
+            Ok(Frame::Map(vec![
+                (Frame::Simple("first".into()), Frame::Integer(1)),
+                (Frame::Simple("second".into()), Frame::Boolean(false)),
+            ]))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("~2\r\n+orange\r\n+apple\r\n")),
+            Ok(Frame::Set(vec![
+                Frame::Simple("orange".into()),
+                Frame::Simple("apple".into()),
+            ]))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("|1\r\n+ttl\r\n:3600\r\n")),
+            Ok(Frame::Attribute(vec![(
+                Frame::Simple("ttl".into()),
+                Frame::Integer(3600)
+            )]))
+        );
+        // Attributes do not count as elements of the aggregates that hold
+        // them, and map keys and values lose theirs
+        let ttl = || Frame::Attribute(vec![(Frame::Simple("ttl".into()), Frame::Integer(3600))]);
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(
+                "*2\r\n:1\r\n|1\r\n+ttl\r\n:3600\r\n:2\r\n"
+            )),
+            Ok(Frame::Array(vec![
+                Frame::Integer(1),
+                ttl(),
+                Frame::Integer(2)
+            ]))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(
+                "%1\r\n+k\r\n|1\r\n+ttl\r\n:3600\r\n:2\r\n"
+            )),
+            Ok(Frame::Map(vec![(
+                Frame::Simple("k".into()),
+                Frame::Integer(2)
+            )]))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("*1\r\n|0\r\n")),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(">2\r\n+message\r\n$5\r\nhello\r\n")),
+            Ok(Frame::Push(vec![
+                Frame::Simple("message".into()),
+                Frame::Bulk(Bytes::from("hello")),
+            ]))
+        );
+
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("%2\r\n+first\r\n:1\r\n+second\r\n")),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("#x\r\n")),
+            Err(ParseError::protocol(1, "invalid boolean"))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from(",1.2.3\r\n")),
+            Err(ParseError::protocol(1, "invalid double"))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("(12a\r\n")),
+            Err(ParseError::protocol(1, "invalid big number"))
+        );
+        assert_eq!(
+            Frame::parse(&mut Bytes::from("=3\r\ntxt\r\n")),
+            Err(ParseError::protocol(0, "invalid verbatim format"))
+        );
+    }
+
+    #[test]
+    fn test_resp3_serialization() {
+        let frames = vec![
+            Frame::Null,
+            Frame::Boolean(true),
+            Frame::Double(-0.5),
+            Frame::Double(f64::INFINITY),
+            Frame::Double(1e300),
+            Frame::BigNumber("123456789012345678901234567890".into()),
+            Frame::Verbatim {
+                format: "mkd".into(),
+                text: Bytes::from("# title"),
+            },
+            Frame::Map(vec![(Frame::Bulk(Bytes::from("key")), Frame::Set(vec![]))]),
+            Frame::Attribute(vec![(Frame::Simple("a".into()), Frame::Integer(1))]),
+            Frame::Push(vec![Frame::Bulk(Bytes::from("message"))]),
+            Frame::Set(vec![
+                Frame::Attribute(vec![(Frame::Simple("a".into()), Frame::Integer(1))]),
+                Frame::Integer(1),
+            ]),
+        ];
+        for frame in frames.iter() {
+            let bytes = frame.serialize_as(Protocol::Resp3);
+            assert_eq!(bytes.len(), frame.encoded_len_as(Protocol::Resp3));
+            assert_eq!(Frame::parse(&mut bytes.clone()).as_ref(), Ok(frame));
+        }
+
+        assert_eq!(
+            Frame::Null.serialize_as(Protocol::Resp3),
+            Bytes::from("_\r\n")
+        );
+        assert_eq!(
+            Frame::Double(1.5).serialize_as(Protocol::Resp3),
+            Bytes::from(",1.5\r\n")
+        );
+        assert_eq!(
+            Frame::Boolean(false).serialize_as(Protocol::Resp3),
+            Bytes::from("#f\r\n")
+        );
+    }
+
+    #[test]
+    fn test_resp3_downgrade_to_resp2() {
+        let map = Frame::Map(vec![
+            (Frame::Bulk(Bytes::from("proto")), Frame::Integer(2)),
+            (Frame::Bulk(Bytes::from("ok")), Frame::Boolean(true)),
+        ]);
+        assert_eq!(
+            map.serialize(),
+            Bytes::from("*4\r\n$5\r\nproto\r\n:2\r\n$2\r\nok\r\n:1\r\n")
+        );
+        assert_eq!(map.serialize().len(), map.encoded_len());
+
+        assert_eq!(Frame::Double(2.0).serialize(), Bytes::from("$1\r\n2\r\n"));
+        assert_eq!(
+            Frame::Verbatim {
+                format: "txt".into(),
+                text: Bytes::from("hi"),
+            }
+            .serialize(),
+            Bytes::from("$2\r\nhi\r\n")
+        );
+        assert_eq!(
+            Frame::Push(vec![Frame::Set(vec![Frame::Integer(1)])]).serialize(),
+            Bytes::from("*1\r\n*1\r\n:1\r\n")
+        );
+        assert_eq!(
+            Frame::Attribute(vec![(Frame::Null, Frame::Null)]).serialize(),
+            Bytes::new()
+        );
+
+        // Aggregates do not count the attributes they hold, which RESP2 drops
+        let attribute = || Frame::Attribute(vec![(Frame::Simple("ttl".into()), Frame::Integer(1))]);
+        let nested = Frame::Push(vec![
+            attribute(),
+            Frame::Integer(1),
+            Frame::Array(vec![Frame::Bulk(Bytes::from("a")), attribute()]),
+            Frame::Map(vec![(Frame::Integer(2), attribute())]),
+        ]);
+        let bytes = nested.serialize();
+        assert_eq!(bytes.len(), nested.encoded_len());
+        assert_eq!(
+            Frame::parse(&mut bytes.clone()),
+            Ok(Frame::Array(vec![
+                Frame::Integer(1),
+                Frame::Array(vec![Frame::Bulk(Bytes::from("a"))]),
+                Frame::Array(vec![Frame::Integer(2)]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_format_double() {
+        assert_eq!(format_double(0.0), "0");
+        assert_eq!(format_double(10.5), "10.5");
+        assert_eq!(format_double(-3.0), "-3");
+        assert_eq!(format_double(1e300), "1e300");
+        assert_eq!(format_double(1.5e-7), "1.5e-7");
+        assert_eq!(format_double(f64::NEG_INFINITY), "-inf");
+        assert_eq!(format_double(f64::NAN), "nan");
+    }
+
+    #[test]
+    fn test_inline_command_deserialization() {
+        let limits = Limits::default();
+        let inline = |line: &'static str| Frame::parse_inline(&mut Bytes::from(line), &limits);
+        let args = |args: &[&'static str]| {
+            let args = args.iter().map(|arg| Frame::Bulk(Bytes::from(*arg)));
+            Ok(Frame::Array(args.collect()))
+        };
+
+        assert_eq!(inline("GET foo\r\n"), args(&["GET", "foo"]));
+        assert_eq!(inline("  set   foo\tbar \n"), args(&["set", "foo", "bar"]));
+        assert_eq!(inline("\r\n"), args(&[]));
+        assert_eq!(
+            inline("SET \"hello world\" 'it\\'s'\n"),
+            args(&["SET", "hello world", "it's"])
+        );
+        assert_eq!(
+            inline("SET key \"\\x41\\n\\\"\\\\\"\n"),
+            args(&["SET", "key", "A\n\"\\"])
+        );
+        assert_eq!(inline("SET '\\x41'\n"), args(&["SET", "\\x41"]));
+        assert_eq!(inline("SET \"\"\n"), args(&["SET", ""]));
+
+        assert_eq!(inline("GET foo"), Err(ParseError::Incomplete));
+        assert_eq!(
+            inline("SET \"foo bar\n"),
+            Err(ParseError::protocol(0, "unbalanced quotes in request"))
+        );
+        assert_eq!(
+            inline("SET \"foo\"bar\n"),
+            Err(ParseError::protocol(0, "unbalanced quotes in request"))
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_bulk_len: 8,
+            max_multibulk_len: 4,
+            max_depth: 2,
+            max_inline_len: 16,
+        };
+        let parse =
+            |frame: &[u8]| Frame::parse_with_limits(&mut Bytes::from(frame.to_vec()), &limits);
+
+        // Frames right at the limits are fine
+        assert!(parse(b"$8\r\n01234567\r\n").is_ok());
+        assert!(parse(b"*4\r\n:1\r\n:2\r\n:3\r\n*1\r\n:4\r\n").is_ok());
+        assert!(parse(b"%2\r\n:1\r\n:2\r\n:3\r\n:4\r\n").is_ok());
+        assert!(parse(b"+0123456789abcdef\r\n").is_ok());
+
+        // The declared length is rejected before the payload arrives
+        assert_eq!(
+            parse(b"$9\r\n"),
+            Err(ParseError::protocol(1, "bulk length exceeds limit"))
+        );
+        assert_eq!(
+            parse(b"=9\r\n"),
+            Err(ParseError::protocol(1, "bulk length exceeds limit"))
+        );
+        assert_eq!(
+            parse(b"*5\r\n"),
+            Err(ParseError::protocol(1, "multibulk length exceeds limit"))
+        );
+        assert_eq!(
+            parse(b"%3\r\n"),
+            Err(ParseError::protocol(1, "multibulk length exceeds limit"))
+        );
+        assert_eq!(
+            parse(b"*1\r\n~1\r\n>1\r\n"),
+            Err(ParseError::protocol(8, "nesting depth exceeds limit"))
+        );
+
+        // Lines are rejected once they are too long, with or without CRLF
+        assert_eq!(
+            parse(b"+0123456789abcdefg\r\n"),
+            Err(ParseError::protocol(1, "line length exceeds limit"))
+        );
+        assert_eq!(
+            parse(b"*1\r\n$1111111111111111111"),
+            Err(ParseError::protocol(5, "line length exceeds limit"))
+        );
+        assert_eq!(parse(b"$111111111111111"), Err(ParseError::Incomplete));
+
+        let inline = |line: &[u8]| Frame::parse_inline(&mut Bytes::from(line.to_vec()), &limits);
+        assert!(inline(b"GET 0123456789ab\r\n").is_err());
+        assert!(inline(b"GET 0123456789a\r\n").is_ok());
+        assert_eq!(
+            inline(b"GET 0123456789abcdef"),
+            Err(ParseError::protocol(0, "too big inline request"))
+        );
+    }
+
+    #[test]
+    fn test_parse_partial_frame() {
+        let limits = Limits::default();
+        let frame = Frame::Array(
+            (0..1000)
+                .map(|i| Frame::Array(vec![Frame::Bulk(Bytes::from(format!("elem:{i}")))]))
+                .collect(),
+        );
+        let bytes = frame.serialize();
+
+        // Feed the frame a few bytes at a time, like reads from a socket. The
+        // elements parsed by each attempt are kept, so that the next attempt
+        // only goes over the element that was cut off.
+        let mut partial = PartialFrame::default();
+        let mut end = 0;
+        let parsed = loop {
+            end = (end + 7).min(bytes.len());
+            let mut unparsed = &bytes[partial.consumed..end];
+            match Frame::_parse_partial(&mut unparsed, &limits, &mut partial) {
+                Ok(parsed) => break parsed,
+                Err(ParseError::Incomplete) => {
+                    assert!(end - partial.consumed < "$9\r\nelem:999\r\n".len())
+                }
+                Err(err) => panic!("{err:?}"),
+            }
+        };
+        assert_eq!(end, bytes.len());
+        assert_eq!(parsed, frame);
+        assert_eq!(partial.consumed, 0);
+
+        // Errors point at an offset from the start of the frame
+        let mut partial = PartialFrame::default();
+        let bytes = b"*3\r\n:1\r\n*1\r\n#x\r\n";
+        assert_eq!(
+            Frame::_parse_partial(&mut &bytes[..10], &limits, &mut partial),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(partial.consumed, 8);
+        assert_eq!(
+            Frame::_parse_partial(&mut &bytes[8..], &limits, &mut partial),
+            Err(ParseError::protocol(13, "invalid boolean"))
+        );
+    }
+
+    #[test]
+    fn test_consecutive_frames_deserialization() {
+        let mut bytes = Bytes::from("$-1\r\n:1\r\n$3\r\nfoo\r\n+OK\r\n");
+        assert_eq!(Frame::parse(&mut bytes), Ok(Frame::Null));
+        assert_eq!(Frame::parse(&mut bytes), Ok(Frame::Integer(1)));
+        assert_eq!(
+            Frame::parse(&mut bytes),
+            Ok(Frame::Bulk(Bytes::from("foo")))
+        );
+        assert_eq!(Frame::parse(&mut bytes), Ok(Frame::Simple("OK".into())));
+        assert!(bytes.is_empty());
+    }
+
+    /// Return both ends of a TCP connection over the loopback interface
+    async fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        return (client.unwrap(), server.unwrap().0);
+    }
+
+    #[tokio::test]
+    async fn test_subscriber() {
+        use tokio_stream::StreamExt;
+
+        let (client, server) = socket_pair().await;
+        let mut server = Connection::new(server);
+        let client = Client {
+            connection: Connection::new(client),
+        };
+        let push = |elems: &[&str]| {
+            return Frame::Push(
+                elems
+                    .iter()
+                    .map(|elem| Frame::Bulk(Bytes::copy_from_slice(elem.as_bytes())))
+                    .collect(),
+            );
+        };
+
+        let serve = async {
+            let request = server.read_frame().await.unwrap().unwrap();
+            assert_eq!(
+                Command::from_frame(&request),
+                Ok(Command::PubSub(PubSubCommand::Subscribe {
+                    channels: vec![Bytes::from("a"), Bytes::from("b")]
+                }))
+            );
+            // A message can arrive between two confirmations
+            let confirmation = |channel: &'static str, count| {
+                return Frame::Push(vec![
+                    Frame::Bulk(Bytes::from("subscribe")),
+                    Frame::Bulk(Bytes::from(channel)),
+                    Frame::Integer(count),
+                ]);
+            };
+            server.write_frame(&confirmation("a", 1)).await.unwrap();
+            server
+                .write_frame(&push(&["message", "a", "first"]))
+                .await
+                .unwrap();
+            server.write_frame(&confirmation("b", 2)).await.unwrap();
+            server
+                .write_frame(&push(&["pmessage", "*", "b", "second"]))
+                .await
+                .unwrap();
+        };
+        let (subscriber, ()) = tokio::join!(client.subscribe(&["a", "b"]), serve);
+        let subscriber = subscriber.unwrap();
+        assert_eq!(subscriber.channels(), [Bytes::from("a"), Bytes::from("b")]);
+        drop(server);
+
+        // The stream ends once the server closed the connection
+        let mut messages = std::pin::pin!(subscriber.into_stream());
+        let mut received = vec![];
+        while let Some(message) = messages.next().await {
+            received.push(message.unwrap());
+        }
+        assert_eq!(
+            received,
+            vec![
+                Message {
+                    channel: Bytes::from("a"),
+                    pattern: None,
+                    content: Bytes::from("first"),
+                },
+                Message {
+                    channel: Bytes::from("b"),
+                    pattern: Some(Bytes::from("*")),
+                    content: Bytes::from("second"),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skip_reply_attributes() {
+        let (client, server) = socket_pair().await;
+        let mut server = server;
+        let mut client = Client {
+            connection: Connection::new(client),
+        };
+
+        // Each reply comes with attributes, which must not be taken for it
+        let serve = async {
+            let mut request = [0; 64];
+            for reply in ["v", "w"] {
+                let _ = server.read(&mut request).await.unwrap();
+                let attributed = format!("|1\r\n+ttl\r\n:3600\r\n$1\r\n{reply}\r\n");
+                server.write_all(attributed.as_bytes()).await.unwrap();
+            }
+        };
+        let get = async {
+            let first = client.get("a").await.unwrap();
+            let second = client.get("b").await.unwrap();
+            (first, second)
+        };
+        let ((first, second), ()) = tokio::join!(get, serve);
+        assert_eq!(first, Some(Bytes::from("v")));
+        assert_eq!(second, Some(Bytes::from("w")));
+    }
+
+    #[tokio::test]
+    async fn test_read_pipelined_frames() {
+        let (mut client, server) = socket_pair().await;
+        let mut connection = Connection::new(server);
+
+        let cmds: Vec<Command> = (0..100)
+            .map(|i| Command::set(Bytes::from(format!("key{i}")), Bytes::from(format!("{i}"))))
+            .collect();
+        let batch: Vec<u8> = cmds
+            .iter()
+            .flat_map(|cmd| cmd.to_frame().serialize())
+            .collect();
+        client.write_all(&batch).await.unwrap();
+        drop(client);
+
+        for cmd in cmds.iter() {
+            let frame = connection.read_frame().await.unwrap().unwrap();
+            assert_eq!(Command::parse_command(&frame).as_ref(), Some(cmd));
+        }
+        assert_eq!(connection.read_frame().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_split_across_writes() {
+        let (mut client, server) = socket_pair().await;
+        let mut connection = Connection::new(server);
+
+        let reader = tokio::spawn(async move {
+            let mut frames = vec![];
+            while let Some(frame) = connection.read_frame().await.unwrap() {
+                frames.push(frame);
+            }
+            frames
+        });
+        // The second frame straddles the two writes
+        client.write_all(b"+OK\r\n$5\r\nhel").await.unwrap();
+        client.flush().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        client.write_all(b"lo\r\n:42\r\n").await.unwrap();
+        drop(client);
+
+        assert_eq!(
+            reader.await.unwrap(),
+            vec![
+                Frame::Simple("OK".into()),
+                Frame::Bulk(Bytes::from("hello")),
+                Frame::Integer(42),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_protocol_error() {
+        let (mut client, server) = socket_pair().await;
+        let mut connection = Connection::new(server);
+
+        client.write_all(b"+OK\r\n$abc\r\n").await.unwrap();
+        assert_eq!(
+            connection.read_frame().await.unwrap(),
+            Some(Frame::Simple("OK".into()))
+        );
+        let err = connection.read_frame().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ParseError>(),
+            Some(&ParseError::protocol(1, "invalid bulk length"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_large_frame() {
+        let (client, server) = socket_pair().await;
+        let mut sender = Connection::new(client);
+        let mut receiver = Connection::new(server);
+
+        // Far larger than what the socket accepts in a single write
+        let payload = Bytes::from(vec![b'x'; 16 * 1024 * 1024]);
+        let frame = Frame::Array(vec![Frame::Bulk(payload.clone()), Frame::Integer(1)]);
+        let reader = tokio::spawn(async move { receiver.read_frame().await.unwrap() });
+        sender.write_frame(&frame).await.unwrap();
+
+        assert_eq!(reader.await.unwrap(), Some(frame));
+    }
+
+    #[tokio::test]
+    async fn test_buffered_frames_are_flushed() {
+        let (client, server) = socket_pair().await;
+        let mut sender = Connection::new(client);
+        let mut receiver = Connection::new(server);
+
+        for i in 0..100 {
+            sender.buffer_frame(&Frame::Integer(i)).await.unwrap();
+        }
+        sender.flush().await.unwrap();
+        for i in 0..100 {
+            assert_eq!(
+                receiver.read_frame().await.unwrap(),
+                Some(Frame::Integer(i))
+            );
+        }
+
+        // Reading from the connection flushes replies that are still buffered
+        receiver
+            .buffer_frame(&Frame::Simple("PONG".into()))
+            .await
+            .unwrap();
+        let reader = tokio::spawn(async move { receiver.read_frame().await.unwrap() });
+        assert_eq!(
+            sender.read_frame().await.unwrap(),
+            Some(Frame::Simple("PONG".into()))
+        );
+        drop(sender);
+        assert_eq!(reader.await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_connection_protocol() {
+        let (mut client, server) = socket_pair().await;
+        let mut connection = Connection::new(server);
+
+        connection.write_frame(&Frame::Null).await.unwrap();
+        connection.set_protocol(Protocol::Resp3);
+        connection.write_frame(&Frame::Null).await.unwrap();
+        drop(connection);
+
+        let mut received = vec![];
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"$-1\r\n_\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_read_inline_commands() {
+        let (mut client, server) = socket_pair().await;
+        let mut connection = Connection::new(server);
+
+        client
+            .write_all(b"SET foo \"bar baz\"\r\n\r\n*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\nDEL foo\n")
+            .await
+            .unwrap();
+        drop(client);
+
+        let mut cmds = vec![];
+        while let Some(frame) = connection.read_frame().await.unwrap() {
+            cmds.push(Command::parse_command(&frame).unwrap());
+        }
+        assert_eq!(
+            cmds,
+            vec![
+                Command::set(Bytes::from("foo"), Bytes::from("bar baz")),
+                Command::get(Bytes::from("foo")),
+                Command::del(Bytes::from("foo")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_over_limits() {
+        let (mut client, server) = socket_pair().await;
+        let mut connection = Connection::new(server);
+        connection.set_limits(Limits {
+            max_bulk_len: 1024,
+            ..Limits::default()
+        });
+
+        client
+            .write_all(b"*2\r\n$3\r\nGET\r\n$1025\r\n")
+            .await
+            .unwrap();
+        let err = connection.read_frame().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ParseError>(),
+            Some(&ParseError::protocol(14, "bulk length exceeds limit"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_truncated_by_peer() {
+        let (mut client, server) = socket_pair().await;
+        let mut connection = Connection::new(server);
+
+        client.write_all(b"*2\r\n$3\r\nGET\r\n").await.unwrap();
+        drop(client);
+        assert!(connection.read_frame().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_hash_replies() {
+        let (client, server) = socket_pair().await;
+        let mut client = Client {
+            connection: Connection::new(client),
+        };
+        let mut server = Connection::new(server);
+
+        let bulk = |s: &'static str| Frame::Bulk(Bytes::from(s));
+        let replies = [
+            // RESP2 peers get HGETALL as a flat array, RESP3 peers as a map
+            Frame::Array(vec![bulk("a"), bulk("1"), bulk("b"), bulk("2")]),
+            Frame::Map(vec![(bulk("a"), bulk("1"))]),
+            Frame::Array(vec![bulk("17"), Frame::Array(vec![bulk("a"), bulk("1")])]),
+            bulk("2.5"),
+        ];
+        tokio::spawn(async move {
+            for reply in replies {
+                server.read_frame().await.unwrap().unwrap();
+                server.write_frame(&reply).await.unwrap();
+            }
+        });
+
+        let hash = client.hgetall("h").await.unwrap();
+        assert_eq!(hash.len(), 2);
+        assert_eq!(hash[&Bytes::from("b")], Bytes::from("2"));
+        let hash = client.hgetall("h").await.unwrap();
+        assert_eq!(hash[&Bytes::from("a")], Bytes::from("1"));
+        let (cursor, page) = client.hscan("h", 0, Some("a*"), None).await.unwrap();
+        assert_eq!(cursor, 17);
+        assert_eq!(page[&Bytes::from("a")], Bytes::from("1"));
+        assert_eq!(client.hincrbyfloat("h", "f", 1.0).await.unwrap(), 2.5);
+    }
+
+    #[tokio::test]
+    async fn test_client_batch_commands() {
+        let (client, server) = socket_pair().await;
+        let mut client = Client {
+            connection: Connection::new(client),
+        };
+        let mut server = Connection::new(server);
+
+        let replies = [
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("1")),
+                Frame::Null,
+                Frame::Bulk(Bytes::from("3")),
+            ]),
+            Frame::Simple("OK".into()),
+            Frame::Integer(0),
+            Frame::Integer(2),
+        ];
+        let server = tokio::spawn(async move {
+            let mut requests = vec![];
+            for reply in replies {
+                requests.push(server.read_frame().await.unwrap().unwrap());
+                server.write_frame(&reply).await.unwrap();
+            }
+            return requests;
+        });
+
+        assert_eq!(
+            client.mget(&["a", "b", "c"]).await.unwrap(),
+            vec![Some(Bytes::from("1")), None, Some(Bytes::from("3"))]
+        );
+        client.mset(&[("a", "1"), ("c", "3")]).await.unwrap();
+        assert!(!client.msetnx(&[("a", "1"), ("b", "2")]).await.unwrap());
+        assert_eq!(client.del_many(&["a", "c"]).await.unwrap(), 2);
+
+        let bulk = |s: &'static str| Frame::Bulk(Bytes::from(s));
+        assert_eq!(
+            server.await.unwrap(),
+            vec![
+                Frame::Array(vec![bulk("MGET"), bulk("a"), bulk("b"), bulk("c")]),
+                Frame::Array(vec![
+                    bulk("MSET"),
+                    bulk("a"),
+                    bulk("1"),
+                    bulk("c"),
+                    bulk("3")
+                ]),
+                Frame::Array(vec![
+                    bulk("MSETNX"),
+                    bulk("a"),
+                    bulk("1"),
+                    bulk("b"),
+                    bulk("2")
+                ]),
+                Frame::Array(vec![bulk("DEL"), bulk("a"), bulk("c")]),
+            ]
+        );
     }
 }